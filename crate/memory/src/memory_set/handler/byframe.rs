@@ -29,13 +29,28 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
         addr: VirtAddr,
         attr: &MemoryAttr,
     ) {
+        if clone_map_shared(&self.allocator, pt, src_pt, addr, attr) {
+            return;
+        }
         self.map(pt, addr, attr);
         let data = src_pt.get_page_slice_mut(addr);
         pt.get_page_slice_mut(addr).copy_from_slice(data);
     }
 
-    fn handle_page_fault(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
-        false
+    fn handle_page_fault(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
+        handle_shared_page_fault(&self.allocator, pt, addr)
+    }
+
+    fn discard(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.unmap(pt, addr);
+        self.map(pt, addr, attr);
+        // the new frame is mapped at once, so clear the old data in it
+        let data = pt.get_page_slice_mut(addr);
+        let len = data.len();
+        for x in data {
+            *x = 0;
+        }
+        pt.flush_cache_copy_user(addr, addr + len, attr.execute);
     }
 }
 
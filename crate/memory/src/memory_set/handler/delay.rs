@@ -36,6 +36,9 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
     ) {
         let entry = src_pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
+            if clone_map_shared(&self.allocator, pt, src_pt, addr, attr) {
+                return;
+            }
             // eager map and copy data
             let data = src_pt.get_page_slice_mut(addr);
             let target = self.allocator.alloc().expect("failed to alloc frame");
@@ -53,7 +56,7 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         let entry = pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
             // not a delay case
-            return false;
+            return handle_shared_page_fault(&self.allocator, pt, addr);
         }
         let frame = self.allocator.alloc().expect("failed to alloc frame");
         entry.set_target(frame);
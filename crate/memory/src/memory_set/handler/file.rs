@@ -14,6 +14,15 @@ pub trait Read: Clone + Send + Sync + 'static {
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
 }
 
+pub trait Write: Read {
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize;
+}
+
+/// Delay mapping a page to an area of a file,
+/// and write dirty pages back to the file when syncing or unmapping.
+#[derive(Clone)]
+pub struct SharedFile<F, T>(pub File<F, T>);
+
 impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
     fn box_clone(&self) -> Box<dyn MemoryHandler> {
         Box::new(self.clone())
@@ -45,6 +54,9 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
     ) {
         let entry = src_pt.get_entry(addr).expect("failed to get entry");
         if entry.present() && !attr.readonly {
+            if clone_map_shared(&self.allocator, pt, src_pt, addr, attr) {
+                return;
+            }
             // eager map and copy data
             let data = src_pt.get_page_slice_mut(addr);
             let target = self.allocator.alloc().expect("failed to alloc frame");
@@ -62,7 +74,7 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
         let addr = addr & !(PAGE_SIZE - 1);
         let entry = pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
-            return false;
+            return handle_shared_page_fault(&self.allocator, pt, addr);
         }
         let execute = entry.execute();
         let frame = self.allocator.alloc().expect("failed to alloc frame");
@@ -91,6 +103,78 @@ impl<F: Read, T: FrameAllocator> File<F, T> {
     }
 }
 
+impl<F: Write, T: FrameAllocator> MemoryHandler for SharedFile<F, T> {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut dyn PageTable, addr: usize, attr: &MemoryAttr) {
+        self.0.map(pt, addr, attr);
+    }
+
+    fn unmap(&self, pt: &mut dyn PageTable, addr: usize) {
+        self.0.write_back(pt, addr);
+        self.0.unmap(pt, addr);
+    }
+
+    fn clone_map(
+        &self,
+        pt: &mut dyn PageTable,
+        src_pt: &mut dyn PageTable,
+        addr: usize,
+        attr: &MemoryAttr,
+    ) {
+        // a written page is copied for each page table,
+        // so flush first to let the new one see the latest data
+        self.0.write_back(src_pt, addr);
+        self.0.clone_map(pt, src_pt, addr, attr);
+    }
+
+    fn handle_page_fault(&self, pt: &mut dyn PageTable, addr: usize) -> bool {
+        self.0.handle_page_fault(pt, addr)
+    }
+
+    fn sync(&self, pt: &mut dyn PageTable, addr: usize) {
+        self.0.write_back(pt, addr);
+    }
+
+    fn discard(&self, pt: &mut dyn PageTable, addr: usize, _attr: &MemoryAttr) {
+        // the page holds the data of the file, which must not be dropped,
+        // so it is only written back and kept
+        self.0.write_back(pt, addr);
+    }
+}
+
+impl<F: Write, T: FrameAllocator> File<F, T> {
+    /// Write the page at `addr` back to the file if it is dirty.
+    fn write_back(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        let addr = addr & !(PAGE_SIZE - 1);
+        match pt.get_entry(addr) {
+            Some(entry) if entry.present() && entry.dirty() => {
+                entry.clear_dirty();
+                entry.update();
+            }
+            _ => return,
+        }
+        let data = pt.get_page_slice_mut(addr);
+        let file_offset = addr + self.file_start - self.mem_start;
+        let write_size = (self.file_end as isize - file_offset as isize)
+            .min(PAGE_SIZE as isize)
+            .max(0) as usize;
+        self.file.write_at(file_offset, &data[..write_size]);
+    }
+}
+
+impl<F, T> Debug for SharedFile<F, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.debug_struct("SharedFileHandler")
+            .field("mem_start", &self.0.mem_start)
+            .field("file_start", &self.0.file_start)
+            .field("file_end", &self.0.file_end)
+            .finish()
+    }
+}
+
 impl<F, T> Debug for File<F, T> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
         f.debug_struct("FileHandler")
@@ -0,0 +1,29 @@
+use super::*;
+
+/// Reserve an area without mapping any page,
+/// so that accessing it always faults.
+#[derive(Debug, Clone)]
+pub struct Guard;
+
+impl MemoryHandler for Guard {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, _pt: &mut dyn PageTable, _addr: VirtAddr, _attr: &MemoryAttr) {}
+
+    fn unmap(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) {}
+
+    fn clone_map(
+        &self,
+        _pt: &mut dyn PageTable,
+        _src_pt: &mut dyn PageTable,
+        _addr: VirtAddr,
+        _attr: &MemoryAttr,
+    ) {
+    }
+
+    fn handle_page_fault(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+}
@@ -23,6 +23,15 @@ pub trait MemoryHandler: Debug + Send + Sync + 'static {
     /// Handle page fault on `addr`
     /// Return true if success, false if error
     fn handle_page_fault(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool;
+
+    /// Write the page of `addr` back to its backing storage if needed
+    fn sync(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) {}
+
+    /// Free the frame of `addr`, so that the page is filled again as newly mapped
+    fn discard(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        self.unmap(pt, addr);
+        self.map(pt, addr, attr);
+    }
 }
 
 impl Clone for Box<dyn MemoryHandler> {
@@ -33,16 +42,170 @@ impl Clone for Box<dyn MemoryHandler> {
 
 pub trait FrameAllocator: Debug + Clone + Send + Sync + 'static {
     fn alloc(&self) -> Option<PhysAddr>;
+    /// Drop a reference to the frame `target`, and free it when it is the last one.
     fn dealloc(&self, target: PhysAddr);
+    /// Add a reference to the frame `target`, so that it can be mapped by one more page table.
+    /// Return false if sharing frames is not supported.
+    fn share(&self, _target: PhysAddr) -> bool {
+        false
+    }
+    /// Number of references to the frame `target`
+    fn ref_count(&self, _target: PhysAddr) -> usize {
+        1
+    }
+}
+
+/// Map `addr` of `pt` to the frame of `addr` of `src_pt`, copy-on-write.
+/// Both entries become readonly until written, and the frame gets one more reference.
+/// Return false if the page can not be shared, then it should be copied.
+fn clone_map_shared(
+    allocator: &impl FrameAllocator,
+    pt: &mut dyn PageTable,
+    src_pt: &mut dyn PageTable,
+    addr: VirtAddr,
+    attr: &MemoryAttr,
+) -> bool {
+    let entry = src_pt.get_entry(addr).expect("failed to get entry");
+    let target = entry.target();
+    entry.set_shared(!attr.readonly);
+    if !(entry.writable_shared() || entry.readonly_shared()) {
+        // the page table can not mark shared pages
+        return false;
+    }
+    if !allocator.share(target) {
+        entry.clear_shared();
+        return false;
+    }
+    entry.set_writable(false);
+    entry.update();
+    let entry = pt.map(addr, target);
+    entry.set_shared(!attr.readonly);
+    attr.apply(entry);
+    true
+}
+
+/// Copy the page of `addr` on the first write to it after sharing.
+/// The copy is skipped if no other page table maps the frame.
+/// Return false if the page is not a writable shared one.
+fn handle_shared_page_fault(
+    allocator: &impl FrameAllocator,
+    pt: &mut dyn PageTable,
+    addr: VirtAddr,
+) -> bool {
+    let addr = addr & !(PAGE_SIZE - 1);
+    let entry = pt.get_entry(addr).expect("failed to get entry");
+    if !entry.present() || !entry.writable_shared() {
+        return false;
+    }
+    let target = entry.target();
+    if allocator.ref_count(target) > 1 {
+        let execute = entry.execute();
+        let data = pt.get_page_slice_mut(addr);
+        let frame = allocator.alloc().expect("failed to alloc frame");
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        entry.set_target(frame);
+        entry.clear_shared();
+        entry.set_writable(true);
+        entry.update();
+        pt.get_page_slice_mut(addr).copy_from_slice(data);
+        pt.flush_cache_copy_user(addr, addr + PAGE_SIZE, execute);
+        allocator.dealloc(target);
+    } else {
+        entry.clear_shared();
+        entry.set_writable(true);
+        entry.update();
+    }
+    true
 }
 
 mod byframe;
 mod delay;
 mod file;
+mod guard;
 mod linear;
 //mod swap;
 
 pub use self::byframe::ByFrame;
 pub use self::delay::Delay;
-pub use self::file::{File, Read};
+pub use self::file::{File, Read, SharedFile, Write};
+pub use self::guard::Guard;
 pub use self::linear::Linear;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paging::MockPageTable;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Reference counts of the frames of the mock physical memory
+    #[derive(Debug, Clone, Default)]
+    struct MockFrameAllocator(Arc<[AtomicUsize; 16]>);
+
+    impl FrameAllocator for MockFrameAllocator {
+        fn alloc(&self) -> Option<PhysAddr> {
+            let frame = self
+                .0
+                .iter()
+                .position(|count| count.load(Ordering::SeqCst) == 0)?;
+            self.0[frame].store(1, Ordering::SeqCst);
+            Some(frame * PAGE_SIZE)
+        }
+        fn dealloc(&self, target: PhysAddr) {
+            self.0[target / PAGE_SIZE].fetch_sub(1, Ordering::SeqCst);
+        }
+        fn share(&self, target: PhysAddr) -> bool {
+            self.0[target / PAGE_SIZE].fetch_add(1, Ordering::SeqCst);
+            true
+        }
+        fn ref_count(&self, target: PhysAddr) -> usize {
+            self.0[target / PAGE_SIZE].load(Ordering::SeqCst)
+        }
+    }
+
+    fn target(pt: &mut MockPageTable, addr: VirtAddr) -> PhysAddr {
+        pt.get_entry(addr).unwrap().target()
+    }
+
+    /// After fork, the first write on each side goes to a page of its own,
+    /// and the frame is copied only while it is still shared
+    #[test]
+    fn fork_then_write() {
+        const ADDR: VirtAddr = 0x1000;
+        let allocator = MockFrameAllocator::default();
+        let handler = ByFrame::new(allocator.clone());
+        let attr = MemoryAttr::default();
+        let mut parent = MockPageTable::new();
+        let mut child = parent.new_sharing();
+        for pt in [&mut parent, &mut child].iter_mut() {
+            let handler = handler.clone();
+            pt.set_handler(Box::new(move |pt: &mut MockPageTable, addr: VirtAddr| {
+                assert!(handler.handle_page_fault(pt, addr));
+            }));
+        }
+        handler.map(&mut parent, ADDR, &attr);
+        parent.write(ADDR, 1);
+        let frame = target(&mut parent, ADDR);
+
+        handler.clone_map(&mut child, &mut parent, ADDR, &attr);
+        assert_eq!(target(&mut child, ADDR), frame);
+        assert_eq!(allocator.ref_count(frame), 2);
+        assert!(!parent.get_entry(ADDR).unwrap().writable());
+        assert_eq!(child.read(ADDR), 1);
+
+        // the child copies the shared frame
+        child.write(ADDR, 2);
+        let copy = target(&mut child, ADDR);
+        assert_ne!(copy, frame);
+        assert_eq!(allocator.ref_count(frame), 1);
+        assert_eq!(allocator.ref_count(copy), 1);
+        assert_eq!(parent.read(ADDR), 1);
+
+        // the parent keeps the frame nobody else maps
+        parent.write(ADDR, 3);
+        assert_eq!(target(&mut parent, ADDR), frame);
+        assert_eq!(allocator.ref_count(frame), 1);
+        assert_eq!(parent.read(ADDR), 3);
+        assert_eq!(child.read(ADDR), 2);
+    }
+}
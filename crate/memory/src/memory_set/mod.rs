@@ -23,6 +23,18 @@ pub struct MemoryArea {
 }
 
 impl MemoryArea {
+    /// Get the start address of the area
+    pub fn start_addr(&self) -> VirtAddr {
+        self.start_addr
+    }
+    /// Get the end address of the area
+    pub fn end_addr(&self) -> VirtAddr {
+        self.end_addr
+    }
+    /// Get the name of the area
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
     /// Test whether a virtual address is in the memory area
     pub fn contains(&self, addr: VirtAddr) -> bool {
         addr >= self.start_addr && addr < self.end_addr
@@ -58,19 +70,46 @@ impl MemoryArea {
         !(p1 <= p2 || p0 >= p3)
     }
     /// Map all pages in the area to page table `pt`
-    fn map(&self, pt: &mut dyn PageTable) {
+    fn map(&self, pt: &mut dyn PageTable, resident: &mut usize) {
         for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.map(pt, page.start_address(), &self.attr);
+            let addr = page.start_address();
+            count_resident(pt, resident, addr, |pt| {
+                self.handler.map(pt, addr, &self.attr)
+            });
         }
     }
     /// Unmap all pages in the area from page table `pt`
-    fn unmap(&self, pt: &mut dyn PageTable) {
+    fn unmap(&self, pt: &mut dyn PageTable, resident: &mut usize) {
         for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.unmap(pt, page.start_address());
+            let addr = page.start_address();
+            count_resident(pt, resident, addr, |pt| self.handler.unmap(pt, addr));
         }
     }
 }
 
+/// Whether the page of `addr` is present in memory
+fn is_present(pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
+    pt.get_entry(addr).map_or(false, |entry| entry.present())
+}
+
+/// Call `f` of a handler on the page of `addr`,
+/// and count the page in `resident` if it is brought in, or out if it is dropped
+fn count_resident<R>(
+    pt: &mut dyn PageTable,
+    resident: &mut usize,
+    addr: VirtAddr,
+    f: impl FnOnce(&mut dyn PageTable) -> R,
+) -> R {
+    let before = is_present(pt, addr);
+    let result = f(pt);
+    match (before, is_present(pt, addr)) {
+        (false, true) => *resident += 1,
+        (true, false) => *resident -= 1,
+        _ => {}
+    }
+    result
+}
+
 /// The attributes of the memory
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
 pub struct MemoryAttr {
@@ -105,7 +144,13 @@ impl MemoryAttr {
     /// NOTE: You may need to set present manually.
     pub fn apply(&self, entry: &mut dyn Entry) {
         entry.set_user(self.user);
-        entry.set_writable(!self.readonly);
+        if entry.writable_shared() || entry.readonly_shared() {
+            // shared pages stay readonly, and are copied when written
+            entry.set_shared(!self.readonly);
+            entry.set_writable(false);
+        } else {
+            entry.set_writable(!self.readonly);
+        }
         entry.set_execute(self.execute);
         entry.set_mmio(self.mmio);
         entry.update();
@@ -119,6 +164,8 @@ impl MemoryAttr {
 pub struct MemorySet<T: PageTableExt> {
     areas: Vec<MemoryArea>,
     page_table: T,
+    /// Number of pages of the areas present in memory
+    resident: usize,
 }
 
 impl<T: PageTableExt> MemorySet<T> {
@@ -127,6 +174,7 @@ impl<T: PageTableExt> MemorySet<T> {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new(),
+            resident: 0,
         }
     }
     /// Create a new `MemorySet` for kernel remap
@@ -134,6 +182,7 @@ impl<T: PageTableExt> MemorySet<T> {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new_bare(),
+            resident: 0,
         }
     }
     /// Check the pointer is within the readable memory
@@ -216,7 +265,7 @@ impl<T: PageTableExt> MemorySet<T> {
             handler: Box::new(handler),
             name,
         };
-        area.map(&mut self.page_table);
+        area.map(&mut self.page_table, &mut self.resident);
         // keep order by start address
         let idx = self
             .areas
@@ -228,13 +277,42 @@ impl<T: PageTableExt> MemorySet<T> {
         self.areas.insert(idx, area);
     }
 
+    /// Extend the area starting at `start_addr` down to `new_start_addr`,
+    /// and map the new pages by its handler.
+    /// Return false if there is no such area, or the new part is not free.
+    pub fn grow_down(&mut self, start_addr: VirtAddr, new_start_addr: VirtAddr) -> bool {
+        let new_start_addr = new_start_addr & !(PAGE_SIZE - 1);
+        if new_start_addr >= start_addr || !self.test_free_area(new_start_addr, start_addr) {
+            return false;
+        }
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+            ref mut resident,
+        } = self;
+        match areas.iter_mut().find(|area| area.start_addr == start_addr) {
+            Some(area) => {
+                for page in Page::range_of(new_start_addr, start_addr) {
+                    let addr = page.start_address();
+                    count_resident(page_table, resident, addr, |pt| {
+                        area.handler.map(pt, addr, &area.attr)
+                    });
+                }
+                // still in order, as the new part is free
+                area.start_addr = new_start_addr;
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Remove the area `[start_addr, end_addr)` from `MemorySet`
     pub fn pop(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
         assert!(start_addr <= end_addr, "invalid memory area");
         for i in 0..self.areas.len() {
             if self.areas[i].start_addr == start_addr && self.areas[i].end_addr == end_addr {
                 let area = self.areas.remove(i);
-                area.unmap(&mut self.page_table);
+                area.unmap(&mut self.page_table, &mut self.resident);
                 return;
             }
         }
@@ -251,7 +329,7 @@ impl<T: PageTableExt> MemorySet<T> {
                 if self.areas[i].start_addr >= start_addr && self.areas[i].end_addr <= end_addr {
                     // subset
                     let area = self.areas.remove(i);
-                    area.unmap(&mut self.page_table);
+                    area.unmap(&mut self.page_table, &mut self.resident);
                     i -= 1;
                 } else if self.areas[i].start_addr >= start_addr
                     && self.areas[i].start_addr < end_addr
@@ -265,7 +343,7 @@ impl<T: PageTableExt> MemorySet<T> {
                         handler: area.handler.box_clone(),
                         name: area.name,
                     };
-                    dead_area.unmap(&mut self.page_table);
+                    dead_area.unmap(&mut self.page_table, &mut self.resident);
                     let new_area = MemoryArea {
                         start_addr: end_addr,
                         end_addr: area.end_addr,
@@ -285,7 +363,7 @@ impl<T: PageTableExt> MemorySet<T> {
                         handler: area.handler.box_clone(),
                         name: area.name,
                     };
-                    dead_area.unmap(&mut self.page_table);
+                    dead_area.unmap(&mut self.page_table, &mut self.resident);
                     let new_area = MemoryArea {
                         start_addr: area.start_addr,
                         end_addr: start_addr,
@@ -304,7 +382,7 @@ impl<T: PageTableExt> MemorySet<T> {
                         handler: area.handler.box_clone(),
                         name: area.name,
                     };
-                    dead_area.unmap(&mut self.page_table);
+                    dead_area.unmap(&mut self.page_table, &mut self.resident);
                     let new_area_left = MemoryArea {
                         start_addr: area.start_addr,
                         end_addr: start_addr,
@@ -328,6 +406,113 @@ impl<T: PageTableExt> MemorySet<T> {
         }
     }
 
+    /// Split the area containing `addr` into two areas at `addr`.
+    fn split_at(&mut self, addr: VirtAddr) {
+        let idx = self
+            .areas
+            .iter()
+            .position(|area| area.start_addr < addr && addr < area.end_addr);
+        if let Some(i) = idx {
+            let area = &mut self.areas[i];
+            let new_area = MemoryArea {
+                start_addr: addr,
+                end_addr: area.end_addr,
+                attr: area.attr,
+                handler: area.handler.box_clone(),
+                name: area.name,
+            };
+            area.end_addr = addr;
+            self.areas.insert(i + 1, new_area);
+        }
+    }
+
+    /// Change the attribute of the area `[start_addr, end_addr)`
+    /// and split existed ones when necessary.
+    pub fn protect(&mut self, start_addr: VirtAddr, end_addr: VirtAddr, attr: MemoryAttr) {
+        assert!(start_addr <= end_addr, "invalid memory area");
+        self.split_at(start_addr);
+        self.split_at(end_addr);
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+            ..
+        } = self;
+        for area in areas.iter_mut() {
+            if area.start_addr >= start_addr && area.end_addr <= end_addr {
+                area.attr = attr;
+                for page in Page::range_of(area.start_addr, area.end_addr) {
+                    if let Some(entry) = page_table.get_entry(page.start_address()) {
+                        attr.apply(entry);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Write the pages in `[start_addr, end_addr)` back to their backing storage
+    pub fn sync(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ..
+        } = self;
+        for area in areas.iter() {
+            if area.is_overlap_with(start_addr, end_addr) {
+                let start = area.start_addr.max(start_addr);
+                let end = area.end_addr.min(end_addr);
+                for page in Page::range_of(start, end) {
+                    area.handler.sync(page_table, page.start_address());
+                }
+            }
+        }
+    }
+
+    /// Free the frames of pages in `[start_addr, end_addr)`.
+    /// The pages are zero-filled or read from their files again on next access.
+    pub fn discard(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ref mut resident,
+        } = self;
+        for area in areas.iter() {
+            if area.is_overlap_with(start_addr, end_addr) {
+                let start = area.start_addr.max(start_addr);
+                let end = area.end_addr.min(end_addr);
+                for page in Page::range_of(start, end) {
+                    let addr = page.start_address();
+                    count_resident(page_table, resident, addr, |pt| {
+                        area.handler.discard(pt, addr, &area.attr)
+                    });
+                }
+            }
+        }
+    }
+
+    /// Fill the pages in `[start_addr, end_addr)` which are not present yet,
+    /// as if they are accessed
+    pub fn prefault(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ref mut resident,
+        } = self;
+        for area in areas.iter() {
+            if area.is_overlap_with(start_addr, end_addr) {
+                let start = area.start_addr.max(start_addr);
+                let end = area.end_addr.min(end_addr);
+                for page in Page::range_of(start, end) {
+                    let addr = page.start_address();
+                    if !is_present(page_table, addr) {
+                        count_resident(page_table, resident, addr, |pt| {
+                            area.handler.handle_page_fault(pt, addr)
+                        });
+                    }
+                }
+            }
+        }
+    }
+
     /// Get iterator of areas
     pub fn iter(&self) -> impl Iterator<Item = &MemoryArea> {
         self.areas.iter()
@@ -352,10 +537,10 @@ impl<T: PageTableExt> MemorySet<T> {
         let Self {
             ref mut page_table,
             ref mut areas,
-            ..
+            ref mut resident,
         } = self;
         for area in areas.iter() {
-            area.unmap(page_table);
+            area.unmap(page_table, resident);
         }
         areas.clear();
     }
@@ -376,16 +561,26 @@ impl<T: PageTableExt> MemorySet<T> {
         &mut self.page_table
     }
 
+    /// Number of pages present in memory, which is kept when they are mapped or unmapped
+    pub fn resident_pages(&self) -> usize {
+        self.resident
+    }
+
     pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
         let area = self.areas.iter().find(|area| area.contains(addr));
         match area {
-            Some(area) => area.handler.handle_page_fault(&mut self.page_table, addr),
+            Some(area) => count_resident(&mut self.page_table, &mut self.resident, addr, |pt| {
+                area.handler.handle_page_fault(pt, addr)
+            }),
             None => false,
         }
     }
 
+    /// Clone the areas and their pages into a new page table.
+    /// The handlers may share frames copy-on-write, which makes pages of `self` readonly.
     pub fn clone(&mut self) -> Self {
         let mut new_page_table = T::new();
+        let mut new_resident = 0;
         let Self {
             ref mut page_table,
             ref areas,
@@ -393,17 +588,16 @@ impl<T: PageTableExt> MemorySet<T> {
         } = self;
         for area in areas.iter() {
             for page in Page::range_of(area.start_addr, area.end_addr) {
-                area.handler.clone_map(
-                    &mut new_page_table,
-                    page_table,
-                    page.start_address(),
-                    &area.attr,
-                );
+                let addr = page.start_address();
+                count_resident(&mut new_page_table, &mut new_resident, addr, |pt| {
+                    area.handler.clone_map(pt, page_table, addr, &area.attr)
+                });
             }
         }
         MemorySet {
             areas: areas.clone(),
             page_table: new_page_table,
+            resident: new_resident,
         }
     }
 }
@@ -5,6 +5,8 @@
 
 use super::*;
 use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::cell::UnsafeCell;
 
 const PAGE_COUNT: usize = 16;
 const PAGE_SIZE: usize = 4096;
@@ -12,7 +14,8 @@ const PAGE_SIZE: usize = 4096;
 // a mock page table for test purpose
 pub struct MockPageTable {
     entries: [MockEntry; PAGE_COUNT],
-    data: [u8; PAGE_SIZE * PAGE_COUNT],
+    /// The physical memory, shared with the page tables created by `new_sharing`
+    data: Rc<UnsafeCell<[u8; PAGE_SIZE * PAGE_COUNT]>>,
     page_fault_handler: Option<PageFaultHandler>,
 }
 
@@ -27,6 +30,9 @@ pub struct MockEntry {
     writable_shared: bool,
     readonly_shared: bool,
     swapped: bool,
+    user: bool,
+    execute: bool,
+    mmio: u8,
 }
 
 impl Entry for MockEntry {
@@ -82,22 +88,22 @@ impl Entry for MockEntry {
         self.swapped = value;
     }
     fn user(&self) -> bool {
-        unimplemented!()
+        self.user
     }
-    fn set_user(&mut self, _value: bool) {
-        unimplemented!()
+    fn set_user(&mut self, value: bool) {
+        self.user = value;
     }
     fn execute(&self) -> bool {
-        unimplemented!()
+        self.execute
     }
-    fn set_execute(&mut self, _value: bool) {
-        unimplemented!()
+    fn set_execute(&mut self, value: bool) {
+        self.execute = value;
     }
     fn mmio(&self) -> u8 {
-        unimplemented!()
+        self.mmio
     }
-    fn set_mmio(&mut self, _value: u8) {
-        unimplemented!()
+    fn set_mmio(&mut self, value: u8) {
+        self.mmio = value;
     }
 }
 
@@ -125,16 +131,16 @@ impl PageTable for MockPageTable {
     fn get_page_slice_mut<'a, 'b>(&'a mut self, addr: VirtAddr) -> &'b mut [u8] {
         self._read(addr);
         let pa = self.translate(addr) & !(PAGE_SIZE - 1);
-        let data = unsafe { &mut *(&mut self.data as *mut [u8; PAGE_SIZE * PAGE_COUNT]) };
-        &mut data[pa..pa + PAGE_SIZE]
+        &mut self.memory()[pa..pa + PAGE_SIZE]
     }
+    fn flush_cache_copy_user(&mut self, _start: VirtAddr, _end: VirtAddr, _execute: bool) {}
     fn read(&mut self, addr: usize) -> u8 {
         self._read(addr);
-        self.data[self.translate(addr)]
+        self.memory()[self.translate(addr)]
     }
     fn write(&mut self, addr: usize, data: u8) {
         self._write(addr);
-        self.data[self.translate(addr)] = data;
+        self.memory()[self.translate(addr)] = data;
     }
 }
 
@@ -144,13 +150,31 @@ impl MockPageTable {
      **  @retval MockPageTable        the mock page table created
      */
     pub fn new() -> Self {
-        use core::mem::MaybeUninit;
         MockPageTable {
             entries: [MockEntry::default(); PAGE_COUNT],
-            data: unsafe { MaybeUninit::zeroed().assume_init() },
+            data: Rc::new(UnsafeCell::new([0; PAGE_SIZE * PAGE_COUNT])),
             page_fault_handler: None,
         }
     }
+    /*
+     **  @brief  create a new MockPageTable sharing the physical memory
+     **          used for mock another address space, as after fork
+     **  @retval MockPageTable        the mock page table created
+     */
+    pub fn new_sharing(&self) -> Self {
+        MockPageTable {
+            entries: [MockEntry::default(); PAGE_COUNT],
+            data: self.data.clone(),
+            page_fault_handler: None,
+        }
+    }
+    /*
+     **  @brief  get the physical memory
+     **  @retval &mut [u8]            the physical memory
+     */
+    fn memory<'a>(&self) -> &'a mut [u8; PAGE_SIZE * PAGE_COUNT] {
+        unsafe { &mut *self.data.get() }
+    }
     /*
      **  @brief  set the page fault handler
      **          used for mock the page fault feature
@@ -184,7 +208,10 @@ impl MockPageTable {
         let entry = &self.entries[addr / PAGE_SIZE];
         assert!(entry.present);
         let pa = (entry.target & !(PAGE_SIZE - 1)) | (addr & (PAGE_SIZE - 1));
-        assert!(pa < self.data.len(), "Physical memory access out of range");
+        assert!(
+            pa < PAGE_SIZE * PAGE_COUNT,
+            "Physical memory access out of range"
+        );
         pa
     }
     /*
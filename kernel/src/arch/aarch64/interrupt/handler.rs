@@ -67,13 +67,14 @@ pub extern "C" fn rust_trap(info: Info, esr: u32, tf: &mut TrapFrame) {
         }
         Kind::Irq => {
             if is_timer_irq() {
-                handle_timer()
+                handle_timer(tf)
             } else {
                 handle_irq(tf)
             }
         }
         _ => crate::trap::error(tf),
     }
+    crate::signal::handle_signal(tf);
     trace!("Exception end");
 }
 
@@ -103,15 +104,15 @@ fn handle_syscall(num: u16, tf: &mut TrapFrame) {
     tf.x0 = ret as usize;
 }
 
-fn handle_timer() {
+fn handle_timer(tf: &TrapFrame) {
     crate::arch::timer::set_next();
-    crate::trap::timer();
+    crate::trap::timer(tf);
 }
 
 fn handle_page_fault(tf: &mut TrapFrame) {
     let addr = FAR_EL1.get() as usize;
-    if !crate::memory::handle_page_fault(addr) {
+    if !crate::memory::handle_page_fault(tf, addr) {
         error!("\nEXCEPTION: Page Fault @ {:#x}", addr);
-        crate::trap::error(tf);
+        crate::trap::segfault(tf, addr);
     }
 }
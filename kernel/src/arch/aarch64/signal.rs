@@ -0,0 +1,51 @@
+//! Signal handler entry for aarch64
+
+use super::interrupt::TrapFrame;
+
+/// Code of the signal trampoline: `mov x8, #SYS_RT_SIGRETURN; svc #0`
+pub const SIGNAL_RET_CODE: [u8; 8] = [0x68, 0x11, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4];
+
+impl TrapFrame {
+    /// Whether the trap comes from user mode
+    pub fn is_user(&self) -> bool {
+        // EL0t
+        self.spsr & 0b1111 == 0
+    }
+
+    pub fn get_sp(&self) -> usize {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, sp: usize) {
+        self.sp = sp;
+    }
+
+    /// Set up registers to call `handler(args)` in user mode on stack `sp`,
+    /// returning to `ret`.
+    pub unsafe fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret: usize,
+        args: [usize; 3],
+    ) {
+        self.x30 = ret; // lr
+        self.sp = sp;
+        self.x0 = args[0];
+        self.x1to29[0] = args[1];
+        self.x1to29[1] = args[2];
+        self.elr = handler;
+    }
+
+    pub fn get_ret(&self) -> usize {
+        self.x0
+    }
+
+    /// Restore the user context saved before entering a signal handler,
+    /// but keep the privileged `spsr`.
+    pub fn restore_signal_context(&mut self, saved: &TrapFrame) {
+        let spsr = self.spsr;
+        *self = saved.clone();
+        self.spsr = spsr;
+    }
+}
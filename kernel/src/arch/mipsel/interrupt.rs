@@ -92,6 +92,7 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
             crate::trap::error(tf)
         }
     }
+    crate::signal::handle_signal(tf);
     trace!("Interrupt end");
 }
 
@@ -99,7 +100,7 @@ fn interrupt_dispatcher(tf: &mut TrapFrame) {
     let pint = tf.cause.pending_interrupt();
     trace!("  Interrupt {:08b} ", pint);
     if (pint & 0b100_000_00) != 0 {
-        timer();
+        timer(tf);
     } else if (pint & 0b011_111_00) != 0 {
         external();
     } else {
@@ -138,9 +139,9 @@ fn ipi() {
     cp0::cause::reset_soft_int1();
 }
 
-fn timer() {
+fn timer(tf: &TrapFrame) {
     super::timer::set_next();
-    crate::trap::timer();
+    crate::trap::timer(tf);
 }
 
 fn syscall(tf: &mut TrapFrame) {
@@ -159,7 +160,12 @@ fn syscall(tf: &mut TrapFrame) {
         tf.v0 = unsafe { *((tf.sp + 28) as *const usize) };
     }
 
-    let ret = crate::syscall::syscall(tf.v0, arguments, tf) as isize;
+    let id = tf.v0;
+    let ret = crate::syscall::syscall(id, arguments, tf) as isize;
+    if id == super::syscall::SYS_RT_SIGRETURN {
+        // v0 and a3 have been restored with the whole context
+        return;
+    }
     // comply with mips n32 abi, always return a positive value
     // https://git.musl-libc.org/cgit/musl/tree/arch/mipsn32/syscall_arch.h
     if (ret < 0) {
@@ -268,7 +274,7 @@ fn page_fault(tf: &mut TrapFrame) {
             };
 
             if !tlb_valid {
-                if !crate::memory::handle_page_fault(addr) {
+                if !crate::memory::handle_page_fault(tf, addr) {
                     extern "C" {
                         fn _copy_user_start();
                         fn _copy_user_end();
@@ -278,14 +284,15 @@ fn page_fault(tf: &mut TrapFrame) {
                         tf.epc = crate::memory::read_user_fixup as usize;
                         return;
                     }
-                    crate::trap::error(tf);
+                    crate::trap::segfault(tf, addr);
+                    return;
                 }
             }
 
             tlb::write_tlb_random(tlb_entry)
         }
         Err(()) => {
-            if !crate::memory::handle_page_fault(addr) {
+            if !crate::memory::handle_page_fault(tf, addr) {
                 extern "C" {
                     fn _copy_user_start();
                     fn _copy_user_end();
@@ -295,7 +302,7 @@ fn page_fault(tf: &mut TrapFrame) {
                     tf.epc = crate::memory::read_user_fixup as usize;
                     return;
                 }
-                crate::trap::error(tf);
+                crate::trap::segfault(tf, addr);
             }
         }
     }
@@ -0,0 +1,55 @@
+//! Signal handler entry for MIPS
+
+use super::interrupt::TrapFrame;
+
+/// Code of the signal trampoline: `li v0, SYS_RT_SIGRETURN; syscall`
+pub const SIGNAL_RET_CODE: [u8; 8] = [0x61, 0x10, 0x02, 0x24, 0x0c, 0x00, 0x00, 0x00];
+
+impl TrapFrame {
+    /// Whether the trap comes from user mode
+    pub fn is_user(&self) -> bool {
+        // KSU == 0b10
+        self.status.bits & 0x18 == 0x10
+    }
+
+    pub fn get_sp(&self) -> usize {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, sp: usize) {
+        self.sp = sp;
+    }
+
+    /// Set up registers to call `handler(args)` in user mode on stack `sp`,
+    /// returning to `ret`.
+    pub unsafe fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret: usize,
+        args: [usize; 3],
+    ) {
+        self.ra = ret;
+        self.sp = sp;
+        self.a0 = args[0];
+        self.a1 = args[1];
+        self.a2 = args[2];
+        self.epc = handler;
+        // position independent code expects the function address in t9
+        self.t9 = handler;
+    }
+
+    pub fn get_ret(&self) -> usize {
+        self.v0
+    }
+
+    /// Restore the user context saved before entering a signal handler,
+    /// but keep the privileged `status` and the kernel stack.
+    pub fn restore_signal_context(&mut self, saved: &TrapFrame) {
+        let status = self.status.bits;
+        let unaligned_kstack = self.unaligned_kstack;
+        *self = saved.clone();
+        self.status.bits = status;
+        self.unaligned_kstack = unaligned_kstack;
+    }
+}
@@ -63,13 +63,14 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
     match tf.scause.cause() {
         Trap::Interrupt(I::SupervisorExternal) => external(),
         Trap::Interrupt(I::SupervisorSoft) => ipi(),
-        Trap::Interrupt(I::SupervisorTimer) => timer(),
+        Trap::Interrupt(I::SupervisorTimer) => timer(tf),
         Trap::Exception(E::UserEnvCall) => syscall(tf),
         Trap::Exception(E::LoadPageFault) => page_fault(tf),
         Trap::Exception(E::StorePageFault) => page_fault(tf),
         Trap::Exception(E::InstructionPageFault) => page_fault(tf),
         _ => crate::trap::error(tf),
     }
+    crate::signal::handle_signal(tf);
     trace!("Interrupt end");
 }
 
@@ -107,9 +108,9 @@ fn ipi() {
     super::sbi::clear_ipi();
 }
 
-fn timer() {
+fn timer(tf: &TrapFrame) {
     super::timer::set_next();
-    crate::trap::timer();
+    crate::trap::timer(tf);
 }
 
 fn syscall(tf: &mut TrapFrame) {
@@ -126,7 +127,7 @@ fn page_fault(tf: &mut TrapFrame) {
     let addr = tf.stval;
     trace!("\nEXCEPTION: Page Fault @ {:#x}", addr);
 
-    if !crate::memory::handle_page_fault(addr) {
+    if !crate::memory::handle_page_fault(tf, addr) {
         extern "C" {
             fn _copy_user_start();
             fn _copy_user_end();
@@ -136,6 +137,6 @@ fn page_fault(tf: &mut TrapFrame) {
             tf.sepc = crate::memory::read_user_fixup as usize;
             return;
         }
-        crate::trap::error(tf);
+        crate::trap::segfault(tf, addr);
     }
 }
@@ -17,6 +17,7 @@ pub mod memory;
 pub mod paging;
 pub mod rand;
 mod sbi;
+pub mod signal;
 pub mod syscall;
 pub mod timer;
 
@@ -0,0 +1,49 @@
+//! Signal handler entry for RISCV
+
+use super::interrupt::TrapFrame;
+use riscv::register::sstatus;
+
+/// Code of the signal trampoline: `li a7, SYS_RT_SIGRETURN; ecall`
+pub const SIGNAL_RET_CODE: [u8; 8] = [0x93, 0x08, 0xb0, 0x08, 0x73, 0x00, 0x00, 0x00];
+
+impl TrapFrame {
+    /// Whether the trap comes from user mode
+    pub fn is_user(&self) -> bool {
+        self.sstatus.spp() == sstatus::SPP::User
+    }
+
+    pub fn get_sp(&self) -> usize {
+        self.x[2]
+    }
+
+    pub fn set_sp(&mut self, sp: usize) {
+        self.x[2] = sp;
+    }
+
+    /// Set up registers to call `handler(args)` in user mode on stack `sp`,
+    /// returning to `ret`.
+    pub unsafe fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret: usize,
+        args: [usize; 3],
+    ) {
+        self.x[1] = ret; // ra
+        self.x[2] = sp;
+        self.x[10..13].copy_from_slice(&args); // a0-a2
+        self.sepc = handler;
+    }
+
+    pub fn get_ret(&self) -> usize {
+        self.x[10]
+    }
+
+    /// Restore the user context saved before entering a signal handler,
+    /// but keep the privileged `sstatus`.
+    pub fn restore_signal_context(&mut self, saved: &TrapFrame) {
+        let sstatus = self.sstatus;
+        *self = saved.clone();
+        self.sstatus = sstatus;
+    }
+}
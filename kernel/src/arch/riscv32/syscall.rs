@@ -279,6 +279,8 @@ pub const SYS_PKEY_ALLOC: usize = 289;
 pub const SYS_PKEY_FREE: usize = 290;
 pub const SYS_SYSRISCV: usize = SYS_ARCH_SPECIFIC_SYSCALL;
 pub const SYS_RISCV_FLUSH_ICACHE: usize = SYS_SYSRISCV + 15;
+// not in Linux, sets or gets tp as arch_prctl on x86_64
+pub const SYS_ARCH_PRCTL: usize = SYS_SYSRISCV + 1;
 
 // custom temporary syscall
 pub const SYS_MAP_PCI_DEVICE: usize = 999;
@@ -46,6 +46,8 @@ pub fn init() {
         Cr0::update(|cr0| {
             cr0.remove(Cr0Flags::EMULATE_COPROCESSOR);
             cr0.insert(Cr0Flags::MONITOR_COPROCESSOR);
+            // let kernel writes to copy-on-write user pages fault as well
+            cr0.insert(Cr0Flags::WRITE_PROTECT);
         });
     }
 }
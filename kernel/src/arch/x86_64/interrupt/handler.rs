@@ -90,7 +90,7 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
             let irq = tf.trap_num as u8 - IRQ0;
             super::ack(irq); // must ack before switching
             match irq {
-                Timer => crate::trap::timer(),
+                Timer => crate::trap::timer(tf),
                 Keyboard => keyboard(),
                 COM1 => com1(),
                 COM2 => com2(),
@@ -114,6 +114,7 @@ pub extern "C" fn rust_trap(tf: &mut TrapFrame) {
         }
         _ => panic!("Unhandled interrupt {:x}", tf.trap_num),
     }
+    crate::signal::handle_signal(tf);
 }
 
 fn breakpoint() {
@@ -142,7 +143,7 @@ fn page_fault(tf: &mut TrapFrame) {
     }
     let code = PageError::from_bits(tf.error_code as u8).unwrap();
 
-    if crate::memory::handle_page_fault(addr) {
+    if crate::memory::handle_page_fault(tf, addr) {
         return;
     }
 
@@ -157,7 +158,7 @@ fn page_fault(tf: &mut TrapFrame) {
     }
 
     error!("\nEXCEPTION: Page Fault @ {:#x}, code: {:?}", addr, code);
-    error(tf);
+    crate::trap::segfault(tf, addr);
 }
 
 fn keyboard() {
@@ -199,11 +200,16 @@ fn ide() {
     trace!("\nInterupt: IDE");
 }
 
+/// Return true if it should return to user with `iretq` instead of `sysretq`,
+/// which overwrites rcx and r11.
 #[no_mangle]
-pub extern "C" fn syscall(tf: &mut TrapFrame) {
+pub extern "C" fn syscall(tf: &mut TrapFrame) -> bool {
     trace!("\nInterupt: Syscall {:#x?}", tf.rax);
     let ret = crate::syscall::syscall(tf.rax, [tf.rdi, tf.rsi, tf.rdx, tf.r10, tf.r8, tf.r9], tf);
     tf.rax = ret as usize;
+    crate::signal::handle_signal(tf);
+    // the context is changed by sigreturn, exec or entering a signal handler
+    tf.rcx != tf.rip || tf.r11 != tf.rflags
 }
 
 fn syscall32(tf: &mut TrapFrame) {
@@ -168,6 +168,12 @@ syscall_entry:
     mov rdi, rsp
     call syscall
 
+    # return by iretq if rcx and r11 must be restored
+    test al, al
+    jz syscall_return
+    cli
+    jmp trap_ret
+
 syscall_return:
 
     # disable interrupt
@@ -0,0 +1,65 @@
+//! Signal handler entry for x86_64
+
+use super::interrupt::TrapFrame;
+
+/// Code of the signal trampoline: `mov rax, SYS_RT_SIGRETURN; syscall`
+pub const SIGNAL_RET_CODE: [u8; 9] = [0x48, 0xc7, 0xc0, 0x0f, 0x00, 0x00, 0x00, 0x0f, 0x05];
+
+impl TrapFrame {
+    /// Whether the trap comes from user mode
+    pub fn is_user(&self) -> bool {
+        self.cs & 0x3 == 0x3
+    }
+
+    pub fn get_sp(&self) -> usize {
+        self.rsp
+    }
+
+    pub fn set_sp(&mut self, sp: usize) {
+        self.rsp = sp;
+    }
+
+    /// Set up registers to call `handler(args)` in user mode on stack `sp`,
+    /// returning to `ret`.
+    ///
+    /// The return address is pushed below `sp`, so the stack must be writable there.
+    pub unsafe fn enter_signal_handler(
+        &mut self,
+        handler: usize,
+        sp: usize,
+        ret: usize,
+        args: [usize; 3],
+    ) {
+        let sp = sp - 8;
+        *(sp as *mut usize) = ret;
+        self.rsp = sp;
+        self.rip = handler;
+        self.rdi = args[0];
+        self.rsi = args[1];
+        self.rdx = args[2];
+        // clear DF as required by the ABI
+        self.rflags &= !0x400;
+    }
+
+    pub fn get_ret(&self) -> usize {
+        self.rax
+    }
+
+    /// Restore the user context saved before entering a signal handler.
+    ///
+    /// Only flags that user programs can change are taken from `saved`.
+    pub fn restore_signal_context(&mut self, saved: &TrapFrame) {
+        // status flags, TF, DF, RF and AC
+        const USER_FLAGS: usize = 0x50dd5;
+        let rflags = self.rflags;
+        let (cs, ss) = (self.cs, self.ss);
+        // user traps of a thread always lay at the top of its kernel stack,
+        // so the offset of fpstate does not change
+        let fpstate_offset = self.fpstate_offset;
+        *self = saved.clone();
+        self.rflags = (rflags & !USER_FLAGS) | (saved.rflags & USER_FLAGS);
+        self.cs = cs;
+        self.ss = ss;
+        self.fpstate_offset = fpstate_offset;
+    }
+}
@@ -4,6 +4,7 @@ pub use crate::arch::consts::*;
 
 pub const MAX_CPU_NUM: usize = 64;
 pub const MAX_PROCESS_NUM: usize = 128;
+pub const MAX_FILE_NUM: usize = 1024;
 
 pub const USEC_PER_TICK: usize = 10000;
 
@@ -6,7 +6,7 @@ use lazy_static::lazy_static;
 use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address};
 use spin::RwLock;
 
-use crate::sync::Condvar;
+use crate::sync::WaitQueue;
 use rcore_fs::dev::{self, BlockDevice, DevError};
 
 #[allow(dead_code)]
@@ -125,7 +125,7 @@ impl BlockDevice for BlockDriver {
 }
 
 lazy_static! {
-    pub static ref SOCKET_ACTIVITY: Condvar = Condvar::new();
+    pub static ref SOCKET_ACTIVITY: WaitQueue = WaitQueue::new();
 }
 
 #[cfg(any(target_arch = "riscv32", target_arch = "riscv64", target_arch = "mips"))]
@@ -46,7 +46,7 @@ impl Driver for E1000Interface {
             let mut sockets = SOCKETS.lock();
             match self.iface.lock().poll(&mut sockets, timestamp) {
                 Ok(_) => {
-                    SOCKET_ACTIVITY.notify_all();
+                    SOCKET_ACTIVITY.wake_all();
                 }
                 Err(err) => {
                     debug!("poll got err {}", err);
@@ -87,7 +87,7 @@ impl Driver for E1000Interface {
         let mut sockets = SOCKETS.lock();
         match self.iface.lock().poll(&mut sockets, timestamp) {
             Ok(_) => {
-                SOCKET_ACTIVITY.notify_all();
+                SOCKET_ACTIVITY.wake_all();
             }
             Err(err) => {
                 debug!("poll got err {}", err);
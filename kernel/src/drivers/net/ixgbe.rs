@@ -56,7 +56,7 @@ impl Driver for IXGBEInterface {
             let mut sockets = SOCKETS.lock();
             match self.iface.lock().poll(&mut sockets, timestamp) {
                 Ok(_) => {
-                    SOCKET_ACTIVITY.notify_all();
+                    SOCKET_ACTIVITY.wake_all();
                 }
                 Err(err) => {
                     debug!("poll got err {}", err);
@@ -97,7 +97,7 @@ impl Driver for IXGBEInterface {
         let mut sockets = SOCKETS.lock();
         match self.iface.lock().poll(&mut sockets, timestamp) {
             Ok(_) => {
-                SOCKET_ACTIVITY.notify_all();
+                SOCKET_ACTIVITY.wake_all();
             }
             Err(err) => {
                 debug!("poll got err {}", err);
@@ -179,7 +179,7 @@ impl Driver for RouterInterface {
                 let mut sockets = SOCKETS.lock();
                 match self.iface.lock().poll(&mut sockets, timestamp) {
                     Ok(_) => {
-                        SOCKET_ACTIVITY.notify_all();
+                        SOCKET_ACTIVITY.wake_all();
                     }
                     Err(err) => {
                         debug!("poll got err {}", err);
@@ -1,72 +1,145 @@
+//! Epoll instances
+//!
+//! An instance keeps the interest list of fds, and finds the ready ones when waited on.
+//! It is woken up by the wait queues shared by all files, like poll and select.
+//! In edge-triggered mode, an fd is reported again only after it is found not ready,
+//! either when waited on or by an I/O call on it which would block.
+//!
+//! The interest list is keyed by fd, so a forked child gets a copy of it,
+//! and closing an fd in one process does not drop the registrations of the other.
+
 use crate::fs::FileLike;
-use crate::memory::MemorySet;
 use crate::process::Process;
-use crate::sync::{Condvar, SpinNoIrqLock};
+use crate::sync::SpinNoIrqLock as Mutex;
 use crate::syscall::{SysError, SysResult};
-use alloc::{collections::BTreeMap, collections::BTreeSet};
-use core::mem::size_of;
-use core::slice;
+use alloc::{collections::BTreeMap, sync::Arc};
 
+/// An epoll instance, shared by the fds duplicated from it
+#[derive(Clone, Default)]
 pub struct EpollInstance {
-    pub events: BTreeMap<usize, EpollEvent>,
-    pub readyList: SpinNoIrqLock<BTreeSet<usize>>,
-    pub newCtlList: SpinNoIrqLock<BTreeSet<usize>>,
+    /// Registered fds
+    interests: Arc<Mutex<BTreeMap<usize, Interest>>>,
+    /// Close-on-exec flag of the fd
+    pub fd_cloexec: bool,
+}
+
+#[derive(Clone)]
+struct Interest {
+    event: EpollEvent,
+    /// Events ready when last checked, for edge-triggered mode.
+    /// They are cleared when an I/O call finds them not ready.
+    ready: u32,
+    /// A one-shot fd is disabled after reporting, until modified
+    disabled: bool,
 }
 
-impl Clone for EpollInstance {
-    fn clone(&self) -> Self {
-        EpollInstance::new(0)
+impl Interest {
+    fn new(event: EpollEvent) -> Self {
+        Interest {
+            event,
+            ready: 0,
+            disabled: false,
+        }
     }
 }
 
 impl EpollInstance {
-    pub fn new(flags: usize) -> Self {
-        return EpollInstance {
-            events: BTreeMap::new(),
-            readyList: Default::default(),
-            newCtlList: Default::default(),
-        };
+    pub fn new(fd_cloexec: bool) -> Self {
+        EpollInstance {
+            interests: Arc::default(),
+            fd_cloexec,
+        }
     }
 
-    pub fn control(&mut self, op: usize, fd: usize, event: &EpollEvent) -> SysResult {
-        match (op as i32) {
+    pub fn control(&self, op: usize, fd: usize, event: EpollEvent) -> SysResult {
+        let mut interests = self.interests.lock();
+        match op as i32 {
             EPollCtlOp::ADD => {
-                self.events.insert(fd, event.clone());
-                self.newCtlList.lock().insert(fd);
+                if interests.contains_key(&fd) {
+                    return Err(SysError::EEXIST);
+                }
+                interests.insert(fd, Interest::new(event));
             }
-
             EPollCtlOp::MOD => {
-                if self.events.get(&fd).is_some() {
-                    self.events.remove(&fd);
-                    self.events.insert(fd, event.clone());
-                    self.newCtlList.lock().insert(fd);
-                } else {
-                    return Err(SysError::EPERM);
-                }
+                let interest = interests.get_mut(&fd).ok_or(SysError::ENOENT)?;
+                // the readiness is reported again, even in edge-triggered mode
+                *interest = Interest::new(event);
             }
-
             EPollCtlOp::DEL => {
-                if self.events.get(&fd).is_some() {
-                    self.events.remove(&fd);
-                } else {
-                    return Err(SysError::EPERM);
-                }
-            }
-            _ => {
-                return Err(SysError::EPERM);
+                interests.remove(&fd).ok_or(SysError::ENOENT)?;
             }
+            _ => return Err(SysError::EINVAL),
         }
         Ok(0)
     }
+
+    /// Report `events` of `fd` again in edge-triggered mode once they are ready,
+    /// after an I/O call on it would block
+    pub fn rearm(&self, fd: usize, events: u32) {
+        if let Some(interest) = self.interests.lock().get_mut(&fd) {
+            interest.ready &= !events;
+        }
+    }
+
+    /// Forget `fd` when it is closed
+    pub fn remove(&self, fd: usize) {
+        self.interests.lock().remove(&fd);
+    }
+
+    /// Fill `events` with the fds ready in `files`, and return the number of them
+    pub fn ready_events(
+        &self,
+        files: &BTreeMap<usize, FileLike>,
+        events: &mut [EpollEvent],
+    ) -> SysResult {
+        let mut count = 0;
+        for (fd, interest) in self.interests.lock().iter_mut() {
+            if count == events.len() {
+                break;
+            }
+            let file_like = match files.get(fd) {
+                Some(file_like) if !interest.disabled => file_like,
+                _ => continue,
+            };
+            let status = file_like.poll()?;
+            let mut ready = 0;
+            // errors are always reported
+            if status.error {
+                ready |= EpollEvent::EPOLLERR;
+            }
+            if status.read && interest.event.contains(EpollEvent::EPOLLIN) {
+                ready |= EpollEvent::EPOLLIN;
+            }
+            if status.write && interest.event.contains(EpollEvent::EPOLLOUT) {
+                ready |= EpollEvent::EPOLLOUT;
+            }
+            let changed = ready & !interest.ready != 0;
+            interest.ready = ready;
+            if ready == 0 || interest.event.contains(EpollEvent::EPOLLET) && !changed {
+                continue;
+            }
+            events[count] = EpollEvent {
+                events: ready,
+                data: interest.event.data,
+            };
+            count += 1;
+            if interest.event.contains(EpollEvent::EPOLLONESHOT) {
+                interest.disabled = true;
+            }
+        }
+        Ok(count)
+    }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct EpollData {
     ptr: u64,
 }
 
-#[repr(packed)]
-#[derive(Clone)]
+/// Packed on x86_64 only, as in Linux
+#[repr(C)]
+#[cfg_attr(target_arch = "x86_64", repr(packed))]
+#[derive(Default, Clone, Copy)]
 pub struct EpollEvent {
     pub events: u32,     /* Epoll events */
     pub data: EpollData, /* User data variable */
@@ -91,38 +164,148 @@ impl EpollEvent {
     pub const EPOLLET: u32 = 1 << 31;
 
     pub fn contains(&self, events: u32) -> bool {
-        if (self.events & events) == 0 {
-            return false;
-        } else {
-            return true;
-        }
+        self.events & events != 0
     }
 }
 
 pub struct EPollCtlOp;
 impl EPollCtlOp {
-    const ADD: i32 = 1; /* Add a file descriptor to the interface.  */
-    const DEL: i32 = 2; /* Remove a file descriptor from the interface.  */
-    const MOD: i32 = 3; /* Change file descriptor epoll_event structure.  */
+    pub const ADD: i32 = 1; /* Add a file descriptor to the interface.  */
+    pub const DEL: i32 = 2; /* Remove a file descriptor from the interface.  */
+    pub const MOD: i32 = 3; /* Change file descriptor epoll_event structure.  */
+}
+
+/// The fds of a forked child, which share open file descriptions with the parent,
+/// except that each epoll instance gets a copy of its interest list.
+/// The fds duplicated from an instance still share the copy.
+pub fn fork_files(files: &BTreeMap<usize, FileLike>) -> BTreeMap<usize, FileLike> {
+    let mut copies: BTreeMap<usize, EpollInstance> = BTreeMap::new();
+    files
+        .iter()
+        .map(|(&fd, file_like)| {
+            let file_like = match file_like {
+                FileLike::EpollInstance(instance) => {
+                    let key = &*instance.interests as *const _ as usize;
+                    let copy = copies.entry(key).or_insert_with(|| EpollInstance {
+                        interests: Arc::new(Mutex::new(instance.interests.lock().clone())),
+                        fd_cloexec: false,
+                    });
+                    FileLike::EpollInstance(EpollInstance {
+                        interests: copy.interests.clone(),
+                        fd_cloexec: instance.fd_cloexec,
+                    })
+                }
+                file_like => file_like.clone(),
+            };
+            (fd, file_like)
+        })
+        .collect()
 }
 
 impl Process {
-    pub fn get_epoll_instance_mut(&mut self, fd: usize) -> Result<&mut EpollInstance, SysError> {
-        match self.get_file_like(fd)? {
-            FileLike::EpollInstance(instance) => Ok(instance),
-            _ => Err(SysError::EPERM),
+    /// Rearm `events` of `fd` in all epoll instances of the process
+    pub fn rearm_epoll(&self, fd: usize, events: u32) {
+        for file_like in self.files.values() {
+            if let FileLike::EpollInstance(instance) = file_like {
+                instance.rearm(fd, events);
+            }
         }
     }
 
     pub fn get_epoll_instance(&self, fd: usize) -> Result<&EpollInstance, SysError> {
-        match self.files.get(&fd) {
-            Some(file_like) => match file_like {
-                FileLike::EpollInstance(instance) => Ok(&instance),
-                _ => Err(SysError::EPERM),
-            },
-            None => {
-                return Err(SysError::EPERM);
-            }
+        match self.files.get(&fd).ok_or(SysError::EBADF)? {
+            FileLike::EpollInstance(instance) => Ok(instance),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+}
+
+pub mod test {
+    //! Interest lists across fork, the close-on-exec flag, and edge-triggered mode
+
+    use super::*;
+    use crate::fs::{FileHandle, OpenOptions, Pipe};
+    use alloc::string::String;
+
+    fn event() -> EpollEvent {
+        EpollEvent {
+            events: EpollEvent::EPOLLIN,
+            data: EpollData::default(),
         }
     }
+
+    /// Closing an fd in the child keeps the registration of the parent
+    fn close_in_forked_child() {
+        let instance = EpollInstance::new(false);
+        instance
+            .control(EPollCtlOp::ADD as usize, 3, event())
+            .unwrap();
+        let mut files = BTreeMap::new();
+        files.insert(4, FileLike::EpollInstance(instance.clone()));
+        files.insert(5, FileLike::EpollInstance(instance.clone()));
+
+        let child_files = fork_files(&files);
+        let (child, child_dup) = match (&child_files[&4], &child_files[&5]) {
+            (FileLike::EpollInstance(a), FileLike::EpollInstance(b)) => (a.clone(), b.clone()),
+            _ => unreachable!(),
+        };
+        assert!(child.interests.lock().contains_key(&3));
+        child.remove(3);
+        assert!(instance.interests.lock().contains_key(&3));
+        // duplicated fds in the child still share the copy
+        assert!(!child_dup.interests.lock().contains_key(&3));
+    }
+
+    fn cloexec() {
+        let file_like = FileLike::EpollInstance(EpollInstance::new(true));
+        assert!(file_like.is_cloexec());
+        assert!(!file_like.dup(false).is_cloexec());
+        assert!(!FileLike::EpollInstance(EpollInstance::new(false)).is_cloexec());
+    }
+
+    /// Data arriving after the reader drained the pipe to EAGAIN is reported,
+    /// though the pipe looked readable at each wait
+    fn edge_triggered_rearm() {
+        let (reader, writer) = Pipe::create_pair();
+        let options = OpenOptions {
+            read: true,
+            write: false,
+            append: false,
+            nonblock: true,
+            path: false,
+        };
+        let reader = FileHandle::new(Arc::new(reader), options, String::from("pipe"), false);
+        let mut files = BTreeMap::new();
+        files.insert(3, FileLike::File(reader));
+        let instance = EpollInstance::new(false);
+        let event = EpollEvent {
+            events: EpollEvent::EPOLLIN | EpollEvent::EPOLLET,
+            data: EpollData::default(),
+        };
+        instance
+            .control(EPollCtlOp::ADD as usize, 3, event)
+            .unwrap();
+        let mut events = [EpollEvent::default(); 1];
+
+        writer.write_at(0, b"ab").unwrap();
+        assert_eq!(instance.ready_events(&files, &mut events), Ok(1));
+        // no new edge
+        assert_eq!(instance.ready_events(&files, &mut events), Ok(0));
+        let mut buf = [0u8; 4];
+        let reader = files.get_mut(&3).unwrap();
+        assert_eq!(reader.read(&mut buf), Ok(2));
+        assert_eq!(reader.read(&mut buf), Err(SysError::EAGAIN));
+        instance.rearm(3, EpollEvent::EPOLLIN);
+        // more data before the next wait
+        writer.write_at(0, b"c").unwrap();
+        assert_eq!(instance.ready_events(&files, &mut events), Ok(1));
+        assert_eq!(instance.ready_events(&files, &mut events), Ok(0));
+    }
+
+    pub fn test_all() {
+        close_in_forked_child();
+        edge_triggered_rearm();
+        cloexec();
+        println!("epoll test end");
+    }
 }
@@ -0,0 +1,132 @@
+//! Implement INode for eventfd
+//!
+//! An eventfd is a 64-bit counter. Writes add to it, and reads take it.
+//! Waiters are woken up through `PIPE_ACTIVITY`, like pipes.
+
+use core::any::Any;
+use core::convert::TryInto;
+use core::mem::size_of;
+
+use rcore_fs::vfs::*;
+
+use super::PIPE_ACTIVITY;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+/// The counter never reaches u64::MAX
+const EVENTFD_MAX: u64 = core::u64::MAX - 1;
+
+pub struct EventFd {
+    count: Mutex<u64>,
+    /// A read takes 1 instead of the whole counter
+    semaphore: bool,
+}
+
+impl EventFd {
+    pub fn new(count: u64, semaphore: bool) -> Self {
+        EventFd {
+            count: Mutex::new(count),
+            semaphore,
+        }
+    }
+}
+
+impl INode for EventFd {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < size_of::<u64>() {
+            return Err(FsError::InvalidParam);
+        }
+        let mut count = self.count.lock();
+        if *count == 0 {
+            return Err(FsError::Again);
+        }
+        let value = if self.semaphore { 1 } else { *count };
+        *count -= value;
+        buf[..size_of::<u64>()].copy_from_slice(&value.to_ne_bytes());
+        PIPE_ACTIVITY.wake_all();
+        Ok(size_of::<u64>())
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        if buf.len() < size_of::<u64>() {
+            return Err(FsError::InvalidParam);
+        }
+        let value = u64::from_ne_bytes(buf[..size_of::<u64>()].try_into().unwrap());
+        if value == core::u64::MAX {
+            return Err(FsError::InvalidParam);
+        }
+        let mut count = self.count.lock();
+        // wait for a read if the counter would overflow
+        if value > EVENTFD_MAX - *count {
+            return Err(FsError::Again);
+        }
+        *count += value;
+        PIPE_ACTIVITY.wake_all();
+        Ok(size_of::<u64>())
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        let count = *self.count.lock();
+        Ok(PollStatus {
+            read: count > 0,
+            write: count < EVENTFD_MAX,
+            error: false,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub mod test {
+    //! Reading and writing the counter of eventfd
+
+    use super::*;
+
+    fn read(eventfd: &EventFd) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        eventfd.read_at(0, &mut buf)?;
+        Ok(u64::from_ne_bytes(buf))
+    }
+
+    fn write(eventfd: &EventFd, value: u64) -> Result<usize> {
+        eventfd.write_at(0, &value.to_ne_bytes())
+    }
+
+    /// A read takes the whole counter, and fails while it is 0
+    fn counter() {
+        let eventfd = EventFd::new(2, false);
+        write(&eventfd, 3).unwrap();
+        assert_eq!(read(&eventfd).unwrap(), 5);
+        assert!(read(&eventfd).is_err());
+        assert!(eventfd.poll().unwrap().write && !eventfd.poll().unwrap().read);
+
+        assert!(eventfd.read_at(0, &mut [0u8; 4]).is_err());
+        assert!(eventfd.write_at(0, &[1u8; 4]).is_err());
+        assert!(write(&eventfd, core::u64::MAX).is_err());
+
+        // the counter is full at u64::MAX - 1
+        write(&eventfd, EVENTFD_MAX).unwrap();
+        assert!(!eventfd.poll().unwrap().write);
+        assert!(write(&eventfd, 1).is_err());
+        assert_eq!(read(&eventfd).unwrap(), EVENTFD_MAX);
+        write(&eventfd, 0).unwrap();
+        assert!(!eventfd.poll().unwrap().read);
+    }
+
+    /// With EFD_SEMAPHORE a read takes 1 at a time
+    fn semaphore() {
+        let eventfd = EventFd::new(0, true);
+        write(&eventfd, 2).unwrap();
+        assert!(eventfd.poll().unwrap().read);
+        assert_eq!(read(&eventfd).unwrap(), 1);
+        assert_eq!(read(&eventfd).unwrap(), 1);
+        assert!(read(&eventfd).is_err());
+    }
+
+    pub fn test_all() {
+        counter();
+        semaphore();
+        println!("eventfd test end");
+    }
+}
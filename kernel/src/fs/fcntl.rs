@@ -0,0 +1,66 @@
+// fcntl commands, flags of file descriptor and file status flags
+
+/// dup to the lowest free fd >= arg
+pub const F_DUPFD: usize = 0;
+/// get fd flags
+pub const F_GETFD: usize = 1;
+/// set fd flags
+pub const F_SETFD: usize = 2;
+/// get file status flags
+pub const F_GETFL: usize = 3;
+/// set file status flags
+pub const F_SETFL: usize = 4;
+/// get a record lock which would block the one given
+#[cfg(not(target_arch = "mips"))]
+pub const F_GETLK: usize = 5;
+#[cfg(target_arch = "mips")]
+pub const F_GETLK: usize = 14;
+/// set or release a record lock, failing if it is blocked
+pub const F_SETLK: usize = 6;
+/// set or release a record lock, waiting while it is blocked
+pub const F_SETLKW: usize = 7;
+// record lock commands with 64-bit offsets, for 32-bit archs
+#[cfg(all(target_pointer_width = "32", not(target_arch = "mips")))]
+pub const F_GETLK64: usize = 12;
+#[cfg(all(target_pointer_width = "32", not(target_arch = "mips")))]
+pub const F_SETLK64: usize = 13;
+#[cfg(all(target_pointer_width = "32", not(target_arch = "mips")))]
+pub const F_SETLKW64: usize = 14;
+#[cfg(target_arch = "mips")]
+pub const F_GETLK64: usize = 33;
+#[cfg(target_arch = "mips")]
+pub const F_SETLK64: usize = 34;
+#[cfg(target_arch = "mips")]
+pub const F_SETLKW64: usize = 35;
+/// like F_DUPFD, but set FD_CLOEXEC on the new fd
+pub const F_DUPFD_CLOEXEC: usize = 1030;
+
+/// close on exec
+pub const FD_CLOEXEC: usize = 1;
+
+// operations of flock
+/// shared lock
+pub const LOCK_SH: usize = 1;
+/// exclusive lock
+pub const LOCK_EX: usize = 2;
+/// don't block when locking
+pub const LOCK_NB: usize = 4;
+/// unlock
+pub const LOCK_UN: usize = 8;
+
+pub const O_WRONLY: usize = 1;
+pub const O_RDWR: usize = 2;
+
+#[cfg(not(target_arch = "mips"))]
+pub const O_APPEND: usize = 0x400;
+#[cfg(target_arch = "mips")]
+pub const O_APPEND: usize = 0x8;
+
+pub const O_PATH: usize = 0x200000;
+
+pub const O_CLOEXEC: usize = 0x80000;
+
+#[cfg(not(target_arch = "mips"))]
+pub const O_NONBLOCK: usize = 0x800;
+#[cfg(target_arch = "mips")]
+pub const O_NONBLOCK: usize = 0x80;
@@ -1,17 +1,77 @@
 //! File handle for process
 
-use crate::thread;
-use alloc::{string::String, sync::Arc};
+use super::fcntl::*;
+use super::lock::set_flock;
+use super::stdio::{Stdin, Stdout};
+use super::{EventFd, Pipe, TimerFd, PIPE_ACTIVITY, STDIN};
+use crate::signal::has_signal_to_do;
+use crate::sync::{SleepLock, SpinNoIrqLock as Mutex, WaitQueue};
+use alloc::{collections::VecDeque, string::String, sync::Arc, vec::Vec};
 use core::fmt;
 
 use rcore_fs::vfs::{FsError, INode, Metadata, PollStatus, Result};
 
+/// Number of locks which serialize appends
+const APPEND_LOCK_COUNT: usize = 64;
+
+lazy_static! {
+    /// Appends to the same inode take the same lock,
+    /// so that finding the end of file and writing there is atomic.
+    static ref APPEND_LOCKS: Vec<SleepLock<()>> =
+        (0..APPEND_LOCK_COUNT).map(|_| SleepLock::new(())).collect();
+}
+
+fn append_lock(metadata: &Metadata) -> &'static SleepLock<()> {
+    &APPEND_LOCKS[(metadata.dev ^ metadata.inode) % APPEND_LOCK_COUNT]
+}
+
+/// A file handle refers to an fd of a process.
+/// The fds duplicated from it or inherited by fork share its open file description.
 #[derive(Clone)]
 pub struct FileHandle {
     inode: Arc<dyn INode>,
+    description: Arc<Mutex<OpenFile>>,
+    pub path: String,
+    /// Close this fd when exec
+    pub fd_cloexec: bool,
+}
+
+/// Open file description, which holds the state of one open of a file
+#[derive(Debug)]
+struct OpenFile {
+    /// For directories, the cookie of the last entry read
     offset: u64,
     options: OpenOptions,
-    pub path: String,
+    dir: DirCursor,
+    /// (device, inode) of the file if a flock() lock is held
+    flocked: Option<(usize, usize)>,
+}
+
+impl Drop for OpenFile {
+    /// The flock() lock is released when the last fd is closed
+    fn drop(&mut self) {
+        if let Some(key) = self.flocked {
+            set_flock(key, self as *const OpenFile as usize, None);
+        }
+    }
+}
+
+/// Entries of a directory stream to be read.
+/// They are read in the order of their cookies, which do not change with the directory,
+/// so an entry is never read again after the offset is set to its cookie.
+#[derive(Debug, Default)]
+struct DirCursor {
+    /// Entries after the offset with their cookies, listed when the last ones are read
+    pending: VecDeque<(u64, String)>,
+}
+
+/// The cookie of the directory entry `name`, a non-zero hash which fits in a positive offset
+fn dir_cookie(name: &str) -> u64 {
+    // FNV-1a
+    let hash = name.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+    });
+    (hash % isize::max_value() as u64).max(1)
 }
 
 #[derive(Debug, Clone)]
@@ -21,6 +81,8 @@ pub struct OpenOptions {
     /// Before each write, the file offset is positioned at the end of the file.
     pub append: bool,
     pub nonblock: bool,
+    /// Only used to locate the file for *at syscalls and fstat, but not for I/O
+    pub path: bool,
 }
 
 #[derive(Debug)]
@@ -31,77 +93,100 @@ pub enum SeekFrom {
 }
 
 impl FileHandle {
-    pub fn new(inode: Arc<dyn INode>, options: OpenOptions, path: String) -> Self {
+    pub fn new(
+        inode: Arc<dyn INode>,
+        options: OpenOptions,
+        path: String,
+        fd_cloexec: bool,
+    ) -> Self {
         return FileHandle {
             inode,
-            offset: 0,
-            options,
+            description: Arc::new(Mutex::new(OpenFile {
+                offset: 0,
+                options,
+                dir: DirCursor::default(),
+                flocked: None,
+            })),
             path,
+            fd_cloexec,
         };
     }
 
     pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let len = self.read_at(self.offset as usize, buf)?;
-        self.offset += len as u64;
+        let offset = self.description.lock().offset as usize;
+        let len = self.read_at(offset, buf)?;
+        self.description.lock().offset = (offset + len) as u64;
         Ok(len)
     }
 
+    /// Read at `offset`, waiting for data unless `nonblock`.
+    /// A blocking read fails with `Again` only when interrupted by a signal.
     pub fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if !self.options.read {
+        let options = self.options();
+        if !options.read {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
-        let mut len: usize = 0;
-        if !self.options.nonblock {
-            // block
-            loop {
-                match self.inode.read_at(offset, buf) {
-                    Ok(read_len) => {
-                        len = read_len;
-                        break;
-                    }
-                    Err(FsError::Again) => {
-                        thread::yield_now();
-                    }
-                    Err(err) => {
-                        return Err(err);
-                    }
-                }
-            }
-        } else {
-            len = self.inode.read_at(offset, buf)?;
+        if options.nonblock {
+            return self.inode.read_at(offset, buf);
         }
-        Ok(len)
+        let inode = &self.inode;
+        WaitQueue::wait_any_until(&[&*PIPE_ACTIVITY, &STDIN.pushed], || {
+            match inode.read_at(offset, buf) {
+                Err(FsError::Again) if !has_signal_to_do() => None,
+                result => Some(result),
+            }
+        })
     }
 
     pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let offset = match self.options.append {
-            true => self.inode.metadata()?.size as u64,
-            false => self.offset,
-        } as usize;
+        if self.options().append && self.is_seekable() {
+            let lock = append_lock(&self.inode.metadata()?);
+            let _guard = lock.lock();
+            let offset = self.inode.metadata()?.size;
+            let len = self.write_at(offset, buf)?;
+            self.description.lock().offset = (offset + len) as u64;
+            return Ok(len);
+        }
+        let offset = self.description.lock().offset as usize;
         let len = self.write_at(offset, buf)?;
-        self.offset = (offset + len) as u64;
+        self.description.lock().offset = (offset + len) as u64;
         Ok(len)
     }
 
+    /// Write at `offset`, waiting for space unless `nonblock`.
+    /// A blocking write fails with `Again` only when interrupted by a signal.
     pub fn write_at(&mut self, offset: usize, buf: &[u8]) -> Result<usize> {
-        if !self.options.write {
+        let options = self.options();
+        if !options.write {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
-        let len = self.inode.write_at(offset, buf)?;
-        Ok(len)
+        if options.nonblock {
+            return self.inode.write_at(offset, buf);
+        }
+        let inode = &self.inode;
+        PIPE_ACTIVITY.wait_until(|| match inode.write_at(offset, buf) {
+            Err(FsError::Again) if !has_signal_to_do() => None,
+            result => Some(result),
+        })
     }
 
     pub fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        self.offset = match pos {
+        let mut description = self.description.lock();
+        // the entries listed are dropped when the position is set
+        match pos {
+            SeekFrom::Current(0) => {}
+            _ => description.dir = DirCursor::default(),
+        }
+        description.offset = match pos {
             SeekFrom::Start(offset) => offset,
             SeekFrom::End(offset) => (self.inode.metadata()?.size as i64 + offset) as u64,
-            SeekFrom::Current(offset) => (self.offset as i64 + offset) as u64,
+            SeekFrom::Current(offset) => (description.offset as i64 + offset) as u64,
         };
-        Ok(self.offset)
+        Ok(description.offset)
     }
 
     pub fn set_len(&mut self, len: u64) -> Result<()> {
-        if !self.options.write {
+        if !self.options().write {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
         self.inode.resize(len as usize)?;
@@ -124,19 +209,62 @@ impl FileHandle {
         self.inode.lookup_follow(path, max_follow)
     }
 
-    pub fn read_entry(&mut self) -> Result<String> {
-        if !self.options.read {
+    /// Get the next directory entry after the offset, and its cookie.
+    /// The entry is read by `consume_entry`.
+    pub fn peek_entry(&mut self) -> Result<(String, u64)> {
+        let mut description = self.description.lock();
+        if !description.options.read {
             return Err(FsError::InvalidParam); // FIXME: => EBADF
         }
-        let name = self.inode.get_entry(self.offset as usize)?;
-        self.offset += 1;
-        Ok(name)
+        let description = &mut *description;
+        if description.dir.pending.is_empty() {
+            let mut entries = Vec::new();
+            for id in 0.. {
+                let name = match self.inode.get_entry(id) {
+                    Err(FsError::EntryNotFound) => break,
+                    r => r,
+                }?;
+                let cookie = dir_cookie(&name);
+                if cookie > description.offset {
+                    entries.push((cookie, name));
+                }
+            }
+            entries.sort();
+            description.dir.pending = VecDeque::from(entries);
+        }
+        match description.dir.pending.front() {
+            Some((cookie, name)) => Ok((name.clone(), *cookie)),
+            None => Err(FsError::EntryNotFound),
+        }
+    }
+
+    /// Read the entry returned by `peek_entry`, moving the offset to its cookie
+    pub fn consume_entry(&mut self) {
+        let mut description = self.description.lock();
+        if let Some((cookie, _)) = description.dir.pending.pop_front() {
+            description.offset = cookie;
+        }
     }
 
     pub fn poll(&self) -> Result<PollStatus> {
         self.inode.poll()
     }
 
+    /// Take a shared or exclusive flock() lock on the file for the open file description,
+    /// or release it if `lock` is None.
+    /// Returns false if another open file description holds a conflicting lock.
+    pub fn flock(&self, lock: Option<bool>) -> Result<bool> {
+        let metadata = self.inode.metadata()?;
+        let key = (metadata.dev, metadata.inode);
+        let mut description = self.description.lock();
+        let holder = &*description as *const OpenFile as usize;
+        if !set_flock(key, holder, lock) {
+            return Ok(false);
+        }
+        description.flocked = lock.map(|_| key);
+        Ok(true)
+    }
+
     pub fn io_control(&self, cmd: u32, arg: usize) -> Result<()> {
         self.inode.io_control(cmd, arg)
     }
@@ -145,11 +273,59 @@ impl FileHandle {
         self.inode.clone()
     }
 
-    pub fn fcntl(&mut self, cmd: usize, arg: usize) -> Result<()> {
-        if arg & 0x800 > 0 && cmd == 4 {
-            self.options.nonblock = true;
+    /// Whether the file has a position, which pipes, eventfds, timerfds and the console do not
+    pub fn is_seekable(&self) -> bool {
+        let inode = self.inode.as_any_ref();
+        !(inode.is::<Pipe>()
+            || inode.is::<EventFd>()
+            || inode.is::<TimerFd>()
+            || inode.is::<Stdin>()
+            || inode.is::<Stdout>())
+    }
+
+    /// Status flags are shared by all fds of the open file description
+    pub fn options(&self) -> OpenOptions {
+        self.description.lock().options.clone()
+    }
+
+    pub fn set_nonblock(&mut self, nonblock: bool) {
+        self.description.lock().options.nonblock = nonblock;
+    }
+
+    pub fn fcntl(&mut self, cmd: usize, arg: usize) -> Result<usize> {
+        match cmd {
+            F_GETFD => Ok(if self.fd_cloexec { FD_CLOEXEC } else { 0 }),
+            F_SETFD => {
+                self.fd_cloexec = arg & FD_CLOEXEC != 0;
+                Ok(0)
+            }
+            F_GETFL => {
+                let options = self.options();
+                let mut flags = match (options.read, options.write) {
+                    (true, true) => O_RDWR,
+                    (false, true) => O_WRONLY,
+                    _ => 0,
+                };
+                if options.append {
+                    flags |= O_APPEND;
+                }
+                if options.nonblock {
+                    flags |= O_NONBLOCK;
+                }
+                if options.path {
+                    flags |= O_PATH;
+                }
+                Ok(flags)
+            }
+            F_SETFL => {
+                // access mode can not be changed
+                let mut description = self.description.lock();
+                description.options.append = arg & O_APPEND != 0;
+                description.options.nonblock = arg & O_NONBLOCK != 0;
+                Ok(0)
+            }
+            _ => Err(FsError::InvalidParam),
         }
-        Ok(())
     }
 }
 
@@ -157,9 +333,130 @@ impl fmt::Debug for FileHandle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         return f
             .debug_struct("FileHandle")
-            .field("offset", &self.offset)
-            .field("options", &self.options)
+            .field("description", &*self.description.lock())
             .field("path", &self.path)
+            .field("fd_cloexec", &self.fd_cloexec)
             .finish();
     }
 }
+
+pub mod test {
+    //! Reading a directory in pieces while it is changed, and flock() locks of open files
+
+    use super::*;
+    use crate::fs::TmpFS;
+    use alloc::collections::BTreeSet;
+    use alloc::format;
+    use rcore_fs::vfs::{FileSystem, FileType};
+
+    const ENTRIES: usize = 1000;
+
+    /// Read up to `count` entries, like one getdents call
+    fn read_entries(dir: &mut FileHandle, count: usize) -> Vec<String> {
+        let mut names = Vec::new();
+        while names.len() < count {
+            match dir.peek_entry() {
+                Ok((name, _)) => names.push(name),
+                Err(FsError::EntryNotFound) => break,
+                Err(_) => panic!("failed to read the directory"),
+            }
+            dir.consume_entry();
+        }
+        names
+    }
+
+    /// Every entry is read exactly once, though entries are added and removed between reads
+    fn read_in_pieces() {
+        let fs = TmpFS::new(crate::fs::tmpfs::DEFAULT_CAPACITY);
+        let root = fs.root_inode();
+        for i in 0..ENTRIES {
+            root.create(&format!("file{}", i), FileType::File, 0o644)
+                .unwrap();
+        }
+        let options = OpenOptions {
+            read: true,
+            write: false,
+            append: false,
+            nonblock: false,
+            path: false,
+        };
+        let mut dir = FileHandle::new(root.clone(), options, String::from("/"), false);
+        let mut read = BTreeSet::new();
+        let mut removed = BTreeSet::new();
+        let mut round = 0;
+        loop {
+            let names = read_entries(&mut dir, 7);
+            if names.is_empty() {
+                break;
+            }
+            for name in names {
+                assert!(read.insert(name), "an entry is read twice");
+            }
+            // remove one entry and add another
+            let name = format!("file{}", round * 13 % ENTRIES);
+            if root.unlink(&name).is_ok() {
+                removed.insert(name);
+            }
+            root.create(&format!("new{}", round), FileType::File, 0o644)
+                .unwrap();
+            round += 1;
+        }
+        for i in 0..ENTRIES {
+            let name = format!("file{}", i);
+            assert!(read.contains(&name) || removed.contains(&name));
+        }
+        // the offset of an entry resumes the stream after it
+        dir.seek(SeekFrom::Start(0)).unwrap();
+        let (first, cookie) = dir.peek_entry().unwrap();
+        dir.consume_entry();
+        let (second, _) = dir.peek_entry().unwrap();
+        dir.seek(SeekFrom::Start(cookie)).unwrap();
+        let (name, _) = dir.peek_entry().unwrap();
+        assert_ne!(name, first);
+        assert_eq!(name, second);
+    }
+
+    /// An exclusive lock conflicts with the locks of other opens of the file,
+    /// but not with the fds sharing its open file description,
+    /// and is released when the last of them is closed
+    fn flock_per_open() {
+        let fs = TmpFS::new(0x1000);
+        let inode = fs
+            .root_inode()
+            .create("file", FileType::File, 0o644)
+            .unwrap();
+        let options = OpenOptions {
+            read: true,
+            write: false,
+            append: false,
+            nonblock: false,
+            path: false,
+        };
+        let path = String::from("/file");
+        let open = || FileHandle::new(inode.clone(), options.clone(), path.clone(), false);
+        let file1 = open();
+        let file2 = open();
+        assert!(file1.flock(Some(true)).unwrap());
+        assert!(!file2.flock(Some(true)).unwrap());
+        assert!(!file2.flock(Some(false)).unwrap());
+        // a dup of the fd holds the same lock
+        let dup = file1.clone();
+        assert!(dup.flock(Some(true)).unwrap());
+        drop(file1);
+        assert!(!file2.flock(Some(false)).unwrap());
+        drop(dup);
+        assert!(file2.flock(Some(false)).unwrap());
+        // shared locks do not conflict
+        let file3 = open();
+        assert!(file3.flock(Some(false)).unwrap());
+        assert!(!file3.flock(Some(true)).unwrap());
+        assert!(file2.flock(None).unwrap());
+        assert!(file3.flock(Some(true)).unwrap());
+    }
+
+    pub fn test_all() {
+        read_in_pieces();
+        flock_per_open();
+        println!("file test end");
+    }
+}
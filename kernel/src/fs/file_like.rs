@@ -1,29 +1,41 @@
 use core::fmt;
 
+use super::fcntl::*;
 use super::ioctl::*;
-use super::FileHandle;
+use super::{FileHandle, Pipe};
 use crate::fs::epoll::EpollInstance;
 use crate::net::Socket;
 use crate::sync::Condvar;
 use crate::syscall::{SysError, SysResult};
 use alloc::boxed::Box;
 use alloc::vec::Vec;
-use rcore_fs::vfs::PollStatus;
+use rcore_fs::vfs::{FsError, PollStatus};
+
+/// Blocking files only fail with `Again` when interrupted by a signal,
+/// and files opened with O_PATH can not do I/O at all.
+pub fn io_error(file: &FileHandle, err: FsError) -> SysError {
+    match err {
+        FsError::Again if !file.options().nonblock => SysError::EINTR,
+        FsError::InvalidParam if file.options().path => SysError::EBADF,
+        err => err.into(),
+    }
+}
 
 // TODO: merge FileLike to FileHandle ?
 // TODO: fix dup and remove Clone
 #[derive(Clone)]
 pub enum FileLike {
     File(FileHandle),
-    Socket(Box<dyn Socket>),
+    /// A socket, and whether its fd is closed on exec
+    Socket(Box<dyn Socket>, bool),
     EpollInstance(EpollInstance),
 }
 
 impl FileLike {
     pub fn read(&mut self, buf: &mut [u8]) -> SysResult {
         let len = match self {
-            FileLike::File(file) => file.read(buf)?,
-            FileLike::Socket(socket) => socket.read(buf).0?,
+            FileLike::File(file) => file.read(buf).map_err(|err| io_error(file, err))?,
+            FileLike::Socket(socket, _) => socket.read(buf).0?,
             FileLike::EpollInstance(instance) => {
                 return Err(SysError::ENOSYS);
             }
@@ -32,8 +44,19 @@ impl FileLike {
     }
     pub fn write(&mut self, buf: &[u8]) -> SysResult {
         let len = match self {
-            FileLike::File(file) => file.write(buf)?,
-            FileLike::Socket(socket) => socket.write(buf, None)?,
+            FileLike::File(file) => {
+                let len = file.write(buf).map_err(|err| io_error(file, err))?;
+                if len == 0 && buf.len() > 0 {
+                    let inode = file.inode();
+                    if let Some(pipe) = inode.as_any_ref().downcast_ref::<Pipe>() {
+                        if pipe.is_broken() {
+                            return Err(SysError::EPIPE);
+                        }
+                    }
+                }
+                len
+            }
+            FileLike::Socket(socket, _) => socket.write(buf, None)?,
             FileLike::EpollInstance(instance) => {
                 return Err(SysError::ENOSYS);
             }
@@ -43,16 +66,39 @@ impl FileLike {
     pub fn ioctl(&mut self, request: usize, arg1: usize, arg2: usize, arg3: usize) -> SysResult {
         match request {
             // TODO: place flags & path in FileLike instead of FileHandle/Socket
-            FIOCLEX => Ok(0),
-            FIONBIO => Ok(0),
+            FIOCLEX | FIONCLEX => {
+                match self {
+                    FileLike::File(file) => file.fd_cloexec = request == FIOCLEX,
+                    FileLike::EpollInstance(instance) => instance.fd_cloexec = request == FIOCLEX,
+                    FileLike::Socket(_, fd_cloexec) => *fd_cloexec = request == FIOCLEX,
+                }
+                Ok(0)
+            }
+            FIONBIO => {
+                // `arg1` points to an int, checked by sys_ioctl
+                let nonblock = unsafe { *(arg1 as *const i32) } != 0;
+                match self {
+                    FileLike::File(file) => file.set_nonblock(nonblock),
+                    FileLike::Socket(socket, _) => socket.set_nonblock(nonblock),
+                    FileLike::EpollInstance(instance) => return Err(SysError::ENOTTY),
+                }
+                Ok(0)
+            }
             _ => {
                 match self {
-                    FileLike::File(file) => file.io_control(request as u32, arg1)?,
-                    FileLike::Socket(socket) => {
+                    FileLike::File(file) => {
+                        file.io_control(request as u32, arg1)
+                            .map_err(|err| match err {
+                                // not a terminal, or unknown request
+                                FsError::NotSupported => SysError::ENOTTY,
+                                err => err.into(),
+                            })?
+                    }
+                    FileLike::Socket(socket, _) => {
                         socket.ioctl(request, arg1, arg2, arg3)?;
                     }
                     FileLike::EpollInstance(instance) => {
-                        return Err(SysError::ENOSYS);
+                        return Err(SysError::ENOTTY);
                     }
                 }
                 Ok(0)
@@ -62,7 +108,7 @@ impl FileLike {
     pub fn poll(&self) -> Result<PollStatus, SysError> {
         let status = match self {
             FileLike::File(file) => file.poll()?,
-            FileLike::Socket(socket) => {
+            FileLike::Socket(socket, _) => {
                 let (read, write, error) = socket.poll();
                 PollStatus { read, write, error }
             }
@@ -74,14 +120,69 @@ impl FileLike {
     }
 
     pub fn fcntl(&mut self, cmd: usize, arg: usize) -> SysResult {
-        match self {
+        let ret = match self {
             FileLike::File(file) => file.fcntl(cmd, arg)?,
-            FileLike::Socket(socket) => {
-                //TODO
-            }
-            FileLike::EpollInstance(instance) => {}
+            FileLike::Socket(socket, fd_cloexec) => match cmd {
+                F_GETFD => {
+                    if *fd_cloexec {
+                        FD_CLOEXEC
+                    } else {
+                        0
+                    }
+                }
+                F_SETFD => {
+                    *fd_cloexec = arg & FD_CLOEXEC != 0;
+                    0
+                }
+                F_GETFL => {
+                    if socket.nonblock() {
+                        O_RDWR | O_NONBLOCK
+                    } else {
+                        O_RDWR
+                    }
+                }
+                F_SETFL => {
+                    socket.set_nonblock(arg & O_NONBLOCK != 0);
+                    0
+                }
+                _ => return Err(SysError::EINVAL),
+            },
+            FileLike::EpollInstance(instance) => match cmd {
+                F_GETFD => {
+                    if instance.fd_cloexec {
+                        FD_CLOEXEC
+                    } else {
+                        0
+                    }
+                }
+                F_SETFD => {
+                    instance.fd_cloexec = arg & FD_CLOEXEC != 0;
+                    0
+                }
+                _ => 0,
+            },
+        };
+        Ok(ret)
+    }
+
+    /// Duplicate the file for a new fd, with its own close-on-exec flag
+    pub fn dup(&self, fd_cloexec: bool) -> FileLike {
+        let mut new = self.clone();
+        match &mut new {
+            FileLike::File(file) => file.fd_cloexec = fd_cloexec,
+            FileLike::EpollInstance(instance) => instance.fd_cloexec = fd_cloexec,
+            FileLike::Socket(_, cloexec) => *cloexec = fd_cloexec,
+        }
+        new
+    }
+
+    /// Whether this fd should be closed when exec
+    pub fn is_cloexec(&self) -> bool {
+        match self {
+            FileLike::File(file) => file.fd_cloexec,
+            FileLike::EpollInstance(instance) => instance.fd_cloexec,
+            FileLike::Socket(_, fd_cloexec) => *fd_cloexec,
         }
-        Ok(0)
     }
 }
 
@@ -89,7 +190,7 @@ impl fmt::Debug for FileLike {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             FileLike::File(file) => write!(f, "File({:?})", file),
-            FileLike::Socket(socket) => write!(f, "Socket({:?})", socket),
+            FileLike::Socket(socket, _) => write!(f, "Socket({:?})", socket),
             FileLike::EpollInstance(instance) => write!(f, "EpollInstance()"),
         }
     }
@@ -7,6 +7,21 @@ pub const TCGETS: usize = 0x5401;
 #[cfg(target_arch = "mips")]
 pub const TCGETS: usize = 0x540D;
 
+#[cfg(not(target_arch = "mips"))]
+pub const TCSETS: usize = 0x5402;
+#[cfg(target_arch = "mips")]
+pub const TCSETS: usize = 0x540E;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TCSETSW: usize = 0x5403;
+#[cfg(target_arch = "mips")]
+pub const TCSETSW: usize = 0x540F;
+
+#[cfg(not(target_arch = "mips"))]
+pub const TCSETSF: usize = 0x5404;
+#[cfg(target_arch = "mips")]
+pub const TCSETSF: usize = 0x5410;
+
 #[cfg(not(target_arch = "mips"))]
 pub const TIOCGPGRP: usize = 0x540F;
 // _IOR('t', 119, int)
@@ -37,4 +52,97 @@ pub const FIOCLEX: usize = 0x6601;
 
 // rustc using pipe and ioctl pipe file with this request id
 // for non-blocking/blocking IO control setting
+#[cfg(not(target_arch = "mips"))]
 pub const FIONBIO: usize = 0x5421;
+#[cfg(target_arch = "mips")]
+pub const FIONBIO: usize = 0x667E;
+
+/// Number of control characters in `Termios`
+#[cfg(not(target_arch = "mips"))]
+pub const NCCS: usize = 19;
+#[cfg(target_arch = "mips")]
+pub const NCCS: usize = 23;
+
+/// `struct termios` used by TCGETS and TCSETS
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+// input modes
+pub const ICRNL: u32 = 0o400;
+pub const IXON: u32 = 0o2000;
+
+// output modes
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+
+// control modes
+pub const B38400: u32 = 0o17;
+pub const CS8: u32 = 0o60;
+pub const CREAD: u32 = 0o200;
+
+// local modes
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHONL: u32 = 0o100;
+#[cfg(not(target_arch = "mips"))]
+pub const IEXTEN: u32 = 0o100000;
+#[cfg(target_arch = "mips")]
+pub const IEXTEN: u32 = 0o400;
+
+// indices of control characters
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+#[cfg(not(target_arch = "mips"))]
+pub const VEOF: usize = 4;
+#[cfg(target_arch = "mips")]
+pub const VEOF: usize = 16;
+pub const VTIME: usize = 5;
+#[cfg(not(target_arch = "mips"))]
+pub const VMIN: usize = 6;
+#[cfg(target_arch = "mips")]
+pub const VMIN: usize = 4;
+
+impl Default for Termios {
+    /// A canonical mode terminal with echo, like a freshly opened tty
+    fn default() -> Self {
+        let mut cc = [0u8; NCCS];
+        cc[VINTR] = 0x03; // ^C
+        cc[VQUIT] = 0x1c; // ^\
+        cc[VERASE] = 0x7f; // DEL
+        cc[VKILL] = 0x15; // ^U
+        cc[VEOF] = 0x04; // ^D
+        cc[VTIME] = 0;
+        cc[VMIN] = 1;
+        Termios {
+            iflag: ICRNL | IXON,
+            oflag: OPOST | ONLCR,
+            cflag: B38400 | CS8 | CREAD,
+            lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | IEXTEN,
+            line: 0,
+            cc,
+        }
+    }
+}
+
+/// `struct winsize` used by TIOCGWINSZ
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WinSize {
+    pub row: u16,
+    pub col: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
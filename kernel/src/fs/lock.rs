@@ -0,0 +1,354 @@
+//! Advisory file locks
+//!
+//! Locks are keyed by (device, inode) of the file.
+//! POSIX record locks are owned by processes,
+//! and a process releases all its locks on a file when it closes any fd of the file.
+//! flock() locks cover whole files, and are owned by open file descriptions,
+//! which release them when the last fd is closed.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use rcore_fs::vfs::Metadata;
+
+use super::FileLike;
+use crate::sync::Condvar;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::{SysError, SysResult};
+
+pub const F_RDLCK: i16 = 0;
+pub const F_WRLCK: i16 = 1;
+pub const F_UNLCK: i16 = 2;
+
+/// A locked byte range of a file, in the layout of `struct flock64`,
+/// which is also `struct flock` of 64-bit archs
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Flock {
+    pub type_: i16,
+    pub whence: i16,
+    pub start: i64,
+    /// 0 locks up to the end of file, however the file grows
+    pub len: i64,
+    pub pid: i32,
+}
+
+/// `struct flock` of 32-bit archs, with 32-bit offsets
+#[cfg(all(target_pointer_width = "32", not(target_arch = "mips")))]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Flock32 {
+    pub type_: i16,
+    pub whence: i16,
+    pub start: i32,
+    pub len: i32,
+    pub pid: i32,
+}
+
+#[cfg(target_arch = "mips")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct Flock32 {
+    pub type_: i16,
+    pub whence: i16,
+    pub start: i32,
+    pub len: i32,
+    pub sysid: i32,
+    pub pid: i32,
+    pub pad: [i32; 4],
+}
+
+#[cfg(target_pointer_width = "32")]
+impl Flock32 {
+    pub fn to_flock(&self) -> Flock {
+        Flock {
+            type_: self.type_,
+            whence: self.whence,
+            start: self.start as i64,
+            len: self.len as i64,
+            pid: self.pid,
+        }
+    }
+
+    /// Update from `flock`, failing if its range does not fit
+    pub fn set_flock(&mut self, flock: &Flock) -> Result<(), SysError> {
+        if flock.start > i32::max_value() as i64 || flock.len > i32::max_value() as i64 {
+            return Err(SysError::EOVERFLOW);
+        }
+        self.type_ = flock.type_;
+        self.whence = flock.whence;
+        self.start = flock.start as i32;
+        self.len = flock.len as i32;
+        self.pid = flock.pid;
+        Ok(())
+    }
+}
+
+/// A lock held by a process on bytes `start..end` of a file
+#[derive(Debug, Copy, Clone)]
+pub struct RecordLock {
+    pub pid: usize,
+    pub start: u64,
+    /// u64::MAX if the lock extends to the end of file
+    pub end: u64,
+    pub write: bool,
+}
+
+impl RecordLock {
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.start < end && start < self.end
+    }
+
+    fn conflicts(&self, other: &RecordLock) -> bool {
+        self.pid != other.pid
+            && (self.write || other.write)
+            && self.overlaps(other.start, other.end)
+    }
+}
+
+lazy_static! {
+    /// Locks of each file, by (device, inode)
+    static ref LOCKS: Mutex<BTreeMap<(usize, usize), Vec<RecordLock>>> =
+        Mutex::new(BTreeMap::new());
+    /// Holders of flock() locks of each file, by (device, inode): (holder, exclusive)
+    static ref FLOCKS: Mutex<BTreeMap<(usize, usize), Vec<(usize, bool)>>> =
+        Mutex::new(BTreeMap::new());
+    /// Notified when any lock is released, for F_SETLKW and blocking flock()
+    pub static ref LOCK_RELEASED: Condvar = Condvar::new();
+}
+
+fn key(metadata: &Metadata) -> (usize, usize) {
+    (metadata.dev, metadata.inode)
+}
+
+/// Get a lock of another process which conflicts with `lock`
+pub fn test_lock(metadata: &Metadata, lock: &RecordLock) -> Option<RecordLock> {
+    LOCKS
+        .lock()
+        .get(&key(metadata))
+        .and_then(|locks| locks.iter().find(|held| held.conflicts(lock)).cloned())
+}
+
+/// Replace the locks of process `pid` on bytes `start..end` by a read or write lock,
+/// or unlock them if `lock` is None.
+/// Fails if a lock of another process conflicts.
+pub fn set_lock(metadata: &Metadata, pid: usize, start: u64, end: u64, lock: Option<bool>) -> bool {
+    let mut table = LOCKS.lock();
+    let locks = table.entry(key(metadata)).or_insert_with(Vec::new);
+    if let Some(write) = lock {
+        let lock = RecordLock {
+            pid,
+            start,
+            end,
+            write,
+        };
+        if locks.iter().any(|held| held.conflicts(&lock)) {
+            return false;
+        }
+    }
+    // split the locks of the process over the range
+    let mut rest = Vec::with_capacity(locks.len() + 1);
+    for held in locks.drain(..) {
+        if held.pid != pid || !held.overlaps(start, end) {
+            rest.push(held);
+            continue;
+        }
+        if held.start < start {
+            rest.push(RecordLock { end: start, ..held });
+        }
+        if held.end > end {
+            rest.push(RecordLock { start: end, ..held });
+        }
+    }
+    if let Some(write) = lock {
+        rest.push(RecordLock {
+            pid,
+            start,
+            end,
+            write,
+        });
+    }
+    if rest.is_empty() {
+        table.remove(&key(metadata));
+    } else {
+        *locks = rest;
+    }
+    drop(table);
+    LOCK_RELEASED.notify_all();
+    true
+}
+
+/// Set the lock as `set_lock`, waiting until no lock of another process conflicts.
+/// Fail with EINTR when `interrupted`.
+pub fn set_lock_wait(
+    metadata: &Metadata,
+    pid: usize,
+    start: u64,
+    end: u64,
+    lock: Option<bool>,
+    interrupted: impl Fn() -> bool,
+) -> SysResult {
+    Condvar::wait_events(&[&*LOCK_RELEASED], || {
+        if set_lock(metadata, pid, start, end, lock) {
+            return Some(Ok(0));
+        }
+        if interrupted() {
+            return Some(Err(SysError::EINTR));
+        }
+        None
+    })
+}
+
+/// Release the locks of process `pid` on the file of `file_like`, when it is closed
+pub fn release_file_locks(pid: usize, file_like: &FileLike) {
+    if let FileLike::File(file) = file_like {
+        if let Ok(metadata) = file.metadata() {
+            set_lock(&metadata, pid, 0, core::u64::MAX, None);
+        }
+    }
+}
+
+/// Release all locks of process `pid`, when it exits
+pub fn release_process_locks(pid: usize) {
+    let mut table = LOCKS.lock();
+    let mut unlocked = Vec::new();
+    for (key, locks) in table.iter_mut() {
+        locks.retain(|held| held.pid != pid);
+        if locks.is_empty() {
+            unlocked.push(*key);
+        }
+    }
+    for key in unlocked {
+        table.remove(&key);
+    }
+    drop(table);
+    LOCK_RELEASED.notify_all();
+}
+
+/// Take a shared or exclusive flock() lock on file `key` for `holder`,
+/// replacing the one it holds, or release it if `lock` is None.
+/// Fails if another holder has a conflicting lock.
+pub fn set_flock(key: (usize, usize), holder: usize, lock: Option<bool>) -> bool {
+    let mut table = FLOCKS.lock();
+    let holders = table.entry(key).or_insert_with(Vec::new);
+    if let Some(exclusive) = lock {
+        if holders
+            .iter()
+            .any(|&(other, other_exclusive)| other != holder && (exclusive || other_exclusive))
+        {
+            return false;
+        }
+    }
+    holders.retain(|&(other, _)| other != holder);
+    if let Some(exclusive) = lock {
+        holders.push((holder, exclusive));
+    }
+    if holders.is_empty() {
+        table.remove(&key);
+    }
+    drop(table);
+    LOCK_RELEASED.notify_all();
+    true
+}
+
+pub mod test {
+    //! Record locks contended by two processes, and released on unlock, close and exit
+
+    use super::*;
+    use crate::fs::{FileHandle, OpenOptions, TmpFS};
+    use crate::thread;
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use rcore_fs::vfs::{FileSystem, FileType, INode};
+
+    // pids of the two processes, which are not real ones
+    const PID1: usize = 0xfe01;
+    const PID2: usize = 0xfe02;
+
+    fn file(root: &Arc<dyn INode>, name: &str) -> FileHandle {
+        let inode = root.create(name, FileType::File, 0o644).unwrap();
+        let options = OpenOptions {
+            read: true,
+            write: true,
+            append: false,
+            nonblock: false,
+            path: false,
+        };
+        FileHandle::new(inode, options, String::from(name), false)
+    }
+
+    fn lock(pid: usize, start: u64, end: u64, write: bool) -> RecordLock {
+        RecordLock {
+            pid,
+            start,
+            end,
+            write,
+        }
+    }
+
+    /// Overlapping locks conflict unless both are read locks,
+    /// and unlocking a part of a lock leaves the rest
+    fn contend() {
+        let fs = TmpFS::new(0x1000);
+        let metadata = file(&fs.root_inode(), "file").metadata().unwrap();
+        assert!(set_lock(&metadata, PID1, 0, 10, Some(true)));
+        assert!(!set_lock(&metadata, PID2, 5, 15, Some(false)));
+        let held = test_lock(&metadata, &lock(PID2, 5, 15, false)).unwrap();
+        assert_eq!((held.pid, held.start, held.end), (PID1, 0, 10));
+        // a process does not conflict with itself
+        assert!(test_lock(&metadata, &lock(PID1, 5, 15, true)).is_none());
+        assert!(set_lock(&metadata, PID2, 10, 20, Some(true)));
+
+        assert!(set_lock(&metadata, PID1, 0, 5, None));
+        assert!(set_lock(&metadata, PID2, 0, 5, Some(true)));
+        assert!(!set_lock(&metadata, PID2, 5, 10, Some(true)));
+        // read locks are shared
+        assert!(set_lock(&metadata, PID1, 5, 10, Some(false)));
+        assert!(set_lock(&metadata, PID2, 5, 10, Some(false)));
+        release_process_locks(PID1);
+        release_process_locks(PID2);
+    }
+
+    /// F_SETLKW waits until the conflicting lock is released.
+    /// It hangs if the waiter is not woken up.
+    fn wait_for_unlock() {
+        let fs = TmpFS::new(0x1000);
+        let file = file(&fs.root_inode(), "file");
+        let metadata = file.metadata().unwrap();
+        assert!(set_lock(&metadata, PID1, 0, core::u64::MAX, Some(true)));
+        let waiter = thread::spawn(move || {
+            let metadata = file.metadata().unwrap();
+            set_lock_wait(&metadata, PID2, 0, 1, Some(true), || false)
+        });
+        thread::yield_now();
+        assert!(set_lock(&metadata, PID1, 0, core::u64::MAX, None));
+        assert_eq!(waiter.join().expect("waiter should finish"), Ok(0));
+        let held = test_lock(&metadata, &lock(PID1, 0, 1, false)).unwrap();
+        assert_eq!(held.pid, PID2);
+        release_process_locks(PID2);
+    }
+
+    /// Closing any fd of the file releases the locks of the process on it,
+    /// and exiting releases all of them
+    fn release_on_close_and_exit() {
+        let fs = TmpFS::new(0x1000);
+        let file1 = file(&fs.root_inode(), "file1");
+        let file2 = file(&fs.root_inode(), "file2");
+        let metadata1 = file1.metadata().unwrap();
+        let metadata2 = file2.metadata().unwrap();
+        assert!(set_lock(&metadata1, PID1, 0, 10, Some(true)));
+        assert!(set_lock(&metadata2, PID1, 0, 10, Some(true)));
+        let other_fd = FileLike::File(file1.clone());
+        release_file_locks(PID1, &other_fd);
+        assert!(test_lock(&metadata1, &lock(PID2, 0, 10, true)).is_none());
+        assert!(test_lock(&metadata2, &lock(PID2, 0, 10, true)).is_some());
+        release_process_locks(PID1);
+        assert!(test_lock(&metadata2, &lock(PID2, 0, 10, true)).is_none());
+    }
+
+    pub fn test_all() {
+        contend();
+        wait_for_unlock();
+        release_on_close_and_exit();
+        println!("lock test end");
+    }
+}
@@ -1,31 +1,43 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
 
 use rcore_fs::dev::block_cache::BlockCache;
 use rcore_fs::vfs::*;
 use rcore_fs_devfs::{special::*, DevFS};
 use rcore_fs_mountfs::MountFS;
-use rcore_fs_ramfs::RamFS;
 use rcore_fs_sfs::SimpleFileSystem;
 
 use crate::drivers::BlockDriver;
 
+pub use self::eventfd::EventFd;
+pub use self::fcntl::*;
 pub use self::file::*;
 pub use self::file_like::*;
-pub use self::pipe::Pipe;
+pub use self::lock::*;
+pub use self::pipe::{Pipe, PipeEnd, PIPE_ACTIVITY};
+pub use self::procfs::ProcFS;
 pub use self::pseudo::*;
 pub use self::random::*;
-pub use self::stdio::{STDIN, STDOUT};
+pub use self::stdio::{Stdin, STDIN, STDOUT};
+pub use self::timerfd::{timerfd_tick, TimerFd};
+pub use self::tmpfs::TmpFS;
 pub use self::vga::*;
 
 mod device;
 pub mod epoll;
+mod eventfd;
+mod fcntl;
 mod file;
 mod file_like;
-mod ioctl;
+pub mod ioctl;
+mod lock;
+pub mod mount;
 mod pipe;
+mod procfs;
 mod pseudo;
 mod random;
 mod stdio;
+mod timerfd;
+mod tmpfs;
 pub mod vga;
 
 // Hard link user programs
@@ -80,12 +92,12 @@ lazy_static! {
         let rootfs = MountFS::new(sfs);
         let root = rootfs.root_inode();
 
-        // create DevFS
+        // create DevFS, sharing the inodes with device nodes
         let devfs = DevFS::new();
-        devfs.add("null", Arc::new(NullINode::default())).expect("failed to mknod /dev/null");
-        devfs.add("zero", Arc::new(ZeroINode::default())).expect("failed to mknod /dev/zero");
-        devfs.add("random", Arc::new(RandomINode::new(false))).expect("failed to mknod /dev/zero");
-        devfs.add("urandom", Arc::new(RandomINode::new(true))).expect("failed to mknod /dev/zero");
+        for &(name, dev) in CHAR_DEVICE_NAMES.iter() {
+            devfs.add(name, CHAR_DEVICES[&dev].clone())
+                .unwrap_or_else(|_| panic!("failed to mknod /dev/{}", name));
+        }
 
         // mount DevFS at /dev
         let dev = root.find(true, "dev").unwrap_or_else(|_| {
@@ -93,18 +105,53 @@ lazy_static! {
         });
         dev.mount(devfs).expect("failed to mount DevFS");
 
-        // mount RamFS at /tmp
-        let ramfs = RamFS::new();
+        // mount TmpFS at /tmp
+        let tmpfs = TmpFS::new(tmpfs::DEFAULT_CAPACITY);
         let tmp = root.find(true, "tmp").unwrap_or_else(|_| {
             root.create("tmp", FileType::Dir, 0o666).expect("failed to mkdir /tmp")
         });
-        tmp.mount(ramfs).expect("failed to mount RamFS");
+        tmp.mount(tmpfs).expect("failed to mount TmpFS");
+
+        // mount ProcFS at /proc
+        let proc = root.find(true, "proc").unwrap_or_else(|_| {
+            root.create("proc", FileType::Dir, 0o555).expect("failed to mkdir /proc")
+        });
+        proc.mount(Arc::new(ProcFS)).expect("failed to mount ProcFS");
 
         root
     };
 }
 
-pub const FOLLOW_MAX_DEPTH: usize = 1;
+lazy_static! {
+    /// Character devices which device nodes can refer to, by (major, minor)
+    pub static ref CHAR_DEVICES: BTreeMap<(usize, usize), Arc<dyn INode>> = {
+        let mut devices: BTreeMap<(usize, usize), Arc<dyn INode>> = BTreeMap::new();
+        devices.insert((1, 3), Arc::new(NullINode::default()));
+        devices.insert((1, 5), Arc::new(ZeroINode::default()));
+        devices.insert((1, 8), Arc::new(RandomINode::new(false)));
+        devices.insert((1, 9), Arc::new(RandomINode::new(true)));
+        devices
+    };
+}
+
+/// Names of character devices in /dev
+const CHAR_DEVICE_NAMES: [(&str, (usize, usize)); 4] = [
+    ("null", (1, 3)),
+    ("zero", (1, 5)),
+    ("random", (1, 8)),
+    ("urandom", (1, 9)),
+];
+
+/// Split a device number into (major, minor), as encoded by glibc's `makedev`
+pub fn dev_split(dev: usize) -> (usize, usize) {
+    let dev = dev as u64;
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    (major as usize, minor as usize)
+}
+
+/// Max number of symbolic links followed in a single path lookup
+pub const FOLLOW_MAX_DEPTH: usize = 40;
 
 pub trait INodeExt {
     fn read_as_vec(&self) -> Result<Vec<u8>>;
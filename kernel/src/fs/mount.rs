@@ -0,0 +1,160 @@
+//! File systems mounted at runtime
+//!
+//! MountFS can not detach a file system, so each one is mounted through a `MountPoint`.
+//! Unmounting empties the `MountPoint`, which then looks like an empty directory.
+//! Mount points must be empty directories, so nothing is hidden after unmounting.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+use rcore_memory::PAGE_SIZE;
+use spin::RwLock;
+
+use crate::sync::SpinNoIrqLock as Mutex;
+
+/// A file system mounted at runtime
+pub struct MountEntry {
+    pub source: String,
+    /// Absolute path of the mount point
+    pub target: String,
+    pub fstype: String,
+    pub point: Arc<MountPoint>,
+    /// The file system seen through the mount point, which the inodes inside belong to
+    pub fs: Arc<dyn FileSystem>,
+}
+
+lazy_static! {
+    /// Mount points created at runtime, in the order of creation.
+    /// They stay after unmounting, and can be used again.
+    pub static ref MOUNTS: Mutex<Vec<MountEntry>> = Mutex::new(Vec::new());
+}
+
+/// Mount points of file systems at boot: (source, target, fstype)
+pub const BOOT_MOUNTS: [(&str, &str, &str); 4] = [
+    ("rootfs", "/", "sfs"),
+    ("devfs", "/dev", "devfs"),
+    ("tmpfs", "/tmp", "tmpfs"),
+    ("proc", "/proc", "proc"),
+];
+
+/// A file system which can be attached and detached
+#[derive(Default)]
+pub struct MountPoint {
+    fs: RwLock<Option<Arc<dyn FileSystem>>>,
+}
+
+impl MountPoint {
+    pub fn new(fs: Arc<dyn FileSystem>) -> Self {
+        MountPoint {
+            fs: RwLock::new(Some(fs)),
+        }
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.fs.read().is_some()
+    }
+
+    pub fn attach(&self, fs: Arc<dyn FileSystem>) {
+        *self.fs.write() = Some(fs);
+    }
+
+    /// Detach the file system, after syncing it
+    pub fn detach(&self) -> Result<()> {
+        if let Some(fs) = self.fs.write().take() {
+            fs.sync()?;
+        }
+        Ok(())
+    }
+}
+
+impl FileSystem for MountPoint {
+    fn sync(&self) -> Result<()> {
+        match self.fs.read().as_ref() {
+            Some(fs) => fs.sync(),
+            None => Ok(()),
+        }
+    }
+
+    fn root_inode(&self) -> Arc<dyn INode> {
+        match self.fs.read().as_ref() {
+            Some(fs) => fs.root_inode(),
+            None => Arc::new(EmptyDir),
+        }
+    }
+
+    fn info(&self) -> FsInfo {
+        match self.fs.read().as_ref() {
+            Some(fs) => fs.info(),
+            None => FsInfo {
+                bsize: PAGE_SIZE,
+                frsize: PAGE_SIZE,
+                blocks: 0,
+                bfree: 0,
+                bavail: 0,
+                files: 0,
+                ffree: 0,
+                namemax: 255,
+            },
+        }
+    }
+}
+
+/// Root of a `MountPoint` without a file system
+struct EmptyDir;
+
+impl INode for EmptyDir {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: false,
+            write: false,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            inode: 0,
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::Dir,
+            mode: 0o755,
+            nlinks: 2,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        match name {
+            "." | ".." => Ok(Arc::new(EmptyDir)),
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
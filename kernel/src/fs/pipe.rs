@@ -1,96 +1,195 @@
 //! Implement INode for Pipe
 
-use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc};
+use alloc::{
+    collections::{vec_deque::VecDeque, BTreeMap},
+    string::String,
+    sync::{Arc, Weak},
+};
 use core::any::Any;
 
 use rcore_fs::vfs::*;
 
-use crate::sync::Condvar;
 use crate::sync::SpinNoIrqLock as Mutex;
+use crate::sync::{Condvar, WaitQueue};
 
-#[derive(Clone)]
+/// Capacity of the ring buffer shared by the two ends of a pipe
+const PIPE_BUF_SIZE: usize = 0x1000;
+
+lazy_static! {
+    /// Woken up when any pipe may become readable or writable,
+    /// for waiting on multiple files
+    pub static ref PIPE_ACTIVITY: WaitQueue = WaitQueue::new();
+    /// Buffers of the named pipes being opened, by (device, inode) of the FIFO
+    static ref FIFOS: Mutex<BTreeMap<(usize, usize), Weak<Mutex<PipeData>>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+#[derive(Clone, PartialEq)]
 pub enum PipeEnd {
     Read,
     Write,
+    /// Both ends, for a FIFO opened for reading and writing
+    Both,
+}
+
+impl PipeEnd {
+    fn readable(&self) -> bool {
+        *self != PipeEnd::Write
+    }
+
+    fn writable(&self) -> bool {
+        *self != PipeEnd::Read
+    }
 }
 
 pub struct PipeData {
     buf: VecDeque<u8>,
     new_data: Condvar,
+    /// Number of open read ends
+    readers: usize,
+    /// Number of open write ends
+    writers: usize,
+}
+
+impl PipeData {
+    fn new() -> Self {
+        PipeData {
+            buf: VecDeque::with_capacity(PIPE_BUF_SIZE),
+            new_data: Condvar::new(),
+            readers: 0,
+            writers: 0,
+        }
+    }
 }
 
-#[derive(Clone)]
 pub struct Pipe {
     data: Arc<Mutex<PipeData>>,
     direction: PipeEnd,
 }
 
 impl Pipe {
+    /// Open a new end on `data`
+    fn new(data: Arc<Mutex<PipeData>>, direction: PipeEnd) -> Pipe {
+        {
+            let mut inner = data.lock();
+            if direction.readable() {
+                inner.readers += 1;
+            }
+            if direction.writable() {
+                inner.writers += 1;
+            }
+        }
+        Pipe { data, direction }
+    }
+
     /// Create a pair of INode: (read, write)
     pub fn create_pair() -> (Pipe, Pipe) {
-        let inner = PipeData {
-            buf: VecDeque::new(),
-            new_data: Condvar::new(),
-        };
-        let data = Arc::new(Mutex::new(inner));
+        let data = Arc::new(Mutex::new(PipeData::new()));
         (
-            Pipe {
-                data: data.clone(),
-                direction: PipeEnd::Read,
-            },
-            Pipe {
-                data: data.clone(),
-                direction: PipeEnd::Write,
-            },
+            Pipe::new(data.clone(), PipeEnd::Read),
+            Pipe::new(data, PipeEnd::Write),
         )
     }
 
     fn can_read(&self) -> bool {
-        if let PipeEnd::Read = self.direction {
-            self.data.lock().buf.len() > 0 || self.is_broken()
+        if self.direction.readable() {
+            let data = self.data.lock();
+            data.buf.len() > 0 || self.is_broken_locked(&data)
         } else {
             false
         }
     }
 
     fn can_write(&self) -> bool {
-        if let PipeEnd::Write = self.direction {
-            !self.is_broken()
+        if self.direction.writable() {
+            let data = self.data.lock();
+            data.buf.len() < PIPE_BUF_SIZE || self.is_broken_locked(&data)
         } else {
             false
         }
     }
 
-    fn is_broken(&self) -> bool {
-        Arc::strong_count(&self.data) < 2
+    /// Open an end of the named pipe `inode`.
+    /// All opens of the same FIFO share one buffer.
+    pub fn open_fifo(inode: &Arc<dyn INode>, direction: PipeEnd) -> Result<Pipe> {
+        let info = inode.metadata()?;
+        let key = (info.dev, info.inode);
+        let mut fifos = FIFOS.lock();
+        let data = match fifos.get(&key).and_then(|weak| weak.upgrade()) {
+            Some(data) => data,
+            None => {
+                let data = Arc::new(Mutex::new(PipeData::new()));
+                fifos.insert(key, Arc::downgrade(&data));
+                data
+            }
+        };
+        let pipe = Pipe::new(data, direction);
+        // wake up the other end waiting for us
+        PIPE_ACTIVITY.wake_all();
+        Ok(pipe)
+    }
+
+    /// All ends of the other direction have been closed,
+    /// which never happens to both ends
+    pub fn is_broken(&self) -> bool {
+        self.is_broken_locked(&self.data.lock())
+    }
+
+    fn is_broken_locked(&self, data: &PipeData) -> bool {
+        match self.direction {
+            PipeEnd::Read => data.writers == 0,
+            PipeEnd::Write => data.readers == 0,
+            PipeEnd::Both => false,
+        }
+    }
+}
+
+/// A cloned end is counted as another open end
+impl Clone for Pipe {
+    fn clone(&self) -> Self {
+        Pipe::new(self.data.clone(), self.direction.clone())
     }
 }
 
 impl INode for Pipe {
     fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if let PipeEnd::Read = self.direction {
+        if self.direction.readable() {
             let mut data = self.data.lock();
-            if let Some(ch) = data.buf.pop_front() {
-                buf[0] = ch;
-                Ok(1)
-            } else {
-                Ok(0)
+            if data.buf.is_empty() {
+                // EOF only after all write ends are closed
+                return if self.is_broken_locked(&data) {
+                    Ok(0)
+                } else {
+                    Err(FsError::Again)
+                };
+            }
+            let len = buf.len().min(data.buf.len());
+            for (dst, src) in buf.iter_mut().zip(data.buf.drain(..len)) {
+                *dst = src;
             }
+            data.new_data.notify_all();
+            PIPE_ACTIVITY.wake_all();
+            Ok(len)
         } else {
             Ok(0)
         }
     }
 
     fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
-        if let PipeEnd::Write = self.direction {
-            if buf.len() > 0 {
-                let mut data = self.data.lock();
-                data.buf.push_back(buf[0]);
-                data.new_data.notify_all();
-                Ok(1)
-            } else {
-                Ok(0)
+        if self.direction.writable() {
+            let mut data = self.data.lock();
+            // no reader left, let the caller report EPIPE
+            if buf.is_empty() || self.is_broken_locked(&data) {
+                return Ok(0);
+            }
+            let len = buf.len().min(PIPE_BUF_SIZE - data.buf.len());
+            if len == 0 {
+                return Err(FsError::Again);
             }
+            data.buf.extend(&buf[..len]);
+            data.new_data.notify_all();
+            PIPE_ACTIVITY.wake_all();
+            Ok(len)
         } else {
             Ok(0)
         }
@@ -108,3 +207,89 @@ impl INode for Pipe {
         self
     }
 }
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        {
+            let mut data = self.data.lock();
+            if self.direction.readable() {
+                data.readers -= 1;
+            }
+            if self.direction.writable() {
+                data.writers -= 1;
+            }
+        }
+        // the other end may see EOF or be able to write
+        PIPE_ACTIVITY.wake_all();
+    }
+}
+
+pub mod test {
+    //! Ends of a pipe counted by direction
+
+    use super::*;
+    use crate::fs::TmpFS;
+
+    /// The reader sees EOF only after every write end is closed
+    fn eof_after_all_writers_closed() {
+        let (reader, writer) = Pipe::create_pair();
+        let writer2 = writer.clone();
+        let reader2 = reader.clone();
+        drop(writer);
+        assert!(!reader.is_broken());
+        match reader.read_at(0, &mut [0u8; 1]) {
+            Err(FsError::Again) => {}
+            _ => panic!("read should not see EOF"),
+        }
+        assert_eq!(writer2.write_at(0, b"x").unwrap(), 1);
+        drop(reader);
+        // another read end is still open
+        assert!(!writer2.is_broken());
+        drop(writer2);
+        let mut buf = [0u8; 2];
+        assert_eq!(reader2.read_at(0, &mut buf).unwrap(), 1);
+        assert_eq!(reader2.read_at(0, &mut buf).unwrap(), 0);
+        assert!(reader2.poll().unwrap().read);
+    }
+
+    /// Writing fails once every read end is closed
+    fn broken_after_all_readers_closed() {
+        let (reader, writer) = Pipe::create_pair();
+        drop(reader);
+        assert!(writer.is_broken());
+        assert_eq!(writer.write_at(0, b"x").unwrap(), 0);
+    }
+
+    /// A FIFO opened for reading and writing is both ends,
+    /// and keeps the other ends from seeing EOF or a broken pipe
+    fn fifo_read_write() {
+        let fs = TmpFS::new(0x1000);
+        let fifo = fs
+            .root_inode()
+            .create("fifo", FileType::NamedPipe, 0o644)
+            .unwrap();
+        let both = Pipe::open_fifo(&fifo, PipeEnd::Both).unwrap();
+        assert!(!both.is_broken());
+        assert_eq!(both.write_at(0, b"xy").unwrap(), 2);
+        let mut buf = [0u8; 1];
+        assert_eq!(both.read_at(0, &mut buf).unwrap(), 1);
+        let reader = Pipe::open_fifo(&fifo, PipeEnd::Read).unwrap();
+        let writer = Pipe::open_fifo(&fifo, PipeEnd::Write).unwrap();
+        assert!(!reader.is_broken() && !writer.is_broken());
+        assert_eq!(reader.read_at(0, &mut buf).unwrap(), 1);
+        drop(writer);
+        match reader.read_at(0, &mut buf) {
+            Err(FsError::Again) => {}
+            _ => panic!("read should not see EOF"),
+        }
+        drop(both);
+        assert_eq!(reader.read_at(0, &mut buf).unwrap(), 0);
+    }
+
+    pub fn test_all() {
+        eof_after_all_writers_closed();
+        broken_after_all_readers_closed();
+        fifo_read_write();
+        println!("pipe test end");
+    }
+}
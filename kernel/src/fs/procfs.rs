@@ -0,0 +1,288 @@
+//! Process file system
+//!
+//! Files are generated from live kernel state on each read.
+//! `/proc/self` is a link to the directory of the calling process.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+use rcore_memory::PAGE_SIZE;
+
+use super::mount::{BOOT_MOUNTS, MOUNTS};
+use crate::memory::frame_usage;
+use crate::process::{current_thread, Process, PROCESSES};
+use crate::sync::SpinNoIrqLock as Mutex;
+
+/// Device number of ProcFS inodes
+const PROCFS_DEV: usize = 0x50;
+
+pub struct ProcFS;
+
+impl FileSystem for ProcFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<dyn INode> {
+        Arc::new(ProcINode::Root)
+    }
+
+    fn info(&self) -> FsInfo {
+        FsInfo {
+            bsize: PAGE_SIZE,
+            frsize: PAGE_SIZE,
+            blocks: 0,
+            bfree: 0,
+            bavail: 0,
+            files: 0,
+            ffree: 0,
+            namemax: 255,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ProcINode {
+    /// `/proc`
+    Root,
+    /// `/proc/meminfo`
+    MemInfo,
+    /// `/proc/mounts`
+    Mounts,
+    /// `/proc/self`, a link to `<pid>` of the process which looked it up
+    SelfLink(usize),
+    /// `/proc/<pid>`
+    ProcessDir(usize),
+    /// `/proc/<pid>/status`
+    Status(usize),
+    /// `/proc/<pid>/cmdline`
+    CmdLine(usize),
+    /// `/proc/<pid>/comm`
+    Comm(usize),
+}
+
+/// Files in each `/proc/<pid>`
+const PROCESS_ENTRIES: [&str; 3] = ["status", "cmdline", "comm"];
+
+/// Pid of the calling process.
+/// It is found without locking the process, which may be locked by the caller.
+fn current_pid() -> Result<usize> {
+    let current = unsafe { current_thread() }.proc.clone();
+    let processes: Vec<_> = PROCESSES
+        .read()
+        .iter()
+        .filter_map(|(&pid, weak)| weak.upgrade().map(|process| (pid, process)))
+        .collect();
+    processes
+        .into_iter()
+        .find(|(_, process)| Arc::ptr_eq(process, &current))
+        .map(|(pid, _)| pid)
+        .ok_or(FsError::EntryNotFound)
+}
+
+fn get_process(pid: usize) -> Result<Arc<Mutex<Process>>> {
+    PROCESSES
+        .read()
+        .get(&pid)
+        .and_then(|weak| weak.upgrade())
+        .ok_or(FsError::EntryNotFound)
+}
+
+/// A thread name without the null terminator
+fn comm_str(comm: &[u8]) -> String {
+    let len = comm.iter().position(|&c| c == 0).unwrap_or(comm.len());
+    String::from_utf8_lossy(&comm[..len]).into_owned()
+}
+
+impl ProcINode {
+    fn is_dir(&self) -> bool {
+        match self {
+            ProcINode::Root | ProcINode::ProcessDir(_) => true,
+            _ => false,
+        }
+    }
+
+    fn id(&self) -> usize {
+        match *self {
+            ProcINode::Root => 1,
+            ProcINode::MemInfo => 2,
+            ProcINode::Mounts => 3,
+            ProcINode::SelfLink(_) => 4,
+            ProcINode::ProcessDir(pid) => (pid + 2) << 2,
+            ProcINode::Status(pid) => (pid + 2) << 2 | 1,
+            ProcINode::CmdLine(pid) => (pid + 2) << 2 | 2,
+            ProcINode::Comm(pid) => (pid + 2) << 2 | 3,
+        }
+    }
+
+    /// Generate the content of a file
+    fn content(&self) -> Result<Vec<u8>> {
+        match *self {
+            ProcINode::MemInfo => {
+                let (total, free) = frame_usage();
+                let content = format!(
+                    "MemTotal:\t{} kB\nMemFree:\t{} kB\n",
+                    total * PAGE_SIZE / 1024,
+                    free * PAGE_SIZE / 1024
+                );
+                Ok(content.into_bytes())
+            }
+            ProcINode::Mounts => {
+                let mut content = String::new();
+                for &(source, target, fstype) in BOOT_MOUNTS.iter() {
+                    content += &format!("{} {} {} rw 0 0\n", source, target, fstype);
+                }
+                for entry in MOUNTS
+                    .lock()
+                    .iter()
+                    .filter(|entry| entry.point.is_attached())
+                {
+                    content += &format!(
+                        "{} {} {} rw 0 0\n",
+                        entry.source, entry.target, entry.fstype
+                    );
+                }
+                Ok(content.into_bytes())
+            }
+            ProcINode::Status(pid) => {
+                let process = get_process(pid)?;
+                let (name, state, threads, parent, cred) = {
+                    let proc = process.lock();
+                    let name = comm_str(&proc.comm);
+                    let state = match proc.threads.is_empty() {
+                        true => "Z (zombie)",
+                        false => "R (running)",
+                    };
+                    let cred = proc.cred.clone();
+                    (name, state, proc.threads.len(), proc.parent.clone(), cred)
+                };
+                // don't hold the process while locking its parent
+                let ppid = parent.upgrade().map_or(0, |parent| parent.lock().pid.get());
+                let content = format!(
+                    "Name:\t{}\nState:\t{}\nPid:\t{}\nPPid:\t{}\nUid:\t{}\t{}\t{}\nGid:\t{}\t{}\t{}\nThreads:\t{}\n",
+                    name, state, pid, ppid, cred.uid, cred.euid, cred.suid, cred.gid, cred.egid, cred.sgid, threads
+                );
+                Ok(content.into_bytes())
+            }
+            ProcINode::CmdLine(pid) => {
+                let process = get_process(pid)?;
+                let proc = process.lock();
+                let mut content = Vec::new();
+                for arg in proc.exec_args.iter() {
+                    content.extend_from_slice(arg.as_bytes());
+                    content.push(0);
+                }
+                Ok(content)
+            }
+            ProcINode::Comm(pid) => {
+                let process = get_process(pid)?;
+                let content = format!("{}\n", comm_str(&process.lock().comm));
+                Ok(content.into_bytes())
+            }
+            ProcINode::SelfLink(pid) => Ok(format!("{}", pid).into_bytes()),
+            _ => Err(FsError::IsDir),
+        }
+    }
+}
+
+impl INode for ProcINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let content = self.content()?;
+        if offset >= content.len() {
+            return Ok(0);
+        }
+        let len = (content.len() - offset).min(buf.len());
+        buf[..len].copy_from_slice(&content[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: false,
+            error: false,
+        })
+    }
+
+    /// The size is 0 as the content is only known when read
+    fn metadata(&self) -> Result<Metadata> {
+        let (type_, mode, nlinks) = match (self.is_dir(), *self) {
+            (true, _) => (FileType::Dir, 0o555, 2),
+            (false, ProcINode::SelfLink(_)) => (FileType::SymLink, 0o777, 1),
+            (false, _) => (FileType::File, 0o444, 1),
+        };
+        Ok(Metadata {
+            dev: PROCFS_DEV,
+            inode: self.id(),
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_,
+            mode,
+            nlinks,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+        })
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        let inode = match (*self, name) {
+            (ProcINode::Root, ".") | (ProcINode::Root, "..") => ProcINode::Root,
+            (ProcINode::Root, "meminfo") => ProcINode::MemInfo,
+            (ProcINode::Root, "mounts") => ProcINode::Mounts,
+            (ProcINode::Root, "self") => ProcINode::SelfLink(current_pid()?),
+            (ProcINode::Root, name) => {
+                let pid = name.parse().map_err(|_| FsError::EntryNotFound)?;
+                get_process(pid)?;
+                ProcINode::ProcessDir(pid)
+            }
+            (ProcINode::ProcessDir(pid), ".") => ProcINode::ProcessDir(pid),
+            (ProcINode::ProcessDir(_), "..") => ProcINode::Root,
+            (ProcINode::ProcessDir(pid), "status") => ProcINode::Status(pid),
+            (ProcINode::ProcessDir(pid), "cmdline") => ProcINode::CmdLine(pid),
+            (ProcINode::ProcessDir(pid), "comm") => ProcINode::Comm(pid),
+            (ProcINode::ProcessDir(_), _) => return Err(FsError::EntryNotFound),
+            _ => return Err(FsError::NotDir),
+        };
+        Ok(Arc::new(inode))
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        match (*self, id) {
+            (_, 0) if self.is_dir() => Ok(String::from(".")),
+            (_, 1) if self.is_dir() => Ok(String::from("..")),
+            (ProcINode::Root, 2) => Ok(String::from("meminfo")),
+            (ProcINode::Root, 3) => Ok(String::from("mounts")),
+            (ProcINode::Root, 4) => Ok(String::from("self")),
+            (ProcINode::Root, id) => PROCESSES
+                .read()
+                .iter()
+                .filter(|(_, weak)| weak.strong_count() > 0)
+                .nth(id - 5)
+                .map(|(pid, _)| format!("{}", pid))
+                .ok_or(FsError::EntryNotFound),
+            (ProcINode::ProcessDir(_), id) => PROCESS_ENTRIES
+                .get(id - 2)
+                .map(|&name| String::from(name))
+                .ok_or(FsError::EntryNotFound),
+            _ => Err(FsError::NotDir),
+        }
+    }
+
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        Arc::new(ProcFS)
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
@@ -1,6 +1,6 @@
 //! Implement INode for RandomINode
 
-use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc};
+use alloc::{collections::vec_deque::VecDeque, string::String};
 use core::any::Any;
 
 use rcore_fs::vfs::*;
@@ -8,13 +8,67 @@ use rcore_fs::vfs::*;
 use crate::sync::Condvar;
 use crate::sync::SpinNoIrqLock as Mutex;
 
-pub struct RandomINodeData {
-    seed: u32,
+lazy_static! {
+    /// State of the kernel PRNG, shared by all random devices.
+    /// It is seeded by the time and the cycle counter on first use, so that it differs on each boot.
+    static ref RANDOM_STATE: Mutex<u64> = {
+        let seed = crate::arch::timer::read_epoch().rotate_left(32) ^ cycle_counter();
+        Mutex::new(mix(0x2545_f491_4f6c_dd1d, &seed.to_le_bytes()))
+    };
 }
 
+/// A counter changing faster than the timer tick, or 0 if there is none
+fn cycle_counter() -> u64 {
+    #[cfg(target_arch = "x86_64")]
+    let cycle = unsafe { core::arch::x86_64::_rdtsc() };
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    let cycle = crate::arch::timer::get_cycle();
+    #[cfg(not(any(
+        target_arch = "x86_64",
+        target_arch = "riscv32",
+        target_arch = "riscv64"
+    )))]
+    let cycle = 0;
+    cycle
+}
+
+/// Mix `buf` into `state`
+fn mix(mut state: u64, buf: &[u8]) -> u64 {
+    for &byte in buf {
+        state = (state ^ byte as u64).rotate_left(8);
+    }
+    // xorshift never leaves the zero state
+    if state == 0 {
+        state = 1;
+    }
+    state
+}
+
+/// Fill `buf` with pseudo-random bytes from the kernel PRNG
+pub fn fill_random(buf: &mut [u8]) {
+    let mut state = RANDOM_STATE.lock();
+    // the time of each read is unpredictable as well
+    let now = crate::trap::uptime_usec() ^ cycle_counter();
+    *state = mix(*state, &now.to_le_bytes());
+    for chunk in buf.chunks_mut(8) {
+        // xorshift64*
+        *state ^= *state >> 12;
+        *state ^= *state << 25;
+        *state ^= *state >> 27;
+        let value = state.wrapping_mul(0x2545_f491_4f6c_dd1d).to_le_bytes();
+        chunk.copy_from_slice(&value[..chunk.len()]);
+    }
+}
+
+/// Mix `buf` into the state of the kernel PRNG
+fn add_entropy(buf: &[u8]) {
+    let mut state = RANDOM_STATE.lock();
+    *state = mix(*state, buf);
+}
+
+/// `/dev/random` and `/dev/urandom`, which read from the kernel PRNG
 #[derive(Clone)]
 pub struct RandomINode {
-    data: Arc<Mutex<RandomINodeData>>,
     secure: bool,
 }
 
@@ -22,30 +76,22 @@ impl RandomINode {
     // urandom -> secure=true
     // random -> secure=false
     pub fn new(secure: bool) -> RandomINode {
-        RandomINode {
-            secure,
-            data: Arc::new(Mutex::new(RandomINodeData { seed: 1 })),
-        }
+        RandomINode { secure }
     }
 }
 
 impl INode for RandomINode {
     fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if buf.len() > 0 {
-            let mut data = self.data.lock();
-            // from K&R
-            for i in 0..buf.len() {
-                data.seed = data.seed.wrapping_mul(1103515245).wrapping_add(12345);
-                buf[i] = (data.seed / 65536) as u8;
-            }
-            Ok(buf.len())
-        } else {
-            Ok(0)
-        }
+        fill_random(buf);
+        Ok(buf.len())
     }
 
-    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
-        Err(FsError::NotSupported)
+    /// Written data is mixed into the PRNG
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        // the time of writes is unpredictable as well
+        add_entropy(&crate::trap::uptime_msec().to_le_bytes());
+        add_entropy(buf);
+        Ok(buf.len())
     }
 
     fn poll(&self) -> Result<PollStatus> {
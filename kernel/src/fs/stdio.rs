@@ -1,25 +1,45 @@
 //! Implement INode for Stdin & Stdout
 
-use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc};
+use alloc::{collections::vec_deque::VecDeque, string::String, sync::Arc, vec::Vec};
 use core::any::Any;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use rcore_fs::vfs::*;
 
 use super::ioctl::*;
-use crate::process::Process;
-use crate::sync::Condvar;
+use crate::process::{Process, INIT_PID, PROCESSES};
+use crate::signal::SIGINT;
 use crate::sync::SpinNoIrqLock as Mutex;
+use crate::sync::WaitQueue;
 
 #[derive(Default)]
 pub struct Stdin {
+    /// Raw input from the console
     buf: Mutex<VecDeque<char>>,
-    pub pushed: Condvar,
+    pub pushed: WaitQueue,
+    /// Terminal settings of the console, shared with stdout
+    termios: Mutex<Termios>,
+    /// The line being edited in canonical mode
+    line: Mutex<Vec<u8>>,
+    /// Processed input which is not read yet
+    ready: Mutex<VecDeque<u8>>,
+    /// Process group receiving signals from the terminal
+    foreground_pgid: Mutex<usize>,
+    /// The session controlled by the terminal, which is init's
+    session: usize,
+    /// SIGINT from the terminal which is not sent yet
+    pending_interrupt: AtomicBool,
 }
 
 impl Stdin {
     pub fn push(&self, c: char) {
+        let termios = self.termios();
+        if termios.lflag & ISIG != 0 && c as u32 == termios.cc[VINTR] as u32 {
+            self.interrupt(&termios);
+            return;
+        }
         self.buf.lock().push_back(c);
-        self.pushed.notify_one();
+        self.pushed.wake_all();
     }
     pub fn pop(&self) -> char {
         #[cfg(feature = "board_k210")]
@@ -42,26 +62,160 @@ impl Stdin {
         }
     }
     pub fn can_read(&self) -> bool {
-        return self.buf.lock().len() > 0;
+        return self.ready.lock().len() > 0 || self.buf.lock().len() > 0;
+    }
+    pub fn termios(&self) -> Termios {
+        *self.termios.lock()
+    }
+    pub fn foreground_pgid(&self) -> usize {
+        *self.foreground_pgid.lock()
+    }
+    /// Whether processes in session `sid` have the console as controlling terminal
+    pub fn is_controlling(&self, sid: usize) -> bool {
+        self.session == sid
+    }
+
+    /// Discard pending input and send SIGINT to the foreground process group.
+    /// Called in interrupt context.
+    fn interrupt(&self, termios: &Termios) {
+        self.buf.lock().clear();
+        self.line.lock().clear();
+        self.ready.lock().clear();
+        if termios.lflag & ECHO != 0 {
+            print!("^C\n");
+        }
+        self.pending_interrupt.store(true, Ordering::Relaxed);
+        self.send_interrupt();
+    }
+
+    /// Send the pending SIGINT to the foreground process group.
+    /// Called in interrupt context, and on each timer tick to retry
+    /// while the process table is locked by the interrupted code.
+    pub fn send_interrupt(&self) {
+        if !self.pending_interrupt.load(Ordering::Relaxed) {
+            return;
+        }
+        let processes: Vec<_> = match PROCESSES.try_read() {
+            Some(table) => table.values().filter_map(|weak| weak.upgrade()).collect(),
+            None => return,
+        };
+        if !self.pending_interrupt.swap(false, Ordering::Relaxed) {
+            return;
+        }
+        let pgid = self.foreground_pgid();
+        for proc in processes.iter() {
+            let mut proc = proc.lock();
+            if proc.pgid == pgid {
+                proc.send_signal(SIGINT);
+            }
+        }
+    }
+
+    fn echo(&self, c: u8, termios: &Termios) {
+        if termios.lflag & ECHO != 0 {
+            print!("{}", c as char);
+        }
+    }
+
+    /// Move raw input to `ready` without editing
+    fn read_raw(&self, ready: &mut VecDeque<u8>, len: usize, termios: &Termios) {
+        let mut buf = self.buf.lock();
+        while ready.len() < len {
+            match buf.pop_front() {
+                Some(c) => {
+                    self.echo(c as u8, termios);
+                    ready.push_back(c as u8);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Edit the line with raw input, and move it to `ready` once finished.
+    /// Return false at end of file.
+    fn read_line(&self, ready: &mut VecDeque<u8>, termios: &Termios) -> bool {
+        let mut line = self.line.lock();
+        let mut buf = self.buf.lock();
+        while let Some(c) = buf.pop_front() {
+            let c = c as u8;
+            if c == termios.cc[VERASE] || c == b'\x08' {
+                if line.pop().is_some() && termios.lflag & ECHOE != 0 {
+                    self.echo(b'\x08', termios);
+                    self.echo(b' ', termios);
+                    self.echo(b'\x08', termios);
+                }
+            } else if c == termios.cc[VKILL] {
+                for _ in line.drain(..) {
+                    if termios.lflag & ECHOK != 0 {
+                        self.echo(b'\x08', termios);
+                        self.echo(b' ', termios);
+                        self.echo(b'\x08', termios);
+                    }
+                }
+            } else if c == termios.cc[VEOF] {
+                // an empty line means end of file
+                ready.extend(line.drain(..));
+                return !ready.is_empty();
+            } else if c == b'\n' {
+                if termios.lflag & ECHONL != 0 {
+                    print!("\n");
+                } else {
+                    self.echo(c, termios);
+                }
+                line.push(c);
+                ready.extend(line.drain(..));
+                break;
+            } else {
+                self.echo(c, termios);
+                line.push(c);
+            }
+        }
+        true
     }
 }
 
+/// Size reported by TIOCGWINSZ
+const CONSOLE_SIZE: WinSize = WinSize {
+    row: 24,
+    col: 80,
+    xpixel: 0,
+    ypixel: 0,
+};
+
 #[derive(Default)]
 pub struct Stdout;
 
 lazy_static! {
-    pub static ref STDIN: Arc<Stdin> = Arc::new(Stdin::default());
+    pub static ref STDIN: Arc<Stdin> = Arc::new(Stdin {
+        // init is the first process, leading the session and the process group
+        foreground_pgid: Mutex::new(INIT_PID),
+        session: INIT_PID,
+        ..Stdin::default()
+    });
     pub static ref STDOUT: Arc<Stdout> = Arc::new(Stdout::default());
 }
 
 impl INode for Stdin {
-    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if self.can_read() {
-            buf[0] = self.pop() as u8;
-            Ok(1)
-        } else {
-            Err(FsError::Again)
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let termios = self.termios();
+        let mut ready = self.ready.lock();
+        if ready.is_empty() {
+            if termios.lflag & ICANON != 0 {
+                if !self.read_line(&mut ready, &termios) {
+                    return Ok(0);
+                }
+            } else {
+                self.read_raw(&mut ready, buf.len(), &termios);
+            }
+        }
+        if ready.is_empty() {
+            return Err(FsError::Again);
+        }
+        let len = buf.len().min(ready.len());
+        for (byte, c) in buf.iter_mut().zip(ready.drain(..len)) {
+            *byte = c;
         }
+        Ok(len)
     }
     fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
         unimplemented!()
@@ -73,16 +227,33 @@ impl INode for Stdin {
             error: false,
         })
     }
+    /// `data` is checked by sys_ioctl
     fn io_control(&self, cmd: u32, data: usize) -> Result<()> {
         match cmd as usize {
-            TCGETS | TIOCGWINSZ | TIOCSPGRP => {
-                // pretend to be tty
+            TCGETS => {
+                unsafe { *(data as *mut Termios) = self.termios() };
+                Ok(())
+            }
+            TCSETS | TCSETSW | TCSETSF => {
+                if cmd as usize == TCSETSF {
+                    // discard pending input
+                    self.buf.lock().clear();
+                    self.line.lock().clear();
+                    self.ready.lock().clear();
+                }
+                *self.termios.lock() = unsafe { *(data as *const Termios) };
+                Ok(())
+            }
+            TIOCGWINSZ => {
+                unsafe { *(data as *mut WinSize) = CONSOLE_SIZE };
+                Ok(())
+            }
+            TIOCSPGRP => {
+                *self.foreground_pgid.lock() = unsafe { *(data as *const u32) } as usize;
                 Ok(())
             }
             TIOCGPGRP => {
-                // pretend to be have a tty process group
-                // TODO: verify pointer
-                unsafe { *(data as *mut u32) = 0 };
+                unsafe { *(data as *mut u32) = *self.foreground_pgid.lock() as u32 };
                 Ok(())
             }
             _ => Err(FsError::NotSupported),
@@ -112,19 +283,8 @@ impl INode for Stdout {
         })
     }
     fn io_control(&self, cmd: u32, data: usize) -> Result<()> {
-        match cmd as usize {
-            TCGETS | TIOCGWINSZ | TIOCSPGRP => {
-                // pretend to be tty
-                Ok(())
-            }
-            TIOCGPGRP => {
-                // pretend to be have a tty process group
-                // TODO: verify pointer
-                unsafe { *(data as *mut u32) = 0 };
-                Ok(())
-            }
-            _ => Err(FsError::NotSupported),
-        }
+        // stdin and stdout are the same terminal
+        STDIN.io_control(cmd, data)
     }
     fn as_any_ref(&self) -> &dyn Any {
         self
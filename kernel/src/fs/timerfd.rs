@@ -0,0 +1,215 @@
+//! Implement INode for timerfd
+//!
+//! Expirations are counted from the deadline whenever the timer is read or polled.
+//! The timer interrupt checks armed timers, and wakes up their readers through `PIPE_ACTIVITY`.
+
+use alloc::{
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::mem::size_of;
+
+use rcore_fs::vfs::*;
+
+use super::PIPE_ACTIVITY;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::trap::uptime_usec;
+
+struct TimerState {
+    /// Next expiration in usec of uptime, or None if disarmed
+    deadline: Option<u64>,
+    /// 0 for a one-shot timer
+    interval: u64,
+    /// Expirations since the last read
+    expirations: u64,
+}
+
+impl TimerState {
+    /// Count the expirations up to `now`, and return whether there are new ones
+    fn update(&mut self, now: u64) -> bool {
+        let deadline = match self.deadline {
+            Some(deadline) if deadline <= now => deadline,
+            _ => return false,
+        };
+        if self.interval == 0 {
+            self.expirations += 1;
+            self.deadline = None;
+        } else {
+            let count = (now - deadline) / self.interval + 1;
+            self.expirations += count;
+            self.deadline = Some(deadline + count * self.interval);
+        }
+        true
+    }
+}
+
+lazy_static! {
+    /// Armed timers, checked by the timer interrupt
+    static ref ARMED: Mutex<Vec<Weak<Mutex<TimerState>>>> = Mutex::new(Vec::new());
+}
+
+pub struct TimerFd {
+    /// The clock which absolute deadlines are relative to
+    pub clock: usize,
+    state: Arc<Mutex<TimerState>>,
+}
+
+impl TimerFd {
+    pub fn new(clock: usize) -> Self {
+        TimerFd {
+            clock,
+            state: Arc::new(Mutex::new(TimerState {
+                deadline: None,
+                interval: 0,
+                expirations: 0,
+            })),
+        }
+    }
+
+    /// Get the time until the next expiration and the interval in usec.
+    /// The time is 0 if the timer is disarmed.
+    pub fn get(&self) -> (u64, u64) {
+        let now = uptime_usec();
+        let mut state = self.state.lock();
+        state.update(now);
+        let remaining = state.deadline.map_or(0, |deadline| deadline - now);
+        (remaining, state.interval)
+    }
+
+    /// Arm the timer to expire at `deadline` in usec of uptime, and then every `interval` usec,
+    /// or disarm it if `deadline` is None. Return the previous setting as `get`.
+    pub fn set(&self, deadline: Option<u64>, interval: u64) -> (u64, u64) {
+        let old = self.get();
+        {
+            let mut state = self.state.lock();
+            state.deadline = deadline;
+            state.interval = interval;
+            state.expirations = 0;
+        }
+        if deadline.is_some() {
+            let mut armed = ARMED.lock();
+            let weak = Arc::downgrade(&self.state);
+            if !armed.iter().any(|other| other.ptr_eq(&weak)) {
+                armed.push(weak);
+            }
+        }
+        old
+    }
+}
+
+/// Count the expirations of armed timers, called by the timer interrupt
+pub fn timerfd_tick() {
+    let now = uptime_usec();
+    let mut fired = false;
+    ARMED.lock().retain(|weak| match weak.upgrade() {
+        Some(state) => {
+            let mut state = state.lock();
+            fired |= state.update(now);
+            state.deadline.is_some()
+        }
+        None => false,
+    });
+    if fired {
+        PIPE_ACTIVITY.wake_all();
+    }
+}
+
+impl INode for TimerFd {
+    /// Take the number of expirations since the last read
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < size_of::<u64>() {
+            return Err(FsError::InvalidParam);
+        }
+        let mut state = self.state.lock();
+        state.update(uptime_usec());
+        if state.expirations == 0 {
+            return Err(FsError::Again);
+        }
+        buf[..size_of::<u64>()].copy_from_slice(&state.expirations.to_ne_bytes());
+        state.expirations = 0;
+        Ok(size_of::<u64>())
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::InvalidParam)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        let mut state = self.state.lock();
+        state.update(uptime_usec());
+        Ok(PollStatus {
+            read: state.expirations > 0,
+            write: false,
+            error: false,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub mod test {
+    //! Counting expirations of timerfd
+
+    use super::*;
+
+    fn one_shot() {
+        let mut state = TimerState {
+            deadline: Some(100),
+            interval: 0,
+            expirations: 0,
+        };
+        assert!(!state.update(99));
+        assert!(state.update(100));
+        assert_eq!((state.deadline, state.expirations), (None, 1));
+        assert!(!state.update(1000));
+        assert_eq!(state.expirations, 1);
+    }
+
+    /// Missed expirations of a periodic timer are all counted
+    fn periodic() {
+        let mut state = TimerState {
+            deadline: Some(100),
+            interval: 50,
+            expirations: 0,
+        };
+        assert!(state.update(220));
+        assert_eq!((state.deadline, state.expirations), (Some(250), 3));
+        assert!(!state.update(249));
+        assert!(state.update(250));
+        assert_eq!((state.deadline, state.expirations), (Some(300), 4));
+    }
+
+    /// A read takes the expirations, and fails if there are none
+    fn set_and_read() {
+        let timer = TimerFd::new(0);
+        let mut buf = [0u8; 8];
+        let far = uptime_usec() + 1_000_000_000;
+        assert_eq!(timer.set(Some(far), 1000), (0, 0));
+        let (remaining, interval) = timer.get();
+        assert!(remaining > 0 && remaining <= 1_000_000_000);
+        assert_eq!(interval, 1000);
+        assert!(timer.read_at(0, &mut buf).is_err());
+        assert!(!timer.poll().unwrap().read);
+
+        // expire now, once
+        let (remaining, _) = timer.set(Some(uptime_usec()), 0);
+        assert!(remaining > 0);
+        assert!(timer.poll().unwrap().read);
+        assert!(timer.read_at(0, &mut buf[..4]).is_err());
+        assert_eq!(timer.read_at(0, &mut buf).unwrap(), 8);
+        assert_eq!(u64::from_ne_bytes(buf), 1);
+        assert!(timer.read_at(0, &mut buf).is_err());
+        assert_eq!(timer.get(), (0, 0));
+        assert!(timer.write_at(0, &buf).is_err());
+    }
+
+    pub fn test_all() {
+        one_shot();
+        periodic();
+        set_and_read();
+        println!("timerfd test end");
+    }
+}
@@ -0,0 +1,531 @@
+//! In-memory file system
+//!
+//! File data is kept in growable buffers and directory entries in maps.
+//! The bytes of file data are limited by the capacity of the file system,
+//! beyond which writes fail with `NoDeviceSpace`.
+
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::any::Any;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use rcore_fs::vfs::*;
+use spin::RwLock;
+
+use crate::syscall::TimeSpec;
+
+/// Capacity of a TmpFS mounted without the `size` option
+pub const DEFAULT_CAPACITY: usize = 4 * 1024 * 1024;
+
+const BLOCK_SIZE: usize = 4096;
+
+/// Device numbers of TmpFS instances
+static NEXT_DEV: AtomicUsize = AtomicUsize::new(0x100);
+
+pub struct TmpFS {
+    root: Arc<TmpINode>,
+    dev: usize,
+    /// Max bytes of file data
+    capacity: usize,
+    /// Bytes of file data
+    used: AtomicUsize,
+    next_id: AtomicUsize,
+}
+
+impl TmpFS {
+    pub fn new(capacity: usize) -> Arc<Self> {
+        let fs = Arc::new(TmpFS {
+            root: TmpINode::new(1, FileType::Dir, 0o777),
+            dev: NEXT_DEV.fetch_add(1, Ordering::Relaxed),
+            capacity,
+            used: AtomicUsize::new(0),
+            next_id: AtomicUsize::new(2),
+        });
+        {
+            let mut root = fs.root.inner.write();
+            root.fs = Arc::downgrade(&fs);
+            root.parent = Arc::downgrade(&fs.root);
+        }
+        fs
+    }
+
+    /// Create with mount options separated by ',', in which `size=<bytes>[k|m|g]` is known
+    pub fn with_options(options: &str) -> Result<Arc<Self>> {
+        let mut capacity = DEFAULT_CAPACITY;
+        for option in options.split(',').filter(|option| !option.is_empty()) {
+            if !option.starts_with("size=") {
+                return Err(FsError::InvalidParam);
+            }
+            let size = &option["size=".len()..];
+            let (number, unit) = match size.chars().last() {
+                Some('k') | Some('K') => (&size[..size.len() - 1], 1 << 10),
+                Some('m') | Some('M') => (&size[..size.len() - 1], 1 << 20),
+                Some('g') | Some('G') => (&size[..size.len() - 1], 1 << 30),
+                _ => (size, 1),
+            };
+            capacity = number
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| number.checked_mul(unit))
+                .ok_or(FsError::InvalidParam)?;
+        }
+        Ok(TmpFS::new(capacity))
+    }
+
+    /// Take up to `len` bytes of free space, and return the bytes taken
+    fn reserve(&self, len: usize) -> usize {
+        let mut used = self.used.load(Ordering::Relaxed);
+        loop {
+            let reserved = len.min(self.capacity.saturating_sub(used));
+            match self.used.compare_exchange_weak(
+                used,
+                used + reserved,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return reserved,
+                Err(current) => used = current,
+            }
+        }
+    }
+
+    fn release(&self, len: usize) {
+        self.used.fetch_sub(len, Ordering::Relaxed);
+    }
+}
+
+impl FileSystem for TmpFS {
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn root_inode(&self) -> Arc<dyn INode> {
+        self.root.clone()
+    }
+
+    fn info(&self) -> FsInfo {
+        let free = self
+            .capacity
+            .saturating_sub(self.used.load(Ordering::Relaxed));
+        FsInfo {
+            bsize: BLOCK_SIZE,
+            frsize: BLOCK_SIZE,
+            blocks: self.capacity / BLOCK_SIZE,
+            bfree: free / BLOCK_SIZE,
+            bavail: free / BLOCK_SIZE,
+            files: 0,
+            ffree: 0,
+            namemax: 255,
+        }
+    }
+}
+
+struct TmpINode {
+    id: usize,
+    type_: FileType,
+    inner: RwLock<TmpINodeInner>,
+}
+
+struct TmpINodeInner {
+    this: Weak<TmpINode>,
+    parent: Weak<TmpINode>,
+    fs: Weak<TmpFS>,
+    content: Vec<u8>,
+    children: BTreeMap<String, Arc<TmpINode>>,
+    mode: u16,
+    nlinks: usize,
+    uid: usize,
+    gid: usize,
+    rdev: usize,
+    atime: Timespec,
+    mtime: Timespec,
+    ctime: Timespec,
+}
+
+impl TmpINode {
+    fn new(id: usize, type_: FileType, mode: u16) -> Arc<Self> {
+        let now: Timespec = TimeSpec::get_epoch().into();
+        let inode = Arc::new(TmpINode {
+            id,
+            type_,
+            inner: RwLock::new(TmpINodeInner {
+                this: Weak::new(),
+                parent: Weak::new(),
+                fs: Weak::new(),
+                content: Vec::new(),
+                children: BTreeMap::new(),
+                mode,
+                nlinks: if type_ == FileType::Dir { 2 } else { 1 },
+                uid: 0,
+                gid: 0,
+                rdev: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+            }),
+        });
+        inode.inner.write().this = Arc::downgrade(&inode);
+        inode
+    }
+
+    fn fs_arc(&self) -> Arc<TmpFS> {
+        self.inner.read().fs()
+    }
+
+    fn has_content(&self) -> bool {
+        self.type_ == FileType::File || self.type_ == FileType::SymLink
+    }
+
+    /// Get `other` as an inode of the same file system
+    fn same_fs<'a>(&self, other: &'a Arc<dyn INode>) -> Result<&'a TmpINode> {
+        let other = other
+            .as_any_ref()
+            .downcast_ref::<TmpINode>()
+            .ok_or(FsError::NotSameFs)?;
+        if !Weak::ptr_eq(&self.inner.read().fs, &other.inner.read().fs) {
+            return Err(FsError::NotSameFs);
+        }
+        Ok(other)
+    }
+}
+
+impl TmpINodeInner {
+    fn fs(&self) -> Arc<TmpFS> {
+        self.fs.upgrade().unwrap()
+    }
+}
+
+impl Drop for TmpINode {
+    fn drop(&mut self) {
+        let inner = self.inner.read();
+        if let Some(fs) = inner.fs.upgrade() {
+            fs.release(inner.content.len());
+        }
+    }
+}
+
+impl INode for TmpINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if !self.has_content() {
+            return Err(FsError::NotFile);
+        }
+        let inner = self.inner.read();
+        if offset >= inner.content.len() {
+            return Ok(0);
+        }
+        let len = (inner.content.len() - offset).min(buf.len());
+        buf[..len].copy_from_slice(&inner.content[offset..offset + len]);
+        Ok(len)
+    }
+
+    /// Write as much as the free space allows
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        if !self.has_content() {
+            return Err(FsError::NotFile);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let mut inner = self.inner.write();
+        let old_len = inner.content.len();
+        let mut end = offset.checked_add(buf.len()).ok_or(FsError::InvalidParam)?;
+        if end > old_len {
+            let reserved = inner.fs().reserve(end - old_len);
+            end = old_len + reserved;
+            if end <= offset {
+                inner.fs().release(reserved);
+                return Err(FsError::NoDeviceSpace);
+            }
+            inner.content.resize(end, 0);
+        }
+        let len = end - offset;
+        inner.content[offset..offset + len].copy_from_slice(&buf[..len]);
+        inner.mtime = TimeSpec::get_epoch().into();
+        Ok(len)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let inner = self.inner.read();
+        let size = match self.type_ {
+            FileType::Dir => inner.children.len() + 2,
+            _ => inner.content.len(),
+        };
+        Ok(Metadata {
+            dev: inner.fs().dev,
+            inode: self.id,
+            size,
+            blk_size: BLOCK_SIZE,
+            blocks: (inner.content.len() + BLOCK_SIZE - 1) / BLOCK_SIZE,
+            atime: inner.atime,
+            mtime: inner.mtime,
+            ctime: inner.ctime,
+            type_: self.type_,
+            mode: inner.mode,
+            nlinks: inner.nlinks,
+            uid: inner.uid,
+            gid: inner.gid,
+            rdev: inner.rdev,
+        })
+    }
+
+    fn set_metadata(&self, metadata: &Metadata) -> Result<()> {
+        let mut inner = self.inner.write();
+        inner.mode = metadata.mode;
+        inner.uid = metadata.uid;
+        inner.gid = metadata.gid;
+        inner.rdev = metadata.rdev;
+        inner.atime = metadata.atime;
+        inner.mtime = metadata.mtime;
+        inner.ctime = metadata.ctime;
+        Ok(())
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&self, len: usize) -> Result<()> {
+        if !self.has_content() {
+            return Err(FsError::NotFile);
+        }
+        let mut inner = self.inner.write();
+        let old_len = inner.content.len();
+        let fs = inner.fs();
+        if len > old_len {
+            let reserved = fs.reserve(len - old_len);
+            if reserved < len - old_len {
+                fs.release(reserved);
+                return Err(FsError::NoDeviceSpace);
+            }
+        } else {
+            fs.release(old_len - len);
+        }
+        inner.content.resize(len, 0);
+        Ok(())
+    }
+
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<dyn INode>> {
+        if self.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        let mut inner = self.inner.write();
+        if name == "." || name == ".." || inner.children.contains_key(name) {
+            return Err(FsError::EntryExist);
+        }
+        let fs = inner.fs();
+        let id = fs.next_id.fetch_add(1, Ordering::Relaxed);
+        let child = TmpINode::new(id, type_, mode as u16);
+        {
+            let mut child_inner = child.inner.write();
+            child_inner.fs = Arc::downgrade(&fs);
+            child_inner.parent = inner.this.clone();
+        }
+        if type_ == FileType::Dir {
+            inner.nlinks += 1;
+        }
+        inner.children.insert(String::from(name), child.clone());
+        Ok(child)
+    }
+
+    fn link(&self, name: &str, other: &Arc<dyn INode>) -> Result<()> {
+        if self.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        let other_inode = self.same_fs(other)?;
+        if other_inode.type_ == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        let mut inner = self.inner.write();
+        if name == "." || name == ".." || inner.children.contains_key(name) {
+            return Err(FsError::EntryExist);
+        }
+        let mut other_inner = other_inode.inner.write();
+        let other_arc = other_inner.this.upgrade().unwrap();
+        other_inner.nlinks += 1;
+        inner.children.insert(String::from(name), other_arc);
+        Ok(())
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        if self.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        if name == "." || name == ".." {
+            return Err(FsError::DirNotEmpty);
+        }
+        let mut inner = self.inner.write();
+        let child = inner
+            .children
+            .get(name)
+            .ok_or(FsError::EntryNotFound)?
+            .clone();
+        let mut child_inner = child.inner.write();
+        if child.type_ == FileType::Dir {
+            if !child_inner.children.is_empty() {
+                return Err(FsError::DirNotEmpty);
+            }
+            inner.nlinks -= 1;
+            child_inner.nlinks = 0;
+        } else {
+            child_inner.nlinks -= 1;
+        }
+        inner.children.remove(name);
+        Ok(())
+    }
+
+    fn move_(&self, old_name: &str, target: &Arc<dyn INode>, new_name: &str) -> Result<()> {
+        let target = self.same_fs(target)?;
+        if target.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        if old_name == "." || old_name == ".." || new_name == "." || new_name == ".." {
+            return Err(FsError::IsDir);
+        }
+        if target.id == self.id {
+            let mut inner = self.inner.write();
+            if inner.children.contains_key(new_name) {
+                return Err(FsError::EntryExist);
+            }
+            let child = inner
+                .children
+                .remove(old_name)
+                .ok_or(FsError::EntryNotFound)?;
+            inner.children.insert(String::from(new_name), child);
+            return Ok(());
+        }
+        // lock the directories in the order of id
+        let (mut inner, mut target_inner) = if self.id < target.id {
+            let inner = self.inner.write();
+            (inner, target.inner.write())
+        } else {
+            let target_inner = target.inner.write();
+            (self.inner.write(), target_inner)
+        };
+        if target_inner.children.contains_key(new_name) {
+            return Err(FsError::EntryExist);
+        }
+        let child = inner
+            .children
+            .remove(old_name)
+            .ok_or(FsError::EntryNotFound)?;
+        if child.type_ == FileType::Dir {
+            // a directory can not be moved into itself
+            let mut ancestor = target_inner.parent.upgrade();
+            let mut is_inside = target.id == child.id;
+            while let Some(dir) = ancestor {
+                if dir.id == child.id {
+                    is_inside = true;
+                }
+                if dir.id == 1 || is_inside || dir.id == self.id {
+                    break;
+                }
+                ancestor = dir.inner.read().parent.upgrade();
+            }
+            if is_inside {
+                inner.children.insert(String::from(old_name), child);
+                return Err(FsError::InvalidParam);
+            }
+            child.inner.write().parent = target_inner.this.clone();
+            inner.nlinks -= 1;
+            target_inner.nlinks += 1;
+        }
+        target_inner.children.insert(String::from(new_name), child);
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        if self.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        let inner = self.inner.read();
+        let inode = match name {
+            "." => inner.this.upgrade(),
+            ".." => inner.parent.upgrade(),
+            _ => inner.children.get(name).cloned(),
+        };
+        match inode {
+            Some(inode) => Ok(inode),
+            None => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn get_entry(&self, id: usize) -> Result<String> {
+        if self.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        match id {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            _ => self
+                .inner
+                .read()
+                .children
+                .keys()
+                .nth(id - 2)
+                .cloned()
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.fs_arc()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+pub mod test {
+    //! The capacity of a TmpFS
+
+    use super::*;
+    use crate::syscall::SysError;
+
+    /// Writes fail with ENOSPC when the capacity is used up, and removing files frees space
+    fn write_until_full() {
+        let fs = TmpFS::with_options("size=8k").unwrap();
+        let root = fs.root_inode();
+        let file = root.create("file", FileType::File, 0o644).unwrap();
+        let chunk = [1u8; 3000];
+        let mut written = 0;
+        let error = loop {
+            match file.write_at(written, &chunk) {
+                Ok(len) => written += len,
+                Err(error) => break error,
+            }
+        };
+        assert_eq!(SysError::from(error), SysError::ENOSPC);
+        assert_eq!(written, 8 * 1024);
+        assert_eq!(fs.info().bfree, 0);
+        let other = root.create("other", FileType::File, 0o644).unwrap();
+        match other.resize(1) {
+            Err(FsError::NoDeviceSpace) => {}
+            _ => panic!("resize should not exceed the capacity"),
+        }
+        drop(file);
+        root.unlink("file").unwrap();
+        assert_eq!(other.write_at(0, &chunk).unwrap(), chunk.len());
+    }
+
+    pub fn test_all() {
+        write_until_full();
+        println!("tmpfs test end");
+    }
+}
@@ -1,72 +1,69 @@
+mod msg;
 mod semary;
+mod shm;
 
+pub use self::msg::*;
 pub use self::semary::*;
-use alloc::collections::BTreeMap;
-use alloc::sync::Arc;
+pub use self::shm::*;
+use crate::process::Credentials;
 
-/// Semaphore table in a process
-#[derive(Default)]
-pub struct SemProc {
-    /// Semaphore arrays
-    arrays: BTreeMap<SemId, Arc<SemArray>>,
-    /// Undo operations when process terminates
-    undos: BTreeMap<(SemId, SemNum), SemOp>,
-}
-
-/// Semaphore set identifier (in a process)
-type SemId = usize;
+/// Key of an IPC object which is never found by key
+pub const IPC_PRIVATE: usize = 0;
 
-/// Semaphore number (in an array)
-type SemNum = u16;
+// flags of shmget, msgget and semget
+pub const IPC_CREAT: usize = 0o1000;
+pub const IPC_EXCL: usize = 0o2000;
+/// Flag of msgsnd, msgrcv and semop to fail instead of blocking
+pub const IPC_NOWAIT: usize = 0o4000;
 
-/// Semaphore operation value
-type SemOp = i16;
+// commands of shmctl, msgctl and semctl
+pub const IPC_RMID: usize = 0;
+pub const IPC_SET: usize = 1;
+pub const IPC_STAT: usize = 2;
 
-impl SemProc {
-    /// Insert the `array` and return its ID
-    pub fn add(&mut self, array: Arc<SemArray>) -> SemId {
-        let id = self.get_free_id();
-        self.arrays.insert(id, array);
-        id
-    }
-    /// Get a free ID
-    fn get_free_id(&self) -> SemId {
-        (0..).find(|i| self.arrays.get(i).is_none()).unwrap()
-    }
-    /// Get an semaphore set by `id`
-    pub fn get(&self, id: SemId) -> Option<Arc<SemArray>> {
-        self.arrays.get(&id).map(|a| a.clone())
-    }
-    /// Add an undo operation
-    pub fn add_undo(&mut self, id: SemId, num: SemNum, op: SemOp) {
-        let old_val = *self.undos.get(&(id, num)).unwrap_or(&0);
-        let new_val = old_val - op;
-        self.undos.insert((id, num), new_val);
-    }
+/// Owner and permissions of an IPC object
+#[derive(Debug, Copy, Clone)]
+pub struct IpcPerm {
+    pub key: usize,
+    pub uid: u32,
+    pub gid: u32,
+    /// Creator user id
+    pub cuid: u32,
+    /// Creator group id
+    pub cgid: u32,
+    /// Lower 9 bits of the flags given on creation
+    pub mode: u16,
 }
 
-/// Fork the semaphore table. Clear undo info.
-impl Clone for SemProc {
-    fn clone(&self) -> Self {
-        SemProc {
-            arrays: self.arrays.clone(),
-            undos: BTreeMap::default(),
+impl IpcPerm {
+    pub fn new(key: usize, mode: u16, cred: &Credentials) -> Self {
+        IpcPerm {
+            key,
+            uid: cred.euid,
+            gid: cred.egid,
+            cuid: cred.euid,
+            cgid: cred.egid,
+            mode: mode & 0o777,
         }
     }
-}
 
-/// Auto perform semaphores undo on drop
-impl Drop for SemProc {
-    fn drop(&mut self) {
-        for (&(id, num), &op) in self.undos.iter() {
-            debug!("semundo: id: {}, num: {}, op: {}", id, num, op);
-            let sem_array = self.arrays[&id].clone();
-            let sem = &sem_array[num as usize];
-            match op {
-                1 => sem.release(),
-                0 => {}
-                _ => unimplemented!("Semaphore: semundo.(Not 1)"),
-            }
+    /// Whether `cred` is granted `access`, which is a combination of r (4), w (2) and x (1)
+    pub fn allowed(&self, cred: &Credentials, access: u16) -> bool {
+        if cred.is_privileged() {
+            return true;
         }
+        let mode = if cred.euid == self.uid || cred.euid == self.cuid {
+            self.mode >> 6
+        } else if cred.in_group(self.gid) || cred.in_group(self.cgid) {
+            self.mode >> 3
+        } else {
+            self.mode
+        };
+        access & !mode & 0o7 == 0
+    }
+
+    /// Whether `cred` may change or remove the object
+    pub fn is_owner(&self, cred: &Credentials) -> bool {
+        cred.is_privileged() || cred.euid == self.uid || cred.euid == self.cuid
     }
 }
@@ -0,0 +1,301 @@
+//! System V message queues
+//!
+//! A queue keeps typed messages in the order they are sent, up to a number of bytes.
+//! Blocked senders and receivers wait on the queue, and are woken up on any change.
+
+use super::IpcPerm;
+use crate::sync::Condvar;
+use crate::sync::SpinLock as Mutex;
+use crate::syscall::{epoch_sec, SysError};
+use alloc::{collections::BTreeMap, collections::VecDeque, sync::Arc, vec::Vec};
+use spin::RwLock;
+
+/// Max size of a message
+pub const MSGMAX: usize = 8192;
+/// Default max number of bytes in a queue
+pub const MSGMNB: usize = 16384;
+
+/// A System V message queue
+pub struct MsgQueue {
+    pub id: usize,
+    pub state: Mutex<MsgState>,
+    /// Notified when a message is sent or received, and when the queue is removed
+    pub changed: Condvar,
+}
+
+pub struct MsgState {
+    pub perm: IpcPerm,
+    messages: VecDeque<Message>,
+    /// Number of bytes in the queue
+    pub cbytes: usize,
+    /// Max number of bytes in the queue
+    pub qbytes: usize,
+    /// Pid of the last msgsnd
+    pub lspid: usize,
+    /// Pid of the last msgrcv
+    pub lrpid: usize,
+    pub stime: usize,
+    pub rtime: usize,
+    pub ctime: usize,
+    /// Removed by IPC_RMID, which fails the waiters
+    removed: bool,
+}
+
+impl MsgState {
+    /// Number of messages in the queue
+    pub fn qnum(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+struct Message {
+    mtype: isize,
+    data: Vec<u8>,
+}
+
+lazy_static! {
+    /// Queues by id
+    static ref QUEUES: RwLock<BTreeMap<usize, Arc<MsgQueue>>> = RwLock::new(BTreeMap::new());
+}
+
+impl MsgQueue {
+    /// Get the queue of `id`
+    pub fn get(id: usize) -> Option<Arc<Self>> {
+        QUEUES.read().get(&id).cloned()
+    }
+
+    /// Get the queue of `key`, or create one if not found and `create`.
+    /// Return the queue and whether it is created.
+    pub fn get_or_create(key: usize, create: bool, perm: IpcPerm) -> Option<(Arc<Self>, bool)> {
+        let mut queues = QUEUES.write();
+        if key != super::IPC_PRIVATE {
+            let found = queues
+                .values()
+                .find(|queue| queue.state.lock().perm.key == key);
+            if let Some(queue) = found {
+                return Some((queue.clone(), false));
+            }
+        }
+        if !create {
+            return None;
+        }
+        let id = (0..).find(|id| !queues.contains_key(id)).unwrap();
+        let queue = Arc::new(MsgQueue {
+            id,
+            state: Mutex::new(MsgState {
+                perm,
+                messages: VecDeque::new(),
+                cbytes: 0,
+                qbytes: MSGMNB,
+                lspid: 0,
+                lrpid: 0,
+                stime: 0,
+                rtime: 0,
+                ctime: epoch_sec(),
+                removed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        queues.insert(id, queue.clone());
+        Some((queue, true))
+    }
+
+    /// Remove the queue at once, discarding the messages and failing the waiters
+    pub fn remove(&self) {
+        QUEUES.write().remove(&self.id);
+        {
+            let mut state = self.state.lock();
+            state.removed = true;
+            state.messages.clear();
+            state.cbytes = 0;
+        }
+        self.changed.notify_all();
+    }
+
+    /// Append a message of `mtype` sent by process `pid`.
+    /// Return false if the queue has no room for it now,
+    /// or fail if it never fits in the queue.
+    pub fn try_send(&self, mtype: isize, data: &[u8], pid: usize) -> Result<bool, SysError> {
+        let mut state = self.state.lock();
+        if state.removed {
+            return Err(SysError::EIDRM);
+        }
+        if data.len() > state.qbytes {
+            return Err(SysError::EINVAL);
+        }
+        if state.cbytes + data.len() > state.qbytes {
+            return Ok(false);
+        }
+        state.messages.push_back(Message {
+            mtype,
+            data: data.to_vec(),
+        });
+        state.cbytes += data.len();
+        state.lspid = pid;
+        state.stime = epoch_sec();
+        drop(state);
+        self.changed.notify_all();
+        Ok(true)
+    }
+
+    /// Take the first message selected by `mtype` for process `pid`:
+    /// any message if 0, the ones of `mtype` if positive, or with `except` the others,
+    /// and the ones of the lowest type not above `|mtype|` if negative.
+    /// A message longer than `max_size` is truncated if `truncate`, or left in the queue.
+    /// Return None if no message is selected.
+    pub fn try_receive(
+        &self,
+        mtype: isize,
+        except: bool,
+        max_size: usize,
+        truncate: bool,
+        pid: usize,
+    ) -> Result<Option<(isize, Vec<u8>)>, SysError> {
+        let mut state = self.state.lock();
+        if state.removed {
+            return Err(SysError::EIDRM);
+        }
+        let selected = if mtype == 0 {
+            state.messages.iter().position(|_| true)
+        } else if mtype > 0 {
+            state
+                .messages
+                .iter()
+                .position(|message| (message.mtype == mtype) != except)
+        } else {
+            state
+                .messages
+                .iter()
+                .enumerate()
+                .filter(|(_, message)| message.mtype as usize <= mtype.wrapping_neg() as usize)
+                .min_by_key(|&(i, message)| (message.mtype, i))
+                .map(|(i, _)| i)
+        };
+        let index = match selected {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        if state.messages[index].data.len() > max_size && !truncate {
+            return Err(SysError::E2BIG);
+        }
+        let mut message = state.messages.remove(index).unwrap();
+        state.cbytes -= message.data.len();
+        state.lrpid = pid;
+        state.rtime = epoch_sec();
+        drop(state);
+        self.changed.notify_all();
+        message.data.truncate(max_size);
+        Ok(Some((message.mtype, message.data)))
+    }
+
+    /// Take a message as `try_receive`, waiting until one is selected.
+    /// Fail if the queue is removed, or with EINTR when `interrupted`.
+    pub fn receive(
+        &self,
+        mtype: isize,
+        except: bool,
+        max_size: usize,
+        truncate: bool,
+        pid: usize,
+        interrupted: impl Fn() -> bool,
+    ) -> Result<(isize, Vec<u8>), SysError> {
+        let receive = || self.try_receive(mtype, except, max_size, truncate, pid);
+        if let Some(message) = receive()? {
+            return Ok(message);
+        }
+        Condvar::wait_events(&[&self.changed], || {
+            match receive() {
+                Ok(Some(message)) => return Some(Ok(message)),
+                Err(err) => return Some(Err(err)),
+                Ok(None) => {}
+            }
+            if interrupted() {
+                return Some(Err(SysError::EINTR));
+            }
+            None
+        })
+    }
+}
+
+pub mod test {
+    //! Selecting messages by type, and removing a queue with a receiver blocked on it
+
+    use super::*;
+    use crate::ipc::IPC_PRIVATE;
+    use crate::process::Credentials;
+    use crate::thread;
+
+    fn create() -> Arc<MsgQueue> {
+        let perm = IpcPerm::new(IPC_PRIVATE, 0o600, &Credentials::root());
+        MsgQueue::get_or_create(IPC_PRIVATE, true, perm).unwrap().0
+    }
+
+    fn receive_type(queue: &MsgQueue, mtype: isize, except: bool) -> Option<isize> {
+        let message = queue.try_receive(mtype, except, MSGMAX, false, 1).unwrap();
+        message.map(|(mtype, _)| mtype)
+    }
+
+    /// 0 takes the first message, a positive type the first one of it
+    /// or with MSG_EXCEPT of another type, and a negative type the lowest up to its magnitude
+    fn select_by_type() {
+        let queue = create();
+        let send = |mtype| assert_eq!(queue.try_send(mtype, &[mtype as u8], 1), Ok(true));
+        for &mtype in [3, 1, 2, 3, 1].iter() {
+            send(mtype);
+        }
+        assert_eq!(receive_type(&queue, 0, false), Some(3));
+        assert_eq!(receive_type(&queue, 3, false), Some(3));
+        assert_eq!(receive_type(&queue, 3, false), None);
+        assert_eq!(receive_type(&queue, 1, true), Some(2));
+        assert_eq!(receive_type(&queue, -1, false), Some(1));
+        assert_eq!(receive_type(&queue, -1, false), Some(1));
+        assert_eq!(receive_type(&queue, -1, false), None);
+        send(4);
+        send(2);
+        // the oldest first among the lowest
+        let message = queue.try_receive(-3, false, MSGMAX, false, 1).unwrap();
+        assert_eq!(message, Some((2, vec![2])));
+        assert_eq!(receive_type(&queue, isize::min_value(), false), Some(2));
+        assert_eq!(receive_type(&queue, isize::min_value(), false), Some(4));
+        assert_eq!(queue.state.lock().qnum(), 0);
+        queue.remove();
+    }
+
+    /// A message longer than the buffer stays in the queue unless truncated
+    fn too_big() {
+        let queue = create();
+        assert_eq!(queue.try_send(1, &[1, 2, 3], 1), Ok(true));
+        assert_eq!(
+            queue.try_receive(0, false, 2, false, 1),
+            Err(SysError::E2BIG)
+        );
+        assert_eq!(
+            queue.try_receive(0, false, 2, true, 1),
+            Ok(Some((1, vec![1, 2])))
+        );
+        assert_eq!(queue.state.lock().cbytes, 0);
+        queue.remove();
+    }
+
+    /// IPC_RMID wakes up a blocked receiver with EIDRM.
+    /// It hangs if the receiver is not woken up.
+    fn remove_wakes_receiver() {
+        let queue = create();
+        let receiver = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.receive(0, false, MSGMAX, false, 1, || false).err())
+        };
+        thread::yield_now();
+        queue.remove();
+        let err = receiver.join().expect("receiver should finish");
+        assert_eq!(err, Some(SysError::EIDRM));
+        assert!(MsgQueue::get(queue.id).is_none());
+    }
+
+    pub fn test_all() {
+        select_by_type();
+        too_big();
+        remove_wakes_receiver();
+        println!("msg test end");
+    }
+}
@@ -1,49 +1,352 @@
-use crate::sync::Semaphore;
+//! System V semaphore sets
+//!
+//! The operations of a semop are performed atomically: if any of them would block,
+//! none is performed, and the caller waits on the set until it is changed.
+
+use super::IpcPerm;
+use crate::sync::Condvar;
 use crate::sync::SpinLock as Mutex;
-use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, sync::Weak, vec::Vec};
-use core::ops::Index;
+use crate::syscall::{epoch_sec, SysError};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
 use spin::RwLock;
 
+/// The max value of a semaphore
+pub const SEMVMX: i16 = 32767;
+
 /// A System V semaphore set
 pub struct SemArray {
-    key: usize,
-    sems: Vec<Semaphore>,
+    pub id: usize,
+    pub state: Mutex<SemState>,
+    /// Notified when any value changes, and when the set is removed
+    pub changed: Condvar,
 }
 
-impl Index<usize> for SemArray {
-    type Output = Semaphore;
-    fn index(&self, idx: usize) -> &Semaphore {
-        &self.sems[idx]
-    }
+pub struct SemState {
+    pub perm: IpcPerm,
+    pub sems: Vec<Sem>,
+    /// Time of the last semop
+    pub otime: usize,
+    pub ctime: usize,
+    /// Removed by IPC_RMID, which fails the waiters
+    removed: bool,
+}
+
+/// A semaphore in a set
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Sem {
+    pub value: i16,
+    /// Pid of the last operation
+    pub pid: usize,
+    /// Number of processes waiting for the value to increase
+    pub ncnt: usize,
+    /// Number of processes waiting for the value to become zero
+    pub zcnt: usize,
 }
 
 lazy_static! {
-    static ref KEY2SEM: RwLock<BTreeMap<usize, Weak<SemArray>>> = RwLock::new(BTreeMap::new());
+    /// Semaphore sets by id
+    static ref SEM_ARRAYS: RwLock<BTreeMap<usize, Arc<SemArray>>> = RwLock::new(BTreeMap::new());
 }
 
 impl SemArray {
-    /// Get the semaphore array with `key`.
-    /// If not exist, create a new one with `nsems` elements.
-    pub fn get_or_create(key: usize, nsems: usize, _flags: usize) -> Arc<Self> {
-        let mut key2sem = KEY2SEM.write();
-
-        // found in the map
-        if let Some(weak_array) = key2sem.get(&key) {
-            if let Some(array) = weak_array.upgrade() {
-                return array;
+    /// Get the semaphore set of `id`
+    pub fn get(id: usize) -> Option<Arc<Self>> {
+        SEM_ARRAYS.read().get(&id).cloned()
+    }
+
+    /// Get the semaphore set of `key`, or create one with `nsems` semaphores
+    /// if not found and `create`. Return the set and whether it is created.
+    pub fn get_or_create(
+        key: usize,
+        nsems: usize,
+        create: bool,
+        perm: IpcPerm,
+    ) -> Option<(Arc<Self>, bool)> {
+        let mut arrays = SEM_ARRAYS.write();
+        if key != super::IPC_PRIVATE {
+            let found = arrays
+                .values()
+                .find(|array| array.state.lock().perm.key == key);
+            if let Some(array) = found {
+                return Some((array.clone(), false));
             }
         }
-        // not found, create one
-        let mut semaphores = Vec::new();
-        for i in 0..nsems {
-            semaphores.push(Semaphore::new(0));
+        if !create {
+            return None;
         }
-        // insert to global map
+        let id = (0..).find(|id| !arrays.contains_key(id)).unwrap();
         let array = Arc::new(SemArray {
-            key,
-            sems: semaphores,
+            id,
+            state: Mutex::new(SemState {
+                perm,
+                sems: vec![Sem::default(); nsems],
+                otime: 0,
+                ctime: epoch_sec(),
+                removed: false,
+            }),
+            changed: Condvar::new(),
+        });
+        arrays.insert(id, array.clone());
+        Some((array, true))
+    }
+
+    /// Remove the set at once, failing the waiters
+    pub fn remove(&self) {
+        SEM_ARRAYS.write().remove(&self.id);
+        self.state.lock().removed = true;
+        self.changed.notify_all();
+    }
+
+    /// Number of semaphores in the set
+    pub fn nsems(&self) -> usize {
+        self.state.lock().sems.len()
+    }
+
+    /// Perform all operations `(num, op)` of process `pid`, or none of them.
+    /// Return the index of the operation which would block if not performed.
+    pub fn try_semop(&self, ops: &[(usize, i16)], pid: usize) -> Result<Option<usize>, SysError> {
+        let mut state = self.state.lock();
+        if state.removed {
+            return Err(SysError::EIDRM);
+        }
+        let mut values: Vec<i32> = state.sems.iter().map(|sem| sem.value as i32).collect();
+        for (i, &(num, op)) in ops.iter().enumerate() {
+            let value = &mut values[num];
+            if (op == 0 && *value != 0) || *value + (op as i32) < 0 {
+                return Ok(Some(i));
+            }
+            *value += op as i32;
+            if *value > SEMVMX as i32 {
+                return Err(SysError::ERANGE);
+            }
+        }
+        for (sem, value) in state.sems.iter_mut().zip(values) {
+            sem.value = value as i16;
+        }
+        for &(num, _) in ops.iter() {
+            state.sems[num].pid = pid;
+        }
+        state.otime = epoch_sec();
+        drop(state);
+        self.changed.notify_all();
+        Ok(None)
+    }
+
+    /// Perform all operations as `try_semop`, waiting until none of them blocks.
+    /// Fail if the set is removed, or with EINTR when `interrupted`.
+    pub fn semop(
+        &self,
+        ops: &[(usize, i16)],
+        pid: usize,
+        interrupted: impl Fn() -> bool,
+    ) -> Result<(), SysError> {
+        let mut blocked = match self.try_semop(ops, pid)? {
+            Some(i) => i,
+            None => return Ok(()),
+        };
+        self.count_waiter(ops[blocked], true);
+        let result = Condvar::wait_events(&[&self.changed], || {
+            match self.try_semop(ops, pid) {
+                Ok(None) => return Some(Ok(())),
+                Err(err) => return Some(Err(err)),
+                // count the waiter for the operation which blocks now
+                Ok(Some(i)) if i != blocked => {
+                    self.count_waiter(ops[blocked], false);
+                    self.count_waiter(ops[i], true);
+                    blocked = i;
+                }
+                Ok(Some(_)) => {}
+            }
+            if interrupted() {
+                return Some(Err(SysError::EINTR));
+            }
+            None
         });
-        key2sem.insert(key, Arc::downgrade(&array));
-        array
+        self.count_waiter(ops[blocked], false);
+        result
+    }
+
+    /// Count a waiter blocked by the operation `(num, op)`, or uncount it if not `waiting`
+    pub fn count_waiter(&self, (num, op): (usize, i16), waiting: bool) {
+        let mut state = self.state.lock();
+        let sem = &mut state.sems[num];
+        let count = if op == 0 {
+            &mut sem.zcnt
+        } else {
+            &mut sem.ncnt
+        };
+        if waiting {
+            *count += 1;
+        } else {
+            *count -= 1;
+        }
+    }
+
+    /// Set the semaphores from `start` to `values`, by process `pid`
+    pub fn set_values(&self, start: usize, values: &[i16], pid: usize) -> Result<(), SysError> {
+        if values.iter().any(|&value| value < 0) {
+            return Err(SysError::ERANGE);
+        }
+        let mut state = self.state.lock();
+        for (sem, &value) in state.sems[start..].iter_mut().zip(values) {
+            sem.value = value;
+            sem.pid = pid;
+        }
+        state.ctime = epoch_sec();
+        drop(state);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Add `adjustment` to semaphore `num`, when the process doing SEM_UNDO operations exits.
+    /// The value is kept in range instead of blocking.
+    fn adjust(&self, num: usize, adjustment: i32, pid: usize) {
+        let mut state = self.state.lock();
+        if state.removed {
+            return;
+        }
+        let sem = &mut state.sems[num];
+        sem.value = (sem.value as i32 + adjustment).max(0).min(SEMVMX as i32) as i16;
+        sem.pid = pid;
+        drop(state);
+        self.changed.notify_all();
+    }
+}
+
+/// Adjustments of a process to undo its SEM_UNDO operations on exit
+#[derive(Default)]
+pub struct SemProc {
+    /// Semaphore sets with adjustments, by id
+    arrays: BTreeMap<usize, Arc<SemArray>>,
+    /// Adjustments by (id, num)
+    undos: BTreeMap<(usize, usize), i32>,
+}
+
+impl SemProc {
+    /// Record the operation `op` on semaphore `num` of `array` to be undone
+    pub fn add_undo(&mut self, array: &Arc<SemArray>, num: usize, op: i16) {
+        let replaced = match self.arrays.get(&array.id) {
+            Some(old) => !Arc::ptr_eq(old, array),
+            None => false,
+        };
+        if replaced {
+            // the id of a removed set is taken by a new one
+            let id = array.id;
+            let stale: Vec<_> = self
+                .undos
+                .keys()
+                .filter(|key| key.0 == id)
+                .cloned()
+                .collect();
+            for key in stale {
+                self.undos.remove(&key);
+            }
+        }
+        *self.undos.entry((array.id, num)).or_insert(0) -= op as i32;
+        self.arrays.insert(array.id, array.clone());
+    }
+
+    /// Apply the adjustments for process `pid`, when it exits
+    pub fn undo(&mut self, pid: usize) {
+        for ((id, num), adjustment) in core::mem::replace(&mut self.undos, BTreeMap::new()) {
+            debug!(
+                "semundo: id: {}, num: {}, adjustment: {}",
+                id, num, adjustment
+            );
+            if adjustment != 0 {
+                self.arrays[&id].adjust(num, adjustment, pid);
+            }
+        }
+        self.arrays.clear();
+    }
+}
+
+/// The child of fork starts without adjustments
+impl Clone for SemProc {
+    fn clone(&self) -> Self {
+        SemProc::default()
+    }
+}
+
+pub mod test {
+    //! Mutual exclusion with a binary semaphore, and undoing SEM_UNDO operations on exit
+
+    use super::*;
+    use crate::ipc::IPC_PRIVATE;
+    use crate::process::Credentials;
+    use crate::thread;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    const ROUNDS: usize = 10;
+
+    fn create(nsems: usize) -> Arc<SemArray> {
+        let perm = IpcPerm::new(IPC_PRIVATE, 0o600, &Credentials::root());
+        SemArray::get_or_create(IPC_PRIVATE, nsems, true, perm)
+            .unwrap()
+            .0
+    }
+
+    fn value(array: &SemArray, num: usize) -> i16 {
+        array.state.lock().sems[num].value
+    }
+
+    /// Two processes taking a semaphore of value 1 are never in the critical section together.
+    /// It hangs if a waiter is not woken up.
+    fn binary_semaphore() {
+        let array = create(1);
+        array.set_values(0, &[1], 1).unwrap();
+        let inside = Arc::new(AtomicUsize::new(0));
+        let workers: Vec<_> = (1..=2)
+            .map(|pid| {
+                let array = array.clone();
+                let inside = inside.clone();
+                thread::spawn(move || {
+                    for _ in 0..ROUNDS {
+                        array.semop(&[(0, -1)], pid, || false).unwrap();
+                        assert_eq!(inside.fetch_add(1, Ordering::SeqCst), 0);
+                        thread::yield_now();
+                        inside.fetch_sub(1, Ordering::SeqCst);
+                        array.semop(&[(0, 1)], pid, || false).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().expect("worker should finish");
+        }
+        assert_eq!(value(&array, 0), 1);
+        assert_eq!(array.state.lock().sems[0].ncnt, 0);
+        array.remove();
+        assert_eq!(array.semop(&[(0, -1)], 1, || false), Err(SysError::EIDRM));
+    }
+
+    /// The SEM_UNDO operations of a process are reverted when it exits
+    fn undo_on_exit() {
+        let array = create(2);
+        array.set_values(0, &[1, 0], 1).unwrap();
+        let mut undo = SemProc::default();
+        let ops = [(0, -1), (1, 2)];
+        assert_eq!(array.try_semop(&ops, 1), Ok(None));
+        for &(num, op) in ops.iter() {
+            undo.add_undo(&array, num, op);
+        }
+        // the child of fork does not undo them
+        let mut child = undo.clone();
+        child.undo(2);
+        assert_eq!(value(&array, 0), 0);
+        assert_eq!(value(&array, 1), 2);
+        undo.undo(1);
+        assert_eq!(value(&array, 0), 1);
+        assert_eq!(value(&array, 1), 0);
+        // undone only once
+        undo.undo(1);
+        assert_eq!(value(&array, 0), 1);
+        array.remove();
+    }
+
+    pub fn test_all() {
+        binary_semaphore();
+        undo_on_exit();
+        println!("semaphore test end");
     }
 }
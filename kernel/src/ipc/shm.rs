@@ -0,0 +1,363 @@
+//! System V shared memory
+//!
+//! A segment is a set of zeroed frames, found by its id or key.
+//! It stays until it is removed by IPC_RMID and detached by all processes,
+//! and the frames are freed when no memory area maps them.
+
+use super::IpcPerm;
+use crate::memory::{
+    phys_to_virt, FrameAllocator, GlobalFrameAlloc, MemoryAttr, MemoryHandler, MemorySet,
+};
+use crate::sync::SpinLock as Mutex;
+use crate::syscall::epoch_sec;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::fmt;
+use rcore_memory::paging::PageTable;
+use rcore_memory::{VirtAddr, PAGE_SIZE};
+use spin::RwLock;
+
+/// A System V shared memory segment
+pub struct ShmSegment {
+    pub id: usize,
+    /// Size in bytes, as given to shmget
+    pub size: usize,
+    frames: Vec<usize>,
+    pub state: Mutex<ShmState>,
+}
+
+pub struct ShmState {
+    pub perm: IpcPerm,
+    /// Creator pid
+    pub cpid: usize,
+    /// Pid of the last shmat or shmdt
+    pub lpid: usize,
+    /// Number of attaches
+    pub nattch: usize,
+    pub atime: usize,
+    pub dtime: usize,
+    pub ctime: usize,
+    /// Removed by IPC_RMID, and destroyed when the last attach is detached
+    pub removed: bool,
+}
+
+lazy_static! {
+    /// Segments by id
+    static ref SEGMENTS: RwLock<BTreeMap<usize, Arc<ShmSegment>>> = RwLock::new(BTreeMap::new());
+}
+
+impl ShmSegment {
+    /// Get the segment of `id`
+    pub fn get(id: usize) -> Option<Arc<Self>> {
+        SEGMENTS.read().get(&id).cloned()
+    }
+
+    /// Get the segment of `key`, or create one of `size` bytes if not found and `create`.
+    /// Return the segment and whether it is created.
+    pub fn get_or_create(
+        key: usize,
+        size: usize,
+        create: bool,
+        perm: IpcPerm,
+        pid: usize,
+    ) -> Option<(Arc<Self>, bool)> {
+        let mut segments = SEGMENTS.write();
+        if key != super::IPC_PRIVATE {
+            let found = segments
+                .values()
+                .find(|segment| segment.state.lock().perm.key == key);
+            if let Some(segment) = found {
+                return Some((segment.clone(), false));
+            }
+        }
+        if !create {
+            return None;
+        }
+        let frames = alloc_zeroed_frames((size + PAGE_SIZE - 1) / PAGE_SIZE)?;
+        let id = (0..).find(|id| !segments.contains_key(id)).unwrap();
+        let segment = Arc::new(ShmSegment {
+            id,
+            size,
+            frames,
+            state: Mutex::new(ShmState {
+                perm,
+                cpid: pid,
+                lpid: 0,
+                nattch: 0,
+                atime: 0,
+                dtime: 0,
+                ctime: epoch_sec(),
+                removed: false,
+            }),
+        });
+        segments.insert(id, segment.clone());
+        Some((segment, true))
+    }
+
+    /// Mark the segment removed, so that it can not be found by key any more.
+    /// It is destroyed when detached by all processes.
+    pub fn remove(&self) {
+        let mut state = self.state.lock();
+        state.removed = true;
+        state.perm.key = super::IPC_PRIVATE;
+        let destroy = state.nattch == 0;
+        drop(state);
+        if destroy {
+            SEGMENTS.write().remove(&self.id);
+        }
+    }
+
+    /// A handler mapping the frames from `start`
+    pub fn handler(self: &Arc<Self>, start: VirtAddr) -> ShmHandler {
+        ShmHandler {
+            segment: self.clone(),
+            start,
+        }
+    }
+
+    fn attach(&self, pid: usize) {
+        let mut state = self.state.lock();
+        state.nattch += 1;
+        state.lpid = pid;
+        state.atime = epoch_sec();
+    }
+
+    fn detach(&self, pid: usize) {
+        {
+            let mut state = self.state.lock();
+            state.lpid = pid;
+            state.dtime = epoch_sec();
+        }
+        self.release();
+    }
+
+    /// Drop an attach, and destroy the segment if it is removed and not attached any more
+    fn release(&self) {
+        let mut state = self.state.lock();
+        state.nattch -= 1;
+        let destroy = state.removed && state.nattch == 0;
+        drop(state);
+        if destroy {
+            SEGMENTS.write().remove(&self.id);
+        }
+    }
+}
+
+/// Allocate `count` frames filled with zero
+fn alloc_zeroed_frames(count: usize) -> Option<Vec<usize>> {
+    let mut frames = Vec::with_capacity(count);
+    for _ in 0..count {
+        match GlobalFrameAlloc.alloc() {
+            Some(frame) => {
+                unsafe {
+                    core::ptr::write_bytes(phys_to_virt(frame) as *mut u8, 0, PAGE_SIZE);
+                }
+                frames.push(frame);
+            }
+            None => {
+                for frame in frames {
+                    GlobalFrameAlloc.dealloc(frame);
+                }
+                return None;
+            }
+        }
+    }
+    Some(frames)
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        for &frame in self.frames.iter() {
+            GlobalFrameAlloc.dealloc(frame);
+        }
+    }
+}
+
+/// Maps the frames of a segment, which are shared instead of copied on fork
+#[derive(Clone)]
+pub struct ShmHandler {
+    segment: Arc<ShmSegment>,
+    /// Where the first frame is mapped
+    start: VirtAddr,
+}
+
+impl fmt::Debug for ShmHandler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShmHandler")
+            .field("id", &self.segment.id)
+            .field("start", &self.start)
+            .finish()
+    }
+}
+
+impl MemoryHandler for ShmHandler {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let frame = self.segment.frames[(addr - self.start) / PAGE_SIZE];
+        let entry = pt.map(addr, frame);
+        attr.apply(entry);
+    }
+
+    fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        pt.unmap(addr);
+    }
+
+    fn clone_map(
+        &self,
+        pt: &mut dyn PageTable,
+        _src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) {
+        self.map(pt, addr, attr);
+    }
+
+    fn handle_page_fault(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+}
+
+/// Segments attached by a process, by address
+#[derive(Default)]
+pub struct ShmProc {
+    attaches: BTreeMap<VirtAddr, Arc<ShmSegment>>,
+}
+
+impl ShmProc {
+    /// Record that `segment` is attached at `addr` by process `pid`
+    pub fn attach(&mut self, addr: VirtAddr, segment: Arc<ShmSegment>, pid: usize) {
+        segment.attach(pid);
+        self.attaches.insert(addr, segment);
+    }
+
+    /// Detach the segment at `addr` for process `pid`, and return it
+    pub fn detach(&mut self, addr: VirtAddr, pid: usize) -> Option<Arc<ShmSegment>> {
+        let segment = self.attaches.remove(&addr)?;
+        segment.detach(pid);
+        Some(segment)
+    }
+
+    /// Detach the segments of which no page is mapped in `vm` any more,
+    /// after a part of the address space is unmapped by process `pid`
+    pub fn detach_unmapped(&mut self, vm: &MemorySet, pid: usize) {
+        let unmapped: Vec<VirtAddr> = self
+            .attaches
+            .iter()
+            .filter(|(&addr, segment)| {
+                let end = addr + ((segment.size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1));
+                !vm.iter()
+                    .any(|area| area.name() == "shm" && area.is_overlap_with(addr, end))
+            })
+            .map(|(&addr, _)| addr)
+            .collect();
+        for addr in unmapped {
+            self.detach(addr, pid);
+        }
+    }
+
+    /// Detach all segments, when the address space is gone
+    pub fn clear(&mut self, pid: usize) {
+        for (_, segment) in core::mem::replace(&mut self.attaches, BTreeMap::new()) {
+            segment.detach(pid);
+        }
+    }
+}
+
+/// The child of fork inherits the attaches
+impl Clone for ShmProc {
+    fn clone(&self) -> Self {
+        for segment in self.attaches.values() {
+            segment.state.lock().nattch += 1;
+        }
+        ShmProc {
+            attaches: self.attaches.clone(),
+        }
+    }
+}
+
+impl Drop for ShmProc {
+    fn drop(&mut self) {
+        for segment in self.attaches.values() {
+            segment.release();
+        }
+    }
+}
+
+pub mod test {
+    //! Attaching, inheriting and removing segments
+
+    use super::*;
+    use crate::ipc::IPC_PRIVATE;
+    use crate::process::Credentials;
+
+    const KEY: usize = 0x5348_4d00;
+    const START: VirtAddr = 0x1000_0000;
+
+    fn create(key: usize, size: usize) -> Arc<ShmSegment> {
+        let perm = IpcPerm::new(key, 0o600, &Credentials::root());
+        let (segment, created) = ShmSegment::get_or_create(key, size, true, perm, 1).unwrap();
+        assert!(created);
+        segment
+    }
+
+    fn nattch(segment: &ShmSegment) -> usize {
+        segment.state.lock().nattch
+    }
+
+    /// The child of fork inherits the attach,
+    /// and a removed segment stays until it is detached by both
+    fn fork_and_remove() {
+        let segment = create(KEY, PAGE_SIZE);
+        let id = segment.id;
+        let mut parent = ShmProc::default();
+        parent.attach(START, segment.clone(), 1);
+        assert_eq!(nattch(&segment), 1);
+        let child = parent.clone();
+        assert_eq!(nattch(&segment), 2);
+
+        segment.remove();
+        let perm = IpcPerm::new(KEY, 0o600, &Credentials::root());
+        assert!(ShmSegment::get_or_create(KEY, PAGE_SIZE, false, perm, 1).is_none());
+        assert!(ShmSegment::get(id).is_some());
+        assert!(parent.detach(START, 1).is_some());
+        assert!(parent.detach(START, 1).is_none());
+        assert_eq!(nattch(&segment), 1);
+        assert!(ShmSegment::get(id).is_some());
+        // the child exits
+        drop(child);
+        assert_eq!(nattch(&segment), 0);
+        assert!(ShmSegment::get(id).is_none());
+    }
+
+    /// A segment is detached when its area is unmapped entirely, as by munmap or mmap MAP_FIXED
+    fn detach_unmapped() {
+        let segment = create(IPC_PRIVATE, 2 * PAGE_SIZE);
+        let mut vm = MemorySet::new();
+        let attr = MemoryAttr::default().user();
+        vm.push(
+            START,
+            START + 2 * PAGE_SIZE,
+            attr,
+            segment.handler(START),
+            "shm",
+        );
+        let mut shm = ShmProc::default();
+        shm.attach(START, segment.clone(), 1);
+        vm.pop_with_split(START, START + PAGE_SIZE);
+        shm.detach_unmapped(&vm, 1);
+        assert_eq!(nattch(&segment), 1);
+        vm.pop_with_split(START + PAGE_SIZE, START + 2 * PAGE_SIZE);
+        shm.detach_unmapped(&vm, 1);
+        assert_eq!(nattch(&segment), 0);
+        segment.remove();
+        assert!(ShmSegment::get(segment.id).is_none());
+    }
+
+    pub fn test_all() {
+        fork_and_remove();
+        detach_unmapped();
+        println!("shm test end");
+    }
+}
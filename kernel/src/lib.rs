@@ -39,6 +39,7 @@ pub mod memory;
 pub mod net;
 pub mod process;
 pub mod shell;
+pub mod signal;
 pub mod sync;
 pub mod syscall;
 pub mod trap;
@@ -61,6 +62,7 @@ pub mod arch;
 pub mod arch;
 
 pub fn kmain() -> ! {
+    process::SCHEDULER.add_cpu(arch::cpu::id());
     processor().run();
 }
 
@@ -13,14 +13,18 @@
 //! mipssim/malta(MIPS) -- 10MB
 
 use super::HEAP_ALLOCATOR;
+use crate::arch::interrupt::TrapFrame;
 pub use crate::arch::paging::*;
-use crate::consts::{KERNEL_OFFSET, MEMORY_OFFSET, PHYSICAL_MEMORY_OFFSET};
+use crate::consts::{KERNEL_OFFSET, MEMORY_OFFSET, PHYSICAL_MEMORY_OFFSET, USER_STACK_SIZE};
 use crate::process::current_thread;
 use crate::sync::SpinNoIrqLock;
+use alloc::collections::BTreeMap;
 use bitmap_allocator::BitAlloc;
 use buddy_system_allocator::Heap;
 use core::mem;
 use core::mem::size_of;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 use log::*;
 pub use rcore_memory::memory_set::{handler::*, MemoryArea, MemoryAttr};
@@ -51,6 +55,28 @@ pub type FrameAlloc = bitmap_allocator::BitAlloc4K;
 lazy_static! {
     pub static ref FRAME_ALLOCATOR: SpinNoIrqLock<FrameAlloc> =
         SpinNoIrqLock::new(FrameAlloc::default());
+    /// Reference counts of frames mapped by more than one page table, by frame number.
+    /// Frames not here have only one reference.
+    static ref SHARED_FRAMES: SpinNoIrqLock<BTreeMap<usize, usize>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+}
+
+/// Number of frames given to the frame allocator
+static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+/// Number of frames allocated by `GlobalFrameAlloc`
+static ALLOCATED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Give the frames in `range` to the frame allocator
+pub fn add_frames(range: Range<usize>) {
+    TOTAL_FRAMES.fetch_add(range.len(), Ordering::Relaxed);
+    FRAME_ALLOCATOR.lock().insert(range);
+}
+
+/// Return `(total, free)` number of frames
+pub fn frame_usage() -> (usize, usize) {
+    let total = TOTAL_FRAMES.load(Ordering::Relaxed);
+    let allocated = ALLOCATED_FRAMES.load(Ordering::Relaxed);
+    (total, total.saturating_sub(allocated))
 }
 
 /// Convert physical address to virtual address
@@ -81,15 +107,37 @@ impl FrameAllocator for GlobalFrameAlloc {
             .lock()
             .alloc()
             .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        if ret.is_some() {
+            ALLOCATED_FRAMES.fetch_add(1, Ordering::Relaxed);
+        }
         trace!("Allocate frame: {:x?}", ret);
         ret
         // TODO: try to swap out when alloc failed
     }
     fn dealloc(&self, target: usize) {
         trace!("Deallocate frame: {:x}", target);
-        FRAME_ALLOCATOR
-            .lock()
-            .dealloc((target - MEMORY_OFFSET) / PAGE_SIZE);
+        let id = (target - MEMORY_OFFSET) / PAGE_SIZE;
+        {
+            let mut shared = SHARED_FRAMES.lock();
+            if let Some(count) = shared.get_mut(&id) {
+                *count -= 1;
+                if *count == 1 {
+                    shared.remove(&id);
+                }
+                return;
+            }
+        }
+        FRAME_ALLOCATOR.lock().dealloc(id);
+        ALLOCATED_FRAMES.fetch_sub(1, Ordering::Relaxed);
+    }
+    fn share(&self, target: usize) -> bool {
+        let id = (target - MEMORY_OFFSET) / PAGE_SIZE;
+        *SHARED_FRAMES.lock().entry(id).or_insert(1) += 1;
+        true
+    }
+    fn ref_count(&self, target: usize) -> usize {
+        let id = (target - MEMORY_OFFSET) / PAGE_SIZE;
+        SHARED_FRAMES.lock().get(&id).cloned().unwrap_or(1)
     }
 }
 
@@ -127,13 +175,101 @@ impl Drop for KernelStack {
     }
 }
 
+/// Size of the top of user stack mapped at exec, which holds the init info
+pub const USER_STACK_INIT_SIZE: usize = PAGE_SIZE * 4;
+
+/// How far below the stack pointer a user access may grow the stack,
+/// enough for an instruction pushing many registers at once
+const STACK_GROWTH_WINDOW: usize = 0x10000 + 32 * size_of::<usize>();
+
 /// Handle page fault at `addr`.
 /// Return true to continue, false to halt.
-pub fn handle_page_fault(addr: usize) -> bool {
+pub fn handle_page_fault(tf: &TrapFrame, addr: usize) -> bool {
     debug!("page fault @ {:#x}", addr);
 
     let thread = unsafe { current_thread() };
-    thread.vm.lock().handle_page_fault(addr)
+    let mut vm = thread.vm.lock();
+    // the kernel may access user memory anywhere below the stack pointer
+    let sp = if tf.is_user() {
+        Some(tf.get_sp())
+    } else {
+        None
+    };
+    vm.handle_page_fault(addr) || grow_user_stack(&mut vm, addr, sp)
+}
+
+/// Extend the user stack down to `addr` if it is above the stack guard,
+/// and not far below `sp`, the stack pointer of the access if from user.
+/// Only the page of `addr` is allocated, the others when accessed.
+/// Return false if the stack can not grow to `addr`.
+pub fn grow_user_stack(vm: &mut MemorySet, addr: usize, sp: Option<usize>) -> bool {
+    if let Some(sp) = sp {
+        if addr.saturating_add(STACK_GROWTH_WINDOW) < sp {
+            return false;
+        }
+    }
+    let guard_end = vm
+        .iter()
+        .find(|area| area.name() == "user_stack_guard")
+        .map(|area| area.end_addr());
+    let stack_start = vm
+        .iter()
+        .find(|area| area.name() == "user_stack")
+        .map(|area| area.start_addr());
+    match (guard_end, stack_start) {
+        (Some(guard_end), Some(stack_start)) if addr >= guard_end && addr < stack_start => {
+            debug!("grow user stack down to {:#x}", addr);
+            vm.grow_down(stack_start, addr) && vm.handle_page_fault(addr)
+        }
+        _ => false,
+    }
+}
+
+/// The lowest address the user stack ending at `stack_top` may grow to,
+/// under the soft RLIMIT_STACK `limit`
+pub fn user_stack_bottom(stack_top: usize, limit: usize) -> usize {
+    let size = limit.min(USER_STACK_SIZE - PAGE_SIZE) & !(PAGE_SIZE - 1);
+    stack_top - size.max(USER_STACK_INIT_SIZE)
+}
+
+/// Move the stack guard for a new soft RLIMIT_STACK `limit`.
+/// The part the stack has grown to is kept,
+/// but it can not grow any more if it is beyond the limit.
+pub fn set_user_stack_limit(vm: &mut MemorySet, limit: usize) {
+    let guard = vm
+        .iter()
+        .find(|area| area.name() == "user_stack_guard")
+        .map(|area| (area.start_addr(), area.end_addr()));
+    let stack = vm
+        .iter()
+        .find(|area| area.name() == "user_stack")
+        .map(|area| (area.start_addr(), area.end_addr()));
+    let ((guard_start, guard_end), (stack_start, stack_end)) = match (guard, stack) {
+        (Some(guard), Some(stack)) => (guard, stack),
+        _ => return,
+    };
+    let new_guard_end = user_stack_bottom(stack_end, limit).min(stack_start);
+    let new_guard_start = new_guard_end - PAGE_SIZE;
+    if new_guard_end == guard_end {
+        return;
+    }
+    vm.pop(guard_start, guard_end);
+    // the stack can not grow over other areas anyway
+    let (start, end) = if vm
+        .iter()
+        .any(|area| area.is_overlap_with(new_guard_start, new_guard_end))
+    {
+        (guard_start, guard_end)
+    } else {
+        (new_guard_start, new_guard_end)
+    };
+    vm.push(
+        start,
+        end,
+        MemoryAttr::default().user(),
+        Guard,
+        "user_stack_guard",
+    );
 }
 
 pub fn init_heap() {
@@ -204,3 +340,183 @@ pub fn copy_from_user<T>(addr: *const T) -> Option<T> {
         _ => None,
     }
 }
+
+/// Copy `len` bytes from `src` to `dst`, one byte at a time.
+/// Return 0, or 1 from `read_user_fixup` if a page fault can not be handled.
+///
+/// The loop is written in assembly so that no instruction touching user memory,
+/// such as a call to memcpy, is placed outside the `.text.copy_user` section.
+/// It keeps no stack frame, so the fixup returns to the caller directly.
+#[naked]
+#[inline(never)]
+#[link_section = ".text.copy_user"]
+unsafe extern "C" fn copy_user_bytes(_dst: *mut u8, _src: *const u8, _len: usize) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    asm!("
+        mov rcx, rdx
+        rep movsb
+        xor eax, eax
+        ret"
+        : : : : "intel" "volatile" );
+    #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+    asm!("
+    1:  beqz a2, 2f
+        lb t0, 0(a1)
+        sb t0, 0(a0)
+        addi a0, a0, 1
+        addi a1, a1, 1
+        addi a2, a2, -1
+        j 1b
+    2:  li a0, 0
+        ret"
+        : : : : "volatile" );
+    #[cfg(target_arch = "aarch64")]
+    asm!("
+    1:  cbz x2, 2f
+        ldrb w3, [x1], #1
+        strb w3, [x0], #1
+        sub x2, x2, #1
+        b 1b
+    2:  mov x0, #0
+        ret"
+        : : : : "volatile" );
+    #[cfg(target_arch = "mips")]
+    asm!("
+        .set noreorder
+    1:  beqz $$a2, 2f
+        nop
+        lb $$t0, 0($$a1)
+        sb $$t0, 0($$a0)
+        addiu $$a0, $$a0, 1
+        addiu $$a1, $$a1, 1
+        b 1b
+        addiu $$a2, $$a2, -1
+    2:  jr $$ra
+        move $$v0, $$zero
+        .set reorder"
+        : : : : "volatile" );
+    core::hint::unreachable_unchecked()
+}
+
+/// Copy `dst.len()` elements from user `src`.
+/// Return false if `src` is not in user space, or a page fault can not be handled.
+pub fn copy_from_user_array<T: Copy>(dst: &mut [T], src: *const T) -> bool {
+    if dst.is_empty() {
+        return true;
+    }
+    let len = dst.len() * size_of::<T>();
+    if !access_ok(src as usize, len) {
+        return false;
+    }
+    unsafe { copy_user_bytes(dst.as_mut_ptr() as *mut u8, src as *const u8, len) == 0 }
+}
+
+/// Copy `src` to user `dst`.
+/// Return false if `dst` is not in user space, or a page fault can not be handled.
+pub fn copy_to_user<T: Copy>(dst: *mut T, src: &T) -> bool {
+    copy_to_user_array(dst, core::slice::from_ref(src))
+}
+
+/// Copy all elements of `src` to user `dst`.
+/// Return false if `dst` is not in user space, or a page fault can not be handled.
+pub fn copy_to_user_array<T: Copy>(dst: *mut T, src: &[T]) -> bool {
+    if src.is_empty() {
+        return true;
+    }
+    let len = src.len() * size_of::<T>();
+    if !access_ok(dst as usize, len) {
+        return false;
+    }
+    unsafe { copy_user_bytes(dst as *mut u8, src.as_ptr() as *const u8, len) == 0 }
+}
+
+pub mod test {
+    //! Lazy growth of the user stack
+
+    use super::*;
+
+    const STACK_TOP: usize = 0x1000_0000;
+
+    /// A stack like the one made at exec, which may grow to `limit` bytes
+    fn setup(limit: usize) -> MemorySet {
+        let mut vm = MemorySet::new();
+        let bottom = user_stack_bottom(STACK_TOP, limit);
+        vm.push(
+            bottom - PAGE_SIZE,
+            bottom,
+            MemoryAttr::default().user(),
+            Guard,
+            "user_stack_guard",
+        );
+        vm.push(
+            STACK_TOP - USER_STACK_INIT_SIZE,
+            STACK_TOP,
+            MemoryAttr::default().user(),
+            Delay::new(GlobalFrameAlloc),
+            "user_stack",
+        );
+        vm
+    }
+
+    fn is_present(vm: &mut MemorySet, addr: usize) -> bool {
+        vm.get_page_table_mut()
+            .get_entry(addr)
+            .map_or(false, |entry| entry.present())
+    }
+
+    /// Only the faulting page is allocated, if it is near the stack pointer of user
+    fn grow_near_sp() {
+        let mut vm = setup(USER_STACK_SIZE);
+        let sp = STACK_TOP - USER_STACK_INIT_SIZE;
+        let addr = sp - PAGE_SIZE;
+        assert!(grow_user_stack(&mut vm, addr, Some(sp)));
+        assert!(is_present(&mut vm, addr));
+        let addr = addr - PAGE_SIZE * 2;
+        let far_sp = addr + STACK_GROWTH_WINDOW + PAGE_SIZE;
+        assert!(!grow_user_stack(&mut vm, addr, Some(far_sp)));
+        // the kernel may access it
+        assert!(grow_user_stack(&mut vm, addr, None));
+        assert!(!is_present(&mut vm, addr + PAGE_SIZE));
+    }
+
+    /// The stack grows until the limit, which takes effect when changed
+    fn grow_to_limit() {
+        let limit = PAGE_SIZE * 8;
+        let bottom = STACK_TOP - limit;
+        let mut vm = setup(limit);
+        assert!(grow_user_stack(&mut vm, bottom, None));
+        assert!(!grow_user_stack(&mut vm, bottom - 1, None));
+        set_user_stack_limit(&mut vm, limit * 2);
+        assert!(grow_user_stack(&mut vm, bottom - 1, None));
+        // the grown part is kept, but it can not grow any more
+        set_user_stack_limit(&mut vm, limit);
+        assert!(is_present(&mut vm, bottom - 1));
+        assert!(!grow_user_stack(&mut vm, bottom - PAGE_SIZE - 1, None));
+    }
+
+    /// The resident set is counted as pages are brought in and dropped
+    fn count_resident() {
+        let mut vm = setup(USER_STACK_SIZE);
+        assert_eq!(vm.resident_pages(), 0);
+        vm.prefault(STACK_TOP - PAGE_SIZE * 2, STACK_TOP);
+        assert_eq!(vm.resident_pages(), 2);
+        let addr = STACK_TOP - USER_STACK_INIT_SIZE - 1;
+        assert!(grow_user_stack(&mut vm, addr, None));
+        assert_eq!(vm.resident_pages(), 3);
+        vm.discard(STACK_TOP - PAGE_SIZE, STACK_TOP);
+        assert_eq!(vm.resident_pages(), 2);
+        // a forked one shares the pages, which are resident in both
+        let forked = vm.clone();
+        assert_eq!(forked.resident_pages(), 2);
+        vm.pop_with_split(STACK_TOP - PAGE_SIZE * 2, STACK_TOP);
+        assert_eq!(vm.resident_pages(), 1);
+        assert_eq!(forked.resident_pages(), 2);
+    }
+
+    pub fn test_all() {
+        grow_near_sp();
+        grow_to_limit();
+        count_resident();
+        println!("user stack test end");
+    }
+}
@@ -1,5 +1,7 @@
 mod structs;
 mod test;
+mod unix;
 
 pub use self::structs::*;
 pub use self::test::server;
+pub use self::unix::*;
@@ -5,6 +5,7 @@ use crate::syscall::*;
 use crate::util;
 use alloc::boxed::Box;
 use alloc::fmt::Debug;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
@@ -48,6 +49,8 @@ pub enum Endpoint {
     Ip(IpEndpoint),
     LinkLevel(LinkLevelEndpoint),
     Netlink(NetlinkEndpoint),
+    /// Path of a unix domain socket, empty if unnamed
+    Unix(String),
 }
 
 /// Common methods that a socket must have
@@ -59,10 +62,10 @@ pub trait Socket: Send + Sync + Debug {
     fn bind(&mut self, _endpoint: Endpoint) -> SysResult {
         Err(SysError::EINVAL)
     }
-    fn listen(&mut self) -> SysResult {
+    fn listen(&mut self, _backlog: usize) -> SysResult {
         Err(SysError::EINVAL)
     }
-    fn shutdown(&self) -> SysResult {
+    fn shutdown(&self, _how: usize) -> SysResult {
         Err(SysError::EINVAL)
     }
     fn accept(&mut self) -> Result<(Box<dyn Socket>, Endpoint), SysError> {
@@ -78,10 +81,26 @@ pub trait Socket: Send + Sync + Debug {
         warn!("setsockopt is unimplemented");
         Ok(0)
     }
+    /// Get the value of an integer option
+    fn getsockopt(&self, level: usize, opt: usize) -> SysResult {
+        match (level, opt) {
+            (SOL_SOCKET, SO_SNDBUF) => Ok(TCP_SENDBUF),
+            (SOL_SOCKET, SO_RCVBUF) => Ok(TCP_RECVBUF),
+            // errors are reported by the failing call, none is left pending
+            (SOL_SOCKET, SO_ERROR) => Ok(0),
+            _ => Err(SysError::ENOPROTOOPT),
+        }
+    }
     fn ioctl(&mut self, _request: usize, _arg1: usize, _arg2: usize, _arg3: usize) -> SysResult {
         warn!("ioctl is unimplemented for this socket");
         Ok(0)
     }
+    fn nonblock(&self) -> bool {
+        false
+    }
+    fn set_nonblock(&self, _nonblock: bool) {
+        warn!("nonblock is unimplemented for this socket");
+    }
     fn box_clone(&self) -> Box<dyn Socket>;
 }
 
@@ -298,7 +317,7 @@ impl Socket for TcpSocketState {
         }
     }
 
-    fn listen(&mut self) -> SysResult {
+    fn listen(&mut self, _backlog: usize) -> SysResult {
         if self.is_listening {
             // it is ok to listen twice
             return Ok(0);
@@ -320,10 +339,13 @@ impl Socket for TcpSocketState {
         }
     }
 
-    fn shutdown(&self) -> SysResult {
-        let mut sockets = SOCKETS.lock();
-        let mut socket = sockets.get::<TcpSocket>(self.handle.0);
-        socket.close();
+    fn shutdown(&self, how: usize) -> SysResult {
+        // smoltcp can only close the sending half
+        if how != SHUT_RD {
+            let mut sockets = SOCKETS.lock();
+            let mut socket = sockets.get::<TcpSocket>(self.handle.0);
+            socket.close();
+        }
         Ok(0)
     }
 
@@ -1094,6 +1116,13 @@ fn poll_ifaces() {
     }
 }
 
+/// Further receptions will be disallowed
+pub const SHUT_RD: usize = 0;
+/// Further transmissions will be disallowed
+pub const SHUT_WR: usize = 1;
+/// Further receptions and transmissions will be disallowed
+pub const SHUT_RDWR: usize = 2;
+
 pub const TCP_SENDBUF: usize = 512 * 1024; // 512K
 pub const TCP_RECVBUF: usize = 512 * 1024; // 512K
 
@@ -0,0 +1,477 @@
+//! Unix domain stream sockets
+
+use super::{Endpoint, Socket, SHUT_RD, SHUT_WR};
+use crate::drivers::SOCKET_ACTIVITY;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::*;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use core::cmp::min;
+
+/// Max number of pending connections of a listening socket
+const UNIX_MAX_BACKLOG: usize = 128;
+/// Default size of the buffer in each direction of a connection
+const UNIX_BUF_SIZE: usize = 64 * 1024; // 64K
+/// Bounds of buffer size set by SO_SNDBUF and SO_RCVBUF
+const UNIX_MIN_BUF_SIZE: usize = 4 * 1024; // 4K
+const UNIX_MAX_BUF_SIZE: usize = 4 * 1024 * 1024; // 4M
+
+lazy_static! {
+    /// Bound unix sockets, indexed by path.
+    ///
+    /// Entries are weak, so a path becomes free again once
+    /// every descriptor of its socket is closed.
+    static ref UNIX_SOCKETS: Mutex<BTreeMap<String, Weak<Mutex<UnixSocketInner>>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Unix domain stream socket.
+/// Clones (dup, fork) share the same underlying socket.
+#[derive(Debug, Clone)]
+pub struct UnixSocketState {
+    inner: Arc<Mutex<UnixSocketInner>>,
+}
+
+#[derive(Debug)]
+struct UnixSocketInner {
+    path: Option<String>,
+    status: UnixStatus,
+    nonblock: bool,
+    sndbuf: usize,
+    rcvbuf: usize,
+}
+
+/// Bytes flowing in one direction of a connection
+#[derive(Debug)]
+struct UnixBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+    /// the writing end is closed, readers get EOF after draining
+    write_closed: bool,
+    /// the reading end is closed, writers get EPIPE
+    read_closed: bool,
+    /// the connection is dropped by the listener before accepted,
+    /// left pending for SO_ERROR of the connecting end
+    refused: bool,
+}
+
+type SharedUnixBuffer = Arc<Mutex<UnixBuffer>>;
+
+impl UnixBuffer {
+    fn new_shared(capacity: usize) -> SharedUnixBuffer {
+        Arc::new(Mutex::new(UnixBuffer {
+            data: VecDeque::new(),
+            capacity,
+            write_closed: false,
+            read_closed: false,
+            refused: false,
+        }))
+    }
+}
+
+#[derive(Debug)]
+enum UnixStatus {
+    Idle,
+    Listening {
+        /// server ends of connections waiting for accept()
+        backlog: VecDeque<UnixSocketState>,
+        max_backlog: usize,
+    },
+    Connected {
+        rx: SharedUnixBuffer,
+        tx: SharedUnixBuffer,
+        peer_path: Option<String>,
+    },
+}
+
+impl Drop for UnixSocketInner {
+    fn drop(&mut self) {
+        match &self.status {
+            UnixStatus::Connected { rx, tx, .. } => {
+                rx.lock().read_closed = true;
+                tx.lock().write_closed = true;
+                // wake up the peer
+                SOCKET_ACTIVITY.wake_all();
+            }
+            UnixStatus::Listening { backlog, .. } => {
+                // the connections are dropped with the backlog
+                for socket in backlog.iter() {
+                    if let UnixStatus::Connected { rx, .. } = &socket.inner.lock().status {
+                        rx.lock().refused = true;
+                    }
+                }
+            }
+            UnixStatus::Idle => {}
+        }
+    }
+}
+
+impl UnixSocketState {
+    pub fn new() -> Self {
+        UnixSocketState {
+            inner: Arc::new(Mutex::new(UnixSocketInner {
+                path: None,
+                status: UnixStatus::Idle,
+                nonblock: false,
+                sndbuf: UNIX_BUF_SIZE,
+                rcvbuf: UNIX_BUF_SIZE,
+            })),
+        }
+    }
+
+    /// Create a pair of connected unnamed sockets
+    pub fn new_pair() -> (Self, Self) {
+        let a_to_b = UnixBuffer::new_shared(UNIX_BUF_SIZE);
+        let b_to_a = UnixBuffer::new_shared(UNIX_BUF_SIZE);
+        let a = UnixSocketState::new_connected(None, None, b_to_a.clone(), a_to_b.clone());
+        let b = UnixSocketState::new_connected(None, None, a_to_b, b_to_a);
+        (a, b)
+    }
+
+    /// Create one end of an established connection
+    fn new_connected(
+        path: Option<String>,
+        peer_path: Option<String>,
+        rx: SharedUnixBuffer,
+        tx: SharedUnixBuffer,
+    ) -> Self {
+        let sndbuf = tx.lock().capacity;
+        let rcvbuf = rx.lock().capacity;
+        UnixSocketState {
+            inner: Arc::new(Mutex::new(UnixSocketInner {
+                path,
+                status: UnixStatus::Connected { rx, tx, peer_path },
+                nonblock: false,
+                sndbuf,
+                rcvbuf,
+            })),
+        }
+    }
+
+    /// Get both buffers and the nonblock flag of a connected socket
+    fn connection(&self) -> Result<(SharedUnixBuffer, SharedUnixBuffer, bool), SysError> {
+        let inner = self.inner.lock();
+        match &inner.status {
+            UnixStatus::Connected { rx, tx, .. } => Ok((rx.clone(), tx.clone(), inner.nonblock)),
+            _ => Err(SysError::ENOTCONN),
+        }
+    }
+}
+
+impl Socket for UnixSocketState {
+    fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
+        let endpoint = self
+            .remote_endpoint()
+            .unwrap_or(Endpoint::Unix(String::new()));
+        let (rx, _, nonblock) = match self.connection() {
+            Ok(connection) => connection,
+            Err(err) => return (Err(err), endpoint),
+        };
+        if data.is_empty() {
+            return (Ok(0), endpoint);
+        }
+        let result = SOCKET_ACTIVITY.wait_interruptible(move || {
+            let mut rx = rx.lock();
+            if rx.read_closed {
+                // shutdown for reading
+                Some(Ok(0))
+            } else if !rx.data.is_empty() {
+                // return what we have, do not wait for a full buffer
+                let len = min(data.len(), rx.data.len());
+                for (dst, src) in data.iter_mut().zip(rx.data.drain(..len)) {
+                    *dst = src;
+                }
+                drop(rx);
+                SOCKET_ACTIVITY.wake_all();
+                Some(Ok(len))
+            } else if rx.write_closed {
+                // EOF
+                Some(Ok(0))
+            } else if nonblock {
+                Some(Err(SysError::EAGAIN))
+            } else {
+                None
+            }
+        });
+        (result, endpoint)
+    }
+
+    fn write(&self, data: &[u8], _sendto_endpoint: Option<Endpoint>) -> SysResult {
+        let (_, tx, nonblock) = self.connection()?;
+        if data.is_empty() {
+            return Ok(0);
+        }
+        SOCKET_ACTIVITY.wait_interruptible(move || {
+            let mut tx = tx.lock();
+            if tx.read_closed || tx.write_closed {
+                return Some(Err(SysError::EPIPE));
+            }
+            let space = tx.capacity.saturating_sub(tx.data.len());
+            if space > 0 {
+                let len = min(data.len(), space);
+                tx.data.extend(&data[..len]);
+                drop(tx);
+                SOCKET_ACTIVITY.wake_all();
+                Some(Ok(len))
+            } else if nonblock {
+                Some(Err(SysError::EAGAIN))
+            } else {
+                None
+            }
+        })
+    }
+
+    fn poll(&self) -> (bool, bool, bool) {
+        let inner = self.inner.lock();
+        match &inner.status {
+            // a new connection
+            UnixStatus::Listening { backlog, .. } => (!backlog.is_empty(), false, false),
+            UnixStatus::Connected { rx, tx, .. } => {
+                let rx = rx.lock();
+                let tx = tx.lock();
+                let input = !rx.data.is_empty() || rx.write_closed;
+                let output = tx.data.len() < tx.capacity || tx.read_closed;
+                (input, output, tx.read_closed)
+            }
+            UnixStatus::Idle => (false, false, false),
+        }
+    }
+
+    fn shutdown(&self, how: usize) -> SysResult {
+        let (rx, tx, _) = self.connection()?;
+        if how != SHUT_WR {
+            let mut rx = rx.lock();
+            rx.read_closed = true;
+            // discard incoming data
+            rx.data.clear();
+        }
+        if how != SHUT_RD {
+            tx.lock().write_closed = true;
+        }
+        SOCKET_ACTIVITY.wake_all();
+        Ok(0)
+    }
+
+    fn connect(&mut self, endpoint: Endpoint) -> SysResult {
+        let path = match endpoint {
+            Endpoint::Unix(path) => path,
+            _ => return Err(SysError::EINVAL),
+        };
+        let (local_path, nonblock, sndbuf, rcvbuf) = {
+            let inner = self.inner.lock();
+            match &inner.status {
+                UnixStatus::Idle => {}
+                UnixStatus::Connected { .. } => return Err(SysError::EISCONN),
+                UnixStatus::Listening { .. } => return Err(SysError::EINVAL),
+            }
+            (
+                inner.path.clone(),
+                inner.nonblock,
+                inner.sndbuf,
+                inner.rcvbuf,
+            )
+        };
+        let listener = UNIX_SOCKETS
+            .lock()
+            .get(&path)
+            .cloned()
+            .ok_or(SysError::ECONNREFUSED)?;
+
+        let rx = UnixBuffer::new_shared(rcvbuf);
+        let tx = UnixBuffer::new_shared(sndbuf);
+        let server =
+            UnixSocketState::new_connected(Some(path.clone()), local_path, tx.clone(), rx.clone());
+
+        // enqueue the connection request
+        SOCKET_ACTIVITY.wait_interruptible(|| {
+            let listener = match listener.upgrade() {
+                Some(listener) => listener,
+                None => return Some(Err(SysError::ECONNREFUSED)),
+            };
+            let mut inner = listener.lock();
+            match &mut inner.status {
+                UnixStatus::Listening {
+                    backlog,
+                    max_backlog,
+                } => {
+                    if backlog.len() < *max_backlog {
+                        backlog.push_back(server.clone());
+                        Some(Ok(()))
+                    } else if nonblock {
+                        Some(Err(SysError::EAGAIN))
+                    } else {
+                        None
+                    }
+                }
+                _ => Some(Err(SysError::ECONNREFUSED)),
+            }
+        })?;
+        SOCKET_ACTIVITY.wake_all();
+
+        // wait until accepted
+        if !nonblock {
+            SOCKET_ACTIVITY.wait_interruptible(|| {
+                let listener = match listener.upgrade() {
+                    Some(listener) => listener,
+                    None => return Some(Err(SysError::ECONNREFUSED)),
+                };
+                let inner = listener.lock();
+                match &inner.status {
+                    UnixStatus::Listening { backlog, .. } => {
+                        if backlog.iter().any(|s| Arc::ptr_eq(&s.inner, &server.inner)) {
+                            None
+                        } else {
+                            Some(Ok(()))
+                        }
+                    }
+                    _ => Some(Err(SysError::ECONNREFUSED)),
+                }
+            })?;
+        }
+
+        self.inner.lock().status = UnixStatus::Connected {
+            rx,
+            tx,
+            peer_path: Some(path),
+        };
+        Ok(0)
+    }
+
+    fn bind(&mut self, endpoint: Endpoint) -> SysResult {
+        let path = match endpoint {
+            Endpoint::Unix(path) => path,
+            _ => return Err(SysError::EINVAL),
+        };
+        let mut inner = self.inner.lock();
+        if inner.path.is_some() {
+            return Err(SysError::EINVAL);
+        }
+        let mut sockets = UNIX_SOCKETS.lock();
+        if let Some(socket) = sockets.get(&path) {
+            if socket.upgrade().is_some() {
+                return Err(SysError::EADDRINUSE);
+            }
+        }
+        sockets.insert(path.clone(), Arc::downgrade(&self.inner));
+        inner.path = Some(path);
+        Ok(0)
+    }
+
+    fn listen(&mut self, backlog: usize) -> SysResult {
+        let max_backlog = backlog.max(1).min(UNIX_MAX_BACKLOG);
+        let mut inner = self.inner.lock();
+        if inner.path.is_none() {
+            return Err(SysError::EINVAL);
+        }
+        match &mut inner.status {
+            UnixStatus::Idle => {
+                inner.status = UnixStatus::Listening {
+                    backlog: VecDeque::new(),
+                    max_backlog,
+                };
+            }
+            // it is ok to listen twice, only the backlog is updated
+            UnixStatus::Listening {
+                max_backlog: old, ..
+            } => *old = max_backlog,
+        }
+        info!("unix socket listening on {:?}", inner.path);
+        Ok(0)
+    }
+
+    fn accept(&mut self) -> Result<(Box<dyn Socket>, Endpoint), SysError> {
+        SOCKET_ACTIVITY.wait_interruptible(move || {
+            let mut inner = self.inner.lock();
+            match &mut inner.status {
+                UnixStatus::Listening { backlog, .. } => {
+                    let socket = backlog.pop_front()?;
+                    drop(inner);
+                    // wake up the connecting peer
+                    SOCKET_ACTIVITY.wake_all();
+                    let remote_endpoint = socket
+                        .remote_endpoint()
+                        .unwrap_or(Endpoint::Unix(String::new()));
+                    let socket: Box<dyn Socket> = Box::new(socket);
+                    Some(Ok((socket, remote_endpoint)))
+                }
+                _ => Some(Err(SysError::EINVAL)),
+            }
+        })
+    }
+
+    fn endpoint(&self) -> Option<Endpoint> {
+        let inner = self.inner.lock();
+        inner.path.clone().map(Endpoint::Unix)
+    }
+
+    fn remote_endpoint(&self) -> Option<Endpoint> {
+        let inner = self.inner.lock();
+        match &inner.status {
+            UnixStatus::Connected { peer_path, .. } => Some(Endpoint::Unix(
+                peer_path.clone().unwrap_or_else(String::new),
+            )),
+            _ => None,
+        }
+    }
+
+    fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        match (level, opt) {
+            (SOL_SOCKET, SO_SNDBUF) | (SOL_SOCKET, SO_RCVBUF) => {
+                if data.len() < 4 {
+                    return Err(SysError::EINVAL);
+                }
+                let size = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]) as usize;
+                let size = size.max(UNIX_MIN_BUF_SIZE).min(UNIX_MAX_BUF_SIZE);
+                let mut inner = self.inner.lock();
+                if opt == SO_SNDBUF {
+                    inner.sndbuf = size;
+                } else {
+                    inner.rcvbuf = size;
+                }
+                if let UnixStatus::Connected { rx, tx, .. } = &inner.status {
+                    let buffer = if opt == SO_SNDBUF { tx } else { rx };
+                    buffer.lock().capacity = size;
+                }
+                // writers may have more space now
+                SOCKET_ACTIVITY.wake_all();
+                Ok(0)
+            }
+            _ => Err(SysError::ENOPROTOOPT),
+        }
+    }
+
+    fn getsockopt(&self, level: usize, opt: usize) -> SysResult {
+        let inner = self.inner.lock();
+        match (level, opt) {
+            (SOL_SOCKET, SO_SNDBUF) => Ok(inner.sndbuf),
+            (SOL_SOCKET, SO_RCVBUF) => Ok(inner.rcvbuf),
+            // a nonblocking connect fails after it returns
+            (SOL_SOCKET, SO_ERROR) => match &inner.status {
+                UnixStatus::Connected { tx, .. } => {
+                    let refused = core::mem::replace(&mut tx.lock().refused, false);
+                    Ok(if refused {
+                        SysError::ECONNREFUSED as usize
+                    } else {
+                        0
+                    })
+                }
+                _ => Ok(0),
+            },
+            _ => Err(SysError::ENOPROTOOPT),
+        }
+    }
+
+    fn nonblock(&self) -> bool {
+        self.inner.lock().nonblock
+    }
+
+    fn set_nonblock(&self, nonblock: bool) {
+        self.inner.lock().nonblock = nonblock;
+    }
+
+    fn box_clone(&self) -> Box<dyn Socket> {
+        Box::new(self.clone())
+    }
+}
@@ -1,20 +1,34 @@
 use alloc::collections::btree_map::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
+use core::mem::size_of;
 use core::ptr::null;
 
+use crate::fs::fill_random;
+
 pub struct ProcInitInfo {
     pub args: Vec<String>,
     pub envs: Vec<String>,
     pub auxv: BTreeMap<u8, usize>,
+    /// The path given to exec, which AT_EXECFN points to
+    pub exec_path: String,
 }
 
 impl ProcInitInfo {
+    /// Build the initial stack below `stack_top` in the layout of System V ABI,
+    /// and return the stack pointer, which points to argc and is 16-byte aligned.
     pub unsafe fn push_at(&self, stack_top: usize) -> usize {
         let mut writer = StackWriter { sp: stack_top };
+        let mut auxv = self.auxv.clone();
         // from stack_top:
-        // program name
-        writer.push_str(&self.args[0]);
+        // program path
+        writer.push_str(&self.exec_path);
+        auxv.insert(AT_EXECFN, writer.sp);
+        // random bytes for the stack protector of libc
+        let mut random = [0u8; 16];
+        fill_random(&mut random);
+        writer.push_slice(&random);
+        auxv.insert(AT_RANDOM, writer.sp);
         // environment strings
         let envs: Vec<_> = self
             .envs
@@ -33,9 +47,13 @@ impl ProcInitInfo {
                 writer.sp
             })
             .collect();
+        // align argc to 16 bytes
+        let words = (auxv.len() + 1) * 2 + envs.len() + 1 + argv.len() + 1 + 1;
+        writer.sp -= writer.sp % size_of::<usize>();
+        writer.sp -= (writer.sp - words * size_of::<usize>()) % 16;
         // auxiliary vector entries
         writer.push_slice(&[null::<u8>(), null::<u8>()]);
-        for (&type_, &value) in self.auxv.iter() {
+        for (&type_, &value) in auxv.iter() {
             writer.push_slice(&[type_ as usize, value]);
         }
         // envionment pointers
@@ -76,3 +94,5 @@ pub const AT_PHNUM: u8 = 5;
 pub const AT_PAGESZ: u8 = 6;
 pub const AT_BASE: u8 = 7;
 pub const AT_ENTRY: u8 = 9;
+pub const AT_RANDOM: u8 = 25;
+pub const AT_EXECFN: u8 = 31;
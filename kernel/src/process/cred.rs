@@ -0,0 +1,177 @@
+//! Credentials of a process
+
+use alloc::vec::Vec;
+
+use crate::syscall::SysError;
+
+/// Max number of supplementary groups
+pub const NGROUPS_MAX: usize = 65536;
+
+/// User and group ids which a process acts as
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub uid: u32,         // real user id
+    pub euid: u32,        // effective user id, used for permission checks
+    pub suid: u32,        // saved set-user-id
+    pub gid: u32,         // real group id
+    pub egid: u32,        // effective group id, used for permission checks
+    pub sgid: u32,        // saved set-group-id
+    pub groups: Vec<u32>, // supplementary groups
+}
+
+impl Credentials {
+    /// Credentials of the first process
+    pub fn root() -> Self {
+        Credentials::default()
+    }
+
+    /// Whether the process is root, which bypasses permission checks
+    pub fn is_privileged(&self) -> bool {
+        self.euid == 0
+    }
+
+    /// Whether `gid` is the effective group or a supplementary group
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.egid == gid || self.groups.contains(&gid)
+    }
+
+    /// Root sets all user ids, others can only set the effective one
+    /// to the real or saved one.
+    pub fn set_uid(&mut self, uid: u32) -> Result<(), SysError> {
+        if self.is_privileged() {
+            self.uid = uid;
+            self.suid = uid;
+        } else if uid != self.uid && uid != self.suid {
+            return Err(SysError::EPERM);
+        }
+        self.euid = uid;
+        Ok(())
+    }
+
+    /// Root sets all group ids, others can only set the effective one
+    /// to the real or saved one.
+    pub fn set_gid(&mut self, gid: u32) -> Result<(), SysError> {
+        if self.is_privileged() {
+            self.gid = gid;
+            self.sgid = gid;
+        } else if gid != self.gid && gid != self.sgid {
+            return Err(SysError::EPERM);
+        }
+        self.egid = gid;
+        Ok(())
+    }
+
+    /// Supplementary groups to be stored in a list of `size`, or none if `size` is 0.
+    /// Fails if they do not fit.
+    pub fn get_groups(&self, size: usize) -> Result<&[u32], SysError> {
+        if size == 0 {
+            return Ok(&[]);
+        }
+        if size < self.groups.len() {
+            return Err(SysError::EINVAL);
+        }
+        Ok(&self.groups)
+    }
+
+    /// Set supplementary groups, only for root
+    pub fn set_groups(&mut self, groups: Vec<u32>) -> Result<(), SysError> {
+        if groups.len() > NGROUPS_MAX {
+            return Err(SysError::EINVAL);
+        }
+        if !self.is_privileged() {
+            return Err(SysError::EPERM);
+        }
+        self.groups = groups;
+        Ok(())
+    }
+
+    /// The saved ids become the effective ones when executing a program
+    pub fn exec(&mut self) {
+        self.suid = self.euid;
+        self.sgid = self.egid;
+    }
+}
+
+pub mod test {
+    //! Changing user and group ids, and supplementary groups
+
+    use super::*;
+    use alloc::vec;
+
+    /// A process of user 1000 running a set-user-id and set-group-id program of 2000
+    fn set_id_program() -> Credentials {
+        Credentials {
+            uid: 1000,
+            euid: 2000,
+            suid: 2000,
+            gid: 1000,
+            egid: 2000,
+            sgid: 2000,
+            groups: Vec::new(),
+        }
+    }
+
+    fn ids(cred: &Credentials) -> [u32; 6] {
+        [
+            cred.uid, cred.euid, cred.suid, cred.gid, cred.egid, cred.sgid,
+        ]
+    }
+
+    /// Root sets all ids, others switch the effective id between the real and saved ones
+    fn set_uid_and_gid() {
+        let mut cred = Credentials::root();
+        assert_eq!(cred.set_gid(100), Ok(()));
+        assert_eq!(cred.set_uid(1000), Ok(()));
+        assert_eq!(ids(&cred), [1000, 1000, 1000, 100, 100, 100]);
+        // root is gone for good
+        assert_eq!(cred.set_uid(0), Err(SysError::EPERM));
+
+        let mut cred = set_id_program();
+        assert_eq!(cred.set_uid(1000), Ok(()));
+        assert_eq!(cred.set_gid(1000), Ok(()));
+        assert_eq!(ids(&cred), [1000, 1000, 2000, 1000, 1000, 2000]);
+        assert_eq!(cred.set_uid(2000), Ok(()));
+        assert_eq!(cred.set_gid(2000), Ok(()));
+        assert_eq!(ids(&cred), [1000, 2000, 2000, 1000, 2000, 2000]);
+        assert_eq!(cred.set_uid(3000), Err(SysError::EPERM));
+        assert_eq!(cred.set_gid(3000), Err(SysError::EPERM));
+        assert_eq!(ids(&cred), [1000, 2000, 2000, 1000, 2000, 2000]);
+    }
+
+    /// After exec the saved ids are the effective ones,
+    /// so a dropped id can not be taken back
+    fn saved_ids_on_exec() {
+        let mut cred = set_id_program();
+        cred.set_uid(1000).unwrap();
+        cred.set_gid(1000).unwrap();
+        cred.exec();
+        assert_eq!(ids(&cred), [1000; 6]);
+        assert_eq!(cred.set_uid(2000), Err(SysError::EPERM));
+        assert_eq!(cred.set_gid(2000), Err(SysError::EPERM));
+    }
+
+    /// Only root sets the supplementary groups, and getgroups needs room for all of them
+    fn supplementary_groups() {
+        let mut cred = Credentials::root();
+        assert_eq!(cred.set_groups(vec![10, 20]), Ok(()));
+        assert!(cred.in_group(20) && !cred.in_group(30));
+        assert_eq!(cred.get_groups(0), Ok(&[][..]));
+        assert_eq!(cred.get_groups(1), Err(SysError::EINVAL));
+        assert_eq!(cred.get_groups(2), Ok(&[10, 20][..]));
+        assert_eq!(
+            cred.set_groups(vec![0; NGROUPS_MAX + 1]),
+            Err(SysError::EINVAL)
+        );
+
+        cred.set_uid(1000).unwrap();
+        assert_eq!(cred.set_groups(Vec::new()), Err(SysError::EPERM));
+        assert_eq!(cred.get_groups(8), Ok(&[10, 20][..]));
+    }
+
+    pub fn test_all() {
+        set_uid_and_gid();
+        saved_ids_on_exec();
+        supplementary_groups();
+        println!("credentials test end");
+    }
+}
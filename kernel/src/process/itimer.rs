@@ -0,0 +1,85 @@
+//! Interval timers of processes
+//!
+//! The ITIMER_REAL timers of all processes are kept in one table,
+//! which the timer interrupt checks to send SIGALRM.
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use super::PROCESSES;
+use crate::signal::SIGALRM;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::trap::uptime_usec;
+
+#[derive(Debug, Copy, Clone)]
+struct RealTimer {
+    /// Next expiration in usec of uptime
+    deadline: u64,
+    /// 0 for a one-shot timer
+    interval: u64,
+}
+
+lazy_static! {
+    /// Armed ITIMER_REAL timers, by pid
+    static ref REAL_TIMERS: Mutex<BTreeMap<usize, RealTimer>> = Mutex::new(BTreeMap::new());
+}
+
+/// Get the time until the ITIMER_REAL of process `pid` expires and its interval in usec.
+/// The time is 0 if the timer is disarmed.
+pub fn get_real_timer(pid: usize) -> (u64, u64) {
+    let now = uptime_usec();
+    match REAL_TIMERS.lock().get(&pid) {
+        // an expired timer which is not handled yet expires in no time
+        Some(timer) => (timer.deadline.saturating_sub(now).max(1), timer.interval),
+        None => (0, 0),
+    }
+}
+
+/// Arm the ITIMER_REAL of process `pid` to expire `value` usec later,
+/// and then every `interval` usec, or disarm it if `value` is 0.
+/// Return the previous setting as `get_real_timer`.
+pub fn set_real_timer(pid: usize, value: u64, interval: u64) -> (u64, u64) {
+    let old = get_real_timer(pid);
+    let mut timers = REAL_TIMERS.lock();
+    if value == 0 {
+        timers.remove(&pid);
+    } else {
+        let deadline = uptime_usec() + value;
+        timers.insert(pid, RealTimer { deadline, interval });
+    }
+    old
+}
+
+/// Send SIGALRM to the processes whose ITIMER_REAL expires, called by the timer interrupt
+pub fn real_timer_tick() {
+    // the process table may be locked by the interrupted code,
+    // then the expired timers are handled on the next tick
+    let processes = match PROCESSES.try_read() {
+        Some(processes) => processes,
+        None => return,
+    };
+    let now = uptime_usec();
+    let mut expired = Vec::new();
+    {
+        let mut timers = REAL_TIMERS.lock();
+        for (&pid, timer) in timers.iter_mut() {
+            if timer.deadline > now {
+                continue;
+            }
+            expired.push(pid);
+            if timer.interval != 0 {
+                let count = (now - timer.deadline) / timer.interval + 1;
+                timer.deadline += count * timer.interval;
+            }
+        }
+        for pid in expired.iter() {
+            if timers[pid].deadline <= now {
+                timers.remove(pid);
+            }
+        }
+    }
+    for pid in expired {
+        if let Some(process) = processes.get(&pid).and_then(|weak| weak.upgrade()) {
+            process.lock().send_signal(SIGALRM);
+        }
+    }
+}
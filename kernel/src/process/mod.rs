@@ -1,3 +1,7 @@
+pub use self::cred::*;
+pub use self::itimer::*;
+pub use self::priority::*;
+pub use self::rlimit::*;
 pub use self::structs::*;
 use crate::arch::cpu;
 use crate::consts::{MAX_CPU_NUM, MAX_PROCESS_NUM};
@@ -6,12 +10,19 @@ use log::*;
 pub use rcore_thread::*;
 
 mod abi;
+mod cred;
+mod itimer;
+mod priority;
+mod rlimit;
 pub mod structs;
 
-pub fn init() {
+lazy_static! {
     // NOTE: max_time_slice <= 5 to ensure 'priority' test pass
-    let scheduler = scheduler::RRScheduler::new(5);
-    let manager = Arc::new(ThreadPool::new(scheduler, MAX_PROCESS_NUM));
+    pub static ref SCHEDULER: PriorityScheduler = PriorityScheduler::new(5);
+}
+
+pub fn init() {
+    let manager = Arc::new(ThreadPool::new(&*SCHEDULER, MAX_PROCESS_NUM));
 
     unsafe {
         for cpu_id in 0..MAX_CPU_NUM {
@@ -19,11 +30,20 @@ pub fn init() {
         }
     }
 
+    // take tid 0, so that the first user process gets INIT_PID as in Linux
+    processor().manager().add(Thread::new_kernel(idle, 0));
     crate::shell::add_user_shell();
 
     info!("process: init end");
 }
 
+/// The thread of pid 0, which never runs
+extern "C" fn idle(_arg: usize) -> ! {
+    loop {
+        crate::thread::park();
+    }
+}
+
 static PROCESSORS: [Processor; MAX_CPU_NUM] = [
     // TODO: More elegant ?
     Processor::new(),
@@ -0,0 +1,182 @@
+//! Priority scheduler
+//!
+//! The runnable thread with the highest priority is picked,
+//! and threads with the same priority run in turn.
+//! A thread gains one level for every `AGING_STEP` picks it misses,
+//! so low priority threads still make progress.
+//!
+//! A thread only runs on the CPUs in its affinity mask.
+
+use alloc::{collections::VecDeque, vec::Vec};
+use log::*;
+use rcore_thread::scheduler::Scheduler;
+use rcore_thread::Tid;
+use spin::Mutex;
+
+/// Priority of threads which never set it
+pub const DEFAULT_PRIORITY: u8 = 1;
+/// Highest priority a thread can set
+pub const MAX_PRIORITY: u8 = 32;
+/// Number of missed picks for a waiting thread to gain one level
+const AGING_STEP: usize = 4;
+
+/// A set of CPUs, CPU `n` is at bit `n`
+pub type CpuMask = u64;
+
+pub struct PriorityScheduler {
+    inner: Mutex<PrioritySchedulerInner>,
+}
+
+struct PrioritySchedulerInner {
+    max_time_slice: usize,
+    /// CPUs which have started scheduling
+    online_cpus: CpuMask,
+    infos: Vec<PriorityInfo>,
+    /// Runnable threads, in the order they were pushed
+    ready: VecDeque<Tid>,
+}
+
+#[derive(Debug, Clone)]
+struct PriorityInfo {
+    priority: u8,
+    rest_slice: usize,
+    /// Picks missed since the thread became runnable
+    waited: usize,
+    affinity: CpuMask,
+}
+
+impl Default for PriorityInfo {
+    fn default() -> Self {
+        PriorityInfo {
+            priority: DEFAULT_PRIORITY,
+            rest_slice: 0,
+            waited: 0,
+            affinity: !0,
+        }
+    }
+}
+
+impl PriorityInfo {
+    fn effective_priority(&self) -> usize {
+        self.priority as usize + self.waited / AGING_STEP
+    }
+}
+
+impl Scheduler for &'static PriorityScheduler {
+    fn push(&self, tid: Tid) {
+        self.inner.lock().push(tid)
+    }
+    fn pop(&self, cpu_id: usize) -> Option<Tid> {
+        self.inner.lock().pop(cpu_id)
+    }
+    fn tick(&self, current_tid: Tid) -> bool {
+        self.inner.lock().tick(current_tid)
+    }
+    fn set_priority(&self, tid: Tid, priority: u8) {
+        self.inner.lock().set_priority(tid, priority)
+    }
+    fn remove(&self, tid: Tid) {
+        self.inner.lock().remove(tid)
+    }
+}
+
+impl PriorityScheduler {
+    pub fn new(max_time_slice: usize) -> Self {
+        PriorityScheduler {
+            inner: Mutex::new(PrioritySchedulerInner {
+                max_time_slice,
+                online_cpus: 0,
+                infos: Vec::default(),
+                ready: VecDeque::default(),
+            }),
+        }
+    }
+
+    /// Called by each CPU before it starts scheduling
+    pub fn add_cpu(&self, cpu_id: usize) {
+        self.inner.lock().online_cpus |= 1 << cpu_id;
+    }
+
+    pub fn online_cpus(&self) -> CpuMask {
+        self.inner.lock().online_cpus
+    }
+
+    /// Get the CPUs thread `tid` can run on
+    pub fn affinity(&self, tid: Tid) -> CpuMask {
+        let mut inner = self.inner.lock();
+        let online_cpus = inner.online_cpus;
+        inner.info(tid).affinity & online_cpus
+    }
+
+    /// Restrict thread `tid` to the CPUs in `mask`.
+    /// Return false if none of them is online.
+    pub fn set_affinity(&self, tid: Tid, mask: CpuMask) -> bool {
+        let mut inner = self.inner.lock();
+        if mask & inner.online_cpus == 0 {
+            return false;
+        }
+        inner.info(tid).affinity = mask;
+        true
+    }
+}
+
+impl PrioritySchedulerInner {
+    fn info(&mut self, tid: Tid) -> &mut PriorityInfo {
+        if tid >= self.infos.len() {
+            self.infos.resize(tid + 1, PriorityInfo::default());
+        }
+        &mut self.infos[tid]
+    }
+
+    fn push(&mut self, tid: Tid) {
+        let max_time_slice = self.max_time_slice;
+        let info = self.info(tid);
+        if info.rest_slice == 0 {
+            info.rest_slice = max_time_slice;
+        }
+        info.waited = 0;
+        self.ready.push_back(tid);
+        trace!("priority push {}", tid);
+    }
+
+    fn pop(&mut self, cpu_id: usize) -> Option<Tid> {
+        // the first one wins a tie, which makes equal priorities round-robin
+        let mut best: Option<(usize, usize)> = None;
+        for (i, &tid) in self.ready.iter().enumerate() {
+            let info = &self.infos[tid];
+            if info.affinity & (1 << cpu_id) == 0 {
+                continue;
+            }
+            let priority = info.effective_priority();
+            match best {
+                Some((_, best_priority)) if best_priority >= priority => {}
+                _ => best = Some((i, priority)),
+            }
+        }
+        let (index, _) = best?;
+        let tid = self.ready.remove(index).unwrap();
+        for &other in self.ready.iter() {
+            self.infos[other].waited += 1;
+        }
+        trace!("priority pop {}", tid);
+        Some(tid)
+    }
+
+    fn tick(&mut self, current: Tid) -> bool {
+        let info = self.info(current);
+        if info.rest_slice == 0 {
+            return true;
+        }
+        info.rest_slice -= 1;
+        info.rest_slice == 0
+    }
+
+    fn set_priority(&mut self, tid: Tid, priority: u8) {
+        self.info(tid).priority = priority.min(MAX_PRIORITY);
+        trace!("priority set {} to {}", tid, priority);
+    }
+
+    fn remove(&mut self, tid: Tid) {
+        self.ready.retain(|&other| other != tid);
+    }
+}
@@ -0,0 +1,45 @@
+//! Resource limits of a process
+
+use crate::consts::{MAX_FILE_NUM, USER_STACK_SIZE};
+
+pub const RLIMIT_CPU: usize = 0;
+pub const RLIMIT_FSIZE: usize = 1;
+pub const RLIMIT_DATA: usize = 2;
+pub const RLIMIT_STACK: usize = 3;
+pub const RLIMIT_CORE: usize = 4;
+pub const RLIMIT_RSS: usize = 5;
+pub const RLIMIT_NPROC: usize = 6;
+pub const RLIMIT_NOFILE: usize = 7;
+pub const RLIMIT_MEMLOCK: usize = 8;
+pub const RLIMIT_AS: usize = 9;
+/// Number of resources
+pub const RLIMIT_NLIMITS: usize = 16;
+
+pub const RLIM_INFINITY: u64 = u64::max_value();
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RLimit {
+    pub cur: u64, // soft limit
+    pub max: u64, // hard limit
+}
+
+impl RLimit {
+    fn fixed(limit: u64) -> Self {
+        RLimit {
+            cur: limit,
+            max: limit,
+        }
+    }
+
+    /// Limits of the first process
+    pub fn default_limits() -> [RLimit; RLIMIT_NLIMITS] {
+        let mut limits = [RLimit::fixed(RLIM_INFINITY); RLIMIT_NLIMITS];
+        limits[RLIMIT_STACK] = RLimit::fixed(USER_STACK_SIZE as u64);
+        limits[RLIMIT_NOFILE] = RLimit::fixed(MAX_FILE_NUM as u64);
+        // 1GB
+        limits[RLIMIT_RSS] = RLimit::fixed(1024 * 1024 * 1024);
+        limits[RLIMIT_AS] = RLimit::fixed(1024 * 1024 * 1024);
+        limits
+    }
+}
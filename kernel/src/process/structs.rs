@@ -8,22 +8,34 @@ use rcore_thread::Tid;
 use spin::RwLock;
 use xmas_elf::{
     header,
-    program::{Flags, SegmentData, Type},
+    program::{Flags, ProgramHeader, SegmentData, Type},
     ElfFile,
 };
 
+use crate::arch::cpu;
 use crate::arch::interrupt::{Context, TrapFrame};
-use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
-use crate::ipc::SemProc;
+use crate::consts::MAX_CPU_NUM;
+use crate::fs::epoll::fork_files;
+use crate::fs::{release_process_locks, FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
+use crate::ipc::{SemProc, ShmProc};
 use crate::memory::{
-    ByFrame, Delay, File, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet, Read,
+    user_stack_bottom, ByFrame, Delay, File, GlobalFrameAlloc, Guard, KernelStack, MemoryAttr,
+    MemorySet, Read, Write, USER_STACK_INIT_SIZE,
 };
-use crate::sync::{Condvar, SpinNoIrqLock as Mutex};
+use crate::sync::{Condvar, SpinNoIrqLock as Mutex, WaitQueue};
+use crate::syscall::SysError;
+use crate::util::user_ptr::USER_END;
 
 use super::abi::{self, ProcInitInfo};
+use super::cred::Credentials;
+use super::rlimit::*;
 use crate::processor;
+use crate::signal::{
+    is_ignored, sigmask, SignalAction, SignalStack, Sigset, SIGNAL_NUM, SIGNAL_TRAMPOLINE, SIG_DFL,
+};
 use core::mem::MaybeUninit;
 use rcore_fs::vfs::INode;
+use rcore_memory::paging::PageTable;
 
 pub struct Thread {
     context: Context,
@@ -31,16 +43,60 @@ pub struct Thread {
     /// Kernel performs futex wake when thread exits.
     /// Ref: [http://man7.org/linux/man-pages/man2/set_tid_address.2.html]
     pub clear_child_tid: usize,
+    /// Signals blocked from delivery to this thread
+    pub sig_mask: Sigset,
+    /// Signals directed to this thread, delivered before those of the process
+    pub pending_signals: Sigset,
+    /// Alternate stack for signal handlers
+    pub sig_alt_stack: SignalStack,
+    /// Address and `si_code` of the page fault which raised SIGSEGV
+    pub segv_info: Option<(usize, i32)>,
+    /// Name of the thread, null-terminated
+    pub name: [u8; TASK_COMM_LEN],
+    /// Timer ticks spent in user and kernel mode
+    pub utime: usize,
+    pub stime: usize,
     // This is same as `proc.vm`
     pub vm: Arc<Mutex<MemorySet>>,
     pub proc: Arc<Mutex<Process>>,
 }
 
+/// Size of thread names, including the null terminator
+pub const TASK_COMM_LEN: usize = 16;
+
+/// Make a thread name from `name`, which is truncated to fit
+pub fn task_comm(name: &str) -> [u8; TASK_COMM_LEN] {
+    let mut comm = [0; TASK_COMM_LEN];
+    let len = name.len().min(TASK_COMM_LEN - 1);
+    comm[..len].copy_from_slice(&name.as_bytes()[..len]);
+    comm
+}
+
+/// The last component of `path`
+pub fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or("")
+}
+
+/// Resource usage of an exited process and its waited children,
+/// which is collected by its parent in `wait4`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChildUsage {
+    pub utime: usize,
+    pub stime: usize,
+    pub max_rss: usize,
+    /// Process group when it exited, for waiting on a group
+    pub pgid: usize,
+}
+
 /// Pid type
 /// For strong type separation
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Pid(usize);
 
+/// Pid of the init process, which takes the orphans.
+/// Pid 0 is taken by the idle thread.
+pub const INIT_PID: usize = 1;
+
 impl Pid {
     pub fn get(&self) -> usize {
         self.0
@@ -48,7 +104,7 @@ impl Pid {
 
     /// Return whether this pid represents the init process
     pub fn is_init(&self) -> bool {
-        self.0 == 0
+        self.0 == INIT_PID
     }
 }
 
@@ -63,25 +119,90 @@ pub struct Process {
     pub vm: Arc<Mutex<MemorySet>>,
     pub files: BTreeMap<usize, FileLike>,
     pub cwd: String,
+    pub umask: usize, // permission bits cleared from created files
     pub exec_path: String,
-    futexes: BTreeMap<usize, Arc<Condvar>>,
+    pub exec_args: Vec<String>, // arguments of the program, shown in /proc/<pid>/cmdline
+    pub comm: [u8; TASK_COMM_LEN], // name of the first thread, shown in /proc/<pid>/comm
     pub semaphores: SemProc,
+    pub shm: ShmProc, // attached shared memory segments
+    pub cred: Credentials,
 
     // relationship
-    pub pid: Pid, // i.e. tgid, usually the tid of first thread
+    pub pid: Pid,    // i.e. tgid, the tid of first thread
+    pub pgid: usize, // process group id
+    pub sid: usize,  // session id
     pub parent: Weak<Mutex<Process>>,
     pub children: Vec<Weak<Mutex<Process>>>,
     pub threads: Vec<Tid>, // threads in the same process
 
     // for waiting child
-    pub child_exit: Arc<Condvar>, // notified when the a child process is going to terminate
+    pub child_exit: Arc<WaitQueue>, // woken up when the a child process is going to terminate
     pub child_exit_code: BTreeMap<usize, usize>, // child process store its exit code here
+    pub child_exit_usage: BTreeMap<usize, ChildUsage>, // and its resource usage
+
+    // heap
+    pub brk_start: usize, // start of the heap, 0 before the first brk since exec
+    pub brk: usize,       // current program break
+
+    // cpu time in timer ticks
+    pub utime: usize,  // user time of all threads
+    pub stime: usize,  // system time of all threads
+    pub cutime: usize, // user time of waited children
+    pub cstime: usize, // system time of waited children
+
+    // peak resident set size in bytes
+    pub max_rss: usize,
+    pub children_max_rss: usize, // of waited children
+
+    // resource limits
+    pub rlimits: [RLimit; RLIMIT_NLIMITS],
+
+    // signal
+    pub signal_actions: [SignalAction; SIGNAL_NUM + 1], // indexed by signal number
+    pub pending_signals: Sigset,
+
+    // vfork
+    pub vfork_done: Option<Arc<VforkDone>>, // set in a vfork child until exec or exit
+}
+
+/// The parent of a vfork child sleeps on it,
+/// until the child stops using the address space of the parent
+#[derive(Default)]
+pub struct VforkDone {
+    done: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl VforkDone {
+    pub fn wait(&self) {
+        let mut done = self.done.lock();
+        while !*done {
+            done = self.condvar.wait(done);
+        }
+    }
+
+    fn notify(&self) {
+        *self.done.lock() = true;
+        self.condvar.notify_all();
+    }
 }
 
 lazy_static! {
     /// Records the mapping between pid and Process struct.
     pub static ref PROCESSES: RwLock<BTreeMap<usize, Weak<Mutex<Process>>>> =
         RwLock::new(BTreeMap::new());
+    /// Pids of the processes removed from the table,
+    /// whose first threads still hold them as tids
+    static ref REAPED_PIDS: Mutex<Vec<Tid>> = Mutex::new(Vec::new());
+}
+
+/// Whether each processor is running a thread, instead of its scheduling loop
+static mut THREAD_RUNNING: [bool; MAX_CPU_NUM] = [false; MAX_CPU_NUM];
+
+/// Whether current processor is running a thread,
+/// so that `current_thread` can be used in interrupts
+pub fn is_thread_running() -> bool {
+    unsafe { THREAD_RUNNING[cpu::id()] }
 }
 
 /// Let `rcore_thread` can switch between our `Thread`
@@ -89,11 +210,18 @@ impl rcore_thread::Context for Thread {
     unsafe fn switch_to(&mut self, target: &mut dyn rcore_thread::Context) {
         use core::mem::transmute;
         let (target, _): (&mut Thread, *const ()) = transmute(target);
+        // a processor always switches between its loop and a thread
+        THREAD_RUNNING[cpu::id()] ^= true;
         self.context.switch(&mut target.context);
     }
 
     fn set_tid(&mut self, tid: Tid) {
         let mut proc = self.proc.lock();
+        // the first thread gives its tid to the process as pid,
+        // so that pids and tids never collide
+        if proc.threads.is_empty() {
+            proc.add_to_table(&self.proc, tid);
+        }
         // add it to threads
         proc.threads.push(tid);
     }
@@ -119,23 +247,48 @@ impl Thread {
             context: unsafe { Context::new_kernel_thread(entry, arg, kstack.top(), vm_token) },
             kstack,
             clear_child_tid: 0,
+            sig_mask: 0,
+            sig_alt_stack: SignalStack::disabled(),
+            pending_signals: 0,
+            segv_info: None,
+            name: [0; TASK_COMM_LEN],
+            utime: 0,
+            stime: 0,
             vm: vm.clone(),
             // TODO: kernel thread should not have a process
-            proc: Process {
+            proc: Arc::new(Mutex::new(Process {
                 vm,
                 files: BTreeMap::default(),
                 cwd: String::from("/"),
+                umask: 0o022,
                 exec_path: String::new(),
+                exec_args: Vec::new(),
+                comm: [0; TASK_COMM_LEN],
                 semaphores: SemProc::default(),
-                futexes: BTreeMap::default(),
+                shm: ShmProc::default(),
+                cred: Credentials::root(),
                 pid: Pid(0),
+                pgid: 0,
+                sid: 0,
                 parent: Weak::new(),
                 children: Vec::new(),
                 threads: Vec::new(),
-                child_exit: Arc::new(Condvar::new()),
+                child_exit: Arc::new(WaitQueue::new()),
                 child_exit_code: BTreeMap::new(),
-            }
-            .add_to_table(),
+                child_exit_usage: BTreeMap::new(),
+                brk_start: 0,
+                brk: 0,
+                utime: 0,
+                stime: 0,
+                cutime: 0,
+                cstime: 0,
+                max_rss: 0,
+                children_max_rss: 0,
+                rlimits: RLimit::default_limits(),
+                signal_actions: [SignalAction::default(); SIGNAL_NUM + 1],
+                pending_signals: 0,
+                vfork_done: None,
+            })),
         })
     }
 
@@ -146,36 +299,26 @@ impl Thread {
         exec_path: &str,
         mut args: Vec<String>,
         envs: Vec<String>,
-    ) -> Result<(MemorySet, usize, usize), &'static str> {
+        stack_limit: usize,
+    ) -> Result<(MemorySet, usize, usize), SysError> {
         // Read ELF header
         // 0x3c0: magic number from ld-musl.so
         let mut data: [u8; 0x3c0] = unsafe { MaybeUninit::zeroed().assume_init() };
-        inode
-            .read_at(0, &mut data)
-            .map_err(|_| "failed to read from INode")?;
+        inode.read_at(0, &mut data)?;
 
         // Parse ELF
-        let elf = ElfFile::new(&data)?;
-
-        // Check ELF type
-        match elf.header.pt2.type_().as_type() {
-            header::Type::Executable => {}
-            header::Type::SharedObject => {}
-            _ => return Err("ELF is not executable or shared object"),
-        }
-
-        // Check ELF arch
-        match elf.header.pt2.machine().as_machine() {
-            #[cfg(target_arch = "x86_64")]
-            header::Machine::X86_64 => {}
-            #[cfg(target_arch = "aarch64")]
-            header::Machine::AArch64 => {}
-            #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
-            header::Machine::Other(243) => {}
-            #[cfg(target_arch = "mips")]
-            header::Machine::Mips => {}
-            _ => return Err("invalid ELF arch"),
-        }
+        let elf = ElfFile::new(&data).map_err(|err| {
+            warn!("exec: {}", err);
+            SysError::ENOEXEC
+        })?;
+        elf.check_loadable().map_err(|err| {
+            warn!("exec: {}", err);
+            SysError::ENOEXEC
+        })?;
+        let interpreter = elf.get_interpreter().map_err(|err| {
+            warn!("exec: {}", err);
+            SysError::ENOEXEC
+        })?;
 
         let mut auxv = {
             let mut map = BTreeMap::new();
@@ -185,29 +328,43 @@ impl Thread {
             map.insert(abi::AT_PHENT, elf.header.pt2.ph_entry_size() as usize);
             map.insert(abi::AT_PHNUM, elf.header.pt2.ph_count() as usize);
             map.insert(abi::AT_PAGESZ, PAGE_SIZE);
+            map.insert(abi::AT_ENTRY, elf.header.pt2.entry_point() as usize);
             map
         };
         let mut entry_addr = elf.header.pt2.entry_point() as usize;
         // Make page table
-        let (mut vm, bias) = elf.make_memory_set(inode);
+        let (mut vm, bias) = elf.make_memory_set(inode).map_err(|err| {
+            warn!("exec: {}", err);
+            SysError::ENOEXEC
+        })?;
 
         // Check interpreter (for dynamic link)
         // When interpreter is used, map both dynamic linker and executable
-        if let Ok(loader_path) = elf.get_interpreter() {
+        if let Some(loader_path) = interpreter {
             info!("Handling interpreter... offset={:x}", bias);
+            let interp_error = |err: &str| {
+                warn!("exec: interpreter {}: {}", loader_path, err);
+                SysError::ELIBBAD
+            };
             // assuming absolute path
             let interp_inode = crate::fs::ROOT_INODE
                 .lookup_follow(loader_path, FOLLOW_MAX_DEPTH)
-                .map_err(|_| "interpreter not found")?;
+                .map_err(|_| interp_error("not found"))?;
             // load loader by bias and set aux vector.
             let mut interp_data: [u8; 0x3c0] = unsafe { MaybeUninit::zeroed().assume_init() };
             interp_inode
                 .read_at(0, &mut interp_data)
-                .map_err(|_| "failed to read from INode")?;
-            let elf_interp = ElfFile::new(&interp_data)?;
-            elf_interp.append_as_interpreter(&interp_inode, &mut vm, bias);
+                .map_err(|_| interp_error("failed to read from INode"))?;
+            let elf_interp = ElfFile::new(&interp_data).map_err(interp_error)?;
+            elf_interp.check_loadable().map_err(interp_error)?;
+            match elf_interp.header.pt2.type_().as_type() {
+                header::Type::SharedObject => {}
+                _ => return Err(interp_error("ELF is not a shared object")),
+            }
+            elf_interp
+                .append_as_interpreter(&interp_inode, &mut vm, bias)
+                .map_err(interp_error)?;
             debug!("entry point: {:x}", elf.header.pt2.entry_point() as usize);
-            auxv.insert(abi::AT_ENTRY, elf.header.pt2.entry_point() as usize);
             auxv.insert(abi::AT_BASE, bias);
             entry_addr = elf_interp.header.pt2.entry_point() as usize + bias;
         }
@@ -215,28 +372,55 @@ impl Thread {
         // User stack
         use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE};
         let mut ustack_top = {
-            let ustack_buttom = USER_STACK_OFFSET;
             let ustack_top = USER_STACK_OFFSET + USER_STACK_SIZE;
+            // the stack grows down on page faults until the guard page
+            let ustack_bottom = user_stack_bottom(ustack_top, stack_limit);
             vm.push(
-                ustack_buttom,
-                ustack_top - PAGE_SIZE * 4,
+                ustack_bottom - PAGE_SIZE,
+                ustack_bottom,
                 MemoryAttr::default().user(),
-                Delay::new(GlobalFrameAlloc),
-                "user_stack_delay",
+                Guard,
+                "user_stack_guard",
             );
-            // We are going to write init info now. So map the last 4 pages eagerly.
             vm.push(
-                ustack_top - PAGE_SIZE * 4,
+                ustack_top - USER_STACK_INIT_SIZE,
                 ustack_top,
                 MemoryAttr::default().user(),
-                ByFrame::new(GlobalFrameAlloc),
+                Delay::new(GlobalFrameAlloc),
                 "user_stack",
             );
+            // We are going to write init info now. So map the top pages eagerly.
+            vm.prefault(ustack_top - USER_STACK_INIT_SIZE, ustack_top);
             ustack_top
         };
 
+        // Signal trampoline, which handlers without a restorer return to
+        vm.push(
+            SIGNAL_TRAMPOLINE,
+            SIGNAL_TRAMPOLINE + PAGE_SIZE,
+            MemoryAttr::default().user().execute(),
+            ByFrame::new(GlobalFrameAlloc),
+            "signal_trampoline",
+        );
+        {
+            use crate::arch::signal::SIGNAL_RET_CODE;
+            let pt = vm.get_page_table_mut();
+            pt.get_page_slice_mut(SIGNAL_TRAMPOLINE)[..SIGNAL_RET_CODE.len()]
+                .copy_from_slice(&SIGNAL_RET_CODE);
+            pt.flush_cache_copy_user(
+                SIGNAL_TRAMPOLINE,
+                SIGNAL_TRAMPOLINE + SIGNAL_RET_CODE.len(),
+                true,
+            );
+        }
+
         // Make init info
-        let init_info = ProcInitInfo { args, envs, auxv };
+        let init_info = ProcInitInfo {
+            args,
+            envs,
+            auxv,
+            exec_path: String::from(exec_path),
+        };
         unsafe {
             vm.with(|| ustack_top = init_info.push_at(ustack_top));
         }
@@ -251,7 +435,10 @@ impl Thread {
         args: Vec<String>,
         envs: Vec<String>,
     ) -> Box<Thread> {
-        let (vm, entry_addr, ustack_top) = Self::new_user_vm(inode, exec_path, args, envs).unwrap();
+        let exec_args = args.clone();
+        let stack_limit = RLimit::default_limits()[RLIMIT_STACK].cur as usize;
+        let (vm, entry_addr, ustack_top) =
+            Self::new_user_vm(inode, exec_path, args, envs, stack_limit).unwrap();
 
         let vm_token = vm.token();
         let vm = Arc::new(Mutex::new(vm));
@@ -267,8 +454,10 @@ impl Thread {
                     write: false,
                     append: false,
                     nonblock: false,
+                    path: false,
                 },
                 String::from("stdin"),
+                false,
             )),
         );
         files.insert(
@@ -280,8 +469,10 @@ impl Thread {
                     write: true,
                     append: false,
                     nonblock: false,
+                    path: false,
                 },
                 String::from("stdout"),
+                false,
             )),
         );
         files.insert(
@@ -293,8 +484,10 @@ impl Thread {
                     write: true,
                     append: false,
                     nonblock: false,
+                    path: false,
                 },
                 String::from("stderr"),
+                false,
             )),
         );
 
@@ -304,49 +497,124 @@ impl Thread {
             },
             kstack,
             clear_child_tid: 0,
+            sig_mask: 0,
+            sig_alt_stack: SignalStack::disabled(),
+            pending_signals: 0,
+            segv_info: None,
+            name: task_comm(file_name(exec_path)),
+            utime: 0,
+            stime: 0,
             vm: vm.clone(),
-            proc: Process {
+            proc: Arc::new(Mutex::new(Process {
                 vm,
                 files,
                 cwd: String::from("/"),
+                umask: 0o022,
                 exec_path: String::from(exec_path),
-                futexes: BTreeMap::default(),
+                exec_args,
+                comm: task_comm(file_name(exec_path)),
                 semaphores: SemProc::default(),
+                shm: ShmProc::default(),
+                cred: Credentials::root(),
                 pid: Pid(0),
+                pgid: 0,
+                sid: 0,
                 parent: Weak::new(),
                 children: Vec::new(),
                 threads: Vec::new(),
-                child_exit: Arc::new(Condvar::new()),
+                child_exit: Arc::new(WaitQueue::new()),
                 child_exit_code: BTreeMap::new(),
-            }
-            .add_to_table(),
+                child_exit_usage: BTreeMap::new(),
+                brk_start: 0,
+                brk: 0,
+                utime: 0,
+                stime: 0,
+                cutime: 0,
+                cstime: 0,
+                max_rss: 0,
+                children_max_rss: 0,
+                rlimits: RLimit::default_limits(),
+                signal_actions: [SignalAction::default(); SIGNAL_NUM + 1],
+                pending_signals: 0,
+                vfork_done: None,
+            })),
         })
     }
 
+    /// Account a timer tick to the thread and its process
+    pub fn tick(&mut self, user: bool) {
+        let mut proc = self.proc.lock();
+        if user {
+            self.utime += 1;
+            proc.utime += 1;
+        } else {
+            self.stime += 1;
+            proc.stime += 1;
+        }
+    }
+
     /// Fork a new process from current one
     pub fn fork(&self, tf: &TrapFrame) -> Box<Thread> {
-        let kstack = KernelStack::new();
+        // private pages are shared with the parent until either writes them
         let vm = self.vm.lock().clone();
-        let vm_token = vm.token();
-        let vm = Arc::new(Mutex::new(vm));
+        self.fork_with_vm(tf, Arc::new(Mutex::new(vm)), None)
+    }
+
+    /// Create a new process sharing the address space of current one.
+    /// The caller should wait on the returned `VforkDone` before returning to user,
+    /// which is notified when the child calls exec or exits.
+    pub fn vfork(&self, tf: &TrapFrame) -> (Box<Thread>, Arc<VforkDone>) {
+        let vfork_done = Arc::new(VforkDone::default());
+        let thread = self.fork_with_vm(tf, self.vm.clone(), Some(vfork_done.clone()));
+        (thread, vfork_done)
+    }
+
+    fn fork_with_vm(
+        &self,
+        tf: &TrapFrame,
+        vm: Arc<Mutex<MemorySet>>,
+        vfork_done: Option<Arc<VforkDone>>,
+    ) -> Box<Thread> {
+        let kstack = KernelStack::new();
+        let vm_token = vm.lock().token();
         let context = unsafe { Context::new_fork(tf, kstack.top(), vm_token) };
 
         let mut proc = self.proc.lock();
-        let new_proc = Process {
+        let new_proc = Arc::new(Mutex::new(Process {
             vm: vm.clone(),
-            files: proc.files.clone(),
+            // the fds of the child share open file descriptions with the parent
+            files: fork_files(&proc.files),
             cwd: proc.cwd.clone(),
+            umask: proc.umask,
             exec_path: proc.exec_path.clone(),
-            futexes: BTreeMap::default(),
+            exec_args: proc.exec_args.clone(),
+            // the forking thread is the first thread of the child
+            comm: self.name,
             semaphores: proc.semaphores.clone(),
+            shm: proc.shm.clone(),
+            cred: proc.cred.clone(),
             pid: Pid(0),
+            pgid: proc.pgid,
+            sid: proc.sid,
             parent: Arc::downgrade(&self.proc),
             children: Vec::new(),
             threads: Vec::new(),
-            child_exit: Arc::new(Condvar::new()),
+            child_exit: Arc::new(WaitQueue::new()),
             child_exit_code: BTreeMap::new(),
-        }
-        .add_to_table();
+            child_exit_usage: BTreeMap::new(),
+            brk_start: proc.brk_start,
+            brk: proc.brk,
+            utime: 0,
+            stime: 0,
+            cutime: 0,
+            cstime: 0,
+            max_rss: 0,
+            children_max_rss: 0,
+            rlimits: proc.rlimits,
+            signal_actions: proc.signal_actions,
+            pending_signals: 0,
+            vfork_done,
+        }));
         // link to parent
         proc.children.push(Arc::downgrade(&new_proc));
 
@@ -354,6 +622,13 @@ impl Thread {
             context,
             kstack,
             clear_child_tid: 0,
+            sig_mask: self.sig_mask,
+            sig_alt_stack: self.sig_alt_stack,
+            pending_signals: 0,
+            segv_info: None,
+            name: self.name,
+            utime: 0,
+            stime: 0,
             vm,
             proc: new_proc,
         })
@@ -373,6 +648,13 @@ impl Thread {
             context: unsafe { Context::new_clone(tf, stack_top, kstack.top(), vm_token, tls) },
             kstack,
             clear_child_tid,
+            sig_mask: self.sig_mask,
+            sig_alt_stack: SignalStack::disabled(),
+            pending_signals: 0,
+            segv_info: None,
+            name: self.name,
+            utime: 0,
+            stime: 0,
             vm: self.vm.clone(),
             proc: self.proc.clone(),
         })
@@ -380,53 +662,182 @@ impl Thread {
 }
 
 impl Process {
-    /// Assign a pid and put itself to global process table.
-    fn add_to_table(mut self) -> Arc<Mutex<Self>> {
-        let mut process_table = PROCESSES.write();
-
-        // assign pid
-        let pid = (0..)
-            .find(|i| match process_table.get(i) {
-                Some(p) => false,
-                _ => true,
-            })
-            .unwrap();
+    /// Take `pid`, the tid of its first thread,
+    /// and put itself to global process table.
+    fn add_to_table(&mut self, self_ref: &Arc<Mutex<Self>>, pid: usize) {
         self.pid = Pid(pid);
+        // a process without parent leads its own process group and session
+        if self.parent.upgrade().is_none() {
+            self.pgid = pid;
+            self.sid = pid;
+        }
+        PROCESSES.write().insert(pid, Arc::downgrade(self_ref));
+    }
 
-        // put to process table
-        let self_ref = Arc::new(Mutex::new(self));
-        process_table.insert(pid, Arc::downgrade(&self_ref));
+    /// Lead a new session in a new process group, without a controlling terminal.
+    /// Return the new session id.
+    pub fn set_sid(&mut self) -> Result<usize, SysError> {
+        let pid = self.pid.get();
+        // a group leader can not leave its group
+        if self.pgid == pid {
+            return Err(SysError::EPERM);
+        }
+        self.pgid = pid;
+        self.sid = pid;
+        Ok(pid)
+    }
 
-        self_ref
+    /// Wake up the parent sleeping in vfork, if this is a vfork child.
+    pub fn release_vfork_parent(&mut self) {
+        if let Some(vfork_done) = self.vfork_done.take() {
+            vfork_done.notify();
+        }
     }
+
+    /// Remove exited process `pid` from global process table,
+    /// once it is waited or will never be.
+    /// The first thread of a process is not detached,
+    /// so the pid is not taken by a new process until then.
+    pub fn remove_from_table(pid: usize) {
+        PROCESSES.write().remove(&pid);
+        REAPED_PIDS.lock().push(pid);
+        Process::release_reaped_pids();
+    }
+
+    /// Give the pids of removed processes back to the thread pool as free tids,
+    /// when their first threads have stopped.
+    /// Called before adding a thread, in case one was still running when removed.
+    pub fn release_reaped_pids() {
+        REAPED_PIDS
+            .lock()
+            .retain(|&pid| processor().manager().try_remove(pid).is_none());
+    }
+
     fn get_free_fd(&self) -> usize {
-        (0..).find(|i| !self.files.contains_key(i)).unwrap()
+        self.get_free_fd_from(0)
+    }
+    /// Get the lowest free fd that is no less than `start`.
+    pub fn get_free_fd_from(&self, start: usize) -> usize {
+        (start..).find(|i| !self.files.contains_key(i)).unwrap()
     }
     /// Add a file to the process, return its fd.
-    pub fn add_file(&mut self, file_like: FileLike) -> usize {
+    /// Fail with `EMFILE` if there is no fd under `RLIMIT_NOFILE`.
+    pub fn add_file(&mut self, file_like: FileLike) -> Result<usize, SysError> {
         let fd = self.get_free_fd();
+        if fd >= self.max_fd() {
+            return Err(SysError::EMFILE);
+        }
         self.files.insert(fd, file_like);
-        fd
+        Ok(fd)
+    }
+    /// Fds must be less than this, according to `RLIMIT_NOFILE`
+    pub fn max_fd(&self) -> usize {
+        self.rlimits[RLIMIT_NOFILE].cur as usize
+    }
+    /// Make `signal` pending and wake up all threads,
+    /// so that they can be interrupted and handle it.
+    /// Init only receives signals it has handlers for,
+    /// so SIGKILL and SIGSTOP are dropped for it as on Linux.
+    pub fn send_signal(&mut self, signal: usize) {
+        if is_ignored(signal, &self.signal_actions[signal]) {
+            return;
+        }
+        if self.pid.is_init() && self.signal_actions[signal].handler == SIG_DFL {
+            return;
+        }
+        self.queue_signal(signal);
+    }
+
+    /// Make `signal` pending with the default action, even if it is ignored or sent to init,
+    /// for a fault after which the process can not go on
+    pub fn force_signal(&mut self, signal: usize) {
+        self.signal_actions[signal] = SignalAction::default();
+        self.queue_signal(signal);
+    }
+
+    fn queue_signal(&mut self, signal: usize) {
+        self.pending_signals |= sigmask(signal);
+        for &tid in self.threads.iter() {
+            processor().manager().wakeup(tid);
+        }
+    }
+
+    /// Sample the resident set size to update the peak
+    pub fn update_max_rss(&mut self) {
+        let rss = self.vm.lock().resident_pages() * PAGE_SIZE;
+        self.max_rss = self.max_rss.max(rss);
     }
-    pub fn get_futex(&mut self, uaddr: usize) -> Arc<Condvar> {
-        if !self.futexes.contains_key(&uaddr) {
-            self.futexes.insert(uaddr, Arc::new(Condvar::new()));
+
+    fn usage(&mut self) -> ChildUsage {
+        self.update_max_rss();
+        ChildUsage {
+            utime: self.utime + self.cutime,
+            stime: self.stime + self.cstime,
+            max_rss: self.max_rss.max(self.children_max_rss),
+            pgid: self.pgid,
         }
-        self.futexes.get(&uaddr).unwrap().clone()
     }
 
-    /// Exit the process.
+    /// Exit the process normally.
     /// Kill all threads and notify parent with the exit code.
-    pub fn exit(&mut self, exit_code: usize) {
+    /// The process must not be locked.
+    pub fn exit(this: &Arc<Mutex<Process>>, exit_code: usize) {
+        Process::exit_with_status(this, (exit_code & 0xff) << 8);
+    }
+
+    /// Exit the process because of an uncaught signal.
+    /// The process must not be locked.
+    pub fn exit_by_signal(this: &Arc<Mutex<Process>>, signal: usize) {
+        Process::exit_with_status(this, signal & 0x7f);
+    }
+
+    /// The `status` is encoded in the layout of `wait4`:
+    /// exit code in bits 8-15, or terminating signal in bits 0-6.
+    ///
+    /// A parent is locked before its children, as in `wait4`,
+    /// so the process is unlocked before notifying its parent.
+    fn exit_with_status(this: &Arc<Mutex<Process>>, status: usize) {
+        let mut proc = this.lock();
+        let pid = proc.pid.get();
         // quit all threads
-        for tid in self.threads.iter() {
+        for tid in proc.threads.iter() {
             processor().manager().exit(*tid, 1);
         }
+        proc.release_vfork_parent();
+        release_process_locks(pid);
+        set_real_timer(pid, 0, 0);
+        proc.shm.clear(pid);
+        proc.semaphores.undo(pid);
+        let usage = proc.usage();
+        let parent = proc.parent.upgrade();
+        let children = core::mem::replace(&mut proc.children, Vec::new());
+        // nobody will wait for the exited children any more
+        for &pid in proc.child_exit_code.keys() {
+            Process::remove_from_table(pid);
+        }
+        proc.child_exit_code.clear();
+        drop(proc);
+
         // notify parent and fill exit code
-        if let Some(parent) = self.parent.upgrade() {
+        if let Some(parent) = parent {
             let mut parent = parent.lock();
-            parent.child_exit_code.insert(self.pid.get(), exit_code);
-            parent.child_exit.notify_one();
+            parent.child_exit_code.insert(pid, status);
+            parent.child_exit_usage.insert(pid, usage);
+            parent.child_exit.wake_all();
+        }
+        // reparent running children to init
+        let init = PROCESSES
+            .read()
+            .get(&INIT_PID)
+            .and_then(|weak| weak.upgrade());
+        if let Some(init) = init {
+            if pid != INIT_PID {
+                for child in children.iter().filter_map(|weak| weak.upgrade()) {
+                    // a child exiting from now on notifies init
+                    child.lock().parent = Arc::downgrade(&init);
+                    init.lock().children.push(Arc::downgrade(&child));
+                }
+            }
         }
     }
 }
@@ -450,11 +861,14 @@ impl ToMemoryAttr for Flags {
 
 /// Helper functions to process ELF file
 trait ElfExt {
+    /// Check the ELF file is an executable or shared object for this arch.
+    fn check_loadable(&self) -> Result<(), &'static str>;
+
     /// Generate a MemorySet according to the ELF file.
-    fn make_memory_set(&self, inode: &Arc<dyn INode>) -> (MemorySet, usize);
+    fn make_memory_set(&self, inode: &Arc<dyn INode>) -> Result<(MemorySet, usize), &'static str>;
 
     /// Get interpreter string if it has.
-    fn get_interpreter(&self) -> Result<&str, &str>;
+    fn get_interpreter(&self) -> Result<Option<&str>, &'static str>;
 
     /// Append current ELF file as interpreter into given memory set.
     /// This will insert the interpreter it a place which is "good enough" (since ld.so should be PIC).
@@ -463,14 +877,37 @@ trait ElfExt {
         inode: &Arc<dyn INode>,
         memory_set: &mut MemorySet,
         bias: usize,
-    );
+    ) -> Result<(), &'static str>;
 
     /// Get virtual address of PHDR section if it has.
     fn get_phdr_vaddr(&self) -> Option<u64>;
 }
 
 impl ElfExt for ElfFile<'_> {
-    fn make_memory_set(&self, inode: &Arc<dyn INode>) -> (MemorySet, usize) {
+    fn check_loadable(&self) -> Result<(), &'static str> {
+        // Check ELF type
+        match self.header.pt2.type_().as_type() {
+            header::Type::Executable => {}
+            header::Type::SharedObject => {}
+            _ => return Err("ELF is not executable or shared object"),
+        }
+
+        // Check ELF arch
+        match self.header.pt2.machine().as_machine() {
+            #[cfg(target_arch = "x86_64")]
+            header::Machine::X86_64 => {}
+            #[cfg(target_arch = "aarch64")]
+            header::Machine::AArch64 => {}
+            #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+            header::Machine::Other(243) => {}
+            #[cfg(target_arch = "mips")]
+            header::Machine::Mips => {}
+            _ => return Err("invalid ELF arch"),
+        }
+        Ok(())
+    }
+
+    fn make_memory_set(&self, inode: &Arc<dyn INode>) -> Result<(MemorySet, usize), &'static str> {
         debug!("creating MemorySet from ELF");
         let mut ms = MemorySet::new();
         let mut farthest_memory: usize = 0;
@@ -478,66 +915,76 @@ impl ElfExt for ElfFile<'_> {
             if ph.get_type() != Ok(Type::Load) {
                 continue;
             }
+            let (start, end, file_end) = check_load_segment(&ms, &ph, 0)?;
             ms.push(
-                ph.virtual_addr() as usize,
-                ph.virtual_addr() as usize + ph.mem_size() as usize,
+                start,
+                end,
                 ph.flags().to_attr(),
                 File {
                     file: INodeForMap(inode.clone()),
-                    mem_start: ph.virtual_addr() as usize,
+                    mem_start: start,
                     file_start: ph.offset() as usize,
-                    file_end: ph.offset() as usize + ph.file_size() as usize,
+                    file_end,
                     allocator: GlobalFrameAlloc,
                 },
                 "elf",
             );
-            if ph.virtual_addr() as usize + ph.mem_size() as usize > farthest_memory {
-                farthest_memory = ph.virtual_addr() as usize + ph.mem_size() as usize;
+            if end > farthest_memory {
+                farthest_memory = end;
             }
         }
-        (
+        Ok((
             ms,
             (Page::of_addr(farthest_memory + PAGE_SIZE)).start_address(),
-        )
+        ))
     }
-    fn append_as_interpreter(&self, inode: &Arc<dyn INode>, ms: &mut MemorySet, bias: usize) {
+    fn append_as_interpreter(
+        &self,
+        inode: &Arc<dyn INode>,
+        ms: &mut MemorySet,
+        bias: usize,
+    ) -> Result<(), &'static str> {
         debug!("inserting interpreter from ELF");
 
         for ph in self.program_iter() {
             if ph.get_type() != Ok(Type::Load) {
                 continue;
             }
+            let (start, end, file_end) = check_load_segment(ms, &ph, bias)?;
             ms.push(
-                ph.virtual_addr() as usize + bias,
-                ph.virtual_addr() as usize + ph.mem_size() as usize + bias,
+                start,
+                end,
                 ph.flags().to_attr(),
                 File {
                     file: INodeForMap(inode.clone()),
-                    mem_start: ph.virtual_addr() as usize + bias,
+                    mem_start: start,
                     file_start: ph.offset() as usize,
-                    file_end: ph.offset() as usize + ph.file_size() as usize,
+                    file_end,
                     allocator: GlobalFrameAlloc,
                 },
                 "elf-interp",
             )
         }
+        Ok(())
     }
-    fn get_interpreter(&self) -> Result<&str, &str> {
-        let header = self
+    fn get_interpreter(&self) -> Result<Option<&str>, &'static str> {
+        let header = match self
             .program_iter()
-            .filter(|ph| ph.get_type() == Ok(Type::Interp))
-            .next()
-            .ok_or("no interp header")?;
+            .find(|ph| ph.get_type() == Ok(Type::Interp))
+        {
+            Some(header) => header,
+            None => return Ok(None),
+        };
         let mut data = match header.get_data(self)? {
             SegmentData::Undefined(data) => data,
-            _ => unreachable!(),
+            _ => return Err("invalid interp header"),
         };
         // skip NULL
         while let Some(0) = data.last() {
             data = &data[..data.len() - 1];
         }
         let path = str::from_utf8(data).map_err(|_| "failed to convert to utf8")?;
-        Ok(path)
+        Ok(Some(path))
     }
 
     fn get_phdr_vaddr(&self) -> Option<u64> {
@@ -560,11 +1007,106 @@ impl ElfExt for ElfFile<'_> {
     }
 }
 
+/// Check a PT_LOAD segment to be loaded at `bias` into `ms`.
+/// Return its start and end address in memory, and its end offset in the file.
+fn check_load_segment(
+    ms: &MemorySet,
+    ph: &ProgramHeader,
+    bias: usize,
+) -> Result<(usize, usize, usize), &'static str> {
+    let mem_size = ph.mem_size() as usize;
+    let file_size = ph.file_size() as usize;
+    if mem_size == 0 {
+        return Err("empty segment");
+    }
+    if file_size > mem_size {
+        return Err("segment file size is larger than its memory size");
+    }
+    let start = (ph.virtual_addr() as usize)
+        .checked_add(bias)
+        .ok_or("segment address overflow")?;
+    let end = start
+        .checked_add(mem_size)
+        .ok_or("segment address overflow")?;
+    let file_end = (ph.offset() as usize)
+        .checked_add(file_size)
+        .ok_or("segment offset overflow")?;
+    if end > USER_END {
+        return Err("segment is not in user space");
+    }
+    if ms.iter().any(|area| area.is_overlap_with(start, end)) {
+        return Err("overlapping segments");
+    }
+    Ok((start, end, file_end))
+}
+
 #[derive(Clone)]
 pub struct INodeForMap(pub Arc<dyn INode>);
 
+/// A failed read leaves the rest of the page zeroed
 impl Read for INodeForMap {
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        self.0.read_at(offset, buf).unwrap()
+        self.0.read_at(offset, buf).unwrap_or_else(|err| {
+            warn!("failed to read mapped file at {:#x}: {:?}", offset, err);
+            0
+        })
+    }
+}
+
+/// A failed write back is dropped, as the page has nowhere else to go
+impl Write for INodeForMap {
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        self.0.write_at(offset, buf).unwrap_or_else(|err| {
+            warn!(
+                "failed to write back mapped file at {:#x}: {:?}",
+                offset, err
+            );
+            0
+        })
+    }
+}
+
+pub mod test {
+    //! The parent of vfork resuming when the child execs or exits
+
+    use super::*;
+    use crate::thread;
+
+    extern "C" fn never_run(_arg: usize) -> ! {
+        unreachable!()
+    }
+
+    /// The child runs in the address space of the parent,
+    /// which waits until the child leaves it by exec or exit.
+    /// It hangs if the parent is not woken up.
+    fn vfork_then_exec_or_exit() {
+        let parent = Thread::new_kernel(never_run, 0);
+        let tf: TrapFrame = unsafe { core::mem::zeroed() };
+
+        let (child, vfork_done) = parent.vfork(&tf);
+        assert!(Arc::ptr_eq(&child.vm, &parent.vm));
+        assert!(Arc::ptr_eq(&child.proc.lock().vm, &parent.vm));
+        let waiter = thread::spawn(move || vfork_done.wait());
+        thread::yield_now();
+        // exec gives the address space back
+        child.proc.lock().release_vfork_parent();
+        waiter.join().expect("parent should resume on exec");
+        assert!(child.proc.lock().vfork_done.is_none());
+
+        let (child, vfork_done) = parent.vfork(&tf);
+        let waiter = thread::spawn(move || vfork_done.wait());
+        thread::yield_now();
+        Process::exit(&child.proc, 0);
+        waiter.join().expect("parent should resume on exit");
+
+        // fork copies the address space instead
+        let child = parent.fork(&tf);
+        assert!(!Arc::ptr_eq(&child.vm, &parent.vm));
+        assert!(child.proc.lock().vfork_done.is_none());
+    }
+
+    pub fn test_all() {
+        vfork_then_exec_or_exit();
+        println!("vfork test end");
     }
 }
@@ -0,0 +1,424 @@
+//! Signal delivery
+//!
+//! Signals sent to a process are recorded as pending in the process,
+//! and delivered by the first thread returning to user mode.
+
+use crate::arch::interrupt::TrapFrame;
+use crate::consts::USER_STACK_OFFSET;
+use crate::memory::grow_user_stack;
+use crate::process::{current_thread, processor, Process};
+use bitflags::*;
+use core::mem::size_of;
+use rcore_memory::PAGE_SIZE;
+
+/// Signals are numbered from 1 to `SIGNAL_NUM`
+pub const SIGNAL_NUM: usize = 64;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGSEGV: usize = 11;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+
+#[cfg(not(target_arch = "mips"))]
+mod numbers {
+    pub const SIGBUS: usize = 7;
+    pub const SIGUSR1: usize = 10;
+    pub const SIGUSR2: usize = 12;
+    pub const SIGCHLD: usize = 17;
+    pub const SIGCONT: usize = 18;
+    pub const SIGSTOP: usize = 19;
+    pub const SIGTSTP: usize = 20;
+    pub const SIGTTIN: usize = 21;
+    pub const SIGTTOU: usize = 22;
+    pub const SIGURG: usize = 23;
+    pub const SIGWINCH: usize = 28;
+}
+
+#[cfg(target_arch = "mips")]
+mod numbers {
+    pub const SIGBUS: usize = 10;
+    pub const SIGUSR1: usize = 16;
+    pub const SIGUSR2: usize = 17;
+    pub const SIGCHLD: usize = 18;
+    pub const SIGWINCH: usize = 20;
+    pub const SIGURG: usize = 21;
+    pub const SIGSTOP: usize = 23;
+    pub const SIGTSTP: usize = 24;
+    pub const SIGCONT: usize = 25;
+    pub const SIGTTIN: usize = 26;
+    pub const SIGTTOU: usize = 27;
+}
+
+pub use self::numbers::*;
+
+/// Signals which can never be blocked
+pub fn unblockable() -> Sigset {
+    sigmask(SIGKILL) | sigmask(SIGSTOP)
+}
+
+/// Default action
+pub const SIG_DFL: usize = 0;
+/// Ignore the signal
+pub const SIG_IGN: usize = 1;
+
+/// A set of signals, signal `n` is at bit `n - 1`
+pub type Sigset = u64;
+
+/// The `sigsetsize` expected from user programs
+#[cfg(not(target_arch = "mips"))]
+pub const SIGSET_SIZE: usize = 8;
+#[cfg(target_arch = "mips")]
+pub const SIGSET_SIZE: usize = 16;
+
+/// Get the set containing only `signal`
+pub fn sigmask(signal: usize) -> Sigset {
+    1 << (signal - 1)
+}
+
+bitflags! {
+    pub struct SignalActionFlags: usize {
+        const NOCLDSTOP = 1;
+        const RESTORER = 0x04000000;
+        const ONSTACK = 0x08000000;
+        const RESTART = 0x10000000;
+        const NODEFER = 0x40000000;
+        const RESETHAND = 0x80000000;
+    }
+}
+
+/// `struct sigaction` used by rt_sigaction
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub restorer: usize,
+    pub mask: Sigset,
+}
+
+/// `struct sigaction` used by rt_sigaction
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignalAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: Sigset,
+}
+
+/// `struct sigaction` used by rt_sigaction
+#[cfg(target_arch = "mips")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignalAction {
+    pub flags: usize,
+    pub handler: usize,
+    pub mask: Sigset,
+    /// signals above 64 are not supported
+    pub mask_high: u64,
+}
+
+impl SignalAction {
+    pub fn flags(&self) -> SignalActionFlags {
+        SignalActionFlags::from_bits_truncate(self.flags)
+    }
+
+    /// The user provided function to return from the handler
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn restorer(&self) -> Option<usize> {
+        if self.flags().contains(SignalActionFlags::RESTORER) && self.restorer != 0 {
+            Some(self.restorer)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    fn restorer(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// `stack_t`
+#[cfg(not(target_arch = "mips"))]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignalStack {
+    pub sp: usize,
+    pub flags: u32,
+    pub size: usize,
+}
+
+/// `stack_t`
+#[cfg(target_arch = "mips")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SignalStack {
+    pub sp: usize,
+    pub size: usize,
+    pub flags: u32,
+}
+
+bitflags! {
+    pub struct SignalStackFlags: u32 {
+        const ONSTACK = 1;
+        const DISABLE = 2;
+    }
+}
+
+impl SignalStack {
+    /// An alternate stack which is not in use
+    pub fn disabled() -> Self {
+        SignalStack {
+            sp: 0,
+            flags: SignalStackFlags::DISABLE.bits(),
+            size: 0,
+        }
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        SignalStackFlags::from_bits_truncate(self.flags).contains(SignalStackFlags::DISABLE)
+    }
+
+    /// Whether `sp` points into this stack
+    pub fn contains(&self, sp: usize) -> bool {
+        !self.is_disabled() && sp > self.sp && sp <= self.sp + self.size
+    }
+}
+
+/// Minimal size of an alternate signal stack
+#[cfg(not(target_arch = "aarch64"))]
+pub const MINSIGSTKSZ: usize = 2048;
+#[cfg(target_arch = "aarch64")]
+pub const MINSIGSTKSZ: usize = 5120;
+
+/// `siginfo_t`, only the signal number is filled,
+/// and the faulting address for SIGSEGV
+#[repr(C)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    pub code: i32,
+    pub addr: usize,
+    _pad: [u8; SIGINFO_PAD],
+}
+
+/// Size of `SigInfo::_pad` to make `siginfo_t` 128 bytes
+#[cfg(target_pointer_width = "64")]
+const SIGINFO_PAD: usize = 104;
+#[cfg(target_pointer_width = "32")]
+const SIGINFO_PAD: usize = 112;
+
+/// `si_code` of signals sent by kill
+pub const SI_USER: i32 = 0;
+/// `si_code` of SIGSEGV: address not mapped
+pub const SEGV_MAPERR: i32 = 1;
+/// `si_code` of SIGSEGV: invalid permissions for mapped object
+pub const SEGV_ACCERR: i32 = 2;
+
+/// `ucontext_t`, but the machine context is our own `TrapFrame`
+#[repr(C)]
+pub struct SignalUserContext {
+    pub flags: usize,
+    pub link: usize,
+    pub stack: SignalStack,
+    pub context: TrapFrame,
+    pub mask: Sigset,
+}
+
+/// Pushed to the user stack before calling a handler
+#[repr(C)]
+pub struct SignalFrame {
+    pub info: SigInfo,
+    pub ucontext: SignalUserContext,
+}
+
+/// The page mapped below user stack,
+/// which handlers without a restorer return to.
+pub const SIGNAL_TRAMPOLINE: usize = USER_STACK_OFFSET - PAGE_SIZE;
+
+/// Skip the red zone below the interrupted sp
+const REDZONE_SIZE: usize = 128;
+
+/// Whether `signal` with `action` is discarded without any effect
+pub fn is_ignored(signal: usize, action: &SignalAction) -> bool {
+    match action.handler {
+        SIG_IGN => true,
+        SIG_DFL => is_default_ignored(signal),
+        _ => false,
+    }
+}
+
+/// Signals which are ignored by default
+fn is_default_ignored(signal: usize) -> bool {
+    match signal {
+        SIGCHLD | SIGCONT | SIGURG | SIGWINCH => true,
+        _ => false,
+    }
+}
+
+/// Signals which stop the process by default
+fn is_default_stop(signal: usize) -> bool {
+    match signal {
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => true,
+        _ => false,
+    }
+}
+
+/// Whether current thread has a pending signal which is not blocked.
+/// The process of current thread must not be locked.
+pub fn has_signal_to_do() -> bool {
+    let thread = unsafe { current_thread() };
+    (thread.proc.lock().pending_signals | thread.pending_signals) & !thread.sig_mask != 0
+}
+
+/// Deliver pending signals to current thread.
+///
+/// Called at the end of a trap, modifying `tf` to enter the handler
+/// when the thread returns to user mode.
+pub fn handle_signal(tf: &mut TrapFrame) {
+    if !tf.is_user() {
+        return;
+    }
+    let thread = unsafe { current_thread() };
+    loop {
+        let mut proc = thread.proc.lock();
+        let thread_deliverable = thread.pending_signals & !thread.sig_mask;
+        let signal = if thread_deliverable != 0 {
+            let signal = thread_deliverable.trailing_zeros() as usize + 1;
+            thread.pending_signals &= !sigmask(signal);
+            signal
+        } else {
+            let deliverable = proc.pending_signals & !thread.sig_mask;
+            if deliverable == 0 {
+                return;
+            }
+            let signal = deliverable.trailing_zeros() as usize + 1;
+            proc.pending_signals &= !sigmask(signal);
+            signal
+        };
+        let action = proc.signal_actions[signal];
+        info!("handle signal {} of process {}", signal, proc.pid);
+
+        match action.handler {
+            SIG_IGN => continue,
+            SIG_DFL if is_default_ignored(signal) => continue,
+            SIG_DFL if is_default_stop(signal) => {
+                warn!(
+                    "stopping a process is unimplemented, ignore signal {}",
+                    signal
+                );
+                continue;
+            }
+            SIG_DFL => {
+                drop(proc);
+                Process::exit_by_signal(&thread.proc, signal);
+                processor().yield_now();
+                unreachable!();
+            }
+            handler => {
+                if action.flags().contains(SignalActionFlags::RESETHAND) {
+                    proc.signal_actions[signal] = SignalAction::default();
+                }
+                drop(proc);
+                if !setup_frame(tf, signal, handler, &action) {
+                    warn!("failed to push signal frame, kill the process");
+                    Process::exit_by_signal(&thread.proc, SIGSEGV);
+                    processor().yield_now();
+                    unreachable!();
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Push a `SignalFrame` to the user stack and set `tf` to call `handler`.
+/// Return false if the stack is not writable.
+fn setup_frame(tf: &mut TrapFrame, signal: usize, handler: usize, action: &SignalAction) -> bool {
+    let thread = unsafe { current_thread() };
+    let old_mask = thread.sig_mask;
+    let alt_stack = thread.sig_alt_stack;
+    let on_alt_stack = alt_stack.contains(tf.get_sp());
+    let sp = if action.flags().contains(SignalActionFlags::ONSTACK)
+        && !alt_stack.is_disabled()
+        && !on_alt_stack
+    {
+        alt_stack.sp + alt_stack.size
+    } else {
+        tf.get_sp() - REDZONE_SIZE
+    };
+    let frame_addr = (sp - size_of::<SignalFrame>()) & !0xf;
+    // leave 16 bytes below the frame for the return address
+    let frame_start = frame_addr - 16;
+    let frame_len = sp - frame_start;
+    {
+        let mut vm = thread.vm.lock();
+        // the frame may be below the part of user stack in use
+        grow_user_stack(&mut vm, frame_start, Some(tf.get_sp()));
+        if unsafe { vm.check_write_array(frame_start as *mut u8, frame_len) }.is_err() {
+            return false;
+        }
+    }
+    let (addr, code) = match signal {
+        SIGSEGV => thread.segv_info.take(),
+        _ => None,
+    }
+    .unwrap_or((0, SI_USER));
+
+    let frame = SignalFrame {
+        info: SigInfo {
+            signo: signal as i32,
+            errno: 0,
+            code,
+            addr,
+            _pad: [0; SIGINFO_PAD],
+        },
+        ucontext: SignalUserContext {
+            flags: 0,
+            link: 0,
+            stack: SignalStack {
+                flags: if on_alt_stack {
+                    SignalStackFlags::ONSTACK.bits()
+                } else {
+                    alt_stack.flags
+                },
+                ..alt_stack
+            },
+            context: tf.clone(),
+            mask: old_mask,
+        },
+    };
+    // block the signal itself while its handler runs
+    let mut handler_mask = action.mask;
+    if !action.flags().contains(SignalActionFlags::NODEFER) {
+        handler_mask |= sigmask(signal);
+    }
+    thread.sig_mask |= handler_mask & !unblockable();
+    // the vm lock is released, since writing may cause page faults
+    unsafe {
+        let frame_ptr = frame_addr as *mut SignalFrame;
+        frame_ptr.write(frame);
+        let ret = action.restorer().unwrap_or(SIGNAL_TRAMPOLINE);
+        tf.enter_signal_handler(
+            handler,
+            frame_addr,
+            ret,
+            [
+                signal,
+                &(*frame_ptr).info as *const _ as usize,
+                &(*frame_ptr).ucontext as *const _ as usize,
+            ],
+        );
+    }
+    true
+}
@@ -20,6 +20,9 @@
 //! * `mpsc`: 消息传递通道。
 //!     多生产者-单消费者的FIFO队列。用于在线程间传递数据。
 //!
+//! * `wait_queue`: 等待队列。
+//!     阻塞的系统调用在队列上睡眠，直到被唤醒且条件满足，或收到信号。
+//!
 //! * `test`: 测试。
 //!     目前分别用`Mutex`和`Condvar`(Monitor)实现了哲学家就餐问题。
 //!
@@ -42,6 +45,8 @@
 //!	    Semaphore --> SpinLock
 //!	    mpsc --> SpinLock
 //!     mpsc --> Condvar
+//!     WaitQueue --> SpinLock
+//!     WaitQueue --> thread
 //!	end
 //! subgraph test
 //!	    Dining_Philosophers --> Mutex
@@ -53,9 +58,11 @@
 pub use self::condvar::*;
 pub use self::mutex::*;
 pub use self::semaphore::*;
+pub use self::wait_queue::WaitQueue;
 
 mod condvar;
 pub mod mpsc;
 mod mutex;
 mod semaphore;
 pub mod test;
+pub mod wait_queue;
@@ -0,0 +1,212 @@
+//! Wait queue for blocking system calls
+//!
+//! A thread sleeps on one or more queues, out of the run queue,
+//! until it is woken up and its condition holds.
+//! `wake_one` takes the first waiter off the queue, so successive wakes
+//! reach different threads, and an interruptible wait ends with EINTR
+//! when the thread has a signal to handle.
+
+use super::*;
+use crate::process::processor;
+use crate::signal::has_signal_to_do;
+use crate::syscall::SysError;
+use crate::thread;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+#[derive(Default)]
+pub struct WaitQueue {
+    waiters: SpinNoIrqLock<VecDeque<Arc<thread::Thread>>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        WaitQueue::default()
+    }
+
+    /// Sleep until `condition` returns Some.
+    /// It is checked before sleeping, and again after each wakeup.
+    pub fn wait_until<T>(&self, condition: impl FnMut() -> Option<T>) -> T {
+        Self::wait_any_until(&[self], condition)
+    }
+
+    /// Sleep on all of `queues` until `condition` returns Some
+    pub fn wait_any_until<T>(queues: &[&WaitQueue], mut condition: impl FnMut() -> Option<T>) -> T {
+        if let Some(result) = condition() {
+            return result;
+        }
+        let thread = thread::current();
+        let tid = thread.id();
+        let token = Arc::new(thread);
+        loop {
+            {
+                // a wake can not slip in between queueing and marking as sleeping
+                let mut locks: Vec<_> = queues.iter().map(|queue| queue.waiters.lock()).collect();
+                for waiters in locks.iter_mut() {
+                    // a wake takes it off the queue
+                    if !waiters.iter().any(|t| Arc::ptr_eq(t, &token)) {
+                        waiters.push_back(token.clone());
+                    }
+                }
+                processor().manager().sleep(tid, 0);
+            }
+            if let Some(result) = condition() {
+                processor().manager().cancel_sleeping(tid);
+                for queue in queues {
+                    queue.waiters.lock().retain(|t| !Arc::ptr_eq(t, &token));
+                }
+                return result;
+            }
+            processor().yield_now();
+        }
+    }
+
+    /// Sleep until `condition` returns Some,
+    /// or fail with EINTR when current thread has a signal to handle.
+    /// The process of current thread must not be locked.
+    pub fn wait_interruptible<T>(
+        &self,
+        condition: impl FnMut() -> Option<Result<T, SysError>>,
+    ) -> Result<T, SysError> {
+        Self::wait_any_interruptible(&[self], condition)
+    }
+
+    /// Sleep on all of `queues` until `condition` returns Some, or a signal arrives
+    pub fn wait_any_interruptible<T>(
+        queues: &[&WaitQueue],
+        mut condition: impl FnMut() -> Option<Result<T, SysError>>,
+    ) -> Result<T, SysError> {
+        Self::wait_any_until(queues, || {
+            if let Some(result) = condition() {
+                return Some(result);
+            }
+            if has_signal_to_do() {
+                return Some(Err(SysError::EINTR));
+            }
+            None
+        })
+    }
+
+    /// Unlock `guard` and sleep until woken up, then lock it again
+    pub fn wait<'a, T, S>(&self, guard: MutexGuard<'a, T, S>) -> MutexGuard<'a, T, S>
+    where
+        S: MutexSupport,
+    {
+        let mutex = guard.mutex;
+        let token = Arc::new(thread::current());
+        let mut waiters = self.waiters.lock();
+        waiters.push_back(token.clone());
+
+        thread::park_action(move || {
+            drop(waiters);
+            drop(guard);
+        });
+        let ret = mutex.lock();
+        self.waiters.lock().retain(|t| !Arc::ptr_eq(t, &token));
+        ret
+    }
+
+    /// Wake up the first waiter, if any
+    pub fn wake_one(&self) {
+        if let Some(t) = self.waiters.lock().pop_front() {
+            t.unpark();
+        }
+    }
+
+    /// Wake up all waiters
+    pub fn wake_all(&self) {
+        for t in self.waiters.lock().drain(..) {
+            t.unpark();
+        }
+    }
+}
+
+pub mod test {
+    //! Many readers blocked on a queue, and one writer
+
+    use super::*;
+    use alloc::vec::Vec;
+
+    const READERS: usize = 16;
+    const ITEMS_PER_READER: usize = 100;
+
+    struct Channel {
+        /// Number of items written and not read yet
+        items: SpinNoIrqLock<usize>,
+        readable: WaitQueue,
+    }
+
+    /// Every item is read exactly once, and each reader gets its share.
+    /// It hangs if a wakeup is lost, or a reader is never woken up.
+    fn many_readers_one_writer() {
+        let channel = Arc::new(Channel {
+            items: SpinNoIrqLock::new(0),
+            readable: WaitQueue::new(),
+        });
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let channel = channel.clone();
+                thread::spawn(move || {
+                    for _ in 0..ITEMS_PER_READER {
+                        channel.readable.wait_until(|| {
+                            let mut items = channel.items.lock();
+                            if *items > 0 {
+                                *items -= 1;
+                                Some(())
+                            } else {
+                                None
+                            }
+                        });
+                    }
+                })
+            })
+            .collect();
+        for i in 0..READERS * ITEMS_PER_READER {
+            *channel.items.lock() += 1;
+            channel.readable.wake_one();
+            if i % READERS == 0 {
+                thread::yield_now();
+            }
+        }
+        for reader in readers {
+            reader.join().expect("reader should finish");
+        }
+        assert_eq!(*channel.items.lock(), 0);
+        assert!(channel.readable.waiters.lock().is_empty());
+    }
+
+    /// All blocked readers wake up at once
+    fn wake_all() {
+        let channel = Arc::new(Channel {
+            items: SpinNoIrqLock::new(0),
+            readable: WaitQueue::new(),
+        });
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let channel = channel.clone();
+                thread::spawn(move || {
+                    channel.readable.wait_until(|| {
+                        if *channel.items.lock() > 0 {
+                            Some(())
+                        } else {
+                            None
+                        }
+                    });
+                })
+            })
+            .collect();
+        thread::yield_now();
+        *channel.items.lock() = 1;
+        channel.readable.wake_all();
+        for reader in readers {
+            reader.join().expect("reader should finish");
+        }
+    }
+
+    pub fn test_all() {
+        many_readers_one_writer();
+        wake_all();
+        println!("wait queue test end");
+    }
+}
@@ -1,5 +1,4 @@
 //! Syscalls for file system
-use core::cell::UnsafeCell;
 use core::cmp::min;
 use core::mem::size_of;
 #[cfg(not(target_arch = "mips"))]
@@ -8,57 +7,86 @@ use rcore_fs::vfs::Timespec;
 use crate::drivers::SOCKET_ACTIVITY;
 use crate::fs::*;
 use crate::memory::MemorySet;
-use crate::sync::{Condvar, SpinNoIrqLock};
+use crate::sync::{Condvar, SpinNoIrqLock, WaitQueue};
 use crate::trap::TICK_ACTIVITY;
 use alloc::{collections::BTreeMap, collections::BTreeSet};
 
 use bitvec::prelude::{BitSlice, BitVec, LittleEndian};
 
 use super::*;
-use crate::fs::epoll::EpollInstance;
+use crate::fs::epoll::{EPollCtlOp, EpollInstance};
+use crate::fs::mount::{MountEntry, MountPoint, BOOT_MOUNTS, MOUNTS};
 use crate::net::server;
 use crate::process::Process;
+use crate::signal::{is_ignored, sigmask, SIGTTIN};
 use alloc::collections::VecDeque;
 use bitflags::_core::task::Poll;
-use rcore_fs::vfs::PollStatus;
+use rcore_fs::vfs::{FileSystem, FsInfo, PollStatus};
+use rcore_fs_mountfs::MNode;
 
 impl Syscall<'_> {
-    pub fn sys_read(&mut self, fd: usize, base: *mut u8, len: usize) -> SysResult {
+    pub fn sys_read(&mut self, fd: usize, base: UserOutPtr<u8>, len: usize) -> SysResult {
         let mut proc = self.process();
         if !proc.pid.is_init() {
             // we trust pid 0 process
             info!("read: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
         }
-        let slice = unsafe { self.vm().check_write_array(base, len)? };
-        let file_like = proc.get_file_like(fd)?;
-        let len = file_like.read(slice)?;
-        Ok(len)
+        let slice = base.as_mut_slice(&self.vm(), len)?;
+        proc.check_tty_read(fd, self.thread.sig_mask)?;
+        // unlock the process while blocking
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
+        let result = file_like.read(slice);
+        self.rearm_if_blocked(fd, EpollEvent::EPOLLIN, result)
     }
 
-    pub fn sys_write(&mut self, fd: usize, base: *const u8, len: usize) -> SysResult {
+    pub fn sys_write(&mut self, fd: usize, base: UserInPtr<u8>, len: usize) -> SysResult {
         let mut proc = self.process();
         if !proc.pid.is_init() {
             // we trust pid 0 process
             info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
         }
-        let slice = unsafe { self.vm().check_read_array(base, len)? };
-        let file_like = proc.get_file_like(fd)?;
-        let len = file_like.write(slice)?;
-        Ok(len)
+        let slice = base.as_slice(&self.vm(), len)?;
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
+        let result = file_like.write(slice);
+        self.rearm_if_blocked(fd, EpollEvent::EPOLLOUT, result)
+    }
+
+    /// Let edge-triggered epoll instances report `fd` again for `events`,
+    /// if the I/O call on it with `result` would block
+    pub fn rearm_if_blocked(&self, fd: usize, events: u32, result: SysResult) -> SysResult {
+        if result == Err(SysError::EAGAIN) {
+            self.process().rearm_epoll(fd, events);
+        }
+        result
     }
 
-    pub fn sys_pread(&mut self, fd: usize, base: *mut u8, len: usize, offset: usize) -> SysResult {
+    /// Read from `fd` at `offset`, without changing its file offset
+    pub fn sys_pread64(
+        &mut self,
+        fd: usize,
+        base: *mut u8,
+        len: usize,
+        offset: usize,
+    ) -> SysResult {
         info!(
             "pread: fd: {}, base: {:?}, len: {}, offset: {}",
             fd, base, len, offset
         );
         let mut proc = self.process();
         let slice = unsafe { self.vm().check_write_array(base, len)? };
-        let len = proc.get_file(fd)?.read_at(offset, slice)?;
+        // don't hold the process while reading, e.g. its /proc files
+        let mut file = proc.get_seekable_file(fd)?.clone();
+        drop(proc);
+        let len = file
+            .read_at(offset, slice)
+            .map_err(|err| io_error(&file, err))?;
         Ok(len)
     }
 
-    pub fn sys_pwrite(
+    /// Write to `fd` at `offset`, without changing its file offset
+    pub fn sys_pwrite64(
         &mut self,
         fd: usize,
         base: *const u8,
@@ -71,7 +99,10 @@ impl Syscall<'_> {
         );
         let mut proc = self.process();
         let slice = unsafe { self.vm().check_read_array(base, len)? };
-        let len = proc.get_file(fd)?.write_at(offset, slice)?;
+        let file = proc.get_seekable_file(fd)?;
+        let len = file
+            .write_at(offset, slice)
+            .map_err(|err| io_error(file, err))?;
         Ok(len)
     }
 
@@ -83,79 +114,103 @@ impl Syscall<'_> {
         nfds: usize,
         timeout: *const TimeSpec,
     ) -> SysResult {
-        let proc = self.process();
         let timeout_msecs = if timeout.is_null() {
-            1 << 31 // infinity
+            -1 // infinity
         } else {
             let timeout = unsafe { self.vm().check_read_ptr(timeout)? };
-            timeout.to_msec()
+            timeout.to_msec() as isize
         };
-        drop(proc);
 
-        self.sys_poll(ufds, nfds, timeout_msecs as usize)
+        self.sys_poll(ufds, nfds, timeout_msecs)
     }
 
-    pub fn sys_poll(&mut self, ufds: *mut PollFd, nfds: usize, timeout_msecs: usize) -> SysResult {
+    /// Wait for events on `nfds` files in `ufds`,
+    /// until `timeout_msecs` passes, or forever if it is negative.
+    /// Return the number of files with events.
+    pub fn sys_poll(&mut self, ufds: *mut PollFd, nfds: usize, timeout_msecs: isize) -> SysResult {
         let proc = self.process();
         if !proc.pid.is_init() {
             // we trust pid 0 process
             info!(
-                "poll: ufds: {:?}, nfds: {}, timeout_msecs: {:#x}",
+                "poll: ufds: {:?}, nfds: {}, timeout_msecs: {}",
                 ufds, nfds, timeout_msecs
             );
         }
-
-        // check whether the fds is valid and is owned by this process
-        let mut condvars = alloc::vec![&(*TICK_ACTIVITY), &STDIN.pushed, &(*SOCKET_ACTIVITY)];
+        if nfds > proc.max_fd() {
+            return Err(SysError::EINVAL);
+        }
+        drop(proc);
 
         let polls = unsafe { self.vm().check_write_array(ufds, nfds)? };
+        let timeout_msecs = if timeout_msecs < 0 {
+            None
+        } else {
+            Some(timeout_msecs as usize)
+        };
 
-        drop(proc);
-
-        let begin_time_ms = crate::trap::uptime_msec();
-        Condvar::wait_events(condvars.as_slice(), move || {
+        self.wait_for_files(timeout_msecs, move |proc| {
             use PollEvents as PE;
-            let proc = self.process();
-            let mut events = 0;
-
+            let mut count = 0;
             // iterate each poll to check whether it is ready
             for poll in polls.iter_mut() {
                 poll.revents = PE::empty();
+                // negative fd is ignored
+                if (poll.fd as i32) < 0 {
+                    continue;
+                }
                 if let Some(file_like) = proc.files.get(&(poll.fd as usize)) {
-                    let status = match file_like.poll() {
-                        Ok(ret) => ret,
-                        Err(err) => return Some(Err(err)),
-                    };
+                    let status = file_like.poll()?;
                     if status.error {
                         poll.revents |= PE::HUP;
-                        events += 1;
                     }
                     if status.read && poll.events.contains(PE::IN) {
                         poll.revents |= PE::IN;
-                        events += 1;
                     }
                     if status.write && poll.events.contains(PE::OUT) {
                         poll.revents |= PE::OUT;
-                        events += 1;
                     }
                 } else {
-                    poll.revents |= PE::ERR;
-                    events += 1;
+                    poll.revents |= PE::INVAL;
+                }
+                if !poll.revents.is_empty() {
+                    count += 1;
                 }
             }
-            drop(proc);
+            Ok(count)
+        })
+    }
 
-            // some event happens, so evoke the process
-            if events > 0 {
-                return Some(Ok(events));
+    /// Block until `check` finds some ready files and returns their number,
+    /// or `timeout_msecs` passes, or a signal arrives.
+    /// Wait forever if `timeout_msecs` is None.
+    fn wait_for_files(
+        &mut self,
+        timeout_msecs: Option<usize>,
+        mut check: impl FnMut(&Process) -> SysResult,
+    ) -> SysResult {
+        // woken up when any file may become ready
+        let queues = [
+            &*TICK_ACTIVITY,
+            &STDIN.pushed,
+            &*SOCKET_ACTIVITY,
+            &*PIPE_ACTIVITY,
+        ];
+        let begin_time_ms = crate::trap::uptime_msec();
+        WaitQueue::wait_any_until(&queues, move || {
+            match check(&self.process()) {
+                Ok(0) => {}
+                result => return Some(result),
             }
-
-            let current_time_ms = crate::trap::uptime_msec();
-            // time runs out, so the evoke the process
-            if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs {
-                return Some(Ok(0));
+            if let Some(timeout_msecs) = timeout_msecs {
+                let current_time_ms = crate::trap::uptime_msec();
+                if current_time_ms - begin_time_ms >= timeout_msecs {
+                    return Some(Ok(0));
+                }
             }
-            return None;
+            if self.has_signal_to_do() {
+                return Some(Err(SysError::EINTR));
+            }
+            None
         })
     }
 
@@ -183,44 +238,34 @@ impl Syscall<'_> {
             "select: nfds: {}, read: {:?}, write: {:?}, err: {:?}, timeout: {:?}",
             nfds, read, write, err, timeout
         );
-        if nfds as u64 == 0 {
-            return Ok(0);
-        }
         let proc = self.process();
+        if nfds > proc.max_fd() {
+            return Err(SysError::EINVAL);
+        }
         let mut read_fds = FdSet::new(&self.vm(), read, nfds)?;
         let mut write_fds = FdSet::new(&self.vm(), write, nfds)?;
         let mut err_fds = FdSet::new(&self.vm(), err, nfds)?;
-        let timeout_msecs = if !timeout.is_null() {
-            let timeout = unsafe { self.vm().check_read_ptr(timeout)? };
-            timeout.to_msec()
+        let timeout_msecs = if timeout.is_null() {
+            None // infinity
         } else {
-            // infinity
-            1 << 31
+            let timeout = unsafe { self.vm().check_read_ptr(timeout)? };
+            Some(timeout.to_msec() as usize)
         };
 
-        let mut condvars = alloc::vec![&(*TICK_ACTIVITY), &STDIN.pushed, &(*SOCKET_ACTIVITY)];
-
         // for debugging
         if cfg!(debug_assertions) {
             debug!("files before select {:#?}", proc.files);
         }
         drop(proc);
 
-        let begin_time_ms = crate::trap::uptime_msec();
-        Condvar::wait_events(condvars.as_slice(), move || {
-            let proc = self.process();
+        self.wait_for_files(timeout_msecs, move |proc| {
             let mut events = 0;
-            for (&fd, file_like) in proc.files.iter() {
-                //                if fd >= nfds {
-                //                    continue;
-                //                }
+            for fd in 0..nfds {
                 if !err_fds.contains(fd) && !read_fds.contains(fd) && !write_fds.contains(fd) {
                     continue;
                 }
-                let status = match file_like.poll() {
-                    Ok(ret) => ret,
-                    Err(err) => return Some(Err(err)),
-                };
+                let file_like = proc.files.get(&fd).ok_or(SysError::EBADF)?;
+                let status = file_like.poll()?;
                 if status.error && err_fds.contains(fd) {
                     err_fds.set(fd);
                     events += 1;
@@ -234,32 +279,14 @@ impl Syscall<'_> {
                     events += 1;
                 }
             }
-            drop(proc);
-
-            if events > 0 {
-                return Some(Ok(events));
-            }
-
-            if timeout_msecs == 0 {
-                // no timeout, return now;
-                return Some(Ok(0));
-            }
-
-            let current_time_ms = crate::trap::uptime_msec();
-            // infinity check
-            if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs as usize
-            {
-                return Some(Ok(0));
-            }
-
-            return None;
+            Ok(events)
         })
     }
 
     pub fn sys_epoll_create(&mut self, size: usize) -> SysResult {
         info!("epoll_create: size: {:?}", size);
 
-        if (size as i32) < 0 {
+        if (size as i32) <= 0 {
             return Err(SysError::EINVAL);
         }
         self.sys_epoll_create1(0)
@@ -267,9 +294,13 @@ impl Syscall<'_> {
 
     pub fn sys_epoll_create1(&mut self, flags: usize) -> SysResult {
         info!("epoll_create1: flags: {:?}", flags);
+        // EPOLL_CLOEXEC is O_CLOEXEC
+        if flags & !OpenFlags::CLOEXEC.bits() != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let fd_cloexec = flags & OpenFlags::CLOEXEC.bits() != 0;
         let mut proc = self.process();
-        let epollInstance = EpollInstance::new(flags);
-        let fd = proc.add_file(FileLike::EpollInstance(epollInstance));
+        let fd = proc.add_file(FileLike::EpollInstance(EpollInstance::new(fd_cloexec)))?;
         Ok(fd)
     }
 
@@ -280,27 +311,23 @@ impl Syscall<'_> {
         fd: usize,
         event: *mut EpollEvent,
     ) -> SysResult {
-        let mut proc = self.process();
+        let proc = self.process();
         if !proc.pid.is_init() {
             // we trust pid 0 process
-            info!("sys_epoll_ctl: epfd: {}, op: {:?}, fd: {:#x}", epfd, op, fd);
+            info!("epoll_ctl: epfd: {}, op: {:?}, fd: {:#x}", epfd, op, fd);
         }
-
-        let _event = unsafe { self.vm().check_read_ptr(event)? };
-
-        if proc.files.get(&fd).is_none() {
-            return Err(SysError::EPERM);
+        let instance = proc.get_epoll_instance(epfd)?;
+        match proc.files.get(&fd).ok_or(SysError::EBADF)? {
+            // epoll instances can not be nested
+            FileLike::EpollInstance(_) => return Err(SysError::EINVAL),
+            _ => {}
         }
-
-        let epollInstance = match proc.get_epoll_instance_mut(epfd) {
-            Ok(ins) => ins,
-            Err(err) => {
-                return Err(err);
-            }
+        // the event is ignored when deleting
+        let event = match op as i32 {
+            EPollCtlOp::DEL => EpollEvent::default(),
+            _ => unsafe { *self.vm().check_read_ptr(event)? },
         };
-
-        let ret = epollInstance.control(op, fd, &_event)?;
-        return Ok(ret);
+        instance.control(op, fd, event)
     }
 
     pub fn sys_epoll_wait(
@@ -319,173 +346,25 @@ impl Syscall<'_> {
         events: *mut EpollEvent,
         maxevents: usize,
         timeout_msecs: usize,
-        sigset_t: usize,
+        _sigset: usize,
     ) -> SysResult {
-        info!("epoll_pwait: epfd: {}, timeout: {:?}", epfd, timeout_msecs);
-
-        let mut proc = self.process();
-        let events = unsafe { self.vm().check_write_array(events, maxevents)? };
-        let epollInstance = proc.get_epoll_instance(epfd)?;
-
-        // add new fds which are registered by epoll_ctl after latest epoll_pwait
-        epollInstance.readyList.lock().clear();
-        epollInstance
-            .readyList
-            .lock()
-            .extend(epollInstance.newCtlList.lock().clone());
-        epollInstance.newCtlList.lock().clear();
-
-        // if registered fd has data to handle and its mode isn't epollet, we need
-        // to add it to the list.
-        let keys: Vec<_> = epollInstance.events.keys().cloned().collect();
-        for (k, v) in epollInstance.events.iter() {
-            if !v.contains(EpollEvent::EPOLLET) {
-                match &proc.files.get(k) {
-                    None => {
-                        //      return Err(SysError::EINVAL);
-                    }
-                    Some(file_like) => {
-                        let status = file_like.poll()?;
-                        if status.write || status.read || status.error {
-                            let mut readylist = epollInstance.readyList.lock();
-                            readylist.insert(*k);
-                        }
-                    }
-                }
-            }
-        }
-        drop(proc);
-
-        let mut callbacks = alloc::vec![];
-        for fd in &keys {
-            let mut proc = self.process();
-            match proc.files.get(&fd) {
-                Some(file_like) => {
-                    match file_like {
-                        FileLike::File(file) => {
-                            &crate::fs::STDIN.pushed.register_epoll_list(
-                                self.thread.proc.clone(),
-                                thread::current().id(),
-                                epfd,
-                                *fd,
-                            );
-                            callbacks.push((0, thread::current().id(), epfd, *fd));
-                        }
-                        FileLike::Socket(socket) => {
-                            &(*crate::drivers::SOCKET_ACTIVITY).register_epoll_list(
-                                self.thread.proc.clone(),
-                                thread::current().id(),
-                                epfd,
-                                *fd,
-                            );
-                            callbacks.push((1, thread::current().id(), epfd, *fd));
-                        }
-                        FileLike::EpollInstance(instance) => {
-                            return Err(SysError::EINVAL);
-                        }
-                    };
-                }
-                None => {}
-            }
-            drop(proc);
+        info!(
+            "epoll_pwait: epfd: {}, maxevents: {}, timeout: {}",
+            epfd, maxevents as i32, timeout_msecs as i32
+        );
+        if maxevents as i32 <= 0 {
+            return Err(SysError::EINVAL);
         }
-
-        let mut condvars = alloc::vec![&(*TICK_ACTIVITY), &STDIN.pushed, &(*SOCKET_ACTIVITY)];
-
-        let begin_time_ms = crate::trap::uptime_msec();
-        let condition = move || {
-            use PollEvents as PE;
-            let mut proc = self.process();
-
-            let epollInstance = match proc.get_epoll_instance_mut(epfd) {
-                Ok(ins) => ins,
-                Err(err) => {
-                    return Some(Err(err));
-                }
-            };
-            let readylist = epollInstance.readyList.lock().clone();
-            let mut events_num = 0;
-
-            for infd in readylist.iter() {
-                let mut status: PollStatus = Default::default();
-                {
-                    if let Some(file_like) = proc.files.get(&infd) {
-                        let _status = match file_like.poll() {
-                            Ok(ret) => ret,
-                            Err(err) => return Some(Err(err)),
-                        };
-                        status.write = _status.write;
-                        status.read = _status.read;
-                        status.error = _status.error;
-                    }
-                }
-
-                {
-                    let epollInstance = match proc.get_epoll_instance_mut(epfd) {
-                        Ok(ins) => ins,
-                        Err(err) => {
-                            return Some(Err(err));
-                        }
-                    };
-                    let epollevent = epollInstance.events.get_mut(&infd)?;
-
-                    if status.error {
-                        events[events_num].events = EpollEvent::EPOLLERR;
-                        events[events_num].data = epollevent.data;
-
-                        events_num += 1;
-                    }
-                    if status.read && epollevent.contains(EpollEvent::EPOLLIN) {
-                        events[events_num].events = EpollEvent::EPOLLIN;
-                        events[events_num].data = epollevent.data;
-                        events_num += 1;
-                    }
-                    if status.write && epollevent.contains(EpollEvent::EPOLLOUT) {
-                        events[events_num].events = EpollEvent::EPOLLOUT;
-                        events[events_num].data = epollevent.data;
-
-                        events_num += 1;
-                    }
-                }
-            }
-
-            {
-                let epollInstance = match proc.get_epoll_instance_mut(epfd) {
-                    Ok(ins) => ins,
-                    Err(err) => {
-                        return Some(Err(err));
-                    }
-                };
-                epollInstance.readyList.lock().clear();
-            }
-
-            drop(proc);
-
-            // some event happens, so evoke the process
-            if events_num > 0 {
-                return Some(Ok(events_num));
-            }
-
-            let current_time_ms = crate::trap::uptime_msec();
-            // time runs out, so the evoke the process
-            if timeout_msecs < (1 << 31) && current_time_ms - begin_time_ms > timeout_msecs {
-                return Some(Ok(0));
-            }
-            return None;
+        let instance = self.process().get_epoll_instance(epfd)?.clone();
+        let events = unsafe { self.vm().check_write_array(events, maxevents)? };
+        // a negative timeout waits forever
+        let timeout_msecs = match timeout_msecs as i32 {
+            timeout if timeout < 0 => None,
+            timeout => Some(timeout as usize),
         };
-
-        let num = Condvar::wait_events(condvars.as_slice(), condition).unwrap();
-
-        for cb in callbacks.iter() {
-            match cb.0 {
-                0 => &crate::fs::STDIN
-                    .pushed
-                    .unregister_epoll_list(cb.1, cb.2, cb.3),
-                1 => &(*crate::drivers::SOCKET_ACTIVITY).unregister_epoll_list(cb.1, cb.2, cb.3),
-                _ => panic!("cb error"),
-            };
-        }
-        Ok(num)
+        self.wait_for_files(timeout_msecs, move |proc| {
+            instance.ready_events(&proc.files, events)
+        })
     }
 
     pub fn sys_readv(&mut self, fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResult {
@@ -494,14 +373,16 @@ impl Syscall<'_> {
             fd, iov_ptr, iov_count
         );
         let mut proc = self.process();
-        let mut iovs = unsafe { IoVecs::check_and_new(iov_ptr, iov_count, &self.vm(), true)? };
+        let iovs = unsafe { IoVecs::check_and_new(iov_ptr, iov_count, &self.vm(), true)? };
 
         // read all data to a buf
-        let file_like = proc.get_file_like(fd)?;
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
         let mut buf = iovs.new_buf(true);
-        let len = file_like.read(buf.as_mut_slice())?;
+        let result = file_like.read(buf.as_mut_slice());
+        let len = self.rearm_if_blocked(fd, EpollEvent::EPOLLIN, result)?;
         // copy data to user
-        iovs.write_all_from_slice(&buf[..len]);
+        let len = iovs.write_all_from_slice(&buf[..len])?;
         Ok(len)
     }
 
@@ -516,10 +397,11 @@ impl Syscall<'_> {
         }
         let iovs = unsafe { IoVecs::check_and_new(iov_ptr, iov_count, &self.vm(), false)? };
 
-        let buf = iovs.read_all_to_vec();
-        let file_like = proc.get_file_like(fd)?;
-        let len = file_like.write(buf.as_slice())?;
-        Ok(len)
+        let buf = iovs.read_all_to_vec()?;
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
+        let result = file_like.write(buf.as_slice());
+        self.rearm_if_blocked(fd, EpollEvent::EPOLLOUT, result)
     }
 
     pub fn sys_open(&mut self, path: *const u8, flags: usize, mode: usize) -> SysResult {
@@ -541,6 +423,8 @@ impl Syscall<'_> {
             dir_fd as isize, path, flags, mode
         );
 
+        // a file created by this call is opened regardless of its mode
+        let mut created = false;
         let inode = if flags.contains(OpenFlags::CREATE) {
             let (dir_path, file_name) = split_path(&path);
             // relative to cwd
@@ -553,25 +437,106 @@ impl Syscall<'_> {
                     file_inode
                 }
                 Err(FsError::EntryNotFound) => {
-                    dir_inode.create(file_name, FileType::File, mode as u32)?
+                    check_access(&proc.cred, &dir_inode.metadata()?, W_OK | X_OK)?;
+                    created = true;
+                    let inode = dir_inode.create(
+                        file_name,
+                        FileType::File,
+                        (mode & 0o7777 & !proc.umask) as u32,
+                    )?;
+                    set_owner(&inode, &proc.cred);
+                    inode
                 }
                 Err(e) => return Err(SysError::from(e)),
             }
         } else {
-            proc.lookup_inode_at(dir_fd, &path, true)?
+            proc.lookup_inode_at(dir_fd, &path, !flags.contains(OpenFlags::NOFOLLOW))?
         };
 
-        let file = FileHandle::new(inode, flags.to_options(), String::from(path));
+        let info = inode.metadata()?;
+        if flags.contains(OpenFlags::DIRECTORY) && info.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
+        }
+        let path_only = flags.contains(OpenFlags::PATH);
+        if !created && !path_only {
+            let mut access = 0;
+            if flags.readable() {
+                access |= R_OK;
+            }
+            if flags.writable() {
+                access |= W_OK;
+            }
+            check_access(&proc.cred, &info, access)?;
+        }
+        if flags.contains(OpenFlags::TRUNCATE) && !path_only {
+            if info.type_ == FileType::Dir {
+                return Err(SysError::EISDIR);
+            }
+            if !flags.writable() {
+                return Err(SysError::EACCES);
+            }
+            if info.type_ == FileType::File {
+                inode.resize(0)?;
+            }
+        }
+
+        // special files are backed by kernel objects
+        let inode: Arc<dyn INode> = match info.type_ {
+            _ if path_only => inode,
+            FileType::NamedPipe => {
+                // don't hold the process while waiting for the other end
+                drop(proc);
+                let pipe = self.open_fifo(&inode, flags)?;
+                proc = self.process();
+                Arc::new(pipe)
+            }
+            FileType::CharDevice if info.rdev != 0 => CHAR_DEVICES
+                .get(&dev_split(info.rdev))
+                .cloned()
+                .ok_or(SysError::ENXIO)?,
+            // block devices are only used by the file systems, not opened through device nodes
+            FileType::BlockDevice if info.rdev != 0 => return Err(SysError::ENXIO),
+            _ => inode,
+        };
+
+        let file = FileHandle::new(
+            inode,
+            flags.to_options(),
+            String::from(path),
+            flags.contains(OpenFlags::CLOEXEC),
+        );
 
         // for debugging
         if cfg!(debug_assertions) {
             debug!("files before open {:#?}", proc.files);
         }
 
-        let fd = proc.add_file(FileLike::File(file));
+        let fd = proc.add_file(FileLike::File(file))?;
         Ok(fd)
     }
 
+    /// Open an end of the FIFO `inode`, and wait for the other end unless O_NONBLOCK.
+    /// Opened for both reading and writing, it is both ends and never waits.
+    fn open_fifo(&mut self, inode: &Arc<dyn INode>, flags: OpenFlags) -> Result<Pipe, SysError> {
+        let direction = match (flags.readable(), flags.writable()) {
+            (true, false) => PipeEnd::Read,
+            (false, true) => PipeEnd::Write,
+            (true, true) => PipeEnd::Both,
+            _ => return Err(SysError::EINVAL),
+        };
+        let is_writer = flags.writable();
+        let pipe = Pipe::open_fifo(inode, direction)?;
+        if flags.contains(OpenFlags::NONBLOCK) {
+            // a writer can not open a FIFO without readers
+            if is_writer && pipe.is_broken() {
+                return Err(SysError::ENXIO);
+            }
+            return Ok(pipe);
+        }
+        PIPE_ACTIVITY.wait_interruptible(|| if pipe.is_broken() { None } else { Some(Ok(())) })?;
+        Ok(pipe)
+    }
+
     pub fn sys_close(&mut self, fd: usize) -> SysResult {
         info!("close: fd: {:?}", fd);
         let mut proc = self.process();
@@ -581,7 +546,7 @@ impl Syscall<'_> {
             debug!("files before close {:#?}", proc.files);
         }
 
-        proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+        proc.close_file(fd).ok_or(SysError::EBADF)?;
         Ok(0)
     }
 
@@ -596,7 +561,6 @@ impl Syscall<'_> {
         mode: usize,
         flags: usize,
     ) -> SysResult {
-        // TODO: check permissions based on uid/gid
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
         let flags = AtFlags::from_bits_truncate(flags);
@@ -609,6 +573,16 @@ impl Syscall<'_> {
         }
         let inode =
             proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
+        // F_OK only tests existence
+        if mode & (R_OK | W_OK | X_OK) == 0 {
+            return Ok(0);
+        }
+        let info = inode.metadata()?;
+        // access is checked with the real ids
+        let mut cred = proc.cred.clone();
+        cred.euid = cred.uid;
+        cred.egid = cred.gid;
+        check_access(&cred, &info, mode & (R_OK | W_OK | X_OK))?;
         Ok(0)
     }
 
@@ -618,15 +592,17 @@ impl Syscall<'_> {
             // we trust pid 0 process
             info!("getcwd: buf: {:?}, len: {:#x}", buf, len);
         }
-        let buf = unsafe { self.vm().check_write_array(buf, len)? };
-        if proc.cwd.len() + 1 > len {
+        // cwd is always absolute and normalized, so root is exactly "/"
+        let cwd_len = proc.cwd.len() + 1;
+        if cwd_len > len {
             return Err(SysError::ERANGE);
         }
+        let buf = unsafe { self.vm().check_write_array(buf, cwd_len)? };
         unsafe { util::write_cstr(buf.as_mut_ptr(), &proc.cwd) }
         Ok(buf.as_ptr() as usize)
     }
 
-    pub fn sys_lstat(&mut self, path: *const u8, stat_ptr: *mut Stat) -> SysResult {
+    pub fn sys_lstat(&mut self, path: *const u8, stat_ptr: UserOutPtr<Stat>) -> SysResult {
         self.sys_fstatat(AT_FDCWD, path, stat_ptr, AtFlags::SYMLINK_NOFOLLOW.bits())
     }
 
@@ -644,12 +620,12 @@ impl Syscall<'_> {
         &mut self,
         dirfd: usize,
         path: *const u8,
-        stat_ptr: *mut Stat,
+        stat_ptr: UserOutPtr<Stat>,
         flags: usize,
     ) -> SysResult {
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
-        let stat_ref = unsafe { self.vm().check_write_ptr(stat_ptr)? };
+        stat_ptr.check(1)?;
         let flags = AtFlags::from_bits_truncate(flags);
         info!(
             "fstatat: dirfd: {}, path: {:?}, stat_ptr: {:?}, flags: {:?}",
@@ -659,11 +635,11 @@ impl Syscall<'_> {
         let inode =
             proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
         let stat = Stat::from(inode.metadata()?);
-        *stat_ref = stat;
+        stat_ptr.write(&self.vm(), stat)?;
         Ok(0)
     }
 
-    pub fn sys_stat(&mut self, path: *const u8, stat_ptr: *mut Stat) -> SysResult {
+    pub fn sys_stat(&mut self, path: *const u8, stat_ptr: UserOutPtr<Stat>) -> SysResult {
         self.sys_fstatat(AT_FDCWD, path, stat_ptr, 0)
     }
 
@@ -671,6 +647,8 @@ impl Syscall<'_> {
         self.sys_readlinkat(AT_FDCWD, path, base, len)
     }
 
+    /// Read the target of the symlink at `path` into `base`,
+    /// truncated to `len` bytes and without the null terminator.
     pub fn sys_readlinkat(
         &mut self,
         dirfd: usize,
@@ -680,20 +658,40 @@ impl Syscall<'_> {
     ) -> SysResult {
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
-        let slice = unsafe { self.vm().check_write_array(base, len)? };
         info!(
             "readlinkat: dirfd: {}, path: {:?}, base: {:?}, len: {}",
             dirfd as isize, path, base, len
         );
+        if len == 0 {
+            return Err(SysError::EINVAL);
+        }
+        let slice = unsafe { self.vm().check_write_array(base, len)? };
 
+        // the last component is not followed
         let inode = proc.lookup_inode_at(dirfd, &path, false)?;
-        if inode.metadata()?.type_ == FileType::SymLink {
-            // TODO: recursive link resolution and loop detection
-            let len = inode.read_at(0, slice)?;
-            Ok(len)
-        } else {
-            Err(SysError::EINVAL)
-        }
+        read_link(&inode, slice)
+    }
+
+    /// Get statistics of the filesystem containing `path`
+    pub fn sys_statfs(&mut self, path: *const u8, buf: *mut StatFs) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        let buf = unsafe { self.vm().check_write_ptr(buf)? };
+        info!("statfs: path: {:?}", path);
+
+        let inode = proc.lookup_inode(&path)?;
+        *buf = StatFs::from(inode.fs().info());
+        Ok(0)
+    }
+
+    /// Get statistics of the filesystem containing file `fd`
+    pub fn sys_fstatfs(&mut self, fd: usize, buf: *mut StatFs) -> SysResult {
+        info!("fstatfs: fd: {}", fd);
+        let mut proc = self.process();
+        let buf = unsafe { self.vm().check_write_ptr(buf)? };
+        let inode = proc.get_file(fd)?.inode();
+        *buf = StatFs::from(inode.fs().info());
+        Ok(0)
     }
 
     pub fn sys_lseek(&mut self, fd: usize, offset: i64, whence: u8) -> SysResult {
@@ -713,27 +711,49 @@ impl Syscall<'_> {
 
     pub fn sys_fsync(&mut self, fd: usize) -> SysResult {
         info!("fsync: fd: {}", fd);
-        self.process().get_file(fd)?.sync_all()?;
-        Ok(0)
+        // flush both data and metadata
+        match self.process().get_file(fd)?.sync_all() {
+            // nothing to flush for pipes and devices
+            Ok(()) | Err(FsError::NotSupported) => Ok(0),
+            Err(err) => Err(err.into()),
+        }
     }
 
     pub fn sys_fdatasync(&mut self, fd: usize) -> SysResult {
         info!("fdatasync: fd: {}", fd);
-        self.process().get_file(fd)?.sync_data()?;
-        Ok(0)
+        match self.process().get_file(fd)?.sync_data() {
+            Ok(()) | Err(FsError::NotSupported) => Ok(0),
+            Err(err) => Err(err.into()),
+        }
     }
 
     pub fn sys_truncate(&mut self, path: *const u8, len: usize) -> SysResult {
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
         info!("truncate: path: {:?}, len: {}", path, len);
-        proc.lookup_inode(&path)?.resize(len)?;
+        if (len as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let inode = proc.lookup_inode(&path)?;
+        if inode.metadata()?.type_ == FileType::Dir {
+            return Err(SysError::EISDIR);
+        }
+        // the VFS fills the grown part with zeros
+        inode.resize(len)?;
         Ok(0)
     }
 
     pub fn sys_ftruncate(&mut self, fd: usize, len: usize) -> SysResult {
         info!("ftruncate: fd: {}, len: {}", fd, len);
-        self.process().get_file(fd)?.set_len(len as u64)?;
+        if (len as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let mut proc = self.process();
+        let file = proc.get_file(fd)?;
+        if file.metadata()?.type_ == FileType::Dir {
+            return Err(SysError::EISDIR);
+        }
+        file.set_len(len as u64)?;
         Ok(0)
     }
 
@@ -754,28 +774,70 @@ impl Syscall<'_> {
         if info.type_ != FileType::Dir {
             return Err(SysError::ENOTDIR);
         }
+        let dir = file.inode();
         let mut writer = DirentBufWriter::new(buf);
         loop {
-            let name = match file.read_entry() {
+            // the offset to resume after this entry
+            let (name, offset) = match file.peek_entry() {
                 Err(FsError::EntryNotFound) => break,
                 r => r,
             }?;
-            // TODO: get ino from dirent
-            let ok = writer.try_write(0, DirentType::from_type(&info.type_).bits(), &name);
-            if !ok {
+            let (ino, type_) = match dir.find(&name).and_then(|inode| inode.metadata()) {
+                Ok(info) => (info.inode as u64, DirentType::from_type(&info.type_)),
+                // the entry may be removed in the meantime
+                Err(_) => (0, DirentType::DT_UNKNOWN),
+            };
+            if !writer.try_write(ino, offset, type_.bits(), &name) {
+                if writer.written_size == 0 {
+                    // not enough space for a single entry
+                    return Err(SysError::EINVAL);
+                }
+                // leave the entry to the next call
                 break;
             }
+            file.consume_entry();
         }
         Ok(writer.written_size)
     }
 
+    pub fn sys_dup(&mut self, fd: usize) -> SysResult {
+        info!("dup: fd: {}", fd);
+        let mut proc = self.process();
+        // the new fd shares the file offset with the old one
+        let file_like = proc.get_file_like(fd)?.dup(false);
+        let new_fd = proc.add_file(file_like)?;
+        Ok(new_fd)
+    }
+
     pub fn sys_dup2(&mut self, fd1: usize, fd2: usize) -> SysResult {
         info!("dup2: from {} to {}", fd1, fd2);
         let mut proc = self.process();
+        if fd2 >= proc.max_fd() {
+            return Err(SysError::EBADF);
+        }
+        let file_like = proc.get_file_like(fd1)?.dup(false);
         // close fd2 first if it is opened
-        proc.files.remove(&fd2);
+        proc.close_file(fd2);
 
-        let file_like = proc.get_file_like(fd1)?.clone();
+        proc.files.insert(fd2, file_like);
+        Ok(fd2)
+    }
+
+    pub fn sys_dup3(&mut self, fd1: usize, fd2: usize, flags: usize) -> SysResult {
+        info!("dup3: from {} to {}, flags: {:#x}", fd1, fd2, flags);
+        let flags = OpenFlags::from_bits_truncate(flags);
+        if fd1 == fd2 || !(flags - OpenFlags::CLOEXEC).is_empty() {
+            return Err(SysError::EINVAL);
+        }
+        let mut proc = self.process();
+        if fd2 >= proc.max_fd() {
+            return Err(SysError::EBADF);
+        }
+        let file_like = proc
+            .get_file_like(fd1)?
+            .dup(flags.contains(OpenFlags::CLOEXEC));
+        // close fd2 first if it is opened
+        proc.close_file(fd2);
         proc.files.insert(fd2, file_like);
         Ok(fd2)
     }
@@ -792,11 +854,207 @@ impl Syscall<'_> {
             "ioctl: fd: {}, request: {:#x}, args: {:#x} {:#x} {:#x}",
             fd, request, arg1, arg2, arg3
         );
+        use crate::fs::ioctl::*;
+        // check the argument of requests known to take a pointer
+        {
+            let vm = self.vm();
+            unsafe {
+                match request {
+                    TCGETS => {
+                        vm.check_write_ptr(arg1 as *mut Termios)?;
+                    }
+                    TCSETS | TCSETSW | TCSETSF => {
+                        vm.check_read_ptr(arg1 as *const Termios)?;
+                    }
+                    TIOCGWINSZ => {
+                        vm.check_write_ptr(arg1 as *mut WinSize)?;
+                    }
+                    TIOCGPGRP => {
+                        vm.check_write_ptr(arg1 as *mut u32)?;
+                    }
+                    TIOCSPGRP => {
+                        vm.check_read_ptr(arg1 as *const u32)?;
+                    }
+                    FIONBIO => {
+                        vm.check_read_ptr(arg1 as *const i32)?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if request == TIOCSPGRP {
+            let pgid = unsafe { *(arg1 as *const i32) };
+            self.check_foreground_pgid(fd, pgid)?;
+        }
         let mut proc = self.process();
         let file_like = proc.get_file_like(fd)?;
         file_like.ioctl(request, arg1, arg2, arg3)
     }
 
+    /// Check that `pgid` can be the foreground process group of the terminal `fd`
+    fn check_foreground_pgid(&mut self, fd: usize, pgid: i32) -> Result<(), SysError> {
+        let sid = {
+            let mut proc = self.process();
+            match proc.get_file_like(fd)? {
+                FileLike::File(file) if file.inode().as_any_ref().is::<Stdin>() => {}
+                _ => return Ok(()),
+            }
+            proc.sid
+        };
+        check_console_pgid(sid, pgid)
+    }
+
+    /// Set the file mode creation mask, and return the previous one
+    pub fn sys_umask(&mut self, mask: usize) -> SysResult {
+        info!("umask: mask: {:#o}", mask);
+        let mut proc = self.process();
+        let old_mask = proc.umask;
+        proc.umask = mask & 0o777;
+        Ok(old_mask)
+    }
+
+    pub fn sys_chmod(&mut self, path: *const u8, mode: usize) -> SysResult {
+        self.sys_fchmodat(AT_FDCWD, path, mode, 0)
+    }
+
+    /// Change the permission bits of the file at `path`
+    pub fn sys_fchmodat(
+        &mut self,
+        dirfd: usize,
+        path: *const u8,
+        mode: usize,
+        flags: usize,
+    ) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        let flags = AtFlags::from_bits_truncate(flags);
+        info!(
+            "fchmodat: dirfd: {}, path: {:?}, mode: {:#o}, flags: {:?}",
+            dirfd as isize, path, mode, flags
+        );
+        let inode =
+            proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
+        set_mode(&inode, &proc.cred, mode)
+    }
+
+    /// Set the access and modification times of the file at `path`,
+    /// or file `dirfd` if `path` is null.
+    /// Both are set to the current time if `times` is null.
+    pub fn sys_utimensat(
+        &mut self,
+        dirfd: usize,
+        path: *const u8,
+        times: *const [TimeSpec; 2],
+        flags: usize,
+    ) -> SysResult {
+        let mut proc = self.process();
+        let flags = AtFlags::from_bits_truncate(flags);
+        let inode = if path.is_null() {
+            info!("utimensat: fd: {}, times: {:?}", dirfd as isize, times);
+            proc.get_file(dirfd)?.inode()
+        } else {
+            let path = check_and_clone_cstr(path)?;
+            info!(
+                "utimensat: dirfd: {}, path: {:?}, times: {:?}, flags: {:?}",
+                dirfd as isize, path, times, flags
+            );
+            proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?
+        };
+
+        let now = TimeSpec::get_epoch();
+        let (atime, mtime) = if times.is_null() {
+            (Some(now), Some(now))
+        } else {
+            let times = unsafe { self.vm().check_read_ptr(times)? };
+            (times[0].to_utime(&now)?, times[1].to_utime(&now)?)
+        };
+        if atime.is_none() && mtime.is_none() {
+            return Ok(0);
+        }
+        let mut info = inode.metadata()?;
+        if let Some(atime) = atime {
+            info.atime = atime.into();
+        }
+        if let Some(mtime) = mtime {
+            info.mtime = mtime.into();
+        }
+        // the status is changed as well
+        info.ctime = now.into();
+        inode.set_metadata(&info)?;
+        Ok(0)
+    }
+
+    /// Change the permission bits of file `fd`
+    pub fn sys_fchmod(&mut self, fd: usize, mode: usize) -> SysResult {
+        info!("fchmod: fd: {}, mode: {:#o}", fd, mode);
+        let mut proc = self.process();
+        let inode = proc.get_file(fd)?.inode();
+        set_mode(&inode, &proc.cred, mode)
+    }
+
+    /// Apply or remove a flock() lock on the whole file of `fd`
+    pub fn sys_flock(&mut self, fd: usize, operation: usize) -> SysResult {
+        info!("flock: fd: {}, operation: {:#x}", fd, operation);
+        let file = self.process().get_file(fd)?.clone();
+        if file.options().path {
+            return Err(SysError::EBADF);
+        }
+        let lock = match operation & !LOCK_NB {
+            LOCK_SH => Some(false),
+            LOCK_EX => Some(true),
+            LOCK_UN => None,
+            _ => return Err(SysError::EINVAL),
+        };
+        if file.flock(lock)? {
+            return Ok(0);
+        }
+        if operation & LOCK_NB != 0 {
+            // EWOULDBLOCK
+            return Err(SysError::EAGAIN);
+        }
+        Condvar::wait_events(&[&*LOCK_RELEASED], || match file.flock(lock) {
+            Ok(true) => Some(Ok(0)),
+            Ok(false) if !self.has_signal_to_do() => None,
+            Ok(false) => Some(Err(SysError::EINTR)),
+            Err(err) => Some(Err(err.into())),
+        })
+    }
+
+    pub fn sys_chown(&mut self, path: *const u8, uid: usize, gid: usize) -> SysResult {
+        self.sys_fchownat(AT_FDCWD, path, uid, gid, 0)
+    }
+
+    /// Change the owner and group of the file at `path`
+    pub fn sys_fchownat(
+        &mut self,
+        dirfd: usize,
+        path: *const u8,
+        uid: usize,
+        gid: usize,
+        flags: usize,
+    ) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        let flags = AtFlags::from_bits_truncate(flags);
+        info!(
+            "fchownat: dirfd: {}, path: {:?}, uid: {}, gid: {}, flags: {:?}",
+            dirfd as isize, path, uid as i32, gid as i32, flags
+        );
+        let inode =
+            proc.lookup_inode_at(dirfd, &path, !flags.contains(AtFlags::SYMLINK_NOFOLLOW))?;
+        change_owner(&inode, &proc.cred, uid as u32, gid as u32)
+    }
+
+    pub fn sys_fchown(&mut self, fd: usize, uid: usize, gid: usize) -> SysResult {
+        info!(
+            "fchown: fd: {}, uid: {}, gid: {}",
+            fd, uid as i32, gid as i32
+        );
+        let mut proc = self.process();
+        let inode = proc.get_file(fd)?.inode();
+        change_owner(&inode, &proc.cred, uid as u32, gid as u32)
+    }
+
     pub fn sys_chdir(&mut self, path: *const u8) -> SysResult {
         let mut proc = self.process();
         let path = check_and_clone_cstr(path)?;
@@ -804,6 +1062,9 @@ impl Syscall<'_> {
             // we trust pid 0 process
             info!("chdir: path: {:?}", path);
         }
+        if path.is_empty() {
+            return Err(SysError::ENOENT);
+        }
 
         let inode = proc.lookup_inode(&path)?;
         let info = inode.metadata()?;
@@ -811,32 +1072,110 @@ impl Syscall<'_> {
             return Err(SysError::ENOTDIR);
         }
 
-        // BUGFIX: '..' and '.'
-        if path.len() > 0 {
-            let cwd = match path.as_bytes()[0] {
-                b'/' => String::from("/"),
-                _ => proc.cwd.clone(),
-            };
-            let mut cwd_vec: Vec<_> = cwd.split("/").filter(|&x| x != "").collect();
-            let path_split = path.split("/").filter(|&x| x != "");
-            for seg in path_split {
-                if seg == ".." {
-                    cwd_vec.pop();
-                } else if seg == "." {
-                    // nothing to do here.
-                } else {
-                    cwd_vec.push(seg);
-                }
-            }
-            proc.cwd = String::from("");
-            for seg in cwd_vec {
-                proc.cwd.push_str("/");
-                proc.cwd.push_str(seg);
-            }
-            if proc.cwd == "" {
-                proc.cwd = String::from("/");
+        proc.cwd = absolute_path(&proc.cwd, &path);
+        Ok(0)
+    }
+
+    /// Mount a file system of `fstype` at the empty directory `target`.
+    ///
+    /// Only file systems without a backing device are supported, so `source` is just a name.
+    /// `data` holds the options of TmpFS, such as `size=1m`.
+    pub fn sys_mount(
+        &mut self,
+        source: *const u8,
+        target: *const u8,
+        fstype: *const u8,
+        flags: usize,
+        data: *const u8,
+    ) -> SysResult {
+        let proc = self.process();
+        let source = check_and_clone_cstr(source)?;
+        let target = check_and_clone_cstr(target)?;
+        let fstype = check_and_clone_cstr(fstype)?;
+        let options = match data.is_null() {
+            true => String::new(),
+            false => check_and_clone_cstr(data)?,
+        };
+        info!(
+            "mount: source: {:?}, target: {:?}, fstype: {:?}, flags: {:#x}, options: {:?}",
+            source, target, fstype, flags, options
+        );
+        // only root can change the mounts
+        if !proc.cred.is_privileged() {
+            return Err(SysError::EPERM);
+        }
+        if flags & (MS_REMOUNT | MS_BIND | MS_MOVE) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let inode = proc.lookup_inode(&target)?;
+        if inode.metadata()?.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
+        }
+        let target = absolute_path(&proc.cwd, &target);
+        drop(proc);
+        // mounting over entries would hide them after unmounting
+        let is_empty = is_empty_dir(&inode);
+
+        let fs: Arc<dyn FileSystem> = match fstype.as_str() {
+            "ramfs" | "tmpfs" => TmpFS::with_options(&options)?,
+            "proc" => Arc::new(ProcFS),
+            _ => return Err(SysError::ENODEV),
+        };
+        let mut mounts = MOUNTS.lock();
+        // an unmounted mount point is an empty directory, which is used again
+        if let Some(entry) = mounts.iter_mut().find(|entry| entry.target == target) {
+            if entry.point.is_attached() {
+                return Err(SysError::EBUSY);
             }
+            entry.point.attach(fs);
+            entry.source = source;
+            entry.fstype = fstype;
+            return Ok(0);
+        }
+        let is_boot_mount = BOOT_MOUNTS.iter().any(|&(_, boot, _)| boot == target);
+        if is_boot_mount || !is_empty {
+            return Err(SysError::EBUSY);
         }
+        let mnode = inode
+            .as_any_ref()
+            .downcast_ref::<MNode>()
+            .ok_or(SysError::EINVAL)?;
+        let point = Arc::new(MountPoint::new(fs));
+        let mounted = mnode.mount(point.clone())?;
+        mounts.push(MountEntry {
+            source,
+            target,
+            fstype,
+            point,
+            fs: mounted,
+        });
+        Ok(0)
+    }
+
+    /// Unmount the file system mounted at `target` by `sys_mount`.
+    /// Fail with `EBUSY` if it is in use by any process, or has file systems mounted inside.
+    pub fn sys_umount(&mut self, target: *const u8, flags: usize) -> SysResult {
+        let proc = self.process();
+        let target = check_and_clone_cstr(target)?;
+        info!("umount: target: {:?}, flags: {:#x}", target, flags);
+        if !proc.cred.is_privileged() {
+            return Err(SysError::EPERM);
+        }
+        let target = absolute_path(&proc.cwd, &target);
+        drop(proc);
+
+        let mounts = MOUNTS.lock();
+        let entry = mounts
+            .iter()
+            .find(|entry| entry.target == target && entry.point.is_attached())
+            .ok_or(SysError::EINVAL)?;
+        let has_inner_mount = mounts
+            .iter()
+            .any(|other| other.point.is_attached() && is_under(&other.target, &target));
+        if has_inner_mount || is_fs_in_use(&entry.fs) {
+            return Err(SysError::EBUSY);
+        }
+        entry.point.detach()?;
         Ok(0)
     }
 
@@ -863,6 +1202,26 @@ impl Syscall<'_> {
         let (new_dir_path, new_file_name) = split_path(&newpath);
         let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path, false)?;
         let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, false)?;
+        if !Arc::ptr_eq(&old_dir_inode.fs(), &new_dir_inode.fs()) {
+            return Err(SysError::EXDEV);
+        }
+        let old_info = old_dir_inode.find(old_file_name)?.metadata()?;
+        let old_is_dir = old_info.type_ == FileType::Dir;
+        // replace an existing destination of a compatible type
+        if let Ok(new_inode) = new_dir_inode.find(new_file_name) {
+            let new_info = new_inode.metadata()?;
+            if new_info.inode == old_info.inode {
+                // both names refer to the same file
+                return Ok(0);
+            }
+            match (old_is_dir, new_info.type_ == FileType::Dir) {
+                (true, true) if !is_empty_dir(&new_inode) => return Err(SysError::ENOTEMPTY),
+                (true, false) => return Err(SysError::ENOTDIR),
+                (false, true) => return Err(SysError::EISDIR),
+                _ => {}
+            }
+            new_dir_inode.unlink(new_file_name)?;
+        }
         old_dir_inode.move_(old_file_name, &new_dir_inode, new_file_name)?;
         Ok(0)
     }
@@ -882,26 +1241,68 @@ impl Syscall<'_> {
 
         let (dir_path, file_name) = split_path(&path);
         let inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
+        let info = inode.metadata()?;
+        if info.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
+        }
         if inode.find(file_name).is_ok() {
             return Err(SysError::EEXIST);
         }
-        inode.create(file_name, FileType::Dir, mode as u32)?;
+        check_access(&proc.cred, &info, W_OK | X_OK)?;
+        // permission, setuid, setgid and sticky bits are taken from mode, without those in umask
+        let dir = inode.create(
+            file_name,
+            FileType::Dir,
+            (mode & 0o7777 & !proc.umask) as u32,
+        )?;
+        set_owner(&dir, &proc.cred);
         Ok(0)
     }
 
-    pub fn sys_rmdir(&mut self, path: *const u8) -> SysResult {
+    pub fn sys_mknod(&mut self, path: *const u8, mode: usize, dev: usize) -> SysResult {
+        self.sys_mknodat(AT_FDCWD, path, mode, dev)
+    }
+
+    /// Create a FIFO, a device node, a socket or a regular file at `path`.
+    /// Device nodes refer to the device `dev`.
+    /// Block device nodes can be created, but opening them fails with ENXIO.
+    pub fn sys_mknodat(
+        &mut self,
+        dirfd: usize,
+        path: *const u8,
+        mode: usize,
+        dev: usize,
+    ) -> SysResult {
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
-        info!("rmdir: path: {:?}", path);
+        info!(
+            "mknodat: dirfd: {}, path: {:?}, mode: {:#o}, dev: {:#x}",
+            dirfd as isize, path, mode, dev
+        );
 
+        let type_ = match StatMode::from_bits_truncate(mode as u32) & StatMode::TYPE_MASK {
+            StatMode::NULL | StatMode::FILE => FileType::File,
+            StatMode::FIFO => FileType::NamedPipe,
+            StatMode::CHAR => FileType::CharDevice,
+            StatMode::BLOCK => FileType::BlockDevice,
+            StatMode::SOCKET => FileType::Socket,
+            _ => return Err(SysError::EINVAL),
+        };
         let (dir_path, file_name) = split_path(&path);
-        let dir_inode = proc.lookup_inode(dir_path)?;
-        let file_inode = dir_inode.find(file_name)?;
-        if file_inode.metadata()?.type_ != FileType::Dir {
-            return Err(SysError::ENOTDIR);
-        }
-        dir_inode.unlink(file_name)?;
-        Ok(0)
+        let dir_inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
+        // permission, setuid, setgid and sticky bits are taken from mode, without those in umask
+        make_node(
+            &dir_inode,
+            file_name,
+            type_,
+            mode & 0o7777 & !proc.umask,
+            dev,
+            &proc.cred,
+        )
+    }
+
+    pub fn sys_rmdir(&mut self, path: *const u8) -> SysResult {
+        self.sys_unlinkat(AT_FDCWD, path, AtFlags::REMOVEDIR.bits())
     }
 
     pub fn sys_link(&mut self, oldpath: *const u8, newpath: *const u8) -> SysResult {
@@ -926,12 +1327,53 @@ impl Syscall<'_> {
         );
 
         let (new_dir_path, new_file_name) = split_path(&newpath);
-        let inode = proc.lookup_inode_at(olddirfd, &oldpath, true)?;
+        let inode =
+            proc.lookup_inode_at(olddirfd, &oldpath, flags.contains(AtFlags::SYMLINK_FOLLOW))?;
+        if inode.metadata()?.type_ == FileType::Dir {
+            return Err(SysError::EPERM);
+        }
         let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, true)?;
+        if !Arc::ptr_eq(&inode.fs(), &new_dir_inode.fs()) {
+            return Err(SysError::EXDEV);
+        }
+        if new_dir_inode.find(new_file_name).is_ok() {
+            return Err(SysError::EEXIST);
+        }
         new_dir_inode.link(new_file_name, &inode)?;
         Ok(0)
     }
 
+    pub fn sys_symlink(&mut self, target: *const u8, linkpath: *const u8) -> SysResult {
+        self.sys_symlinkat(target, AT_FDCWD, linkpath)
+    }
+
+    pub fn sys_symlinkat(
+        &mut self,
+        target: *const u8,
+        newdirfd: usize,
+        linkpath: *const u8,
+    ) -> SysResult {
+        let proc = self.process();
+        let target = check_and_clone_cstr(target)?;
+        let linkpath = check_and_clone_cstr(linkpath)?;
+        info!(
+            "symlinkat: target: {:?}, newdirfd: {}, linkpath: {:?}",
+            target, newdirfd as isize, linkpath
+        );
+        if target.is_empty() {
+            return Err(SysError::ENOENT);
+        }
+
+        let (dir_path, file_name) = split_path(&linkpath);
+        let dir_inode = proc.lookup_inode_at(newdirfd, dir_path, true)?;
+        if dir_inode.find(file_name).is_ok() {
+            return Err(SysError::EEXIST);
+        }
+        let inode = dir_inode.create(file_name, FileType::SymLink, 0o777)?;
+        inode.write_at(0, target.as_bytes())?;
+        Ok(0)
+    }
+
     pub fn sys_unlink(&mut self, path: *const u8) -> SysResult {
         self.sys_unlinkat(AT_FDCWD, path, 0)
     }
@@ -948,15 +1390,66 @@ impl Syscall<'_> {
         let (dir_path, file_name) = split_path(&path);
         let dir_inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
         let file_inode = dir_inode.find(file_name)?;
-        if file_inode.metadata()?.type_ == FileType::Dir {
+        check_access(&proc.cred, &dir_inode.metadata()?, W_OK | X_OK)?;
+        let is_dir = file_inode.metadata()?.type_ == FileType::Dir;
+        if flags.contains(AtFlags::REMOVEDIR) {
+            if !is_dir {
+                return Err(SysError::ENOTDIR);
+            }
+            if file_name == "." || file_name == ".." {
+                return Err(SysError::EINVAL);
+            }
+            if !is_empty_dir(&file_inode) {
+                return Err(SysError::ENOTEMPTY);
+            }
+        } else if is_dir {
             return Err(SysError::EISDIR);
         }
+        // open file handles keep their own reference to the inode
         dir_inode.unlink(file_name)?;
         Ok(0)
     }
 
+    pub fn sys_eventfd(&mut self, initval: usize) -> SysResult {
+        self.sys_eventfd2(initval, 0)
+    }
+
+    /// Create an eventfd with the counter `initval`
+    pub fn sys_eventfd2(&mut self, initval: usize, flags: usize) -> SysResult {
+        info!("eventfd2: initval: {}, flags: {:#x}", initval, flags);
+        if flags & !(EFD_SEMAPHORE | (OpenFlags::CLOEXEC | OpenFlags::NONBLOCK).bits()) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let semaphore = flags & EFD_SEMAPHORE != 0;
+        let flags = OpenFlags::from_bits_truncate(flags);
+        let file = FileHandle::new(
+            Arc::new(EventFd::new(initval as u32 as u64, semaphore)),
+            OpenOptions {
+                read: true,
+                write: true,
+                append: false,
+                nonblock: flags.contains(OpenFlags::NONBLOCK),
+                path: false,
+            },
+            String::from("anon_inode:[eventfd]"),
+            flags.contains(OpenFlags::CLOEXEC),
+        );
+        let fd = self.process().add_file(FileLike::File(file))?;
+        Ok(fd)
+    }
+
     pub fn sys_pipe(&mut self, fds: *mut u32) -> SysResult {
-        info!("pipe: fds: {:?}", fds);
+        self.sys_pipe2(fds, 0)
+    }
+
+    pub fn sys_pipe2(&mut self, fds: *mut u32, flags: usize) -> SysResult {
+        info!("pipe2: fds: {:?}, flags: {:#x}", fds, flags);
+        if flags & !(OpenFlags::CLOEXEC | OpenFlags::NONBLOCK).bits() != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let flags = OpenFlags::from_bits_truncate(flags);
+        let fd_cloexec = flags.contains(OpenFlags::CLOEXEC);
+        let nonblock = flags.contains(OpenFlags::NONBLOCK);
 
         let mut proc = self.process();
         let fds = unsafe { self.vm().check_write_array(fds, 2)? };
@@ -968,21 +1461,31 @@ impl Syscall<'_> {
                 read: true,
                 write: false,
                 append: false,
-                nonblock: false,
+                nonblock,
+                path: false,
             },
             String::from("pipe_r:[]"),
-        )));
+            fd_cloexec,
+        )))?;
 
-        let write_fd = proc.add_file(FileLike::File(FileHandle::new(
+        let write_fd = match proc.add_file(FileLike::File(FileHandle::new(
             Arc::new(write),
             OpenOptions {
                 read: false,
                 write: true,
                 append: false,
-                nonblock: false,
+                nonblock,
+                path: false,
             },
             String::from("pipe_w:[]"),
-        )));
+            fd_cloexec,
+        ))) {
+            Ok(fd) => fd,
+            Err(err) => {
+                proc.files.remove(&read_fd);
+                return Err(err);
+            }
+        };
 
         fds[0] = read_fd as u32;
         fds[1] = write_fd as u32;
@@ -993,6 +1496,8 @@ impl Syscall<'_> {
     }
 
     pub fn sys_sync(&mut self) -> SysResult {
+        info!("sync");
+        // MountFS also syncs the filesystems mounted on it
         ROOT_INODE.fs().sync()?;
         Ok(0)
     }
@@ -1004,7 +1509,69 @@ impl Syscall<'_> {
         offset_ptr: *mut usize,
         count: usize,
     ) -> SysResult {
-        self.sys_copy_file_range(in_fd, offset_ptr, out_fd, 0 as *mut usize, count, 0)
+        info!(
+            "sendfile: out: {}, in: {}, offset_ptr: {:?}, count: {}",
+            out_fd, in_fd, offset_ptr, count
+        );
+        let mut proc = self.process();
+        // the handle shares the file offset with the one in the table
+        let mut in_file = proc.get_file(in_fd)?.clone();
+        let offset = if offset_ptr.is_null() {
+            None
+        } else {
+            if !in_file.is_seekable() {
+                return Err(SysError::EINVAL);
+            }
+            Some(unsafe { self.vm().check_write_ptr(offset_ptr)? })
+        };
+        let mut out_file = proc.get_file_like(out_fd)?.clone();
+        drop(proc);
+
+        let mut read_offset = match offset {
+            Some(&mut offset) => offset,
+            None => in_file.seek(SeekFrom::Current(0))? as usize,
+        };
+        let mut buffer = [0u8; 4096];
+        let mut total_written = 0;
+        let result = 'transfer: loop {
+            if total_written >= count {
+                break Ok(());
+            }
+            let len = min(buffer.len(), count - total_written);
+            let read_len = match in_file.read_at(read_offset, &mut buffer[..len]) {
+                Ok(0) => break Ok(()),
+                Ok(read_len) => read_len,
+                Err(err) => break Err(io_error(&in_file, err)),
+            };
+            // write the whole chunk before reading the next one
+            let mut written = 0;
+            while written < read_len {
+                let write_len = match out_file.write(&buffer[written..read_len]) {
+                    Ok(0) => break 'transfer Ok(()),
+                    Ok(write_len) => write_len,
+                    Err(err) => break 'transfer Err(err),
+                };
+                written += write_len;
+                read_offset += write_len;
+                total_written += write_len;
+            }
+        };
+
+        let result = match offset {
+            Some(offset) => {
+                *offset = read_offset;
+                result
+            }
+            None => match in_file.seek(SeekFrom::Start(read_offset as u64)) {
+                Ok(_) => result,
+                Err(err) => result.and(Err(err.into())),
+            },
+        };
+        // report the error only if nothing is transferred
+        match result {
+            Err(err) if total_written == 0 => Err(err),
+            _ => Ok(total_written),
+        }
     }
 
     pub fn sys_copy_file_range(
@@ -1020,11 +1587,11 @@ impl Syscall<'_> {
             "copy_file_range:BEG in: {}, out: {}, in_offset: {:?}, out_offset: {:?}, count: {} flags {}",
             in_fd, out_fd, in_offset, out_offset, count, flags
         );
-        let proc = self.process();
-        // We know it's save, pacify the borrow checker
-        let proc_cell = UnsafeCell::new(proc);
-        let in_file = unsafe { (*proc_cell.get()).get_file(in_fd)? };
-        let out_file = unsafe { (*proc_cell.get()).get_file(out_fd)? };
+        let mut proc = self.process();
+        // the handles share file offsets with the ones in the table
+        let mut in_file = proc.get_file(in_fd)?.clone();
+        let mut out_file = proc.get_file(out_fd)?.clone();
+        drop(proc);
         let mut buffer = [0u8; 1024];
 
         // for in_offset and out_offset
@@ -1051,7 +1618,9 @@ impl Syscall<'_> {
         let mut total_written = 0;
         while bytes_read < count {
             let len = min(buffer.len(), count - bytes_read);
-            let read_len = in_file.read_at(read_offset, &mut buffer[..len])?;
+            let read_len = in_file
+                .read_at(read_offset, &mut buffer[..len])
+                .map_err(|err| io_error(&in_file, err))?;
             if read_len == 0 {
                 break;
             }
@@ -1100,11 +1669,139 @@ impl Syscall<'_> {
         info!("fcntl: fd: {}, cmd: {:x}, arg: {}", fd, cmd, arg);
         let mut proc = self.process();
         let file_like = proc.get_file_like(fd)?;
-        file_like.fcntl(cmd, arg)
+        match cmd {
+            F_DUPFD | F_DUPFD_CLOEXEC => {
+                if arg >= proc.max_fd() {
+                    return Err(SysError::EINVAL);
+                }
+                let new_file_like = file_like.dup(cmd == F_DUPFD_CLOEXEC);
+                let new_fd = proc.get_free_fd_from(arg);
+                if new_fd >= proc.max_fd() {
+                    return Err(SysError::EMFILE);
+                }
+                proc.files.insert(new_fd, new_file_like);
+                Ok(new_fd)
+            }
+            #[cfg(target_pointer_width = "64")]
+            F_GETLK | F_SETLK | F_SETLKW => {
+                drop(proc);
+                let flock = unsafe { self.vm().check_write_ptr(arg as *mut Flock)? };
+                self.fcntl_record_lock(fd, cmd, flock)
+            }
+            #[cfg(target_pointer_width = "32")]
+            F_GETLK | F_SETLK | F_SETLKW => {
+                drop(proc);
+                let flock32 = unsafe { self.vm().check_write_ptr(arg as *mut Flock32)? };
+                let mut flock = flock32.to_flock();
+                self.fcntl_record_lock(fd, cmd, &mut flock)?;
+                if cmd == F_GETLK {
+                    flock32.set_flock(&flock)?;
+                }
+                Ok(0)
+            }
+            #[cfg(target_pointer_width = "32")]
+            F_GETLK64 | F_SETLK64 | F_SETLKW64 => {
+                drop(proc);
+                let cmd = match cmd {
+                    F_GETLK64 => F_GETLK,
+                    F_SETLK64 => F_SETLK,
+                    _ => F_SETLKW,
+                };
+                let flock = unsafe { self.vm().check_write_ptr(arg as *mut Flock)? };
+                self.fcntl_record_lock(fd, cmd, flock)
+            }
+            _ => file_like.fcntl(cmd, arg),
+        }
+    }
+
+    /// Test, set or release the record lock `flock` on `fd`.
+    /// For F_GETLK, `flock` is updated to the lock which blocks it.
+    fn fcntl_record_lock(&mut self, fd: usize, cmd: usize, flock: &mut Flock) -> SysResult {
+        let mut proc = self.process();
+        let pid = proc.pid.get();
+        let mut file = proc.get_file(fd)?.clone();
+        drop(proc);
+        let metadata = file.metadata()?;
+        let base = match flock.whence as u8 {
+            SEEK_SET => 0,
+            SEEK_CUR => file.seek(SeekFrom::Current(0))? as i64,
+            SEEK_END => metadata.size as i64,
+            _ => return Err(SysError::EINVAL),
+        };
+        let start = base.checked_add(flock.start).ok_or(SysError::EOVERFLOW)?;
+        // a negative length covers the bytes before start
+        let (start, len) = if flock.len < 0 {
+            let len = flock.len.checked_neg().ok_or(SysError::EINVAL)?;
+            (start - len, len)
+        } else {
+            (start, flock.len)
+        };
+        if start < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let end = match len {
+            0 => core::u64::MAX,
+            len => start.checked_add(len).ok_or(SysError::EOVERFLOW)? as u64,
+        };
+        let start = start as u64;
+        let lock = match flock.type_ {
+            F_RDLCK => Some(false),
+            F_WRLCK => Some(true),
+            F_UNLCK if cmd != F_GETLK => None,
+            _ => return Err(SysError::EINVAL),
+        };
+
+        if cmd == F_GETLK {
+            let lock = RecordLock {
+                pid,
+                start,
+                end,
+                write: lock == Some(true),
+            };
+            match test_lock(&metadata, &lock) {
+                Some(held) => {
+                    flock.type_ = if held.write { F_WRLCK } else { F_RDLCK };
+                    flock.whence = SEEK_SET as i16;
+                    flock.start = held.start as i64;
+                    flock.len = match held.end {
+                        core::u64::MAX => 0,
+                        end => (end - held.start) as i64,
+                    };
+                    flock.pid = held.pid as i32;
+                }
+                None => flock.type_ = F_UNLCK,
+            }
+            return Ok(0);
+        }
+
+        let options = file.options();
+        match lock {
+            Some(false) if !options.read => return Err(SysError::EBADF),
+            Some(true) if !options.write => return Err(SysError::EBADF),
+            _ => {}
+        }
+        if set_lock(&metadata, pid, start, end, lock) {
+            return Ok(0);
+        }
+        if cmd == F_SETLK {
+            return Err(SysError::EAGAIN);
+        }
+        set_lock_wait(&metadata, pid, start, end, lock, || self.has_signal_to_do())
     }
 }
 
 impl Process {
+    /// Close `fd`, releasing the record locks of the file and removing it from epoll instances
+    pub fn close_file(&mut self, fd: usize) -> Option<FileLike> {
+        let file_like = self.files.remove(&fd)?;
+        release_file_locks(self.pid.get(), &file_like);
+        for other in self.files.values() {
+            if let FileLike::EpollInstance(instance) = other {
+                instance.remove(fd);
+            }
+        }
+        Some(file_like)
+    }
     pub fn get_file_like(&mut self, fd: usize) -> Result<&mut FileLike, SysError> {
         self.files.get_mut(&fd).ok_or(SysError::EBADF)
     }
@@ -1114,6 +1811,30 @@ impl Process {
             _ => Err(SysError::EBADF),
         }
     }
+    /// Send SIGTTIN to a background process reading its controlling terminal `fd`
+    pub fn check_tty_read(&mut self, fd: usize, sig_mask: Sigset) -> Result<(), SysError> {
+        let is_tty = match self.get_file_like(fd)? {
+            FileLike::File(file) => file.inode().as_any_ref().is::<Stdin>(),
+            _ => false,
+        };
+        // a new session has no controlling terminal
+        if !is_tty || !STDIN.is_controlling(self.sid) || self.pgid == STDIN.foreground_pgid() {
+            return Ok(());
+        }
+        if is_ignored(SIGTTIN, &self.signal_actions[SIGTTIN]) || sig_mask & sigmask(SIGTTIN) != 0 {
+            return Err(SysError::EIO);
+        }
+        self.send_signal(SIGTTIN);
+        Err(SysError::EINTR)
+    }
+
+    /// Get a file which supports positioned I/O, or ESPIPE for pipes and sockets
+    pub fn get_seekable_file(&mut self, fd: usize) -> Result<&mut FileHandle, SysError> {
+        match self.get_file_like(fd)? {
+            FileLike::File(file) if file.is_seekable() => Ok(file),
+            _ => Err(SysError::ESPIPE),
+        }
+    }
     pub fn get_file_const(&self, fd: usize) -> Result<&FileHandle, SysError> {
         match self.files.get(&fd).ok_or(SysError::EBADF)? {
             FileLike::File(file) => Ok(file),
@@ -1162,18 +1883,35 @@ impl Process {
             _ => {}
         }
 
-        let follow_max_depth = if follow { FOLLOW_MAX_DEPTH } else { 0 };
-        if dirfd == AT_FDCWD {
-            Ok(ROOT_INODE
-                .lookup(&self.cwd)?
-                .lookup_follow(path, follow_max_depth)?)
+        let base = if path.starts_with('/') {
+            // dirfd is ignored for absolute paths
+            ROOT_INODE.clone()
+        } else if dirfd == AT_FDCWD {
+            ROOT_INODE.lookup(&self.cwd)?
         } else {
             let file = match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
                 FileLike::File(file) => file,
                 _ => return Err(SysError::EBADF),
             };
-            Ok(file.lookup_follow(path, follow_max_depth)?)
+            // an empty path refers to the file itself
+            if !path.is_empty() && file.metadata()?.type_ != FileType::Dir {
+                return Err(SysError::ENOTDIR);
+            }
+            file.inode()
+        };
+        let inode = match path.rfind('/') {
+            // links before the last component are always followed
+            Some(pos) if !follow && pos + 1 < path.len() => base
+                .lookup_follow(&path[..=pos], FOLLOW_MAX_DEPTH)?
+                .lookup_follow(&path[pos + 1..], 0)?,
+            _ if !follow => base.lookup_follow(path, 0)?,
+            _ => base.lookup_follow(path, FOLLOW_MAX_DEPTH)?,
+        };
+        // still a link after following FOLLOW_MAX_DEPTH times: must be a loop
+        if follow && inode.metadata()?.type_ == FileType::SymLink {
+            return Err(SysError::ELOOP);
         }
+        Ok(inode)
     }
 
     pub fn lookup_inode(&self, path: &str) -> Result<Arc<dyn INode>, SysError> {
@@ -1192,35 +1930,221 @@ fn split_path(path: &str) -> (&str, &str) {
     (dir_path, file_name)
 }
 
-impl From<FsError> for SysError {
-    fn from(error: FsError) -> Self {
-        match error {
-            FsError::NotSupported => SysError::ENOSYS,
-            FsError::NotFile => SysError::EISDIR,
-            FsError::IsDir => SysError::EISDIR,
-            FsError::NotDir => SysError::ENOTDIR,
-            FsError::EntryNotFound => SysError::ENOENT,
-            FsError::EntryExist => SysError::EEXIST,
-            FsError::NotSameFs => SysError::EXDEV,
-            FsError::InvalidParam => SysError::EINVAL,
-            FsError::NoDeviceSpace => SysError::ENOMEM,
-            FsError::DirRemoved => SysError::ENOENT,
-            FsError::DirNotEmpty => SysError::ENOTEMPTY,
-            FsError::WrongFs => SysError::EINVAL,
-            FsError::DeviceError => SysError::EIO,
-            FsError::IOCTLError => SysError::EINVAL,
-            FsError::NoDevice => SysError::EINVAL,
-            FsError::Again => SysError::EAGAIN,
-            FsError::SymLoop => SysError::ELOOP,
-            FsError::Busy => SysError::EBUSY,
+/// Get the absolute path of `path` relative to `cwd`, without '.' and '..'
+pub fn absolute_path(cwd: &str, path: &str) -> String {
+    let base = if path.starts_with('/') { "/" } else { cwd };
+    let mut segs: Vec<_> = base.split('/').filter(|&x| x != "").collect();
+    for seg in path.split('/').filter(|&x| x != "") {
+        match seg {
+            ".." => {
+                segs.pop();
+            }
+            "." => {}
+            _ => segs.push(seg),
+        }
+    }
+    if segs.is_empty() {
+        return String::from("/");
+    }
+    let mut abs_path = String::new();
+    for seg in segs {
+        abs_path.push('/');
+        abs_path.push_str(seg);
+    }
+    abs_path
+}
+
+/// Whether the absolute `path` is `dir` or inside it
+fn is_under(path: &str, dir: &str) -> bool {
+    path == dir || (path.starts_with(dir) && path[dir.len()..].starts_with('/'))
+}
+
+/// Whether any process has its cwd or an open file in the file system `fs`
+fn is_fs_in_use(fs: &Arc<dyn FileSystem>) -> bool {
+    let processes: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .collect();
+    processes.iter().any(|process| {
+        let proc = process.lock();
+        let in_fs = |inode: Arc<dyn INode>| Arc::ptr_eq(&inode.fs(), fs);
+        // the cwd is kept by path, so find the directory it names now
+        proc.lookup_inode(".").map_or(false, in_fs)
+            || proc.files.values().any(|file_like| match file_like {
+                FileLike::File(file) => in_fs(file.inode()),
+                _ => false,
+            })
+    })
+}
+
+/// Whether a directory has no entries other than '.' and '..'
+fn is_empty_dir(inode: &Arc<dyn INode>) -> bool {
+    let mut id = 0;
+    while let Ok(name) = inode.get_entry(id) {
+        if name != "." && name != ".." {
+            return false;
+        }
+        id += 1;
+    }
+    true
+}
+
+/// Check that `cred` is granted `access`, a mask of R_OK, W_OK and X_OK, to a file.
+/// Root is granted everything, except executing a file without any execute bit.
+fn check_access(cred: &Credentials, info: &Metadata, access: usize) -> Result<(), SysError> {
+    if cred.is_privileged() {
+        if access & X_OK != 0 && info.type_ != FileType::Dir && info.mode & 0o111 == 0 {
+            return Err(SysError::EACCES);
+        }
+        return Ok(());
+    }
+    let bits = if cred.euid as usize == info.uid {
+        info.mode >> 6
+    } else if cred.in_group(info.gid as u32) {
+        info.mode >> 3
+    } else {
+        info.mode
+    };
+    if bits as usize & access != access {
+        return Err(SysError::EACCES);
+    }
+    Ok(())
+}
+
+/// Make a newly created `inode` owned by the effective ids of `cred`.
+/// It stays owned by root on file systems which can't record an owner.
+fn set_owner(inode: &Arc<dyn INode>, cred: &Credentials) {
+    if let Ok(mut info) = inode.metadata() {
+        if info.uid != cred.euid as usize || info.gid != cred.egid as usize {
+            info.uid = cred.euid as usize;
+            info.gid = cred.egid as usize;
+            inode.set_metadata(&info).ok();
+        }
+    }
+}
+
+/// Id given to chown which leaves the owner or group unchanged
+const UNCHANGED_ID: u32 = -1i32 as u32;
+
+/// Change the owner and group of `inode`.
+/// Only root can change the owner, and the owner can change the group to one of its groups.
+fn change_owner(inode: &Arc<dyn INode>, cred: &Credentials, uid: u32, gid: u32) -> SysResult {
+    let mut info = inode.metadata()?;
+    if !cred.is_privileged() {
+        if uid != UNCHANGED_ID && uid as usize != info.uid {
+            return Err(SysError::EPERM);
+        }
+        if gid != UNCHANGED_ID
+            && (cred.euid as usize != info.uid || !cred.in_group(gid) && gid as usize != info.gid)
+        {
+            return Err(SysError::EPERM);
+        }
+    }
+    if uid == UNCHANGED_ID && gid == UNCHANGED_ID {
+        return Ok(0);
+    }
+    if uid != UNCHANGED_ID {
+        info.uid = uid as usize;
+    }
+    if gid != UNCHANGED_ID {
+        info.gid = gid as usize;
+    }
+    // a program must not keep running as the previous owner
+    if info.type_ != FileType::Dir {
+        info.mode &= !0o6000;
+    }
+    inode.set_metadata(&info)?;
+    Ok(0)
+}
+
+/// Create a node of `type_` named `name` in `dir_inode`, owned by `cred`.
+/// Device nodes refer to the device `dev`, and only root can create them.
+fn make_node(
+    dir_inode: &Arc<dyn INode>,
+    name: &str,
+    type_: FileType,
+    mode: usize,
+    dev: usize,
+    cred: &Credentials,
+) -> SysResult {
+    let dir_info = dir_inode.metadata()?;
+    if dir_info.type_ != FileType::Dir {
+        return Err(SysError::ENOTDIR);
+    }
+    if dir_inode.find(name).is_ok() {
+        return Err(SysError::EEXIST);
+    }
+    check_access(cred, &dir_info, W_OK | X_OK)?;
+    let is_device = type_ == FileType::CharDevice || type_ == FileType::BlockDevice;
+    if is_device && !cred.is_privileged() {
+        return Err(SysError::EPERM);
+    }
+    let inode = dir_inode.create(name, type_, mode as u32)?;
+    set_owner(&inode, cred);
+    if is_device {
+        let mut info = inode.metadata()?;
+        info.rdev = dev;
+        if let Err(err) = inode.set_metadata(&info) {
+            // the file system can not keep the device number
+            dir_inode.unlink(name)?;
+            return Err(err.into());
         }
     }
+    Ok(0)
+}
+
+/// Check that `pgid` can be the foreground process group of the console,
+/// set by a process in session `sid`:
+/// the console must be its controlling terminal, and the group must exist in the session.
+fn check_console_pgid(sid: usize, pgid: i32) -> Result<(), SysError> {
+    if !STDIN.is_controlling(sid) {
+        return Err(SysError::ENOTTY);
+    }
+    if pgid < 0 {
+        return Err(SysError::EINVAL);
+    }
+    let processes: Vec<_> = PROCESSES
+        .read()
+        .values()
+        .filter_map(|weak| weak.upgrade())
+        .collect();
+    let exists = processes.iter().any(|proc| {
+        let proc = proc.lock();
+        proc.pgid == pgid as usize && proc.sid == sid
+    });
+    if !exists {
+        return Err(SysError::EPERM);
+    }
+    Ok(())
+}
+
+/// Read the target of symlink `inode` to `buf`, truncated if it does not fit
+fn read_link(inode: &Arc<dyn INode>, buf: &mut [u8]) -> SysResult {
+    if inode.metadata()?.type_ != FileType::SymLink {
+        return Err(SysError::EINVAL);
+    }
+    Ok(inode.read_at(0, buf)?)
+}
+
+/// Change the permission bits of `inode`, keeping its file type.
+/// Only the owner and root can change them.
+fn set_mode(inode: &Arc<dyn INode>, cred: &Credentials, mode: usize) -> SysResult {
+    let mut info = inode.metadata()?;
+    if !cred.is_privileged() && cred.euid as usize != info.uid {
+        return Err(SysError::EPERM);
+    }
+    info.mode = (mode & 0o7777) as u16;
+    inode.set_metadata(&info)?;
+    Ok(0)
 }
 
 bitflags! {
     struct AtFlags: usize {
         const EMPTY_PATH = 0x1000;
         const SYMLINK_NOFOLLOW = 0x100;
+        const REMOVEDIR = 0x200;
+        const SYMLINK_FOLLOW = 0x400;
     }
 }
 
@@ -1240,6 +2164,16 @@ bitflags! {
         const TRUNCATE = 1 << 9;
         /// append on each write
         const APPEND = 1 << 10;
+        /// non-blocking IO
+        const NONBLOCK = 1 << 11;
+        /// error if the file is not a directory
+        const DIRECTORY = 1 << 16;
+        /// do not follow a symbolic link in the last component
+        const NOFOLLOW = 1 << 17;
+        /// close on exec
+        const CLOEXEC = 1 << 19;
+        /// only locate the file, without opening it for I/O
+        const PATH = 1 << 21;
     }
 }
 
@@ -1253,11 +2187,21 @@ impl OpenFlags {
         b == OpenFlags::WRONLY.bits() || b == OpenFlags::RDWR.bits()
     }
     fn to_options(&self) -> OpenOptions {
+        if self.contains(OpenFlags::PATH) {
+            return OpenOptions {
+                read: false,
+                write: false,
+                append: false,
+                nonblock: false,
+                path: true,
+            };
+        }
         OpenOptions {
             read: self.readable(),
             write: self.writable(),
             append: self.contains(OpenFlags::APPEND),
-            nonblock: false,
+            nonblock: self.contains(OpenFlags::NONBLOCK),
+            path: false,
         }
     }
 }
@@ -1292,7 +2236,8 @@ impl<'a> DirentBufWriter<'a> {
             buf,
         }
     }
-    fn try_write(&mut self, inode: u64, type_: u8, name: &str) -> bool {
+    /// Write an entry if the rest of buffer is large enough
+    fn try_write(&mut self, inode: u64, offset: u64, type_: u8, name: &str) -> bool {
         let len = ::core::mem::size_of::<LinuxDirent64>() + name.len() + 1;
         let len = (len + 7) / 8 * 8; // align up
         if self.rest_size < len {
@@ -1300,7 +2245,7 @@ impl<'a> DirentBufWriter<'a> {
         }
         let dent = LinuxDirent64 {
             ino: inode,
-            offset: 0,
+            offset,
             reclen: len as u16,
             type_,
             name: [],
@@ -1619,10 +2564,100 @@ impl From<Metadata> for Stat {
     }
 }
 
+/// `struct statfs`
+#[cfg(not(target_arch = "mips"))]
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct StatFs {
+    /// Type of filesystem
+    type_: usize,
+    /// Optimal transfer block size
+    bsize: usize,
+    /// Total data blocks in filesystem
+    blocks: usize,
+    /// Free blocks in filesystem
+    bfree: usize,
+    /// Free blocks available to unprivileged user
+    bavail: usize,
+    /// Total inodes in filesystem
+    files: usize,
+    /// Free inodes in filesystem
+    ffree: usize,
+    /// Filesystem ID
+    fsid: [i32; 2],
+    /// Maximum length of filenames
+    namelen: usize,
+    /// Fragment size
+    frsize: usize,
+    /// Mount flags of filesystem
+    flags: usize,
+    spare: [usize; 4],
+}
+
+/// `struct statfs`
+#[cfg(target_arch = "mips")]
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct StatFs {
+    /// Type of filesystem
+    type_: usize,
+    /// Optimal transfer block size
+    bsize: usize,
+    /// Fragment size
+    frsize: usize,
+    /// Total data blocks in filesystem
+    blocks: usize,
+    /// Free blocks in filesystem
+    bfree: usize,
+    /// Total inodes in filesystem
+    files: usize,
+    /// Free inodes in filesystem
+    ffree: usize,
+    /// Free blocks available to unprivileged user
+    bavail: usize,
+    /// Filesystem ID
+    fsid: [i32; 2],
+    /// Maximum length of filenames
+    namelen: usize,
+    /// Mount flags of filesystem
+    flags: usize,
+    spare: [usize; 5],
+}
+
+impl From<FsInfo> for StatFs {
+    /// Filesystems not tracking free space report zeros
+    fn from(info: FsInfo) -> Self {
+        StatFs {
+            bsize: info.bsize,
+            blocks: info.blocks,
+            bfree: info.bfree,
+            bavail: info.bavail,
+            files: info.files,
+            ffree: info.ffree,
+            namelen: info.namemax,
+            frsize: info.frsize,
+            ..StatFs::default()
+        }
+    }
+}
+
+// flags of mount
+const MS_REMOUNT: usize = 32;
+const MS_BIND: usize = 4096;
+const MS_MOVE: usize = 8192;
+
 const SEEK_SET: u8 = 0;
 const SEEK_CUR: u8 = 1;
 const SEEK_END: u8 = 2;
 
+// flags of eventfd2, besides O_CLOEXEC and O_NONBLOCK
+const EFD_SEMAPHORE: usize = 1;
+
+// mode of faccessat
+const R_OK: usize = 4;
+const W_OK: usize = 2;
+const X_OK: usize = 1;
+
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct IoVec {
@@ -1632,63 +2667,122 @@ pub struct IoVec {
     len: usize,
 }
 
+/// Maximum number of iovecs in a request
+pub const IOV_MAX: usize = 1024;
+
 /// A valid IoVecs request from user
+///
+/// Buffers are only accessed through `copy_from_user_array` and `copy_to_user_array`,
+/// so that a page fault during the copy fails with `EFAULT`.
 #[derive(Debug)]
-pub struct IoVecs(Vec<&'static mut [u8]>);
+pub struct IoVecs(Vec<IoVec>);
 
 impl IoVecs {
+    /// Read and check the iovec array from user.
+    ///
+    /// Fail with `EINVAL` if there are more than `IOV_MAX` iovecs or the total length overflows,
+    /// and with `EFAULT` if the array or a buffer is not in user space.
+    /// The request is cut at the first buffer which is not mapped,
+    /// so that the bytes before it are still transferred.
     pub unsafe fn check_and_new(
         iov_ptr: *const IoVec,
         iov_count: usize,
         vm: &MemorySet,
         readv: bool,
     ) -> Result<Self, SysError> {
-        let iovs = vm.check_read_array(iov_ptr, iov_count)?.to_vec();
-        // check all bufs in iov
+        if iov_count > IOV_MAX {
+            return Err(SysError::EINVAL);
+        }
+        UserInPtr::<IoVec>::from(iov_ptr as usize).check(iov_count)?;
+        vm.check_read_array(iov_ptr, iov_count)?;
+        let mut iovs = vec![
+            IoVec {
+                base: core::ptr::null_mut(),
+                len: 0
+            };
+            iov_count
+        ];
+        if !copy_from_user_array(&mut iovs, iov_ptr) {
+            return Err(SysError::EFAULT);
+        }
+        let mut total_len = 0usize;
         for iov in iovs.iter() {
+            total_len = total_len
+                .checked_add(iov.len)
+                .filter(|&len| len <= isize::max_value() as usize)
+                .ok_or(SysError::EINVAL)?;
+            // skip empty iov
+            if iov.len == 0 {
+                continue;
+            }
+            UserInPtr::<u8>::from(iov.base as usize).check(iov.len)?;
+        }
+        // check all bufs in iov
+        for (i, iov) in iovs.iter().enumerate() {
             // skip empty iov
             if iov.len == 0 {
                 continue;
             }
-            if readv {
-                vm.check_write_array(iov.base, iov.len)?;
+            let mapped = if readv {
+                vm.check_write_array(iov.base, iov.len).is_ok()
             } else {
-                vm.check_read_array(iov.base, iov.len)?;
+                vm.check_read_array(iov.base, iov.len).is_ok()
+            };
+            if !mapped {
+                if iovs[..i].iter().all(|iov| iov.len == 0) {
+                    return Err(SysError::EFAULT);
+                }
+                iovs.truncate(i);
+                break;
             }
         }
-        let slices = iovs
-            .iter()
-            .map(|iov| slice::from_raw_parts_mut(iov.base, iov.len))
-            .collect();
-        Ok(IoVecs(slices))
+        Ok(IoVecs(iovs))
     }
 
-    pub fn read_all_to_vec(&self) -> Vec<u8> {
-        let mut buf = self.new_buf(false);
-        for slice in self.0.iter() {
-            buf.extend(slice.iter());
+    /// Gather data from all buffers.
+    /// If a buffer faults, return the data before it.
+    pub fn read_all_to_vec(&self) -> Result<Vec<u8>, SysError> {
+        let mut buf = self.new_buf(true);
+        let mut copied_len = 0;
+        for iov in self.0.iter() {
+            let dst = &mut buf[copied_len..copied_len + iov.len];
+            if !copy_from_user_array(dst, iov.base) {
+                if copied_len == 0 {
+                    return Err(SysError::EFAULT);
+                }
+                break;
+            }
+            copied_len += iov.len;
         }
-        buf
+        buf.truncate(copied_len);
+        Ok(buf)
     }
 
-    pub fn write_all_from_slice(&mut self, buf: &[u8]) {
+    /// Scatter `buf` to the buffers, and return the number of bytes copied.
+    /// If a buffer faults, return the bytes copied before it.
+    pub fn write_all_from_slice(&self, buf: &[u8]) -> Result<usize, SysError> {
         let mut copied_len = 0;
-        for slice in self.0.iter_mut() {
-            let copy_len = min(slice.len(), buf.len() - copied_len);
+        for iov in self.0.iter() {
+            let copy_len = min(iov.len, buf.len() - copied_len);
             if copy_len == 0 {
                 continue;
             }
-
-            slice[..copy_len].copy_from_slice(&buf[copied_len..copied_len + copy_len]);
+            if !copy_to_user_array(iov.base, &buf[copied_len..copied_len + copy_len]) {
+                if copied_len == 0 {
+                    return Err(SysError::EFAULT);
+                }
+                break;
+            }
             copied_len += copy_len;
         }
+        Ok(copied_len)
     }
 
     /// Create a new Vec buffer from IoVecs
     /// For readv:  `set_len` is true,  Vec.len = total_len.
     /// For writev: `set_len` is false, Vec.cap = total_len.
     pub fn new_buf(&self, set_len: bool) -> Vec<u8> {
-        let total_len = self.0.iter().map(|slice| slice.len()).sum::<usize>();
+        let total_len = self.0.iter().map(|iov| iov.len).sum::<usize>();
         let mut buf = Vec::with_capacity(total_len);
         if set_len {
             unsafe {
@@ -1780,3 +2874,177 @@ impl FdSet {
 
 /// Pathname is interpreted relative to the current working directory(CWD)
 const AT_FDCWD: usize = -100isize as usize;
+
+pub mod test {
+    //! Permission checks of chmod, chown and mknod, reading symlinks,
+    //! and setting the foreground process group of the console
+
+    use super::*;
+    use rcore_fs::vfs::FileSystem;
+
+    fn user(uid: u32) -> Credentials {
+        Credentials {
+            uid,
+            euid: uid,
+            suid: uid,
+            gid: uid,
+            egid: uid,
+            sgid: uid,
+            groups: Vec::new(),
+        }
+    }
+
+    /// A directory of root which others can not write, and a file of user 1000 in it.
+    /// The file system is returned to be kept while the inodes are used.
+    fn setup() -> (Arc<TmpFS>, Arc<dyn INode>, Arc<dyn INode>) {
+        let fs = TmpFS::new(0x10000);
+        let dir = fs.root_inode();
+        set_mode(&dir, &Credentials::root(), 0o755).unwrap();
+        let file = dir.create("file", FileType::File, 0o644).unwrap();
+        set_owner(&file, &user(1000));
+        (fs, dir, file)
+    }
+
+    fn chmod_by_owner_or_root() {
+        let (_fs, _, file) = setup();
+        assert_eq!(set_mode(&file, &user(1001), 0o777), Err(SysError::EPERM));
+        assert_eq!(file.metadata().unwrap().mode, 0o644);
+        assert_eq!(set_mode(&file, &user(1000), 0o600), Ok(0));
+        assert_eq!(set_mode(&file, &Credentials::root(), 0o640), Ok(0));
+        assert_eq!(file.metadata().unwrap().mode, 0o640);
+    }
+
+    fn mknod_in_unwritable_dir() {
+        let (_fs, dir, _) = setup();
+        let result = make_node(&dir, "fifo", FileType::NamedPipe, 0o644, 0, &user(1000));
+        assert_eq!(result, Err(SysError::EACCES));
+        assert!(dir.find("fifo").is_err());
+        let result = make_node(
+            &dir,
+            "fifo",
+            FileType::NamedPipe,
+            0o644,
+            0,
+            &Credentials::root(),
+        );
+        assert_eq!(result, Ok(0));
+    }
+
+    fn mknod_device_by_root() {
+        let (_fs, dir, _) = setup();
+        set_mode(&dir, &Credentials::root(), 0o777).unwrap();
+        for &type_ in [FileType::CharDevice, FileType::BlockDevice].iter() {
+            let result = make_node(&dir, "dev", type_, 0o644, 0x101, &user(1000));
+            assert_eq!(result, Err(SysError::EPERM));
+            assert!(dir.find("dev").is_err());
+        }
+        make_node(&dir, "fifo", FileType::NamedPipe, 0o644, 0, &user(1000)).unwrap();
+        assert_eq!(dir.find("fifo").unwrap().metadata().unwrap().uid, 1000);
+        make_node(
+            &dir,
+            "dev",
+            FileType::CharDevice,
+            0o644,
+            0x101,
+            &Credentials::root(),
+        )
+        .unwrap();
+        assert_eq!(dir.find("dev").unwrap().metadata().unwrap().rdev, 0x101);
+    }
+
+    /// The target is read back without a NUL, and truncated to the buffer
+    fn readlink_target() {
+        let (_fs, dir, file) = setup();
+        let link = dir.create("link", FileType::SymLink, 0o777).unwrap();
+        link.write_at(0, b"/some/target").unwrap();
+        let mut buf = [0xffu8; 16];
+        assert_eq!(read_link(&link, &mut buf), Ok(12));
+        assert_eq!(&buf[..12], b"/some/target");
+        assert_eq!(buf[12], 0xff);
+        assert_eq!(read_link(&link, &mut buf[..5]), Ok(5));
+        assert_eq!(&buf[..5], b"/some");
+        assert_eq!(read_link(&file, &mut buf), Err(SysError::EINVAL));
+        assert_eq!(read_link(&dir, &mut buf), Err(SysError::EINVAL));
+    }
+
+    /// The owner only changes the group to one of its groups, root changes both,
+    /// and -1 leaves an id unchanged. A set-user-id program loses the bit.
+    fn chown_then_stat() {
+        let (_fs, _, file) = setup();
+        let mut owner = user(1000);
+        owner.groups = vec![100];
+        assert_eq!(
+            change_owner(&file, &owner, UNCHANGED_ID, UNCHANGED_ID),
+            Ok(0)
+        );
+        assert_eq!(
+            change_owner(&file, &owner, 1001, UNCHANGED_ID),
+            Err(SysError::EPERM)
+        );
+        assert_eq!(
+            change_owner(&file, &owner, UNCHANGED_ID, 200),
+            Err(SysError::EPERM)
+        );
+        assert_eq!(
+            change_owner(&file, &user(1001), UNCHANGED_ID, 1001),
+            Err(SysError::EPERM)
+        );
+        let stat = Stat::from(file.metadata().unwrap());
+        assert_eq!((stat.uid, stat.gid), (1000, 1000));
+
+        set_mode(&file, &owner, 0o4755).unwrap();
+        assert_eq!(change_owner(&file, &owner, UNCHANGED_ID, 100), Ok(0));
+        let stat = Stat::from(file.metadata().unwrap());
+        assert_eq!((stat.uid, stat.gid), (1000, 100));
+        assert_eq!(stat.mode.bits() & 0o7777, 0o755);
+
+        let root = Credentials::root();
+        assert_eq!(change_owner(&file, &root, 2000, UNCHANGED_ID), Ok(0));
+        let stat = Stat::from(file.metadata().unwrap());
+        assert_eq!((stat.uid, stat.gid), (2000, 100));
+        assert_eq!(change_owner(&file, &root, UNCHANGED_ID, 0), Ok(0));
+        let stat = Stat::from(file.metadata().unwrap());
+        assert_eq!((stat.uid, stat.gid), (2000, 0));
+    }
+
+    extern "C" fn never_run(_arg: usize) -> ! {
+        unreachable!()
+    }
+
+    /// A group of init's session can be the foreground one, until its process leaves by setsid
+    fn foreground_pgid_in_session() {
+        const PID: usize = 0xffff;
+        const PGID: usize = 0xfff0;
+        let thread = Thread::new_kernel(never_run, 0);
+        {
+            let mut proc = thread.proc.lock();
+            proc.pgid = PGID;
+            proc.sid = INIT_PID;
+        }
+        PROCESSES.write().insert(PID, Arc::downgrade(&thread.proc));
+        assert_eq!(check_console_pgid(INIT_PID, PGID as i32), Ok(()));
+        assert_eq!(
+            check_console_pgid(INIT_PID, PGID as i32 + 1),
+            Err(SysError::EPERM)
+        );
+        assert_eq!(check_console_pgid(INIT_PID, -1), Err(SysError::EINVAL));
+        let sid = thread.proc.lock().set_sid().unwrap();
+        assert_eq!(check_console_pgid(sid, sid as i32), Err(SysError::ENOTTY));
+        // the group has left init's session
+        assert_eq!(
+            check_console_pgid(INIT_PID, PGID as i32),
+            Err(SysError::EPERM)
+        );
+        PROCESSES.write().remove(&PID);
+    }
+
+    pub fn test_all() {
+        foreground_pgid_in_session();
+        chmod_by_owner_or_root();
+        chown_then_stat();
+        mknod_in_unwritable_dir();
+        mknod_device_by_root();
+        readlink_target();
+        println!("fs permission test end");
+    }
+}
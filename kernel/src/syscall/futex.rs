@@ -0,0 +1,202 @@
+//! Syscalls for futex
+//!
+//! Waiters of a private futex are queued by the address space and the address of the word.
+//! Waiters of a shared futex are queued by the physical address of the word,
+//! so that threads of processes sharing the memory meet at the same queue.
+
+use super::*;
+use crate::sync::SpinNoIrqLock;
+use crate::trap::TICK_ACTIVITY;
+use alloc::collections::VecDeque;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use rcore_memory::PAGE_SIZE;
+
+const FUTEX_WAIT: u32 = 0;
+const FUTEX_WAKE: u32 = 1;
+const FUTEX_PRIVATE_FLAG: u32 = 0x80;
+const FUTEX_CLOCK_REALTIME: u32 = 0x100;
+
+/// Identity of a futex queue
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum FutexKey {
+    /// The address space and the virtual address, for `FUTEX_PRIVATE_FLAG`
+    Private { vm: usize, addr: usize },
+    /// The physical address
+    Shared(usize),
+}
+
+lazy_static! {
+    /// Waiters of each futex
+    static ref FUTEXES: SpinNoIrqLock<BTreeMap<FutexKey, VecDeque<Arc<FutexWaiter>>>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+}
+
+#[derive(Default)]
+struct FutexWaiter {
+    /// Set when it is removed from the queue by a wake
+    woken: AtomicBool,
+    queue: WaitQueue,
+}
+
+/// Wake up to `n` waiters of the futex of `key`.
+/// Return the number of waiters woken up.
+pub fn futex_wake(key: FutexKey, n: usize) -> usize {
+    let mut futexes = FUTEXES.lock();
+    let queue = match futexes.get_mut(&key) {
+        Some(queue) => queue,
+        None => return 0,
+    };
+    let mut count = 0;
+    while count < n {
+        match queue.pop_front() {
+            Some(waiter) => {
+                waiter.woken.store(true, Ordering::Release);
+                waiter.queue.wake_one();
+                count += 1;
+            }
+            None => break,
+        }
+    }
+    if queue.is_empty() {
+        futexes.remove(&key);
+    }
+    count
+}
+
+impl Syscall<'_> {
+    /// Get the key of the futex at user address `uaddr`.
+    ///
+    /// A shared key is the physical address, so the word must have been written
+    /// before, to break copy-on-write and get the frame it will stay in.
+    pub fn futex_key(&self, uaddr: usize, private: bool) -> Result<FutexKey, SysError> {
+        if private {
+            return Ok(FutexKey::Private {
+                vm: &*self.thread.vm as *const _ as usize,
+                addr: uaddr,
+            });
+        }
+        match self.vm().translate(uaddr) {
+            Some(page) => Ok(FutexKey::Shared(
+                (page & !(PAGE_SIZE - 1)) + uaddr % PAGE_SIZE,
+            )),
+            None => Err(SysError::EFAULT),
+        }
+    }
+
+    /// Fast user-space locking.
+    ///
+    /// `FUTEX_WAIT` sleeps until woken if `*uaddr == val`, or until the relative `timeout`.
+    /// `FUTEX_WAKE` wakes up to `val` waiters, and returns the number of them.
+    ///
+    /// `uaddr2` and `val3` are only used by operations which are not supported.
+    pub fn sys_futex(
+        &mut self,
+        uaddr: usize,
+        op: u32,
+        val: i32,
+        timeout: *const TimeSpec,
+        uaddr2: usize,
+        val3: u32,
+    ) -> SysResult {
+        info!(
+            "futex: [{}] uaddr: {:#x}, op: {:#x}, val: {}, timeout_ptr: {:?}, uaddr2: {:#x}, val3: {:#x}",
+            thread::current().id(),
+            uaddr,
+            op,
+            val,
+            timeout,
+            uaddr2,
+            val3
+        );
+        if uaddr % size_of::<u32>() != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let atomic = unsafe { self.vm().check_write_ptr(uaddr as *mut AtomicI32)? };
+        let private = op & FUTEX_PRIVATE_FLAG != 0;
+        if !private {
+            // write fault on the page before finding its physical address,
+            // so that a copy-on-write page is copied now instead of after queueing
+            atomic.fetch_add(0, Ordering::AcqRel);
+        }
+        let key = self.futex_key(uaddr, private)?;
+
+        match op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+            FUTEX_WAIT => {
+                let timeout_msecs = if timeout.is_null() {
+                    None
+                } else {
+                    let timeout = unsafe { *self.vm().check_read_ptr(timeout)? };
+                    if !timeout.is_valid() {
+                        return Err(SysError::EINVAL);
+                    }
+                    Some(timeout.to_msec() as usize)
+                };
+                self.futex_wait(key, atomic, val, timeout_msecs)
+            }
+            FUTEX_WAKE => Ok(futex_wake(key, val as usize)),
+            _ => {
+                warn!("unsupported futex operation: {}", op);
+                Err(SysError::ENOSYS)
+            }
+        }
+    }
+
+    fn futex_wait(
+        &mut self,
+        key: FutexKey,
+        atomic: &AtomicI32,
+        val: i32,
+        timeout_msecs: Option<usize>,
+    ) -> SysResult {
+        let waiter = Arc::new(FutexWaiter::default());
+        {
+            // a wake can not slip in between the check and queueing
+            let mut futexes = FUTEXES.lock();
+            if atomic.load(Ordering::Acquire) != val {
+                return Err(SysError::EAGAIN);
+            }
+            futexes
+                .entry(key)
+                .or_insert_with(VecDeque::new)
+                .push_back(waiter.clone());
+        }
+
+        let mut queues = vec![&waiter.queue];
+        if timeout_msecs.is_some() {
+            queues.push(&*TICK_ACTIVITY);
+        }
+        let begin_time_ms = crate::trap::uptime_msec();
+        let result = WaitQueue::wait_any_until(&queues, || {
+            if waiter.woken.load(Ordering::Acquire) {
+                return Some(Ok(0));
+            }
+            if let Some(timeout_msecs) = timeout_msecs {
+                let current_time_ms = crate::trap::uptime_msec();
+                if current_time_ms - begin_time_ms >= timeout_msecs {
+                    return Some(Err(SysError::ETIMEDOUT));
+                }
+            }
+            if self.has_signal_to_do() {
+                return Some(Err(SysError::EINTR));
+            }
+            None
+        });
+        if result.is_ok() {
+            return result;
+        }
+
+        // leave the queue, unless a wake has just taken it
+        let mut futexes = FUTEXES.lock();
+        if waiter.woken.load(Ordering::Acquire) {
+            return Ok(0);
+        }
+        if let Some(queue) = futexes.get_mut(&key) {
+            queue.retain(|other| !Arc::ptr_eq(other, &waiter));
+            if queue.is_empty() {
+                futexes.remove(&key);
+            }
+        }
+        result
+    }
+}
@@ -3,6 +3,9 @@ use crate::sync::SpinLock as Mutex;
 use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, sync::Weak, vec::Vec};
 use bitflags::*;
 use core::cell::UnsafeCell;
+use core::mem::size_of;
+use rcore_memory::memory_set::MemoryAttr;
+use rcore_memory::PAGE_SIZE;
 use spin::RwLock;
 
 pub use crate::ipc::*;
@@ -10,8 +13,13 @@ pub use crate::ipc::*;
 use super::*;
 
 impl Syscall<'_> {
+    /// Get the semaphore set of `key`, or create one with `nsems` semaphores,
+    /// and return its id
     pub fn sys_semget(&self, key: usize, nsems: isize, flags: usize) -> SysResult {
-        info!("semget: key: {}", key);
+        info!(
+            "semget: key: {}, nsems: {}, flags: {:#o}",
+            key, nsems, flags
+        );
 
         /// The maximum semaphores per semaphore set
         const SEMMSL: usize = 256;
@@ -20,70 +28,582 @@ impl Syscall<'_> {
             return Err(SysError::EINVAL);
         }
         let nsems = nsems as usize;
-
-        let sem_array = SemArray::get_or_create(key, nsems, flags);
-        let id = self.process().semaphores.add(sem_array);
-        Ok(id)
+        let proc = self.process();
+        let perm = IpcPerm::new(key, flags as u16, &proc.cred);
+        let create = key == IPC_PRIVATE || flags & IPC_CREAT != 0;
+        if create && nsems == 0 {
+            return Err(SysError::EINVAL);
+        }
+        let (array, created) =
+            SemArray::get_or_create(key, nsems, create, perm).ok_or(SysError::ENOENT)?;
+        if !created {
+            if flags & IPC_CREAT != 0 && flags & IPC_EXCL != 0 {
+                return Err(SysError::EEXIST);
+            }
+            if nsems > array.nsems() {
+                return Err(SysError::EINVAL);
+            }
+            let access = ((flags >> 6) | (flags >> 3) | flags) as u16;
+            if !array.state.lock().perm.allowed(&proc.cred, access) {
+                return Err(SysError::EACCES);
+            }
+        }
+        Ok(array.id)
     }
 
+    /// Perform the operations on semaphores of set `id` atomically,
+    /// waiting until none of them blocks unless IPC_NOWAIT
     pub fn sys_semop(&self, id: usize, ops: *const SemBuf, num_ops: usize) -> SysResult {
-        info!("semop: id: {}", id);
-        let ops = unsafe { self.vm().check_read_array(ops, num_ops)? };
+        info!("semop: id: {}, num_ops: {}", id, num_ops);
 
-        let sem_array = self.process().semaphores.get(id).ok_or(SysError::EINVAL)?;
-        for &SemBuf { num, op, flags } in ops.iter() {
-            let flags = SemFlags::from_bits_truncate(flags);
-            if flags.contains(SemFlags::IPC_NOWAIT) {
-                unimplemented!("Semaphore: semop.IPC_NOWAIT");
-            }
-            let sem = &sem_array[num as usize];
+        /// The maximum operations per semop
+        const SEMOPM: usize = 500;
 
-            let _result = match op {
-                1 => sem.release(),
-                -1 => sem.acquire(),
-                _ => unimplemented!("Semaphore: semop.(Not 1/-1)"),
+        if num_ops == 0 {
+            return Err(SysError::EINVAL);
+        }
+        if num_ops > SEMOPM {
+            return Err(SysError::E2BIG);
+        }
+        let ops = unsafe { self.vm().check_read_array(ops, num_ops)? }.to_vec();
+        let array = SemArray::get(id).ok_or(SysError::EINVAL)?;
+        let pid = {
+            let proc = self.process();
+            // waiting for zero only needs to read
+            let access = if ops.iter().any(|op| op.op != 0) {
+                0o2
+            } else {
+                0o4
             };
-            if flags.contains(SemFlags::SEM_UNDO) {
-                self.process().semaphores.add_undo(id, num, op);
+            if !array.state.lock().perm.allowed(&proc.cred, access) {
+                return Err(SysError::EACCES);
+            }
+            proc.pid.get()
+        };
+        if ops.iter().any(|op| op.num as usize >= array.nsems()) {
+            return Err(SysError::EFBIG);
+        }
+        let list: Vec<(usize, i16)> = ops.iter().map(|op| (op.num as usize, op.op)).collect();
+
+        if let Some(blocked) = array.try_semop(&list, pid)? {
+            if SemFlags::from_bits_truncate(ops[blocked].flags).contains(SemFlags::IPC_NOWAIT) {
+                return Err(SysError::EAGAIN);
+            }
+            array.semop(&list, pid, || self.has_signal_to_do())?;
+        }
+
+        let mut proc = self.process();
+        for op in ops.iter() {
+            if SemFlags::from_bits_truncate(op.flags).contains(SemFlags::SEM_UNDO) {
+                proc.semaphores.add_undo(&array, op.num as usize, op.op);
             }
         }
         Ok(0)
     }
 
-    pub fn sys_semctl(&self, id: usize, num: usize, cmd: usize, arg: isize) -> SysResult {
+    /// Control semaphore `num` of set `id`, or the whole set.
+    /// `arg` is a value for SETVAL, an array of values for GETALL and SETALL,
+    /// or a `SemIdDs` for IPC_STAT and IPC_SET.
+    pub fn sys_semctl(&self, id: usize, num: usize, cmd: usize, arg: usize) -> SysResult {
         info!("semctl: id: {}, num: {}, cmd: {}", id, num, cmd);
-        let sem_array = self.process().semaphores.get(id).ok_or(SysError::EINVAL)?;
-        let sem = &sem_array[num as usize];
 
+        const GETPID: usize = 11;
         const GETVAL: usize = 12;
         const GETALL: usize = 13;
+        const GETNCNT: usize = 14;
+        const GETZCNT: usize = 15;
         const SETVAL: usize = 16;
         const SETALL: usize = 17;
 
-        match cmd {
-            SETVAL => sem.set(arg),
-            _ => unimplemented!("Semaphore: Semctl.(Not setval)"),
+        let proc = self.process();
+        let array = SemArray::get(id).ok_or(SysError::EINVAL)?;
+        let nsems = array.nsems();
+        let pid = proc.pid.get();
+        let check_access = |access| {
+            if array.state.lock().perm.allowed(&proc.cred, access) {
+                Ok(())
+            } else {
+                Err(SysError::EACCES)
+            }
+        };
+        // the layout is always the 64-bit one
+        match cmd & !IPC_64 {
+            GETPID | GETVAL | GETNCNT | GETZCNT | SETVAL if num >= nsems => Err(SysError::EINVAL),
+            GETPID | GETVAL | GETNCNT | GETZCNT => {
+                check_access(0o4)?;
+                let sem = array.state.lock().sems[num];
+                Ok(match cmd & !IPC_64 {
+                    GETPID => sem.pid,
+                    GETVAL => sem.value as usize,
+                    GETNCNT => sem.ncnt,
+                    _ => sem.zcnt,
+                })
+            }
+            GETALL => {
+                check_access(0o4)?;
+                let values = unsafe { self.vm().check_write_array(arg as *mut u16, nsems)? };
+                for (value, sem) in values.iter_mut().zip(array.state.lock().sems.iter()) {
+                    *value = sem.value as u16;
+                }
+                Ok(0)
+            }
+            SETVAL => {
+                check_access(0o2)?;
+                let value = arg as i32;
+                if value < 0 || value > SEMVMX as i32 {
+                    return Err(SysError::ERANGE);
+                }
+                array.set_values(num, &[value as i16], pid)?;
+                Ok(0)
+            }
+            SETALL => {
+                check_access(0o2)?;
+                let values = unsafe { self.vm().check_read_array(arg as *const u16, nsems)? };
+                // values over SEMVMX become negative
+                let values: Vec<i16> = values.iter().map(|&value| value as i16).collect();
+                array.set_values(0, &values, pid)?;
+                Ok(0)
+            }
+            IPC_STAT => {
+                check_access(0o4)?;
+                let buf = unsafe { self.vm().check_write_ptr(arg as *mut SemIdDs)? };
+                let state = array.state.lock();
+                *buf = SemIdDs {
+                    perm: IpcPerm64::from(&state.perm),
+                    otime: state.otime,
+                    ctime: state.ctime,
+                    nsems,
+                    ..SemIdDs::default()
+                };
+                Ok(0)
+            }
+            IPC_SET => {
+                let buf = unsafe { self.vm().check_read_ptr(arg as *const SemIdDs)? };
+                let mut state = array.state.lock();
+                if !state.perm.is_owner(&proc.cred) {
+                    return Err(SysError::EPERM);
+                }
+                state.perm.uid = buf.perm.uid;
+                state.perm.gid = buf.perm.gid;
+                state.perm.mode = buf.perm.mode as u16 & 0o777;
+                state.ctime = epoch_sec();
+                Ok(0)
+            }
+            IPC_RMID => {
+                if !array.state.lock().perm.is_owner(&proc.cred) {
+                    return Err(SysError::EPERM);
+                }
+                array.remove();
+                Ok(0)
+            }
+            _ => Err(SysError::EINVAL),
+        }
+    }
+
+    /// Get the shared memory segment of `key`, or create one of `size` bytes,
+    /// and return its id
+    pub fn sys_shmget(&self, key: usize, size: usize, flags: usize) -> SysResult {
+        info!(
+            "shmget: key: {}, size: {:#x}, flags: {:#o}",
+            key, size, flags
+        );
+        let proc = self.process();
+        let perm = IpcPerm::new(key, flags as u16, &proc.cred);
+        let create = key == IPC_PRIVATE || flags & IPC_CREAT != 0;
+        if create && size == 0 {
+            return Err(SysError::EINVAL);
         }
+        let (segment, created) = ShmSegment::get_or_create(key, size, create, perm, proc.pid.get())
+            .ok_or(if create {
+                SysError::ENOMEM
+            } else {
+                SysError::ENOENT
+            })?;
+        if !created {
+            if flags & IPC_CREAT != 0 && flags & IPC_EXCL != 0 {
+                return Err(SysError::EEXIST);
+            }
+            if size > segment.size {
+                return Err(SysError::EINVAL);
+            }
+            let access = ((flags >> 6) | (flags >> 3) | flags) as u16;
+            if !segment.state.lock().perm.allowed(&proc.cred, access) {
+                return Err(SysError::EACCES);
+            }
+        }
+        Ok(segment.id)
+    }
+
+    /// Attach the shared memory segment `id` at `addr`, or anywhere if `addr` is 0
+    pub fn sys_shmat(&self, id: usize, mut addr: usize, flags: usize) -> SysResult {
+        info!("shmat: id: {}, addr: {:#x}, flags: {:#o}", id, addr, flags);
+        let mut proc = self.process();
+        let segment = ShmSegment::get(id).ok_or(SysError::EINVAL)?;
+        let readonly = flags & SHM_RDONLY != 0;
+        let access = if readonly { 0o4 } else { 0o6 };
+        if !segment.state.lock().perm.allowed(&proc.cred, access) {
+            return Err(SysError::EACCES);
+        }
+        let len = (segment.size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let mut vm = self.vm();
+        if addr == 0 {
+            addr = vm.find_free_area(PAGE_SIZE, len);
+        } else {
+            if flags & SHM_RND != 0 {
+                addr &= !(PAGE_SIZE - 1);
+            }
+            if addr % PAGE_SIZE != 0 {
+                return Err(SysError::EINVAL);
+            }
+            if vm.iter().any(|area| area.is_overlap_with(addr, addr + len)) {
+                return Err(SysError::EINVAL);
+            }
+        }
+        let mut attr = MemoryAttr::default().user();
+        if readonly {
+            attr = attr.readonly();
+        }
+        vm.push(addr, addr + len, attr, segment.handler(addr), "shm");
+        let pid = proc.pid.get();
+        proc.shm.attach(addr, segment, pid);
+        Ok(addr)
+    }
+
+    /// Detach the shared memory segment attached at `addr`
+    pub fn sys_shmdt(&self, addr: usize) -> SysResult {
+        info!("shmdt: addr: {:#x}", addr);
+        let mut proc = self.process();
+        let pid = proc.pid.get();
+        let segment = proc.shm.detach(addr, pid).ok_or(SysError::EINVAL)?;
+        let len = (segment.size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        self.vm().pop_with_split(addr, addr + len);
         Ok(0)
     }
+
+    /// Get the message queue of `key`, or create one, and return its id
+    pub fn sys_msgget(&self, key: usize, flags: usize) -> SysResult {
+        info!("msgget: key: {}, flags: {:#o}", key, flags);
+        let proc = self.process();
+        let perm = IpcPerm::new(key, flags as u16, &proc.cred);
+        let create = key == IPC_PRIVATE || flags & IPC_CREAT != 0;
+        let (queue, created) =
+            MsgQueue::get_or_create(key, create, perm).ok_or(SysError::ENOENT)?;
+        if !created {
+            if flags & IPC_CREAT != 0 && flags & IPC_EXCL != 0 {
+                return Err(SysError::EEXIST);
+            }
+            let access = ((flags >> 6) | (flags >> 3) | flags) as u16;
+            if !queue.state.lock().perm.allowed(&proc.cred, access) {
+                return Err(SysError::EACCES);
+            }
+        }
+        Ok(queue.id)
+    }
+
+    /// Send the message at `msgp`, which is a type followed by `size` bytes,
+    /// waiting for room in the queue unless IPC_NOWAIT
+    pub fn sys_msgsnd(&self, id: usize, msgp: usize, size: usize, flags: usize) -> SysResult {
+        info!(
+            "msgsnd: id: {}, msgp: {:#x}, size: {}, flags: {:#o}",
+            id, msgp, size, flags
+        );
+        if size > MSGMAX {
+            return Err(SysError::EINVAL);
+        }
+        let (mtype, data) = {
+            let vm = self.vm();
+            let mtype = unsafe { *vm.check_read_ptr(msgp as *const isize)? };
+            let text = msgp + size_of::<isize>();
+            let data = unsafe { vm.check_read_array(text as *const u8, size)? }.to_vec();
+            (mtype, data)
+        };
+        if mtype < 1 {
+            return Err(SysError::EINVAL);
+        }
+        let queue = MsgQueue::get(id).ok_or(SysError::EINVAL)?;
+        let pid = {
+            let proc = self.process();
+            if !queue.state.lock().perm.allowed(&proc.cred, 0o2) {
+                return Err(SysError::EACCES);
+            }
+            proc.pid.get()
+        };
+        if queue.try_send(mtype, &data, pid)? {
+            return Ok(0);
+        }
+        if flags & IPC_NOWAIT != 0 {
+            return Err(SysError::EAGAIN);
+        }
+        Condvar::wait_events(&[&queue.changed], || {
+            match queue.try_send(mtype, &data, pid) {
+                Ok(true) => return Some(Ok(0)),
+                Err(err) => return Some(Err(err)),
+                Ok(false) => {}
+            }
+            if self.has_signal_to_do() {
+                return Some(Err(SysError::EINTR));
+            }
+            None
+        })
+    }
+
+    /// Receive a message selected by `msgtyp` into `msgp`, as a type followed by up to `size` bytes,
+    /// waiting for one unless IPC_NOWAIT. Return the size of the message.
+    pub fn sys_msgrcv(
+        &self,
+        id: usize,
+        msgp: usize,
+        size: usize,
+        msgtyp: isize,
+        flags: usize,
+    ) -> SysResult {
+        info!(
+            "msgrcv: id: {}, msgp: {:#x}, size: {}, msgtyp: {}, flags: {:#o}",
+            id, msgp, size, msgtyp, flags
+        );
+        if (size as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let queue = MsgQueue::get(id).ok_or(SysError::EINVAL)?;
+        let pid = {
+            let proc = self.process();
+            if !queue.state.lock().perm.allowed(&proc.cred, 0o4) {
+                return Err(SysError::EACCES);
+            }
+            proc.pid.get()
+        };
+        let except = flags & MSG_EXCEPT != 0;
+        let truncate = flags & MSG_NOERROR != 0;
+        let (mtype, data) = if flags & IPC_NOWAIT != 0 {
+            queue
+                .try_receive(msgtyp, except, size, truncate, pid)?
+                .ok_or(SysError::ENOMSG)?
+        } else {
+            queue.receive(msgtyp, except, size, truncate, pid, || {
+                self.has_signal_to_do()
+            })?
+        };
+        let vm = self.vm();
+        unsafe {
+            *vm.check_write_ptr(msgp as *mut isize)? = mtype;
+            let text = msgp + size_of::<isize>();
+            vm.check_write_array(text as *mut u8, data.len())?
+                .copy_from_slice(&data);
+        }
+        Ok(data.len())
+    }
+
+    pub fn sys_msgctl(&self, id: usize, cmd: usize, buf: *mut MsqIdDs) -> SysResult {
+        info!("msgctl: id: {}, cmd: {}", id, cmd);
+        let proc = self.process();
+        let queue = MsgQueue::get(id).ok_or(SysError::EINVAL)?;
+        // the layout is always the 64-bit one
+        match cmd & !IPC_64 {
+            IPC_STAT => {
+                let buf = unsafe { self.vm().check_write_ptr(buf)? };
+                let state = queue.state.lock();
+                if !state.perm.allowed(&proc.cred, 0o4) {
+                    return Err(SysError::EACCES);
+                }
+                *buf = MsqIdDs {
+                    perm: IpcPerm64::from(&state.perm),
+                    stime: state.stime,
+                    rtime: state.rtime,
+                    ctime: state.ctime,
+                    cbytes: state.cbytes,
+                    qnum: state.qnum(),
+                    qbytes: state.qbytes,
+                    lspid: state.lspid as i32,
+                    lrpid: state.lrpid as i32,
+                    ..MsqIdDs::default()
+                };
+            }
+            IPC_SET => {
+                let buf = unsafe { self.vm().check_read_ptr(buf)? };
+                let mut state = queue.state.lock();
+                if !state.perm.is_owner(&proc.cred) {
+                    return Err(SysError::EPERM);
+                }
+                // only root can raise the limit over the default
+                if buf.qbytes > MSGMNB && !proc.cred.is_privileged() {
+                    return Err(SysError::EPERM);
+                }
+                state.perm.uid = buf.perm.uid;
+                state.perm.gid = buf.perm.gid;
+                state.perm.mode = buf.perm.mode as u16 & 0o777;
+                state.qbytes = buf.qbytes;
+                state.ctime = epoch_sec();
+                drop(state);
+                // senders may have room now
+                queue.changed.notify_all();
+            }
+            IPC_RMID => {
+                if !queue.state.lock().perm.is_owner(&proc.cred) {
+                    return Err(SysError::EPERM);
+                }
+                queue.remove();
+            }
+            _ => return Err(SysError::EINVAL),
+        }
+        Ok(0)
+    }
+
+    pub fn sys_shmctl(&self, id: usize, cmd: usize, buf: *mut ShmIdDs) -> SysResult {
+        info!("shmctl: id: {}, cmd: {}", id, cmd);
+        let proc = self.process();
+        let segment = ShmSegment::get(id).ok_or(SysError::EINVAL)?;
+        // the layout is always the 64-bit one
+        match cmd & !IPC_64 {
+            IPC_STAT => {
+                let buf = unsafe { self.vm().check_write_ptr(buf)? };
+                let state = segment.state.lock();
+                if !state.perm.allowed(&proc.cred, 0o4) {
+                    return Err(SysError::EACCES);
+                }
+                *buf = ShmIdDs {
+                    perm: IpcPerm64::from(&state.perm),
+                    segsz: segment.size,
+                    atime: state.atime,
+                    dtime: state.dtime,
+                    ctime: state.ctime,
+                    cpid: state.cpid as i32,
+                    lpid: state.lpid as i32,
+                    nattch: state.nattch,
+                    ..ShmIdDs::default()
+                };
+            }
+            IPC_SET => {
+                let buf = unsafe { self.vm().check_read_ptr(buf)? };
+                let mut state = segment.state.lock();
+                if !state.perm.is_owner(&proc.cred) {
+                    return Err(SysError::EPERM);
+                }
+                state.perm.uid = buf.perm.uid;
+                state.perm.gid = buf.perm.gid;
+                state.perm.mode = buf.perm.mode as u16 & 0o777;
+                state.ctime = epoch_sec();
+            }
+            IPC_RMID => {
+                if !segment.state.lock().perm.is_owner(&proc.cred) {
+                    return Err(SysError::EPERM);
+                }
+                segment.remove();
+            }
+            _ => return Err(SysError::EINVAL),
+        }
+        Ok(0)
+    }
+}
+
+// flags of msgrcv
+const MSG_NOERROR: usize = 0o10000;
+const MSG_EXCEPT: usize = 0o20000;
+
+// flags of shmat
+const SHM_RDONLY: usize = 0o10000;
+const SHM_RND: usize = 0o20000;
+
+/// Set in commands of *ctl by libc to ask for the 64-bit layouts of structs
+const IPC_64: usize = 0x100;
+
+/// Owner and permissions of an IPC object, in the layout of `struct ipc64_perm`
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct IpcPerm64 {
+    pub key: i32,
+    pub uid: u32,
+    pub gid: u32,
+    pub cuid: u32,
+    pub cgid: u32,
+    pub mode: u32,
+    pub seq: u16,
+    _pad: u16,
+    _unused: [usize; 2],
+}
+
+impl From<&IpcPerm> for IpcPerm64 {
+    fn from(perm: &IpcPerm) -> Self {
+        IpcPerm64 {
+            key: perm.key as i32,
+            uid: perm.uid,
+            gid: perm.gid,
+            cuid: perm.cuid,
+            cgid: perm.cgid,
+            mode: perm.mode as u32,
+            ..IpcPerm64::default()
+        }
+    }
+}
+
+/// Status of a semaphore set, in the layout of `struct semid64_ds`
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SemIdDs {
+    pub perm: IpcPerm64,
+    pub otime: usize,
+    #[cfg(target_pointer_width = "32")]
+    _otime_high: usize,
+    pub ctime: usize,
+    #[cfg(target_pointer_width = "32")]
+    _ctime_high: usize,
+    pub nsems: usize,
+    _unused: [usize; 2],
+}
+
+/// Status of a message queue, in the layout of `struct msqid64_ds`
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct MsqIdDs {
+    pub perm: IpcPerm64,
+    pub stime: usize,
+    #[cfg(target_pointer_width = "32")]
+    _stime_high: usize,
+    pub rtime: usize,
+    #[cfg(target_pointer_width = "32")]
+    _rtime_high: usize,
+    pub ctime: usize,
+    #[cfg(target_pointer_width = "32")]
+    _ctime_high: usize,
+    pub cbytes: usize,
+    pub qnum: usize,
+    pub qbytes: usize,
+    pub lspid: i32,
+    pub lrpid: i32,
+    _unused: [usize; 2],
+}
+
+/// Status of a shared memory segment, in the layout of `struct shmid64_ds`
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct ShmIdDs {
+    pub perm: IpcPerm64,
+    pub segsz: usize,
+    pub atime: usize,
+    #[cfg(target_pointer_width = "32")]
+    _atime_high: usize,
+    pub dtime: usize,
+    #[cfg(target_pointer_width = "32")]
+    _dtime_high: usize,
+    pub ctime: usize,
+    #[cfg(target_pointer_width = "32")]
+    _ctime_high: usize,
+    pub cpid: i32,
+    pub lpid: i32,
+    pub nattch: usize,
+    _unused: [usize; 2],
 }
 
 /// An operation to be performed on a single semaphore
 ///
 /// Ref: [http://man7.org/linux/man-pages/man2/semop.2.html]
 #[repr(C)]
+#[derive(Debug, Copy, Clone)]
 pub struct SemBuf {
     num: u16,
     op: i16,
     flags: i16,
 }
 
-pub union SemctlUnion {
-    val: isize,
-    buf: usize,   // semid_ds*, unimplemented
-    array: usize, // short*, unimplemented
-} // unused
-
 bitflags! {
     pub struct SemFlags: i16 {
         /// For SemOP
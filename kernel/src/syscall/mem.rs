@@ -1,4 +1,4 @@
-use rcore_memory::memory_set::handler::{Delay, File, Linear};
+use rcore_memory::memory_set::handler::{Delay, File, Linear, SharedFile};
 use rcore_memory::memory_set::MemoryAttr;
 use rcore_memory::PAGE_SIZE;
 
@@ -24,21 +24,33 @@ impl Syscall<'_> {
         );
 
         let mut proc = self.process();
-        if addr == 0 {
-            // although NULL can be a valid address
-            // but in C, NULL is regarded as allocation failure
-            // so just skip it
-            addr = PAGE_SIZE;
+        if len == 0 {
+            return Err(SysError::EINVAL);
         }
 
         if flags.contains(MmapFlags::FIXED) {
+            if addr % PAGE_SIZE != 0 {
+                return Err(SysError::EINVAL);
+            }
             // we have to map it to addr, so remove the old mapping first
-            self.vm().pop_with_split(addr, addr + len);
+            let mut vm = self.vm();
+            vm.pop_with_split(addr, addr + len);
+            // the segments replaced entirely are detached, as if by shmdt
+            let pid = proc.pid.get();
+            proc.shm.detach_unmapped(&vm, pid);
         } else {
+            if addr == 0 {
+                // although NULL can be a valid address
+                // but in C, NULL is regarded as allocation failure
+                // so just skip it
+                addr = PAGE_SIZE;
+            }
+            // the kernel chooses a free range near the hint
             addr = self.vm().find_free_area(addr, len);
         }
 
         if flags.contains(MmapFlags::ANONYMOUS) {
+            // fd and offset are ignored, pages are zero-filled on first access
             if flags.contains(MmapFlags::SHARED) {
                 return Err(SysError::EINVAL);
             }
@@ -75,20 +87,35 @@ impl Syscall<'_> {
                     }
                 }
                 _ => {
-                    let inode = file.inode();
-                    self.vm().push(
-                        addr,
-                        addr + len,
-                        prot.to_attr(),
-                        File {
-                            file: INodeForMap(inode),
-                            mem_start: addr,
-                            file_start: offset,
-                            file_end: offset + len,
-                            allocator: GlobalFrameAlloc,
-                        },
-                        "mmap_file",
-                    );
+                    if !file.options().read {
+                        return Err(SysError::EACCES);
+                    }
+                    let shared = flags.contains(MmapFlags::SHARED);
+                    if shared && prot.contains(MmapProt::WRITE) && !file.options().write {
+                        return Err(SysError::EACCES);
+                    }
+                    // pages are read from the file lazily on page fault
+                    let handler = File {
+                        file: INodeForMap(file.inode()),
+                        mem_start: addr,
+                        file_start: offset,
+                        file_end: offset + len,
+                        allocator: GlobalFrameAlloc,
+                    };
+                    if shared {
+                        // dirty pages are written back on unmap
+                        self.vm().push(
+                            addr,
+                            addr + len,
+                            prot.to_attr(),
+                            SharedFile(handler),
+                            "mmap_file_shared",
+                        );
+                    } else {
+                        // writes go to private frames and never reach the file
+                        self.vm()
+                            .push(addr, addr + len, prot.to_attr(), handler, "mmap_file");
+                    }
                     return Ok(addr);
                 }
             };
@@ -101,26 +128,158 @@ impl Syscall<'_> {
             "mprotect: addr={:#x}, size={:#x}, prot={:?}",
             addr, len, prot
         );
+        if addr % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        if len == 0 {
+            return Ok(0);
+        }
+        let end_addr = (addr + len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
         let attr = prot.to_attr();
 
-        // FIXME: properly set the attribute of the area
-        //        now some mut ptr check is fault
-        let vm = self.vm();
-        let memory_area = vm
-            .iter()
-            .find(|area| area.is_overlap_with(addr, addr + len));
-        if memory_area.is_none() {
+        let mut vm = self.vm();
+        // every page in the range must be mapped
+        let mut mapped_end = addr;
+        for area in vm.iter() {
+            if area.start_addr() <= mapped_end && mapped_end < area.end_addr() {
+                mapped_end = area.end_addr();
+            }
+        }
+        if mapped_end < end_addr {
             return Err(SysError::ENOMEM);
         }
+        vm.protect(addr, end_addr, attr);
         Ok(0)
     }
 
+    /// Set the program break to `addr`, return the new break.
+    /// Return the current break if `addr` is 0 or can not be reached.
+    pub fn sys_brk(&mut self, addr: usize) -> SysResult {
+        info!("brk: addr={:#x}", addr);
+        let mut proc = self.process();
+        if proc.brk_start == 0 {
+            // the heap starts at the page after the executable
+            let elf_end = self
+                .vm()
+                .iter()
+                .filter(|area| area.name() == "elf")
+                .map(|area| area.end_addr())
+                .max()
+                .ok_or(SysError::ENOMEM)?;
+            proc.brk_start = (elf_end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+            proc.brk = proc.brk_start;
+        }
+        if addr < proc.brk_start {
+            return Ok(proc.brk);
+        }
+
+        let old_end = (proc.brk + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let new_end = (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if new_end > old_end {
+            let mut vm = self.vm();
+            if vm.iter().any(|area| area.is_overlap_with(old_end, new_end)) {
+                return Ok(proc.brk);
+            }
+            vm.push(
+                old_end,
+                new_end,
+                MemoryAttr::default().user(),
+                Delay::new(GlobalFrameAlloc),
+                "heap",
+            );
+        } else if new_end < old_end {
+            self.vm().pop_with_split(new_end, old_end);
+        }
+        proc.brk = addr;
+        Ok(addr)
+    }
+
+    pub fn sys_msync(&mut self, addr: usize, len: usize, flags: usize) -> SysResult {
+        let flags = MsyncFlags::from_bits_truncate(flags);
+        info!(
+            "msync: addr={:#x}, size={:#x}, flags={:?}",
+            addr, len, flags
+        );
+        if addr % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        if len == 0 {
+            return Ok(0);
+        }
+        let mut vm = self.vm();
+        let in_one_area = vm
+            .iter()
+            .any(|area| area.contains(addr) && addr + len <= area.end_addr());
+        if !in_one_area {
+            return Err(SysError::EINVAL);
+        }
+        // MS_ASYNC is handled as MS_SYNC
+        vm.sync(addr, addr + len);
+        Ok(0)
+    }
+
+    /// Give advice about the use of memory in a range.
+    /// MADV_DONTNEED frees the pages and MADV_WILLNEED fills them in advance,
+    /// and other advice is ignored.
+    pub fn sys_madvise(&mut self, addr: usize, len: usize, advice: usize) -> SysResult {
+        info!(
+            "madvise: addr={:#x}, size={:#x}, advice={}",
+            addr, len, advice
+        );
+        // sample before the resident set shrinks
+        self.process().update_max_rss();
+        madvise(&mut self.vm(), addr, len, advice)
+    }
+
     pub fn sys_munmap(&mut self, addr: usize, len: usize) -> SysResult {
         info!("munmap addr={:#x}, size={:#x}", addr, len);
-        self.vm().pop_with_split(addr, addr + len);
+        // sample before the resident set shrinks
+        let mut proc = self.process();
+        proc.update_max_rss();
+        let mut vm = self.vm();
+        vm.pop_with_split(addr, addr + len);
+        // the segments unmapped entirely are detached, as if by shmdt
+        let pid = proc.pid.get();
+        proc.shm.detach_unmapped(&vm, pid);
         Ok(0)
     }
 }
+
+/// Apply `advice` to the pages in `[addr, addr + len)`
+fn madvise(vm: &mut MemorySet, addr: usize, len: usize, advice: usize) -> SysResult {
+    if addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    let end_addr = match addr
+        .checked_add(len)
+        .and_then(|end| end.checked_add(PAGE_SIZE - 1))
+    {
+        Some(end) => end & !(PAGE_SIZE - 1),
+        None => return Err(SysError::EINVAL),
+    };
+    // every page in the range must be mapped
+    let mut mapped_end = addr;
+    for area in vm.iter() {
+        if area.start_addr() <= mapped_end && mapped_end < area.end_addr() {
+            mapped_end = area.end_addr();
+        }
+    }
+    if mapped_end < end_addr {
+        return Err(SysError::ENOMEM);
+    }
+    match advice {
+        MADV_DONTNEED => vm.discard(addr, end_addr),
+        MADV_WILLNEED => vm.prefault(addr, end_addr),
+        _ => {}
+    }
+    Ok(0)
+}
+const MADV_WILLNEED: usize = 3;
+const MADV_DONTNEED: usize = 4;
+
 bitflags! {
     pub struct MmapProt: usize {
         /// Data cannot be accessed
@@ -162,14 +321,126 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct MsyncFlags: usize {
+        /// Schedule the writeback and return immediately
+        const ASYNC = 1 << 0;
+        /// Invalidate other mappings of the same file
+        const INVALIDATE = 1 << 1;
+        /// Write back and wait for it to complete
+        const SYNC = 1 << 2;
+    }
+}
+
 impl MmapProt {
     fn to_attr(self) -> MemoryAttr {
         let mut attr = MemoryAttr::default().user();
         if self.contains(MmapProt::EXEC) {
             attr = attr.execute();
         }
-        // FIXME: see sys_mprotect
-        //        if !self.contains(MmapProt::WRITE) { attr = attr.readonly(); }
+        if !self.contains(MmapProt::WRITE) {
+            attr = attr.readonly();
+        }
         attr
     }
 }
+
+pub mod test {
+    //! Pages dropped by madvise
+
+    use super::*;
+    use crate::fs::TmpFS;
+    use rcore_fs::vfs::FileSystem;
+
+    const START: usize = 0x1000_0000;
+
+    fn is_present(vm: &mut MemorySet, addr: usize) -> bool {
+        vm.get_page_table_mut()
+            .get_entry(addr)
+            .map_or(false, |entry| entry.present())
+    }
+
+    /// The range must be aligned, not overflow, and be mapped
+    fn bad_range() {
+        let mut vm = MemorySet::new();
+        let attr = MemoryAttr::default().user();
+        vm.push(
+            START,
+            START + PAGE_SIZE,
+            attr,
+            Delay::new(GlobalFrameAlloc),
+            "anon",
+        );
+        assert_eq!(
+            madvise(&mut vm, START + 1, PAGE_SIZE, MADV_DONTNEED),
+            Err(SysError::EINVAL)
+        );
+        assert_eq!(
+            madvise(&mut vm, START, usize::max_value() - START, MADV_DONTNEED),
+            Err(SysError::EINVAL)
+        );
+        assert_eq!(
+            madvise(&mut vm, START, PAGE_SIZE * 2, MADV_DONTNEED),
+            Err(SysError::ENOMEM)
+        );
+        assert_eq!(madvise(&mut vm, START, 0, MADV_DONTNEED), Ok(0));
+    }
+
+    /// An anonymous page is zero-filled again on next access
+    fn dontneed_anonymous() {
+        let mut vm = MemorySet::new();
+        let attr = MemoryAttr::default().user();
+        vm.push(
+            START,
+            START + PAGE_SIZE,
+            attr,
+            Delay::new(GlobalFrameAlloc),
+            "anon",
+        );
+        assert!(vm.handle_page_fault(START));
+        vm.get_page_table_mut().get_page_slice_mut(START)[0] = 1;
+        assert_eq!(madvise(&mut vm, START, PAGE_SIZE, MADV_DONTNEED), Ok(0));
+        assert!(!is_present(&mut vm, START));
+        assert_eq!(madvise(&mut vm, START, PAGE_SIZE, MADV_WILLNEED), Ok(0));
+        assert!(is_present(&mut vm, START));
+        assert_eq!(vm.get_page_table_mut().get_page_slice_mut(START)[0], 0);
+    }
+
+    /// A page of a shared file mapping keeps the data written to it
+    fn dontneed_shared_file() {
+        let fs = TmpFS::new(0x10000);
+        let file = fs
+            .root_inode()
+            .create("file", FileType::File, 0o644)
+            .unwrap();
+        file.resize(PAGE_SIZE).unwrap();
+        let mut vm = MemorySet::new();
+        let handler = File {
+            file: INodeForMap(file.clone()),
+            mem_start: START,
+            file_start: 0,
+            file_end: PAGE_SIZE,
+            allocator: GlobalFrameAlloc,
+        };
+        let attr = MemoryAttr::default().user();
+        vm.push(
+            START,
+            START + PAGE_SIZE,
+            attr,
+            SharedFile(handler),
+            "shared",
+        );
+        assert!(vm.handle_page_fault(START));
+        vm.get_page_table_mut().get_page_slice_mut(START)[0] = 1;
+        assert_eq!(madvise(&mut vm, START, PAGE_SIZE, MADV_DONTNEED), Ok(0));
+        assert!(is_present(&mut vm, START));
+        assert_eq!(vm.get_page_table_mut().get_page_slice_mut(START)[0], 1);
+    }
+
+    pub fn test_all() {
+        bad_range();
+        dontneed_anonymous();
+        dontneed_shared_file();
+        println!("madvise test end");
+    }
+}
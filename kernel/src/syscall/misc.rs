@@ -1,135 +1,137 @@
 use super::*;
 use crate::arch::cpu;
-use crate::consts::USER_STACK_SIZE;
-use core::mem::size_of;
-use core::sync::atomic::{AtomicI32, Ordering};
+use crate::memory::{frame_usage, set_user_stack_limit};
+use rcore_memory::PAGE_SIZE;
 
 impl Syscall<'_> {
-    #[cfg(target_arch = "x86_64")]
+    /// Set or get the TLS base of the thread,
+    /// which is FSBASE on x86_64 and the tp register on riscv
+    #[cfg(any(
+        target_arch = "x86_64",
+        target_arch = "riscv32",
+        target_arch = "riscv64"
+    ))]
     pub fn sys_arch_prctl(&mut self, code: i32, addr: usize) -> SysResult {
-        const ARCH_SET_FS: i32 = 0x1002;
-        match code {
-            ARCH_SET_FS => {
-                info!("sys_arch_prctl: set FSBASE to {:#x}", addr);
-                self.tf.fsbase = addr;
+        info!("arch_prctl: code: {:#x}, addr: {:#x}", code, addr);
+        let mut value = addr;
+        let value = match code {
+            ARCH_GET_FS => unsafe { self.vm().check_write_ptr(addr as *mut usize)? },
+            _ => &mut value,
+        };
+        arch_prctl(tls_base(self.tf), code, value)
+    }
+
+    /// Operate on the calling thread or process.
+    /// Only PR_SET_NAME and PR_GET_NAME for the thread name are supported.
+    pub fn sys_prctl(&mut self, option: usize, arg2: usize) -> SysResult {
+        info!("prctl: option: {}, arg2: {:#x}", option, arg2);
+        match option {
+            PR_SET_NAME => {
+                // the name is truncated, so nothing after the first 15 bytes is read
+                let user = arg2 as *const u8;
+                let mut name = [0u8; TASK_COMM_LEN];
+                for (i, byte) in name.iter_mut().take(TASK_COMM_LEN - 1).enumerate() {
+                    *byte = copy_from_user(unsafe { user.add(i) }).ok_or(SysError::EFAULT)?;
+                    if *byte == 0 {
+                        break;
+                    }
+                }
+                self.thread.name = name;
+                // the first thread names the process
+                let mut proc = self.process();
+                if proc.threads.first() == Some(&processor().tid()) {
+                    proc.comm = name;
+                }
+                Ok(0)
+            }
+            PR_GET_NAME => {
+                let buf = unsafe {
+                    self.vm()
+                        .check_write_array(arg2 as *mut u8, TASK_COMM_LEN)?
+                };
+                buf.copy_from_slice(&self.thread.name);
                 Ok(0)
             }
             _ => Err(SysError::EINVAL),
         }
     }
 
-    pub fn sys_uname(&mut self, buf: *mut u8) -> SysResult {
+    /// Get the name and version of the system, and the machine it runs on
+    pub fn sys_uname(&mut self, buf: *mut UtsName) -> SysResult {
         info!("uname: buf: {:?}", buf);
-
-        let offset = 65;
-        let strings = ["rCore", "orz", "0.1.0", "1", "machine", "domain"];
-        let buf = unsafe { self.vm().check_write_array(buf, strings.len() * offset)? };
-
-        for i in 0..strings.len() {
-            unsafe {
-                util::write_cstr(&mut buf[i * offset], &strings[i]);
-            }
+        let buf = unsafe { self.vm().check_write_ptr(buf)? };
+        let strings = ["rCore", "orz", "0.1.0", "1", UTS_MACHINE, "domain"];
+        for (field, string) in buf.fields.iter_mut().zip(strings.iter()) {
+            *field = [0; UTS_LEN];
+            field[..string.len()].copy_from_slice(string.as_bytes());
         }
         Ok(0)
     }
 
-    pub fn sys_sched_getaffinity(&mut self, pid: usize, size: usize, mask: *mut u32) -> SysResult {
-        info!(
-            "sched_getaffinity: pid: {}, size: {}, mask: {:?}",
-            pid, size, mask
-        );
-        let mask = unsafe { self.vm().check_write_array(mask, size / size_of::<u32>())? };
-
-        // we only have 4 cpu at most.
-        // so just set it.
-        mask[0] = 0b1111;
-        Ok(0)
-    }
-
+    /// Get the uptime, memory usage in pages and the number of processes.
+    /// Loads, swap and shared memory are not tracked.
     pub fn sys_sysinfo(&mut self, sys_info: *mut SysInfo) -> SysResult {
+        info!("sysinfo: sys_info: {:?}", sys_info);
         let sys_info = unsafe { self.vm().check_write_ptr(sys_info)? };
 
-        let sysinfo = SysInfo::default();
-        *sys_info = sysinfo;
+        let (total, free) = frame_usage();
+        let procs = PROCESSES
+            .read()
+            .values()
+            .filter(|weak| weak.strong_count() > 0)
+            .count();
+        *sys_info = SysInfo {
+            uptime: crate::trap::uptime_msec() / 1000,
+            totalram: total,
+            freeram: free,
+            procs: procs as u16,
+            mem_unit: PAGE_SIZE as u32,
+            ..SysInfo::default()
+        };
         Ok(0)
     }
 
-    pub fn sys_futex(
-        &mut self,
-        uaddr: usize,
-        op: u32,
-        val: i32,
-        timeout: *const TimeSpec,
-    ) -> SysResult {
+    /// Power off, halt or restart the machine.
+    /// `magic` and `magic2` must be the magic numbers of Linux,
+    /// and `arg` is only used by unsupported commands.
+    pub fn sys_reboot(&mut self, magic: u32, magic2: u32, cmd: u32, _arg: *const u8) -> SysResult {
         info!(
-            "futex: [{}] uaddr: {:#x}, op: {:#x}, val: {}, timeout_ptr: {:?}",
-            thread::current().id(),
-            uaddr,
-            op,
-            val,
-            timeout
+            "reboot: magic: {:#x}, magic2: {:#x}, cmd: {:#x}",
+            magic, magic2, cmd
         );
-        if op & OP_PRIVATE == 0 {
-            warn!("process-shared futex is unimplemented");
-        }
-        if uaddr % size_of::<u32>() != 0 {
+        if magic != LINUX_REBOOT_MAGIC1 || !LINUX_REBOOT_MAGIC2.contains(&magic2) {
             return Err(SysError::EINVAL);
         }
-        let atomic = unsafe { self.vm().check_write_ptr(uaddr as *mut AtomicI32)? };
-
-        const OP_WAIT: u32 = 0;
-        const OP_WAKE: u32 = 1;
-        const OP_PRIVATE: u32 = 0x80;
-
-        let mut proc = self.process();
-        let queue = proc.get_futex(uaddr);
-
-        match op & 0xf {
-            OP_WAIT => {
-                let _timeout = if timeout.is_null() {
-                    None
-                } else {
-                    Some(unsafe { *self.vm().check_read_ptr(timeout)? })
-                };
-
-                if atomic.load(Ordering::Acquire) != val {
-                    return Err(SysError::EAGAIN);
-                }
-                // FIXME: support timeout
-                queue.wait(proc);
-                Ok(0)
-            }
-            OP_WAKE => {
-                let woken_up_count = queue.notify_n(val as usize);
-                Ok(woken_up_count)
+        if !self.process().cred.is_privileged() {
+            return Err(SysError::EPERM);
+        }
+        match cmd {
+            LINUX_REBOOT_CMD_POWER_OFF | LINUX_REBOOT_CMD_HALT => {
+                warn!("system is going to power off");
+                unsafe { cpu::exit_in_qemu(1) }
             }
-            _ => {
-                warn!("unsupported futex operation: {}", op);
-                Err(SysError::ENOSYS)
+            LINUX_REBOOT_CMD_RESTART => {
+                warn!("system is going to restart");
+                unsafe { cpu::reboot() }
             }
+            // Ctrl-Alt-Del is never delivered
+            LINUX_REBOOT_CMD_CAD_ON | LINUX_REBOOT_CMD_CAD_OFF => Ok(0),
+            _ => Err(SysError::EINVAL),
         }
     }
 
-    pub fn sys_reboot(
-        &mut self,
-        _magic: u32,
-        _magic2: u32,
-        cmd: u32,
-        _arg: *const u8,
-    ) -> SysResult {
-        // we will skip verifying magic
-        if cmd == LINUX_REBOOT_CMD_HALT {
-            unsafe {
-                cpu::exit_in_qemu(1);
-            }
-        } else if cmd == LINUX_REBOOT_CMD_RESTART {
-            unsafe {
-                cpu::reboot();
-            }
-        }
-        Ok(0)
+    pub fn sys_getrlimit(&mut self, resource: usize, rlim: *mut RLimit) -> SysResult {
+        self.sys_prlimit64(0, resource, core::ptr::null(), rlim)
     }
 
+    pub fn sys_setrlimit(&mut self, resource: usize, rlim: *const RLimit) -> SysResult {
+        self.sys_prlimit64(0, resource, rlim, core::ptr::null_mut())
+    }
+
+    /// Get and set resource limits of process `pid`, 0 for current process.
+    ///
+    /// The soft limit can be set up to the hard limit,
+    /// and the hard limit can only be lowered.
     pub fn sys_prlimit64(
         &mut self,
         pid: usize,
@@ -141,40 +143,29 @@ impl Syscall<'_> {
             "prlimit64: pid: {}, resource: {}, new_limit: {:x?}, old_limit: {:x?}",
             pid, resource, new_limit, old_limit
         );
-        match resource {
-            RLIMIT_STACK => {
-                if !old_limit.is_null() {
-                    let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
-                    *old_limit = RLimit {
-                        cur: USER_STACK_SIZE as u64,
-                        max: USER_STACK_SIZE as u64,
-                    };
-                }
-                Ok(0)
-            }
-            RLIMIT_NOFILE => {
-                if !old_limit.is_null() {
-                    let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
-                    *old_limit = RLimit {
-                        cur: 1024,
-                        max: 1024,
-                    };
-                }
-                Ok(0)
-            }
-            RLIMIT_RSS | RLIMIT_AS => {
-                if !old_limit.is_null() {
-                    let old_limit = unsafe { self.vm().check_write_ptr(old_limit)? };
-                    // 1GB
-                    *old_limit = RLimit {
-                        cur: 1024 * 1024 * 1024,
-                        max: 1024 * 1024 * 1024,
-                    };
-                }
-                Ok(0)
-            }
-            _ => Err(SysError::ENOSYS),
+        if resource >= RLIMIT_NLIMITS {
+            return Err(SysError::EINVAL);
+        }
+        let new_limit = if new_limit.is_null() {
+            None
+        } else {
+            Some(unsafe { *self.vm().check_read_ptr(new_limit)? })
+        };
+        let old_limit = if old_limit.is_null() {
+            None
+        } else {
+            Some(unsafe { self.vm().check_write_ptr(old_limit)? })
+        };
+
+        let target = self.get_process(pid)?;
+        let mut proc = target.lock();
+        if let Some(old_limit) = old_limit {
+            *old_limit = proc.rlimits[resource];
         }
+        if let Some(new_limit) = new_limit {
+            set_rlimit(&mut proc, resource, new_limit)?;
+        }
+        Ok(0)
     }
 
     pub fn sys_getrandom(&mut self, buf: *mut u8, len: usize, _flag: u32) -> SysResult {
@@ -192,6 +183,52 @@ impl Syscall<'_> {
     }
 }
 
+/// Set the limit of `resource` for process `proc`.
+/// The soft limit can be set up to the hard limit, and the hard limit can only be lowered.
+fn set_rlimit(proc: &mut Process, resource: usize, new_limit: RLimit) -> Result<(), SysError> {
+    if new_limit.cur > new_limit.max {
+        return Err(SysError::EINVAL);
+    }
+    if new_limit.max > proc.rlimits[resource].max {
+        return Err(SysError::EPERM);
+    }
+    proc.rlimits[resource] = new_limit;
+    if resource == RLIMIT_STACK {
+        set_user_stack_limit(&mut proc.vm.lock(), new_limit.cur as usize);
+    }
+    Ok(())
+}
+
+/// The TLS base in the trap frame
+#[cfg(target_arch = "x86_64")]
+fn tls_base(tf: &mut TrapFrame) -> &mut usize {
+    &mut tf.fsbase
+}
+
+/// The TLS base in the trap frame
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+fn tls_base(tf: &mut TrapFrame) -> &mut usize {
+    &mut tf.x[4]
+}
+
+/// Set the TLS base `tls` to `value` for ARCH_SET_FS, or read it into `value` for ARCH_GET_FS
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "riscv32",
+    target_arch = "riscv64"
+))]
+fn arch_prctl(tls: &mut usize, code: i32, value: &mut usize) -> SysResult {
+    match code {
+        ARCH_SET_FS => *tls = *value,
+        ARCH_GET_FS => *value = *tls,
+        _ => return Err(SysError::EINVAL),
+    }
+    Ok(0)
+}
+
+const LINUX_REBOOT_MAGIC1: u32 = 0xfee1dead;
+const LINUX_REBOOT_MAGIC2: [u32; 4] = [672274793, 85072278, 369367448, 537993216];
+
 const LINUX_REBOOT_CMD_RESTART: u32 = 0x01234567;
 const LINUX_REBOOT_CMD_HALT: u32 = 0xCDEF0123;
 const LINUX_REBOOT_CMD_CAD_ON: u32 = 0x89ABCDEF;
@@ -201,31 +238,136 @@ const LINUX_REBOOT_CMD_RESTART2: u32 = 0xA1B2C3D4;
 const LINUX_REBOOT_CMD_SW_SUSPEND: u32 = 0xD000FCE2;
 const LINUX_REBOOT_CMD_KEXEC: u32 = 0x45584543;
 
+// codes of arch_prctl
+const ARCH_SET_FS: i32 = 0x1002;
+const ARCH_GET_FS: i32 = 0x1003;
+
+// options of prctl
+const PR_SET_NAME: usize = 15;
+const PR_GET_NAME: usize = 16;
+
+/// Length of each field of `UtsName`, including the null terminator
+const UTS_LEN: usize = 65;
+
+/// The target arch
+#[cfg(target_arch = "x86_64")]
+const UTS_MACHINE: &str = "x86_64";
+#[cfg(target_arch = "riscv32")]
+const UTS_MACHINE: &str = "riscv32";
+#[cfg(target_arch = "riscv64")]
+const UTS_MACHINE: &str = "riscv64";
+#[cfg(target_arch = "aarch64")]
+const UTS_MACHINE: &str = "aarch64";
+#[cfg(target_arch = "mips")]
+const UTS_MACHINE: &str = "mips";
+
+/// System identity: sysname, nodename, release, version, machine and domainname
+#[repr(C)]
+pub struct UtsName {
+    fields: [[u8; UTS_LEN]; 6],
+}
+
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct SysInfo {
-    uptime: u64,
-    loads: [u64; 3],
-    totalram: u64,
-    freeram: u64,
-    sharedram: u64,
-    bufferram: u64,
-    totalswap: u64,
-    freeswap: u64,
+    uptime: usize,
+    loads: [usize; 3],
+    totalram: usize,
+    freeram: usize,
+    sharedram: usize,
+    bufferram: usize,
+    totalswap: usize,
+    freeswap: usize,
     procs: u16,
-    totalhigh: u64,
-    freehigh: u64,
+    totalhigh: usize,
+    freehigh: usize,
+    /// Unit of the memory sizes in bytes
     mem_unit: u32,
+    _pad: [u8; SYSINFO_PAD],
 }
 
-const RLIMIT_STACK: usize = 3;
-const RLIMIT_RSS: usize = 5;
-const RLIMIT_NOFILE: usize = 7;
-const RLIMIT_AS: usize = 9;
+/// Padding of `SysInfo` to the size on 64-bit
+const SYSINFO_PAD: usize = 20 - 2 * core::mem::size_of::<usize>() - 4;
 
-#[repr(C)]
-#[derive(Debug, Default)]
-pub struct RLimit {
-    cur: u64, // soft limit
-    max: u64, // hard limit
+pub mod test {
+    //! Enforcing the limit of open files, and the TLS base of arch_prctl
+
+    use super::*;
+    use crate::fs::FileLike;
+    use crate::net::UnixSocketState;
+    use alloc::boxed::Box;
+
+    extern "C" fn never_run(_arg: usize) -> ! {
+        unreachable!()
+    }
+
+    fn socket() -> FileLike {
+        FileLike::Socket(Box::new(UnixSocketState::new()), false)
+    }
+
+    /// New fds fail with EMFILE at the soft limit, which can be raised up to the hard limit
+    fn nofile_limit() {
+        let thread = Thread::new_kernel(never_run, 0);
+        let mut proc = thread.proc.lock();
+        let limit = |cur, max| RLimit { cur, max };
+        set_rlimit(&mut proc, RLIMIT_NOFILE, limit(2, 4)).unwrap();
+        assert_eq!(proc.add_file(socket()), Ok(0));
+        assert_eq!(proc.add_file(socket()), Ok(1));
+        assert_eq!(proc.add_file(socket()).err(), Some(SysError::EMFILE));
+        assert_eq!(
+            set_rlimit(&mut proc, RLIMIT_NOFILE, limit(8, 8)),
+            Err(SysError::EPERM)
+        );
+        assert_eq!(
+            set_rlimit(&mut proc, RLIMIT_NOFILE, limit(4, 3)),
+            Err(SysError::EINVAL)
+        );
+        assert_eq!(proc.rlimits[RLIMIT_NOFILE].cur, 2);
+        set_rlimit(&mut proc, RLIMIT_NOFILE, limit(4, 4)).unwrap();
+        assert_eq!(proc.add_file(socket()), Ok(2));
+        // a closed fd is taken again
+        proc.files.remove(&0);
+        assert_eq!(proc.add_file(socket()), Ok(0));
+    }
+
+    /// ARCH_GET_FS reads back the TLS base set by ARCH_SET_FS
+    #[cfg(any(
+        target_arch = "x86_64",
+        target_arch = "riscv32",
+        target_arch = "riscv64"
+    ))]
+    fn arch_prctl_round_trip() {
+        let mut tf: TrapFrame = unsafe { core::mem::zeroed() };
+        let mut value = 0x1234_5000;
+        assert_eq!(
+            arch_prctl(tls_base(&mut tf), ARCH_SET_FS, &mut value),
+            Ok(0)
+        );
+        let mut value = 0;
+        assert_eq!(
+            arch_prctl(tls_base(&mut tf), ARCH_GET_FS, &mut value),
+            Ok(0)
+        );
+        assert_eq!(value, 0x1234_5000);
+        #[cfg(target_arch = "x86_64")]
+        assert_eq!(tf.fsbase, 0x1234_5000);
+        #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+        assert_eq!(tf.x[4], 0x1234_5000);
+        // ARCH_SET_GS is not supported
+        assert_eq!(
+            arch_prctl(tls_base(&mut tf), 0x1001, &mut value),
+            Err(SysError::EINVAL)
+        );
+    }
+
+    pub fn test_all() {
+        nofile_limit();
+        #[cfg(any(
+            target_arch = "x86_64",
+            target_arch = "riscv32",
+            target_arch = "riscv64"
+        ))]
+        arch_prctl_round_trip();
+        println!("rlimit test end");
+    }
 }
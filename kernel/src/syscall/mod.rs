@@ -11,30 +11,36 @@ use crate::arch::cpu;
 use crate::arch::interrupt::TrapFrame;
 use crate::arch::syscall::*;
 use crate::fs::epoll::EpollEvent;
-use crate::memory::{copy_from_user, MemorySet};
+use crate::memory::{copy_from_user, copy_from_user_array, copy_to_user_array, MemorySet};
 use crate::process::*;
-use crate::sync::{Condvar, MutexGuard, SpinNoIrq};
+use crate::signal::{SignalAction, SignalStack, Sigset};
+use crate::sync::{Condvar, MutexGuard, SpinNoIrq, WaitQueue};
 use crate::thread;
 use crate::util;
+use crate::util::user_ptr::{UserInPtr, UserOutPtr};
 
 pub use self::custom::*;
 pub use self::fs::*;
+pub use self::futex::*;
 pub use self::ipc::*;
 pub use self::lkm::*;
 pub use self::mem::*;
 pub use self::misc::*;
 pub use self::net::*;
 pub use self::proc::*;
+pub use self::signal::*;
 pub use self::time::*;
 
 mod custom;
 mod fs;
+mod futex;
 mod ipc;
 mod lkm;
 mod mem;
 mod misc;
 mod net;
 mod proc;
+mod signal;
 mod time;
 
 #[cfg(feature = "profile")]
@@ -91,18 +97,18 @@ impl Syscall<'_> {
         // And https://fedora.juszkiewicz.com.pl/syscalls.html.
         let ret = match id {
             // file
-            SYS_READ => self.sys_read(args[0], args[1] as *mut u8, args[2]),
-            SYS_WRITE => self.sys_write(args[0], args[1] as *const u8, args[2]),
+            SYS_READ => self.sys_read(args[0], args[1].into(), args[2]),
+            SYS_WRITE => self.sys_write(args[0], args[1].into(), args[2]),
             SYS_OPENAT => self.sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
             SYS_CLOSE => self.sys_close(args[0]),
             SYS_FSTAT => self.sys_fstat(args[0], args[1] as *mut Stat),
             SYS_NEWFSTATAT => {
-                self.sys_fstatat(args[0], args[1] as *const u8, args[2] as *mut Stat, args[3])
+                self.sys_fstatat(args[0], args[1] as *const u8, args[2].into(), args[3])
             }
             SYS_LSEEK => self.sys_lseek(args[0], args[1] as i64, args[2] as u8),
             SYS_IOCTL => self.sys_ioctl(args[0], args[1], args[2], args[3], args[4]),
-            SYS_PREAD64 => self.sys_pread(args[0], args[1] as *mut u8, args[2], args[3]),
-            SYS_PWRITE64 => self.sys_pwrite(args[0], args[1] as *const u8, args[2], args[3]),
+            SYS_PREAD64 => self.sys_pread64(args[0], args[1] as *mut u8, args[2], args[3]),
+            SYS_PWRITE64 => self.sys_pwrite64(args[0], args[1] as *const u8, args[2], args[3]),
             SYS_READV => self.sys_readv(args[0], args[1] as *const IoVec, args[2]),
             SYS_WRITEV => self.sys_writev(args[0], args[1] as *const IoVec, args[2]),
             SYS_SENDFILE => self.sys_sendfile(args[0], args[1], args[2] as *mut usize, args[3]),
@@ -113,7 +119,7 @@ impl Syscall<'_> {
                 );
                 self.sys_fcntl(args[0], args[1], args[2])
             }
-            SYS_FLOCK => self.unimplemented("flock", Ok(0)),
+            SYS_FLOCK => self.sys_flock(args[0], args[1]),
             SYS_FSYNC => self.sys_fsync(args[0]),
             SYS_FDATASYNC => self.sys_fdatasync(args[0]),
             SYS_TRUNCATE => self.sys_truncate(args[0] as *const u8, args[1]),
@@ -125,6 +131,7 @@ impl Syscall<'_> {
                 self.sys_renameat(args[0], args[1] as *const u8, args[2], args[3] as *const u8)
             }
             SYS_MKDIRAT => self.sys_mkdirat(args[0], args[1] as *const u8, args[2]),
+            SYS_MKNODAT => self.sys_mknodat(args[0], args[1] as *const u8, args[2], args[3]),
             SYS_LINKAT => self.sys_linkat(
                 args[0],
                 args[1] as *const u8,
@@ -133,18 +140,28 @@ impl Syscall<'_> {
                 args[4],
             ),
             SYS_UNLINKAT => self.sys_unlinkat(args[0], args[1] as *const u8, args[2]),
-            SYS_SYMLINKAT => self.unimplemented("symlinkat", Err(SysError::EACCES)),
+            SYS_SYMLINKAT => {
+                self.sys_symlinkat(args[0] as *const u8, args[1], args[2] as *const u8)
+            }
             SYS_READLINKAT => {
                 self.sys_readlinkat(args[0], args[1] as *const u8, args[2] as *mut u8, args[3])
             }
-            SYS_FCHMOD => self.unimplemented("fchmod", Ok(0)),
-            SYS_FCHMODAT => self.unimplemented("fchmodat", Ok(0)),
-            SYS_FCHOWN => self.unimplemented("fchown", Ok(0)),
-            SYS_FCHOWNAT => self.unimplemented("fchownat", Ok(0)),
+            SYS_FCHMOD => self.sys_fchmod(args[0], args[1]),
+            SYS_FCHMODAT => self.sys_fchmodat(args[0], args[1] as *const u8, args[2], args[3]),
+            SYS_FCHOWN => self.sys_fchown(args[0], args[1], args[2]),
+            SYS_FCHOWNAT => {
+                self.sys_fchownat(args[0], args[1] as *const u8, args[2], args[3], args[4])
+            }
             SYS_FACCESSAT => self.sys_faccessat(args[0], args[1] as *const u8, args[2], args[3]),
-            SYS_DUP3 => self.sys_dup2(args[0], args[1]), // TODO: handle `flags`
-            SYS_PIPE2 => self.sys_pipe(args[0] as *mut u32), // TODO: handle `flags`
-            SYS_UTIMENSAT => self.unimplemented("utimensat", Ok(0)),
+            SYS_DUP => self.sys_dup(args[0]),
+            SYS_DUP3 => self.sys_dup3(args[0], args[1], args[2]),
+            SYS_PIPE2 => self.sys_pipe2(args[0] as *mut u32, args[1]),
+            SYS_UTIMENSAT => self.sys_utimensat(
+                args[0],
+                args[1] as *const u8,
+                args[2] as *const [TimeSpec; 2],
+                args[3],
+            ),
             SYS_COPY_FILE_RANGE => self.sys_copy_file_range(
                 args[0],
                 args[1] as *mut usize,
@@ -177,33 +194,64 @@ impl Syscall<'_> {
                 args[3],
                 args[4],
             ),
-            SYS_EVENTFD2 => self.unimplemented("eventfd2", Err(SysError::EACCES)),
+            SYS_EVENTFD2 => self.sys_eventfd2(args[0], args[1]),
+            SYS_TIMERFD_CREATE => self.sys_timerfd_create(args[0], args[1]),
+            SYS_TIMERFD_SETTIME => self.sys_timerfd_settime(
+                args[0],
+                args[1],
+                args[2] as *const ITimerSpec,
+                args[3] as *mut ITimerSpec,
+            ),
+            SYS_TIMERFD_GETTIME => self.sys_timerfd_gettime(args[0], args[1] as *mut ITimerSpec),
 
-            SYS_SOCKETPAIR => self.unimplemented("socketpair", Err(SysError::EACCES)),
+            SYS_SOCKETPAIR => self.sys_socketpair(args[0], args[1], args[2], args[3] as *mut u32),
             // file system
-            SYS_STATFS => self.unimplemented("statfs", Err(SysError::EACCES)),
-            SYS_FSTATFS => self.unimplemented("fstatfs", Err(SysError::EACCES)),
+            SYS_STATFS => self.sys_statfs(args[0] as *const u8, args[1] as *mut StatFs),
+            SYS_FSTATFS => self.sys_fstatfs(args[0], args[1] as *mut StatFs),
             SYS_SYNC => self.sys_sync(),
-            SYS_MOUNT => self.unimplemented("mount", Err(SysError::EACCES)),
-            SYS_UMOUNT2 => self.unimplemented("umount2", Err(SysError::EACCES)),
+            SYS_MOUNT => self.sys_mount(
+                args[0] as *const u8,
+                args[1] as *const u8,
+                args[2] as *const u8,
+                args[3],
+                args[4] as *const u8,
+            ),
+            SYS_UMOUNT2 => self.sys_umount(args[0] as *const u8, args[1]),
 
             // memory
-            SYS_BRK => self.unimplemented("brk", Err(SysError::ENOMEM)),
+            SYS_BRK => self.sys_brk(args[0]),
             SYS_MMAP => self.sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
             SYS_MPROTECT => self.sys_mprotect(args[0], args[1], args[2]),
             SYS_MUNMAP => self.sys_munmap(args[0], args[1]),
-            SYS_MADVISE => self.unimplemented("madvise", Ok(0)),
+            SYS_MSYNC => self.sys_msync(args[0], args[1], args[2]),
+            SYS_MADVISE => self.sys_madvise(args[0], args[1], args[2]),
 
             // signal
-            SYS_RT_SIGACTION => self.unimplemented("sigaction", Ok(0)),
-            SYS_RT_SIGPROCMASK => self.unimplemented("sigprocmask", Ok(0)),
-            SYS_SIGALTSTACK => self.unimplemented("sigaltstack", Ok(0)),
-            SYS_KILL => self.sys_kill(args[0], args[1]),
+            SYS_RT_SIGACTION => self.sys_rt_sigaction(
+                args[0],
+                args[1] as *const SignalAction,
+                args[2] as *mut SignalAction,
+                args[3],
+            ),
+            SYS_RT_SIGPROCMASK => self.sys_rt_sigprocmask(
+                args[0],
+                args[1] as *const Sigset,
+                args[2] as *mut Sigset,
+                args[3],
+            ),
+            SYS_SIGALTSTACK => {
+                self.sys_sigaltstack(args[0] as *const SignalStack, args[1] as *mut SignalStack)
+            }
+            SYS_RT_SIGRETURN => self.sys_rt_sigreturn(),
+            SYS_KILL => self.sys_kill(args[0] as isize, args[1]),
 
             // schedule
             SYS_SCHED_YIELD => self.sys_yield(),
             SYS_SCHED_GETAFFINITY => {
-                self.sys_sched_getaffinity(args[0], args[1], args[2] as *mut u32)
+                self.sys_sched_getaffinity(args[0], args[1], args[2] as *mut u8)
+            }
+            SYS_SCHED_SETAFFINITY => {
+                self.sys_sched_setaffinity(args[0], args[1], args[2] as *const u8)
             }
 
             // socket
@@ -264,22 +312,34 @@ impl Syscall<'_> {
             ),
             SYS_EXIT => self.sys_exit(args[0] as usize),
             SYS_EXIT_GROUP => self.sys_exit_group(args[0]),
-            SYS_WAIT4 => self.sys_wait4(args[0] as isize, args[1] as *mut i32), // TODO: wait4
+            SYS_WAIT4 => self.sys_wait4(
+                args[0] as isize,
+                args[1] as *mut i32,
+                args[2],
+                args[3] as *mut RUsage,
+            ),
             SYS_SET_TID_ADDRESS => self.sys_set_tid_address(args[0] as *mut u32),
             SYS_FUTEX => self.sys_futex(
                 args[0],
                 args[1] as u32,
                 args[2] as i32,
                 args[3] as *const TimeSpec,
+                args[4],
+                args[5] as u32,
             ),
             SYS_TKILL => self.unimplemented("tkill", Ok(0)),
 
             // time
-            SYS_NANOSLEEP => self.sys_nanosleep(args[0] as *const TimeSpec),
-            SYS_SETITIMER => self.unimplemented("setitimer", Ok(0)),
-            SYS_GETTIMEOFDAY => {
-                self.sys_gettimeofday(args[0] as *mut TimeVal, args[1] as *const u8)
+            SYS_NANOSLEEP => {
+                self.sys_nanosleep(args[0] as *const TimeSpec, args[1] as *mut TimeSpec)
             }
+            SYS_GETITIMER => self.sys_getitimer(args[0], args[1] as *mut ITimerVal),
+            SYS_SETITIMER => self.sys_setitimer(
+                args[0],
+                args[1] as *const ITimerVal,
+                args[2] as *mut ITimerVal,
+            ),
+            SYS_GETTIMEOFDAY => self.sys_gettimeofday(args[0] as *mut TimeVal, args[1] as *mut u8),
             SYS_CLOCK_GETTIME => self.sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
 
             // sem
@@ -288,31 +348,55 @@ impl Syscall<'_> {
             #[cfg(not(target_arch = "mips"))]
             SYS_SEMOP => self.sys_semop(args[0], args[1] as *const SemBuf, args[2]),
             #[cfg(not(target_arch = "mips"))]
-            SYS_SEMCTL => self.sys_semctl(args[0], args[1], args[2], args[3] as isize),
+            SYS_SEMCTL => self.sys_semctl(args[0], args[1], args[2], args[3]),
+
+            // shm
+            #[cfg(not(target_arch = "mips"))]
+            SYS_SHMGET => self.sys_shmget(args[0], args[1], args[2]),
+            #[cfg(not(target_arch = "mips"))]
+            SYS_SHMAT => self.sys_shmat(args[0], args[1], args[2]),
+            #[cfg(not(target_arch = "mips"))]
+            SYS_SHMDT => self.sys_shmdt(args[0]),
+            #[cfg(not(target_arch = "mips"))]
+            SYS_SHMCTL => self.sys_shmctl(args[0], args[1], args[2] as *mut ShmIdDs),
+
+            // msg
+            #[cfg(not(target_arch = "mips"))]
+            SYS_MSGGET => self.sys_msgget(args[0], args[1]),
+            #[cfg(not(target_arch = "mips"))]
+            SYS_MSGSND => self.sys_msgsnd(args[0], args[1], args[2], args[3]),
+            #[cfg(not(target_arch = "mips"))]
+            SYS_MSGRCV => self.sys_msgrcv(args[0], args[1], args[2], args[3] as isize, args[4]),
+            #[cfg(not(target_arch = "mips"))]
+            SYS_MSGCTL => self.sys_msgctl(args[0], args[1], args[2] as *mut MsqIdDs),
 
             // system
             SYS_GETPID => self.sys_getpid(),
             SYS_GETTID => self.sys_gettid(),
-            SYS_UNAME => self.sys_uname(args[0] as *mut u8),
-            SYS_UMASK => self.unimplemented("umask", Ok(0o777)),
-            //        SYS_GETRLIMIT => self.sys_getrlimit(),
-            //        SYS_SETRLIMIT => self.sys_setrlimit(),
+            SYS_UNAME => self.sys_uname(args[0] as *mut UtsName),
+            SYS_UMASK => self.sys_umask(args[0]),
+            SYS_GETRLIMIT => self.sys_getrlimit(args[0], args[1] as *mut RLimit),
+            SYS_SETRLIMIT => self.sys_setrlimit(args[0], args[1] as *const RLimit),
             SYS_GETRUSAGE => self.sys_getrusage(args[0], args[1] as *mut RUsage),
             SYS_SYSINFO => self.sys_sysinfo(args[0] as *mut SysInfo),
             SYS_TIMES => self.sys_times(args[0] as *mut Tms),
-            SYS_GETUID => self.unimplemented("getuid", Ok(0)),
-            SYS_GETGID => self.unimplemented("getgid", Ok(0)),
-            SYS_SETUID => self.unimplemented("setuid", Ok(0)),
-            SYS_GETEUID => self.unimplemented("geteuid", Ok(0)),
-            SYS_GETEGID => self.unimplemented("getegid", Ok(0)),
-            SYS_SETPGID => self.unimplemented("setpgid", Ok(0)),
+            SYS_GETUID => self.sys_getuid(),
+            SYS_GETGID => self.sys_getgid(),
+            SYS_SETUID => self.sys_setuid(args[0]),
+            SYS_SETGID => self.sys_setgid(args[0]),
+            SYS_GETEUID => self.sys_geteuid(),
+            SYS_GETEGID => self.sys_getegid(),
+            SYS_SETPGID => self.sys_setpgid(args[0], args[1] as isize),
             SYS_GETPPID => self.sys_getppid(),
-            SYS_SETSID => self.unimplemented("setsid", Ok(0)),
-            SYS_GETPGID => self.unimplemented("getpgid", Ok(0)),
-            SYS_GETGROUPS => self.unimplemented("getgroups", Ok(0)),
-            SYS_SETGROUPS => self.unimplemented("setgroups", Ok(0)),
+            SYS_SETSID => self.sys_setsid(),
+            SYS_GETPGID => self.sys_getpgid(args[0]),
+            SYS_GETSID => self.sys_getsid(args[0]),
+            SYS_GETGROUPS => self.sys_getgroups(args[0], args[1] as *mut u32),
+            SYS_SETGROUPS => self.sys_setgroups(args[0], args[1] as *const u32),
             SYS_SETPRIORITY => self.sys_set_priority(args[0]),
-            SYS_PRCTL => self.unimplemented("prctl", Ok(0)),
+            SYS_PRCTL => self.sys_prctl(args[0], args[1]),
+            #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+            SYS_ARCH_PRCTL => self.sys_arch_prctl(args[0] as i32, args[1]),
             SYS_MEMBARRIER => self.unimplemented("membarrier", Ok(0)),
             SYS_PRLIMIT64 => self.sys_prlimit64(
                 args[0],
@@ -359,8 +443,9 @@ impl Syscall<'_> {
                 if let Some(ret) = ret {
                     ret
                 } else {
+                    // let the program know, e.g. libc probing for a syscall
                     error!("unknown syscall id: {}, args: {:x?}", id, args);
-                    crate::trap::error(self.tf);
+                    Err(SysError::ENOSYS)
                 }
             }
         };
@@ -396,13 +481,15 @@ impl Syscall<'_> {
     fn mips_syscall(&mut self, id: usize, args: [usize; 6]) -> Option<SysResult> {
         let ret = match id {
             SYS_OPEN => self.sys_open(args[0] as *const u8, args[1], args[2]),
-            SYS_POLL => self.sys_poll(args[0] as *mut PollFd, args[1], args[2]),
+            SYS_MKNOD => self.sys_mknod(args[0] as *const u8, args[1], args[2]),
+            SYS_GETPGRP => self.sys_getpgid(0),
+            SYS_POLL => self.sys_poll(args[0] as *mut PollFd, args[1], args[2] as isize),
             SYS_DUP2 => self.sys_dup2(args[0], args[1]),
             SYS_FORK => self.sys_fork(),
             SYS_MMAP2 => self.sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5] * 4096),
             SYS_FSTAT64 => self.sys_fstat(args[0], args[1] as *mut Stat),
-            SYS_LSTAT64 => self.sys_lstat(args[0] as *const u8, args[1] as *mut Stat),
-            SYS_STAT64 => self.sys_stat(args[0] as *const u8, args[1] as *mut Stat),
+            SYS_LSTAT64 => self.sys_lstat(args[0] as *const u8, args[1].into()),
+            SYS_STAT64 => self.sys_stat(args[0] as *const u8, args[1].into()),
             SYS_PIPE => {
                 let fd_ptr = args[0] as *mut u32;
                 match self.sys_pipe(fd_ptr) {
@@ -416,7 +503,7 @@ impl Syscall<'_> {
                     Err(err) => Err(err),
                 }
             }
-            SYS_FCNTL64 => self.unimplemented("fcntl64", Ok(0)),
+            SYS_FCNTL64 => self.sys_fcntl(args[0], args[1], args[2]),
             SYS_SET_THREAD_AREA => {
                 info!("set_thread_area: tls: 0x{:x}", args[0]);
                 extern "C" {
@@ -430,12 +517,43 @@ impl Syscall<'_> {
                 Ok(0)
             }
             SYS_IPC => match args[0] {
-                1 => self.sys_semop(args[1], args[2] as *const SemBuf, args[3]),
+                1 => self.sys_semop(args[1], args[4] as *const SemBuf, args[2]),
                 2 => self.sys_semget(args[1], args[2] as isize, args[3]),
-                3 => self.sys_semctl(args[1], args[2], args[3], args[4] as isize),
+                // the argument is passed through the pointer in the fourth argument
+                3 => {
+                    let arg = unsafe { self.vm().check_read_ptr(args[4] as *const usize) };
+                    match arg {
+                        Ok(&arg) => self.sys_semctl(args[1], args[2], args[3], arg),
+                        Err(err) => Err(err.into()),
+                    }
+                }
+                11 => self.sys_msgsnd(args[1], args[4], args[2], args[3]),
+                // the buffer and type are passed in an array pointed by the fourth argument
+                12 => {
+                    let kludge = unsafe { self.vm().check_read_ptr(args[4] as *const [usize; 2]) };
+                    match kludge {
+                        Ok(&[msgp, msgtyp]) => {
+                            self.sys_msgrcv(args[1], msgp, args[2], msgtyp as isize, args[3])
+                        }
+                        Err(err) => Err(err.into()),
+                    }
+                }
+                13 => self.sys_msgget(args[1], args[2]),
+                14 => self.sys_msgctl(args[1], args[2], args[4] as *mut MsqIdDs),
+                // the address is returned through the pointer in the third argument
+                21 => self.sys_shmat(args[1], args[4], args[2]).and_then(|addr| {
+                    let raddr = unsafe { self.vm().check_write_ptr(args[3] as *mut usize)? };
+                    *raddr = addr;
+                    Ok(0)
+                }),
+                22 => self.sys_shmdt(args[4]),
+                23 => self.sys_shmget(args[1], args[2], args[3]),
+                24 => self.sys_shmctl(args[1], args[2], args[4] as *mut ShmIdDs),
                 _ => return None,
             },
+            SYS_ALARM => self.sys_alarm(args[0]),
             SYS_EPOLL_CREATE => self.sys_epoll_create(args[0]),
+            SYS_EVENTFD => self.sys_eventfd(args[0]),
             SYS_EPOLL_WAIT => {
                 self.sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3])
             }
@@ -449,9 +567,9 @@ impl Syscall<'_> {
     fn x86_64_syscall(&mut self, id: usize, args: [usize; 6]) -> Option<SysResult> {
         let ret = match id {
             SYS_OPEN => self.sys_open(args[0] as *const u8, args[1], args[2]),
-            SYS_STAT => self.sys_stat(args[0] as *const u8, args[1] as *mut Stat),
-            SYS_LSTAT => self.sys_lstat(args[0] as *const u8, args[1] as *mut Stat),
-            SYS_POLL => self.sys_poll(args[0] as *mut PollFd, args[1], args[2]),
+            SYS_STAT => self.sys_stat(args[0] as *const u8, args[1].into()),
+            SYS_LSTAT => self.sys_lstat(args[0] as *const u8, args[1].into()),
+            SYS_POLL => self.sys_poll(args[0] as *mut PollFd, args[1], args[2] as isize),
             SYS_ACCESS => self.sys_access(args[0] as *const u8, args[1]),
             SYS_PIPE => self.sys_pipe(args[0] as *mut u32),
             SYS_SELECT => self.sys_select(
@@ -462,20 +580,24 @@ impl Syscall<'_> {
                 args[4] as *const TimeVal,
             ),
             SYS_DUP2 => self.sys_dup2(args[0], args[1]),
-            SYS_ALARM => self.unimplemented("alarm", Ok(0)),
+            SYS_ALARM => self.sys_alarm(args[0]),
             SYS_FORK => self.sys_fork(),
             SYS_VFORK => self.sys_vfork(),
             SYS_RENAME => self.sys_rename(args[0] as *const u8, args[1] as *const u8),
             SYS_MKDIR => self.sys_mkdir(args[0] as *const u8, args[1]),
+            SYS_MKNOD => self.sys_mknod(args[0] as *const u8, args[1], args[2]),
+            SYS_GETPGRP => self.sys_getpgid(0),
             SYS_RMDIR => self.sys_rmdir(args[0] as *const u8),
             SYS_LINK => self.sys_link(args[0] as *const u8, args[1] as *const u8),
             SYS_UNLINK => self.sys_unlink(args[0] as *const u8),
+            SYS_SYMLINK => self.sys_symlink(args[0] as *const u8, args[1] as *const u8),
             SYS_READLINK => self.sys_readlink(args[0] as *const u8, args[1] as *mut u8, args[2]),
-            SYS_CHMOD => self.unimplemented("chmod", Ok(0)),
-            SYS_CHOWN => self.unimplemented("chown", Ok(0)),
+            SYS_CHMOD => self.sys_chmod(args[0] as *const u8, args[1]),
+            SYS_CHOWN => self.sys_chown(args[0] as *const u8, args[1], args[2]),
             SYS_ARCH_PRCTL => self.sys_arch_prctl(args[0] as i32, args[1]),
             SYS_TIME => self.sys_time(args[0] as *mut u64),
             SYS_EPOLL_CREATE => self.sys_epoll_create(args[0]),
+            SYS_EVENTFD => self.sys_eventfd(args[0]),
             SYS_EPOLL_WAIT => {
                 self.sys_epoll_wait(args[0], args[1] as *mut EpollEvent, args[2], args[3])
             }
@@ -487,9 +609,10 @@ impl Syscall<'_> {
 
 pub type SysResult = Result<usize, SysError>;
 
+/// Error numbers, with the values of Linux `asm-generic/errno.h`
 #[allow(dead_code)]
 #[repr(isize)]
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq)]
 pub enum SysError {
     EUNDEF = 0,
     EPERM = 1,
@@ -532,13 +655,19 @@ pub enum SysError {
     ENOSYS = 38,
     ENOTEMPTY = 39,
     ELOOP = 40,
-    ENOTSOCK = 80,
+    ENOMSG = 42,
+    EIDRM = 43,
+    EOVERFLOW = 75,
+    ELIBBAD = 80,
+    ENOTSOCK = 88,
     ENOPROTOOPT = 92,
     EPFNOSUPPORT = 96,
     EAFNOSUPPORT = 97,
+    EADDRINUSE = 98,
     ENOBUFS = 105,
     EISCONN = 106,
     ENOTCONN = 107,
+    ETIMEDOUT = 110,
     ECONNREFUSED = 111,
 }
 
@@ -590,13 +719,19 @@ impl fmt::Display for SysError {
                 ENOSYS => "Function not implemented",
                 ENOTEMPTY => "Directory not empty",
                 ELOOP => "Too many symbolic links encountered",
+                ENOMSG => "No message of desired type",
+                EIDRM => "Identifier removed",
+                EOVERFLOW => "Value too large for defined data type",
+                ELIBBAD => "Accessing a corrupted shared library",
                 ENOTSOCK => "Socket operation on non-socket",
                 ENOPROTOOPT => "Protocol not available",
                 EPFNOSUPPORT => "Protocol family not supported",
                 EAFNOSUPPORT => "Address family not supported by protocol",
+                EADDRINUSE => "Address already in use",
                 ENOBUFS => "No buffer space available",
                 EISCONN => "Transport endpoint is already connected",
                 ENOTCONN => "Transport endpoint is not connected",
+                ETIMEDOUT => "Connection timed out",
                 ECONNREFUSED => "Connection refused",
                 _ => "Unknown error",
             },
@@ -604,6 +739,31 @@ impl fmt::Display for SysError {
     }
 }
 
+impl From<FsError> for SysError {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotSupported => SysError::ENOSYS,
+            FsError::NotFile => SysError::EISDIR,
+            FsError::IsDir => SysError::EISDIR,
+            FsError::NotDir => SysError::ENOTDIR,
+            FsError::EntryNotFound => SysError::ENOENT,
+            FsError::EntryExist => SysError::EEXIST,
+            FsError::NotSameFs => SysError::EXDEV,
+            FsError::InvalidParam => SysError::EINVAL,
+            FsError::NoDeviceSpace => SysError::ENOSPC,
+            FsError::DirRemoved => SysError::ENOENT,
+            FsError::DirNotEmpty => SysError::ENOTEMPTY,
+            FsError::WrongFs => SysError::EINVAL,
+            FsError::DeviceError => SysError::EIO,
+            FsError::IOCTLError => SysError::EINVAL,
+            FsError::NoDevice => SysError::ENODEV,
+            FsError::Again => SysError::EAGAIN,
+            FsError::SymLoop => SysError::ELOOP,
+            FsError::Busy => SysError::EBUSY,
+        }
+    }
+}
+
 impl From<VMError> for SysError {
     fn from(_: VMError) -> Self {
         SysError::EFAULT
@@ -612,13 +772,13 @@ impl From<VMError> for SysError {
 
 const SPIN_WAIT_TIMES: usize = 100;
 
-pub fn spin_and_wait<T>(condvars: &[&Condvar], mut action: impl FnMut() -> Option<T>) -> T {
+pub fn spin_and_wait<T>(queues: &[&WaitQueue], mut action: impl FnMut() -> Option<T>) -> T {
     for _i in 0..SPIN_WAIT_TIMES {
         if let Some(result) = action() {
             return result;
         }
     }
-    Condvar::wait_events(&condvars, action)
+    WaitQueue::wait_any_until(queues, action)
 }
 
 pub fn check_and_clone_cstr(user: *const u8) -> Result<String, SysError> {
@@ -655,3 +815,41 @@ pub fn check_and_clone_cstr_array(user: *const *const u8) -> Result<Vec<String>,
         Ok(buffer)
     }
 }
+
+pub mod test {
+    //! Error numbers of file system errors
+
+    use super::*;
+
+    /// Each error maps to the closest errno
+    fn fs_error_to_errno() {
+        let cases = vec![
+            (FsError::NotSupported, SysError::ENOSYS),
+            (FsError::NotFile, SysError::EISDIR),
+            (FsError::IsDir, SysError::EISDIR),
+            (FsError::NotDir, SysError::ENOTDIR),
+            (FsError::EntryNotFound, SysError::ENOENT),
+            (FsError::EntryExist, SysError::EEXIST),
+            (FsError::NotSameFs, SysError::EXDEV),
+            (FsError::InvalidParam, SysError::EINVAL),
+            (FsError::NoDeviceSpace, SysError::ENOSPC),
+            (FsError::DirRemoved, SysError::ENOENT),
+            (FsError::DirNotEmpty, SysError::ENOTEMPTY),
+            (FsError::WrongFs, SysError::EINVAL),
+            (FsError::DeviceError, SysError::EIO),
+            (FsError::IOCTLError, SysError::EINVAL),
+            (FsError::NoDevice, SysError::ENODEV),
+            (FsError::Again, SysError::EAGAIN),
+            (FsError::SymLoop, SysError::ELOOP),
+            (FsError::Busy, SysError::EBUSY),
+        ];
+        for (error, errno) in cases {
+            assert_eq!(SysError::from(error), errno);
+        }
+    }
+
+    pub fn test_all() {
+        fs_error_to_errno();
+        println!("fs errno test end");
+    }
+}
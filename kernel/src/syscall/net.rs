@@ -6,7 +6,8 @@ use crate::fs::FileLike;
 use crate::memory::MemorySet;
 use crate::net::{
     Endpoint, LinkLevelEndpoint, NetlinkEndpoint, NetlinkSocketState, PacketSocketState,
-    RawSocketState, Socket, TcpSocketState, UdpSocketState,
+    RawSocketState, Socket, TcpSocketState, UdpSocketState, UnixSocketState, SHUT_RD, SHUT_RDWR,
+    SHUT_WR,
 };
 use alloc::boxed::Box;
 use core::cmp::min;
@@ -16,6 +17,7 @@ use smoltcp::wire::*;
 impl Syscall<'_> {
     pub fn sys_socket(&mut self, domain: usize, socket_type: usize, protocol: usize) -> SysResult {
         let domain = AddressFamily::from(domain as u16);
+        let socket_type_flags = socket_type;
         let socket_type = SocketType::from(socket_type as u8 & SOCK_TYPE_MASK);
         info!(
             "socket: domain: {:?}, socket_type: {:?}, protocol: {}",
@@ -23,12 +25,16 @@ impl Syscall<'_> {
         );
         let mut proc = self.process();
         let socket: Box<dyn Socket> = match domain {
-            AddressFamily::Internet | AddressFamily::Unix => match socket_type {
+            AddressFamily::Internet => match socket_type {
                 SocketType::Stream => Box::new(TcpSocketState::new()),
                 SocketType::Datagram => Box::new(UdpSocketState::new()),
                 SocketType::Raw => Box::new(RawSocketState::new(protocol as u8)),
                 _ => return Err(SysError::EINVAL),
             },
+            AddressFamily::Unix => match socket_type {
+                SocketType::Stream => Box::new(UnixSocketState::new()),
+                _ => return Err(SysError::EINVAL),
+            },
             AddressFamily::Packet => match socket_type {
                 SocketType::Raw => Box::new(PacketSocketState::new()),
                 _ => return Err(SysError::EINVAL),
@@ -39,10 +45,56 @@ impl Syscall<'_> {
             },
             _ => return Err(SysError::EAFNOSUPPORT),
         };
-        let fd = proc.add_file(FileLike::Socket(socket));
+        if socket_type_flags & SOCK_NONBLOCK != 0 {
+            socket.set_nonblock(true);
+        }
+        let fd_cloexec = socket_type_flags & SOCK_CLOEXEC != 0;
+        let fd = proc.add_file(FileLike::Socket(socket, fd_cloexec))?;
         Ok(fd)
     }
 
+    pub fn sys_socketpair(
+        &mut self,
+        domain: usize,
+        socket_type: usize,
+        protocol: usize,
+        fds: *mut u32,
+    ) -> SysResult {
+        let domain = AddressFamily::from(domain as u16);
+        let socket_type_flags = socket_type;
+        let socket_type = SocketType::from(socket_type as u8 & SOCK_TYPE_MASK);
+        info!(
+            "socketpair: domain: {:?}, socket_type: {:?}, protocol: {}, fds: {:?}",
+            domain, socket_type, protocol, fds
+        );
+        // only unix stream sockets can be created connected
+        if domain != AddressFamily::Unix || socket_type != SocketType::Stream {
+            return Err(SysError::EAFNOSUPPORT);
+        }
+
+        let mut proc = self.process();
+        let fds = unsafe { self.vm().check_write_array(fds, 2)? };
+        let (socket0, socket1) = UnixSocketState::new_pair();
+        if socket_type_flags & SOCK_NONBLOCK != 0 {
+            socket0.set_nonblock(true);
+            socket1.set_nonblock(true);
+        }
+        let fd_cloexec = socket_type_flags & SOCK_CLOEXEC != 0;
+        let fd0 = proc.add_file(FileLike::Socket(Box::new(socket0), fd_cloexec))?;
+        let fd1 = match proc.add_file(FileLike::Socket(Box::new(socket1), fd_cloexec)) {
+            Ok(fd) => fd,
+            Err(err) => {
+                proc.files.remove(&fd0);
+                return Err(err);
+            }
+        };
+        fds[0] = fd0 as u32;
+        fds[1] = fd1 as u32;
+
+        info!("socketpair: created fds: {} {}", fds[0], fds[1]);
+        Ok(0)
+    }
+
     pub fn sys_setsockopt(
         &mut self,
         fd: usize,
@@ -75,21 +127,15 @@ impl Syscall<'_> {
         );
         let optlen = unsafe { self.vm().check_write_ptr(optlen)? };
         match level {
-            SOL_SOCKET => match optname {
-                SO_SNDBUF => {
-                    let optval = unsafe { self.vm().check_write_ptr(optval as *mut u32)? };
-                    *optval = crate::net::TCP_SENDBUF as u32;
-                    *optlen = 4;
-                    Ok(0)
-                }
-                SO_RCVBUF => {
-                    let optval = unsafe { self.vm().check_write_ptr(optval as *mut u32)? };
-                    *optval = crate::net::TCP_RECVBUF as u32;
-                    *optlen = 4;
-                    Ok(0)
-                }
-                _ => Err(SysError::ENOPROTOOPT),
-            },
+            SOL_SOCKET => {
+                let mut proc = self.process();
+                let socket = proc.get_socket(fd)?;
+                let value = socket.getsockopt(level, optname)?;
+                let optval = unsafe { self.vm().check_write_ptr(optval as *mut u32)? };
+                *optval = value as u32;
+                *optlen = 4;
+                Ok(0)
+            }
             IPPROTO_TCP => match optname {
                 TCP_CONGESTION => Ok(0),
                 _ => Err(SysError::ENOPROTOOPT),
@@ -104,10 +150,10 @@ impl Syscall<'_> {
             fd, addr, addr_len
         );
 
-        let mut proc = self.process();
-        let endpoint = sockaddr_to_endpoint(&mut self.vm(), addr, addr_len)?;
-        let socket = proc.get_socket(fd)?;
+        let endpoint = self.get_endpoint(addr, addr_len)?;
+        let mut socket = self.clone_socket(fd)?;
         socket.connect(endpoint)?;
+        self.put_back_socket(fd, socket);
         Ok(0)
     }
 
@@ -125,18 +171,17 @@ impl Syscall<'_> {
             fd, base, len, addr, addr_len
         );
 
-        let mut proc = self.process();
-
         let slice = unsafe { self.vm().check_read_array(base, len)? };
         let endpoint = if addr.is_null() {
             None
         } else {
-            let endpoint = sockaddr_to_endpoint(&mut self.vm(), addr, addr_len)?;
+            let endpoint = self.get_endpoint(addr, addr_len)?;
             info!("sys_sendto: sending to endpoint {:?}", endpoint);
             Some(endpoint)
         };
-        let socket = proc.get_socket(fd)?;
-        socket.write(&slice, endpoint)
+        let socket = self.clone_socket(fd)?;
+        let result = socket.write(&slice, endpoint);
+        self.rearm_if_blocked(fd, EpollEvent::EPOLLOUT, result)
     }
 
     pub fn sys_recvfrom(
@@ -153,11 +198,10 @@ impl Syscall<'_> {
             fd, base, len, flags, addr, addr_len
         );
 
-        let mut proc = self.process();
-
         let mut slice = unsafe { self.vm().check_write_array(base, len)? };
-        let socket = proc.get_socket(fd)?;
+        let socket = self.clone_socket(fd)?;
         let (result, endpoint) = socket.read(&mut slice);
+        let result = self.rearm_if_blocked(fd, EpollEvent::EPOLLIN, result);
 
         if result.is_ok() && !addr.is_null() {
             let sockaddr_in = SockAddr::from(endpoint);
@@ -171,18 +215,17 @@ impl Syscall<'_> {
 
     pub fn sys_recvmsg(&mut self, fd: usize, msg: *mut MsgHdr, flags: usize) -> SysResult {
         info!("recvmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
-        let mut proc = self.process();
         let hdr = unsafe { self.vm().check_write_ptr(msg)? };
-        let mut iovs =
-            unsafe { IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &self.vm(), true)? };
+        let iovs = unsafe { IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &self.vm(), true)? };
 
         let mut buf = iovs.new_buf(true);
-        let socket = proc.get_socket(fd)?;
+        let socket = self.clone_socket(fd)?;
         let (result, endpoint) = socket.read(&mut buf);
+        let result = self.rearm_if_blocked(fd, EpollEvent::EPOLLIN, result);
 
         if let Ok(len) = result {
             // copy data to user
-            iovs.write_all_from_slice(&buf[..len]);
+            iovs.write_all_from_slice(&buf[..len])?;
             let sockaddr_in = SockAddr::from(endpoint);
             unsafe {
                 sockaddr_in.write_to(
@@ -197,11 +240,10 @@ impl Syscall<'_> {
 
     pub fn sys_bind(&mut self, fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResult {
         info!("sys_bind: fd: {} addr: {:?} len: {}", fd, addr, addr_len);
-        let mut proc = self.process();
-
-        let endpoint = sockaddr_to_endpoint(&mut self.vm(), addr, addr_len)?;
+        let endpoint = self.get_endpoint(addr, addr_len)?;
         info!("sys_bind: fd: {} bind to {:?}", fd, endpoint);
 
+        let mut proc = self.process();
         let socket = proc.get_socket(fd)?;
         socket.bind(endpoint)
     }
@@ -213,7 +255,7 @@ impl Syscall<'_> {
         let mut proc = self.process();
 
         let socket = proc.get_socket(fd)?;
-        socket.listen()
+        socket.listen(backlog)
     }
 
     pub fn sys_shutdown(&mut self, fd: usize, how: usize) -> SysResult {
@@ -221,7 +263,10 @@ impl Syscall<'_> {
         let mut proc = self.process();
 
         let socket = proc.get_socket(fd)?;
-        socket.shutdown()
+        match how {
+            SHUT_RD | SHUT_WR | SHUT_RDWR => socket.shutdown(how),
+            _ => Err(SysError::EINVAL),
+        }
     }
 
     pub fn sys_accept(&mut self, fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResult {
@@ -231,12 +276,14 @@ impl Syscall<'_> {
         );
         // smoltcp tcp sockets do not support backlog
         // open multiple sockets for each connection
-        let mut proc = self.process();
-
-        let socket = proc.get_socket(fd)?;
+        let mut socket = self.clone_socket(fd)?;
         let (new_socket, remote_endpoint) = socket.accept()?;
+        // a tcp socket listens on a new handle after accept
+        self.put_back_socket(fd, socket);
 
-        let new_fd = proc.add_file(FileLike::Socket(new_socket));
+        let new_fd = self
+            .process()
+            .add_file(FileLike::Socket(new_socket, false))?;
 
         if !addr.is_null() {
             let sockaddr_in = SockAddr::from(remote_endpoint);
@@ -300,12 +347,43 @@ impl Syscall<'_> {
         }
         Ok(0)
     }
+
+    /// Read the address `addr` given by the caller.
+    /// A unix socket path is made absolute, so that it names the same socket in any cwd.
+    fn get_endpoint(
+        &mut self,
+        addr: *const SockAddr,
+        addr_len: usize,
+    ) -> Result<Endpoint, SysError> {
+        let endpoint = sockaddr_to_endpoint(&mut self.vm(), addr, addr_len)?;
+        match endpoint {
+            Endpoint::Unix(path) => Ok(Endpoint::Unix(absolute_path(&self.process().cwd, &path))),
+            endpoint => Ok(endpoint),
+        }
+    }
+}
+
+impl Syscall<'_> {
+    /// Clone the socket of `fd` out of the file table,
+    /// so that it can block without holding the process lock.
+    /// Clones share the underlying socket.
+    fn clone_socket(&mut self, fd: usize) -> Result<Box<dyn Socket>, SysError> {
+        Ok(self.process().get_socket(fd)?.clone())
+    }
+
+    /// Store the socket changed by a blocking `connect` or `accept` back to `fd`,
+    /// unless the descriptor has been closed meanwhile
+    fn put_back_socket(&mut self, fd: usize, socket: Box<dyn Socket>) {
+        if let Ok(old) = self.process().get_socket(fd) {
+            *old = socket;
+        }
+    }
 }
 
 impl Process {
     fn get_socket(&mut self, fd: usize) -> Result<&mut Box<dyn Socket>, SysError> {
         match self.get_file_like(fd)? {
-            FileLike::Socket(socket) => Ok(socket),
+            FileLike::Socket(socket, _) => Ok(socket),
             _ => Err(SysError::EBADF),
         }
     }
@@ -401,6 +479,17 @@ impl From<Endpoint> for SockAddr {
                     nl_groups: netlink.multicast_groups_mask,
                 },
             }
+        } else if let Endpoint::Unix(path) = endpoint {
+            let mut sun_path = [0u8; 108];
+            // keep the terminating NUL
+            let len = min(path.len(), sun_path.len() - 1);
+            sun_path[..len].copy_from_slice(&path.as_bytes()[..len]);
+            SockAddr {
+                addr_un: SockAddrUn {
+                    sun_family: AddressFamily::Unix.into(),
+                    sun_path,
+                },
+            }
         } else {
             unimplemented!("only ip");
         }
@@ -418,7 +507,8 @@ fn sockaddr_to_endpoint(
         return Err(SysError::EINVAL);
     }
     let addr = unsafe { vm.check_read_ptr(addr)? };
-    if len < addr.len()? {
+    // unix socket path may not be NUL terminated within len
+    if AddressFamily::from(unsafe { addr.family }) != AddressFamily::Unix && len < addr.len()? {
         return Err(SysError::EINVAL);
     }
     unsafe {
@@ -430,7 +520,17 @@ fn sockaddr_to_endpoint(
                 ));
                 Ok(Endpoint::Ip((addr, port).into()))
             }
-            AddressFamily::Unix => Err(SysError::EINVAL),
+            AddressFamily::Unix => {
+                let path = &addr.addr_un.sun_path;
+                let path = &path[..min(len - size_of::<u16>(), path.len())];
+                let len = path.iter().position(|&c| c == 0).unwrap_or(path.len());
+                // abstract namespace is not supported
+                if len == 0 {
+                    return Err(SysError::EINVAL);
+                }
+                let path = core::str::from_utf8(&path[..len]).map_err(|_| SysError::EINVAL)?;
+                Ok(Endpoint::Unix(String::from(path)))
+            }
             AddressFamily::Packet => Ok(Endpoint::LinkLevel(LinkLevelEndpoint::new(
                 addr.addr_ll.sll_ifindex as usize,
             ))),
@@ -449,7 +549,15 @@ impl SockAddr {
             AddressFamily::Internet => Ok(size_of::<SockAddrIn>()),
             AddressFamily::Packet => Ok(size_of::<SockAddrLl>()),
             AddressFamily::Netlink => Ok(size_of::<SockAddrNl>()),
-            AddressFamily::Unix => Err(SysError::EINVAL),
+            AddressFamily::Unix => {
+                let path = unsafe { &self.addr_un.sun_path };
+                match path.iter().position(|&c| c == 0) {
+                    // unnamed socket
+                    Some(0) => Ok(size_of::<u16>()),
+                    Some(len) => Ok(size_of::<u16>() + len + 1),
+                    None => Ok(size_of::<SockAddrUn>()),
+                }
+            }
             _ => Err(SysError::EINVAL),
         }
     }
@@ -506,6 +614,10 @@ enum_with_unknown! {
 }
 
 const SOCK_TYPE_MASK: u8 = 0xf;
+/// Same as O_NONBLOCK
+const SOCK_NONBLOCK: usize = crate::fs::O_NONBLOCK;
+/// Same as O_CLOEXEC
+const SOCK_CLOEXEC: usize = crate::fs::O_CLOEXEC;
 
 enum_with_unknown! {
     /// Socket types
@@ -523,9 +635,10 @@ const IPPROTO_IP: usize = 0;
 const IPPROTO_ICMP: usize = 1;
 const IPPROTO_TCP: usize = 6;
 
-const SOL_SOCKET: usize = 1;
-const SO_SNDBUF: usize = 7;
-const SO_RCVBUF: usize = 8;
+pub const SOL_SOCKET: usize = 1;
+pub const SO_ERROR: usize = 4;
+pub const SO_SNDBUF: usize = 7;
+pub const SO_RCVBUF: usize = 8;
 const SO_LINGER: usize = 13;
 
 const TCP_CONGESTION: usize = 13;
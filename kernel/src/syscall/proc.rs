@@ -1,27 +1,51 @@
 //! Syscalls for process
 
 use super::*;
+use crate::signal::{SignalAction, SignalStack, SIGNAL_NUM, SIG_IGN};
+use crate::sync::SpinNoIrqLock;
+use core::mem::size_of;
 
 impl Syscall<'_> {
     /// Fork the current process. Return the child's PID.
     pub fn sys_fork(&mut self) -> SysResult {
         let new_thread = self.thread.fork(self.tf);
-        let pid = new_thread.proc.lock().pid.get();
-        let tid = processor().manager().add(new_thread);
-        processor().manager().detach(tid);
+        // the pid is assigned when its thread is added.
+        // The thread is not detached, so that the pid is kept until the child is waited.
+        Process::release_reaped_pids();
+        let pid = processor().manager().add(new_thread);
         info!("fork: {} -> {}", thread::current().id(), pid);
         Ok(pid)
     }
 
+    /// Create a child process sharing the address space with the current one.
+    /// The current process is suspended until the child calls exec or exits.
+    /// Return the child's PID.
     pub fn sys_vfork(&mut self) -> SysResult {
-        self.sys_fork()
+        self.vfork_on_stack(0)
+    }
+
+    /// Vfork with the child on user stack `newsp`, or on the stack of the caller if 0
+    fn vfork_on_stack(&mut self, newsp: usize) -> SysResult {
+        let mut tf = self.tf.clone();
+        if newsp != 0 {
+            tf.set_sp(newsp);
+        }
+        let (new_thread, vfork_done) = self.thread.vfork(&tf);
+        Process::release_reaped_pids();
+        let pid = processor().manager().add(new_thread);
+        info!("vfork: {} -> {}", thread::current().id(), pid);
+        vfork_done.wait();
+        info!("vfork: {} resumed", thread::current().id());
+        Ok(pid)
     }
 
     /// Create a new thread in the current process.
     /// The new thread's stack pointer will be set to `newsp`,
     /// and thread pointer will be set to `newtls`.
-    /// The child tid will be stored at both `parent_tid` and `child_tid`.
-    /// This is partially implemented for musl only.
+    /// The child tid will be stored at `parent_tid` with `CLONE_PARENT_SETTID`,
+    /// and at `child_tid` with `CLONE_CHILD_SETTID`.
+    /// Without `CLONE_VM`, this is the same as `fork`,
+    /// and with `CLONE_VM | CLONE_VFORK` but not `CLONE_THREAD`, the same as `vfork`.
     pub fn sys_clone(
         &mut self,
         flags: usize,
@@ -35,74 +59,124 @@ impl Syscall<'_> {
             "clone: flags: {:?} == {:#x}, newsp: {:#x}, parent_tid: {:?}, child_tid: {:?}, newtls: {:#x}",
             clone_flags, flags, newsp, parent_tid, child_tid, newtls
         );
-        if flags == 0x4111 || flags == 0x11 {
+        if !clone_flags.contains(CloneFlags::VM) {
             warn!("sys_clone is calling sys_fork instead, ignoring other args");
             return self.sys_fork();
         }
-        if (flags != 0x7d0f00) && (flags != 0x5d0f00) {
-            //0x5d0f00 is the args from gcc of alpine linux
-            //warn!("sys_clone only support musl pthread_create");
-            panic!(
-                "sys_clone only support sys_fork OR musl pthread_create without flags{:x}",
-                flags
-            );
-            //return Err(SysError::ENOSYS);
+        if !clone_flags.contains(CloneFlags::THREAD) {
+            if clone_flags.contains(CloneFlags::VFORK) {
+                // vfork and posix_spawn of musl come this way
+                return self.vfork_on_stack(newsp);
+            }
+            // other processes sharing one address space are not supported
+            warn!("sys_clone with CLONE_VM but without CLONE_THREAD is unsupported");
+            return Err(SysError::ENOSYS);
+        }
+        if newsp == 0 {
+            return Err(SysError::EINVAL);
         }
-        let parent_tid_ref = unsafe { self.vm().check_write_ptr(parent_tid)? };
-        let child_tid_ref = unsafe { self.vm().check_write_ptr(child_tid)? };
-        let new_thread = self
-            .thread
-            .clone(self.tf, newsp, newtls, child_tid as usize);
+        let parent_tid_ref = if clone_flags.contains(CloneFlags::PARENT_SETTID) {
+            Some(unsafe { self.vm().check_write_ptr(parent_tid)? })
+        } else {
+            None
+        };
+        let child_tid_ref = if clone_flags.contains(CloneFlags::CHILD_SETTID) {
+            Some(unsafe { self.vm().check_write_ptr(child_tid)? })
+        } else {
+            None
+        };
+        let clear_child_tid = if clone_flags.contains(CloneFlags::CHILD_CLEARTID) {
+            child_tid as usize
+        } else {
+            0
+        };
+        let new_thread = self.thread.clone(self.tf, newsp, newtls, clear_child_tid);
+        Process::release_reaped_pids();
         let tid = processor().manager().add(new_thread);
         processor().manager().detach(tid);
         info!("clone: {} -> {}", thread::current().id(), tid);
-        *parent_tid_ref = tid as u32;
-        *child_tid_ref = tid as u32;
+        if let Some(parent_tid_ref) = parent_tid_ref {
+            *parent_tid_ref = tid as u32;
+        }
+        if let Some(child_tid_ref) = child_tid_ref {
+            *child_tid_ref = tid as u32;
+        }
         Ok(tid)
     }
 
     /// Wait for the process exit.
-    /// Return the PID. Store exit code to `wstatus` if it's not null.
-    pub fn sys_wait4(&mut self, pid: isize, wstatus: *mut i32) -> SysResult {
-        info!("wait4: pid: {}, code: {:?}", pid, wstatus);
+    /// Return the PID. Store exit code to `wstatus` if it's not null,
+    /// and the CPU times and max resident set size of the child to `rusage` if it's not null.
+    pub fn sys_wait4(
+        &mut self,
+        pid: isize,
+        wstatus: *mut i32,
+        options: usize,
+        rusage: *mut RUsage,
+    ) -> SysResult {
+        info!(
+            "wait4: pid: {}, code: {:?}, options: {:#x}, rusage: {:?}",
+            pid, wstatus, options, rusage
+        );
+        let options = WaitOptions::from_bits_truncate(options);
         let wstatus = if !wstatus.is_null() {
             Some(unsafe { self.vm().check_write_ptr(wstatus)? })
         } else {
             None
         };
+        let rusage = if !rusage.is_null() {
+            Some(unsafe { self.vm().check_write_ptr(rusage)? })
+        } else {
+            None
+        };
         #[derive(Debug)]
         enum WaitFor {
             AnyChild,
-            AnyChildInGroup,
+            AnyChildInGroup(usize),
             Pid(usize),
         }
         let target = match pid {
             -1 => WaitFor::AnyChild,
-            0 => WaitFor::AnyChildInGroup,
+            0 => WaitFor::AnyChildInGroup(self.process().pgid),
             p if p > 0 => WaitFor::Pid(p as usize),
-            _ => unimplemented!(),
+            p => WaitFor::AnyChildInGroup(p.wrapping_neg() as usize),
         };
         loop {
             let mut proc = self.process();
             // check child_exit_code
             let find = match target {
-                WaitFor::AnyChild | WaitFor::AnyChildInGroup => proc
+                WaitFor::AnyChild => proc
                     .child_exit_code
                     .iter()
                     .next()
                     .map(|(&pid, &code)| (pid, code)),
+                WaitFor::AnyChildInGroup(pgid) => proc
+                    .child_exit_code
+                    .iter()
+                    .find(|(pid, _)| {
+                        proc.child_exit_usage
+                            .get(pid)
+                            .map_or(false, |usage| usage.pgid == pgid)
+                    })
+                    .map(|(&pid, &code)| (pid, code)),
                 WaitFor::Pid(pid) => proc.child_exit_code.get(&pid).map(|&code| (pid, code)),
             };
             // if found, return
             if let Some((pid, exit_code)) = find {
                 proc.child_exit_code.remove(&pid);
-                {
-                    let mut process_table = PROCESSES.write();
-                    process_table.remove(&pid);
-                }
+                // usage of the child is collected only when waited
+                let usage = proc.child_exit_usage.remove(&pid).unwrap_or_default();
+                proc.cutime += usage.utime;
+                proc.cstime += usage.stime;
+                proc.children_max_rss = proc.children_max_rss.max(usage.max_rss);
+                Process::remove_from_table(pid);
                 if let Some(wstatus) = wstatus {
+                    // exit code is already encoded by `Process::exit`
                     *wstatus = exit_code as i32;
                 }
+                if let Some(rusage) = rusage {
+                    *rusage = RUsage::new(usage.utime, usage.stime, usage.max_rss);
+                }
                 return Ok(pid);
             }
             // if not, check pid
@@ -113,7 +187,10 @@ impl Syscall<'_> {
                     .filter_map(|weak| weak.upgrade())
                     .collect();
                 match target {
-                    WaitFor::AnyChild | WaitFor::AnyChildInGroup => children.len() == 0,
+                    WaitFor::AnyChild => children.len() == 0,
+                    WaitFor::AnyChildInGroup(pgid) => {
+                        !children.iter().any(|p| p.lock().pgid == pgid)
+                    }
                     WaitFor::Pid(pid) => children
                         .iter()
                         .find(|p| p.lock().pid.get() == pid)
@@ -123,13 +200,19 @@ impl Syscall<'_> {
             if invalid {
                 return Err(SysError::ECHILD);
             }
+            if options.contains(WaitOptions::NOHANG) {
+                return Ok(0);
+            }
+            if (proc.pending_signals | self.thread.pending_signals) & !self.thread.sig_mask != 0 {
+                return Err(SysError::EINTR);
+            }
             info!(
                 "wait: thread {} -> {:?}, sleep",
                 thread::current().id(),
                 target
             );
-            let condvar = proc.child_exit.clone();
-            condvar.wait(proc);
+            let queue = proc.child_exit.clone();
+            queue.wait(proc);
         }
     }
 
@@ -180,20 +263,72 @@ impl Syscall<'_> {
 
         // Read program file
         let inode = proc.lookup_inode(&path)?;
+        let stack_limit = proc.rlimits[RLIMIT_STACK].cur as usize;
 
         // Make new Thread
         let (mut vm, entry_addr, ustack_top) =
-            Thread::new_user_vm(&inode, &path, args, envs).map_err(|_| SysError::EINVAL)?;
+            Thread::new_user_vm(&inode, &path, args.clone(), envs, stack_limit)?;
 
         // Activate new page table
-        core::mem::swap(&mut *self.vm(), &mut vm);
-        unsafe {
-            self.vm().activate();
+        proc.update_max_rss();
+        let mut vfork_vm = None;
+        if proc.vfork_done.is_some() {
+            // leave the address space to the parent
+            let vm = Arc::new(SpinNoIrqLock::new(vm));
+            unsafe {
+                vm.lock().activate();
+            }
+            proc.vm = vm.clone();
+            proc.release_vfork_parent();
+            vfork_vm = Some(vm);
+        } else {
+            core::mem::swap(&mut *self.vm(), &mut vm);
+            unsafe {
+                self.vm().activate();
+            }
+        }
+
+        // Close file descriptors marked close-on-exec
+        let cloexec_fds: Vec<usize> = proc
+            .files
+            .iter()
+            .filter(|(_, file_like)| file_like.is_cloexec())
+            .map(|(&fd, _)| fd)
+            .collect();
+        for fd in cloexec_fds {
+            proc.close_file(fd);
+        }
+
+        // Segments attached by the old program are gone with its address space
+        let pid = proc.pid.get();
+        proc.shm.clear(pid);
+
+        // The heap of new program is set up on its first brk
+        proc.brk_start = 0;
+        proc.brk = 0;
+
+        // Handlers are gone with the old program, ignored signals stay ignored
+        for action in proc.signal_actions.iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SignalAction::default();
+            }
         }
 
+        proc.cred.exec();
+
         // Modify exec path
         proc.exec_path = path.clone();
+        proc.exec_args = args;
+        // the thread is the only one left
+        proc.comm = task_comm(file_name(&path));
         drop(proc);
+        self.thread.name = task_comm(file_name(&path));
+        if let Some(vm) = vfork_vm {
+            self.thread.vm = vm;
+        }
+        self.thread.sig_alt_stack = SignalStack::disabled();
+        // the address belongs to the old program
+        self.thread.clear_child_tid = 0;
 
         // Modify the TrapFrame
         *self.tf = TrapFrame::new_user_thread(entry_addr, ustack_top);
@@ -207,27 +342,58 @@ impl Syscall<'_> {
         Ok(0)
     }
 
-    /// Kill the process
-    pub fn sys_kill(&mut self, pid: usize, sig: usize) -> SysResult {
+    /// Send signal `sig` to process `pid`
+    ///
+    /// `pid` 0 means the process group of caller,
+    /// -1 means all processes except init and caller,
+    /// and other negative values mean the process group `-pid`.
+    pub fn sys_kill(&mut self, pid: isize, sig: usize) -> SysResult {
         info!(
             "kill: thread {} kill process {} with signal {}",
             thread::current().id(),
             pid,
             sig
         );
-        let current_pid = self.process().pid.get().clone();
-        if current_pid == pid {
-            // killing myself
-            self.sys_exit_group(sig);
-        } else {
-            if let Some(proc_arc) = PROCESSES.read().get(&pid).and_then(|weak| weak.upgrade()) {
-                let mut proc = proc_arc.lock();
-                proc.exit(sig);
-                Ok(0)
-            } else {
-                Err(SysError::EINVAL)
+        if sig > SIGNAL_NUM {
+            return Err(SysError::EINVAL);
+        }
+        let (current_pid, current_pgid) = {
+            let proc = self.process();
+            (proc.pid.get(), proc.pgid)
+        };
+        // don't hold the table while locking processes
+        let processes: Vec<_> = PROCESSES
+            .read()
+            .values()
+            .filter_map(|weak| weak.upgrade())
+            .collect();
+        let mut found = false;
+        for proc in processes.iter() {
+            // broadcast does not include caller
+            if pid == -1 && Arc::ptr_eq(proc, &self.thread.proc) {
+                continue;
+            }
+            let mut proc = proc.lock();
+            let matched = match pid {
+                0 => proc.pgid == current_pgid,
+                -1 => !proc.pid.is_init(),
+                p if p > 0 => proc.pid.get() == p as usize,
+                p => proc.pgid == (-p) as usize,
+            };
+            if !matched {
+                continue;
+            }
+            found = true;
+            // signal 0 only checks the existence
+            if sig != 0 {
+                proc.send_signal(sig);
             }
         }
+        if found {
+            Ok(0)
+        } else {
+            Err(SysError::ESRCH)
+        }
     }
 
     /// Get the current process id
@@ -236,22 +402,161 @@ impl Syscall<'_> {
         Ok(self.process().pid.get())
     }
 
-    /// Get the current thread id
+    /// Get the current thread id,
+    /// which equals to the pid only for the first thread of a process
     pub fn sys_gettid(&mut self) -> SysResult {
         info!("gettid");
-        // use pid as tid for now
         Ok(thread::current().id())
     }
 
-    /// Get the parent process id
+    /// Get the parent process id.
+    /// Orphans are reparented to init on their parent's exit, so only init has no parent.
     pub fn sys_getppid(&mut self) -> SysResult {
-        if let Some(parent) = self.process().parent.upgrade() {
+        // don't hold the process while locking its parent
+        let parent = self.process().parent.upgrade();
+        if let Some(parent) = parent {
             Ok(parent.lock().pid.get())
         } else {
             Ok(0)
         }
     }
 
+    pub fn sys_getuid(&mut self) -> SysResult {
+        Ok(self.process().cred.uid as usize)
+    }
+
+    pub fn sys_geteuid(&mut self) -> SysResult {
+        Ok(self.process().cred.euid as usize)
+    }
+
+    pub fn sys_getgid(&mut self) -> SysResult {
+        Ok(self.process().cred.gid as usize)
+    }
+
+    pub fn sys_getegid(&mut self) -> SysResult {
+        Ok(self.process().cred.egid as usize)
+    }
+
+    /// Set the user id.
+    /// Root sets the real, effective and saved ids to any `uid`,
+    /// others can only set the effective id to the real or saved one.
+    pub fn sys_setuid(&mut self, uid: usize) -> SysResult {
+        info!("setuid: uid: {}", uid);
+        self.process().cred.set_uid(uid as u32)?;
+        Ok(0)
+    }
+
+    /// Set the group id, following the same rules as `setuid`.
+    pub fn sys_setgid(&mut self, gid: usize) -> SysResult {
+        info!("setgid: gid: {}", gid);
+        self.process().cred.set_gid(gid as u32)?;
+        Ok(0)
+    }
+
+    /// Store supplementary groups to `list` of size `size`, and return the number of them.
+    /// `size` 0 only returns the number.
+    pub fn sys_getgroups(&mut self, size: usize, list: *mut u32) -> SysResult {
+        let cred = self.process().cred.clone();
+        let groups = cred.get_groups(size)?;
+        if !groups.is_empty() {
+            let list = unsafe { self.vm().check_write_array(list, groups.len())? };
+            list.copy_from_slice(groups);
+        }
+        Ok(cred.groups.len())
+    }
+
+    /// Set supplementary groups from `list` of size `size`, only for root.
+    pub fn sys_setgroups(&mut self, size: usize, list: *const u32) -> SysResult {
+        info!("setgroups: size: {}", size);
+        if size > NGROUPS_MAX {
+            return Err(SysError::EINVAL);
+        }
+        let groups = unsafe { self.vm().check_read_array(list, size)? }.to_vec();
+        self.process().cred.set_groups(groups)?;
+        Ok(0)
+    }
+
+    /// Get process `pid`, or the caller if `pid` is 0
+    pub fn get_process(&self, pid: usize) -> Result<Arc<SpinNoIrqLock<Process>>, SysError> {
+        if pid == 0 || pid == self.process().pid.get() {
+            return Ok(self.thread.proc.clone());
+        }
+        PROCESSES
+            .read()
+            .get(&pid)
+            .and_then(|weak| weak.upgrade())
+            .ok_or(SysError::ESRCH)
+    }
+
+    /// Move process `pid` (0 for the caller) into process group `pgid`.
+    /// `pgid` 0 means a new group led by the process.
+    pub fn sys_setpgid(&mut self, pid: usize, pgid: isize) -> SysResult {
+        info!("setpgid: pid: {}, pgid: {}", pid, pgid);
+        if pgid < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let target = self.get_process(pid)?;
+        // only the caller and its children can be moved
+        if !Arc::ptr_eq(&target, &self.thread.proc) {
+            let parent = target.lock().parent.upgrade();
+            match parent {
+                Some(parent) if Arc::ptr_eq(&parent, &self.thread.proc) => {}
+                _ => return Err(SysError::ESRCH),
+            }
+        }
+        let caller_sid = self.process().sid;
+        let (target_pid, target_sid) = {
+            let proc = target.lock();
+            (proc.pid.get(), proc.sid)
+        };
+        let pgid = if pgid == 0 { target_pid } else { pgid as usize };
+        // a session leader can not be moved, nor can a process leave its session
+        if target_sid == target_pid || target_sid != caller_sid {
+            return Err(SysError::EPERM);
+        }
+        if pgid != target_pid {
+            // the group to join must exist in the same session
+            let processes: Vec<_> = PROCESSES
+                .read()
+                .values()
+                .filter_map(|weak| weak.upgrade())
+                .collect();
+            let exists = processes.iter().any(|proc| {
+                let proc = proc.lock();
+                proc.pgid == pgid && proc.sid == caller_sid
+            });
+            if !exists {
+                return Err(SysError::EPERM);
+            }
+        }
+        target.lock().pgid = pgid;
+        Ok(0)
+    }
+
+    /// Get the process group of process `pid`, or the caller if `pid` is 0
+    pub fn sys_getpgid(&mut self, pid: usize) -> SysResult {
+        info!("getpgid: pid: {}", pid);
+        let proc = self.get_process(pid)?;
+        let pgid = proc.lock().pgid;
+        Ok(pgid)
+    }
+
+    /// Get the session of process `pid`, or the caller if `pid` is 0
+    pub fn sys_getsid(&mut self, pid: usize) -> SysResult {
+        info!("getsid: pid: {}", pid);
+        let proc = self.get_process(pid)?;
+        let sid = proc.lock().sid;
+        Ok(sid)
+    }
+
+    /// Create a new session led by the caller, in a new process group,
+    /// and without a controlling terminal.
+    /// Return the new session id.
+    pub fn sys_setsid(&mut self) -> SysResult {
+        info!("setsid");
+        self.process().set_sid()
+    }
+
     /// Exit the current thread
     pub fn sys_exit(&mut self, exit_code: usize) -> ! {
         let tid = thread::current().id();
@@ -261,25 +566,14 @@ impl Syscall<'_> {
 
         // for last thread, exit the process
         if proc.threads.len() == 0 {
-            proc.exit(exit_code);
-        }
-
-        // perform futex wake 1
-        // ref: http://man7.org/linux/man-pages/man2/set_tid_address.2.html
-        // FIXME: do it in all possible ways a thread can exit
-        //        it has memory access so we can't move it to Thread::drop?
-        let clear_child_tid = self.thread.clear_child_tid as *mut u32;
-        if !clear_child_tid.is_null() {
-            info!("exit: futex {:#?} wake 1", clear_child_tid);
-            if let Ok(clear_child_tid_ref) = unsafe { self.vm().check_write_ptr(clear_child_tid) } {
-                *clear_child_tid_ref = 0;
-                let queue = proc.get_futex(clear_child_tid as usize);
-                queue.notify_one();
-            }
+            drop(proc);
+            Process::exit(&self.thread.proc, exit_code);
+        } else {
+            drop(proc);
+            // other threads may be joining it
+            self.clear_child_tid();
         }
 
-        drop(proc);
-
         processor().manager().exit(tid, exit_code as usize);
         processor().yield_now();
         unreachable!();
@@ -287,29 +581,102 @@ impl Syscall<'_> {
 
     /// Exit the current thread group (i.e. process)
     pub fn sys_exit_group(&mut self, exit_code: usize) -> ! {
-        let mut proc = self.process();
-        info!("exit_group: {}, code: {}", proc.pid, exit_code);
+        info!("exit_group: {}, code: {}", self.process().pid, exit_code);
 
-        proc.exit(exit_code);
+        Process::exit(&self.thread.proc, exit_code);
 
         processor().yield_now();
         unreachable!();
     }
 
-    pub fn sys_nanosleep(&mut self, req: *const TimeSpec) -> SysResult {
-        let time = unsafe { *self.vm().check_read_ptr(req)? };
-        info!("nanosleep: time: {:#?}", time);
-        // TODO: handle spurious wakeup
-        thread::sleep(time.to_duration());
+    /// Set the scheduling priority of current thread,
+    /// higher values are picked first and are clamped to `MAX_PRIORITY`.
+    pub fn sys_set_priority(&mut self, priority: usize) -> SysResult {
+        let tid = thread::current().id();
+        let priority = priority.min(MAX_PRIORITY as usize) as u8;
+        processor().manager().set_priority(tid, priority);
         Ok(0)
     }
 
-    pub fn sys_set_priority(&mut self, priority: usize) -> SysResult {
-        let pid = thread::current().id();
-        processor().manager().set_priority(pid, priority as u8);
+    /// Get thread `tid`, or the caller if `tid` is 0
+    fn get_thread_id(&self, tid: usize) -> Result<Tid, SysError> {
+        let current = thread::current().id();
+        if tid == 0 || tid == current {
+            return Ok(current);
+        }
+        let procs: Vec<_> = PROCESSES
+            .read()
+            .values()
+            .filter_map(|weak| weak.upgrade())
+            .collect();
+        for proc in procs {
+            if proc.lock().threads.contains(&tid) {
+                return Ok(tid);
+            }
+        }
+        Err(SysError::ESRCH)
+    }
+
+    /// Get the CPUs thread `pid` can run on.
+    /// Return the size of the mask written to `mask`.
+    pub fn sys_sched_getaffinity(&mut self, pid: usize, size: usize, mask: *mut u8) -> SysResult {
+        info!(
+            "sched_getaffinity: pid: {}, size: {}, mask: {:?}",
+            pid, size, mask
+        );
+        let len = size_of::<CpuMask>();
+        if size < len {
+            return Err(SysError::EINVAL);
+        }
+        let mask = unsafe { self.vm().check_write_array(mask, len)? };
+        let tid = self.get_thread_id(pid)?;
+        mask.copy_from_slice(&SCHEDULER.affinity(tid).to_ne_bytes());
+        Ok(len)
+    }
+
+    /// Restrict thread `pid` to the CPUs in `mask`,
+    /// which must contain an online CPU.
+    pub fn sys_sched_setaffinity(&mut self, pid: usize, size: usize, mask: *const u8) -> SysResult {
+        info!(
+            "sched_setaffinity: pid: {}, size: {}, mask: {:?}",
+            pid, size, mask
+        );
+        let len = size.min(size_of::<CpuMask>());
+        let mask = unsafe { self.vm().check_read_array(mask, len)? };
+        let mut bytes = [0u8; size_of::<CpuMask>()];
+        bytes[..len].copy_from_slice(mask);
+        let mask = CpuMask::from_ne_bytes(bytes);
+        let tid = self.get_thread_id(pid)?;
+        if !SCHEDULER.set_affinity(tid, mask) {
+            return Err(SysError::EINVAL);
+        }
+        // move to an allowed CPU
+        if tid == thread::current().id() && mask & (1 << cpu::id()) == 0 {
+            thread::yield_now();
+        }
         Ok(0)
     }
 
+    /// Clear the tid at `clear_child_tid` and perform futex wake 1 on it,
+    /// which lets `pthread_join` know the thread has exited.
+    /// Ref: [http://man7.org/linux/man-pages/man2/set_tid_address.2.html]
+    ///
+    /// Nothing is done if the address is not writable anymore.
+    fn clear_child_tid(&self) {
+        let clear_child_tid = self.thread.clear_child_tid as *mut u32;
+        if clear_child_tid.is_null() {
+            return;
+        }
+        if let Ok(clear_child_tid_ref) = unsafe { self.vm().check_write_ptr(clear_child_tid) } {
+            info!("exit: futex {:#?} wake 1", clear_child_tid);
+            *clear_child_tid_ref = 0;
+            if let Ok(key) = self.futex_key(clear_child_tid as usize, false) {
+                futex_wake(key, 1);
+            }
+        }
+    }
+
+    /// Set `clear_child_tid` of current thread, and return its tid
     pub fn sys_set_tid_address(&mut self, tidptr: *mut u32) -> SysResult {
         info!("set_tid_address: {:?}", tidptr);
         self.thread.clear_child_tid = tidptr as usize;
@@ -317,6 +684,15 @@ impl Syscall<'_> {
     }
 }
 
+bitflags! {
+    pub struct WaitOptions: usize {
+        /// return immediately if no child has exited
+        const NOHANG = 1;
+        /// also return if a child has stopped
+        const UNTRACED = 2;
+    }
+}
+
 bitflags! {
     pub struct CloneFlags: usize {
         const CSIGNAL =         0x000000ff;
@@ -0,0 +1,146 @@
+//! Syscalls for signal
+
+use super::*;
+use crate::signal::*;
+
+impl Syscall<'_> {
+    /// Whether a signal is waiting to be delivered to current thread,
+    /// which should interrupt a blocking syscall
+    pub fn has_signal_to_do(&self) -> bool {
+        (self.process().pending_signals | self.thread.pending_signals) & !self.thread.sig_mask != 0
+    }
+
+    /// Examine and change the action taken on receipt of `signum`
+    pub fn sys_rt_sigaction(
+        &mut self,
+        signum: usize,
+        act: *const SignalAction,
+        oldact: *mut SignalAction,
+        sigsetsize: usize,
+    ) -> SysResult {
+        info!(
+            "rt_sigaction: signum: {}, act: {:?}, oldact: {:?}",
+            signum, act, oldact
+        );
+        if signum == 0 || signum > SIGNAL_NUM || sigsetsize != SIGSET_SIZE {
+            return Err(SysError::EINVAL);
+        }
+        let new_action = if act.is_null() {
+            None
+        } else {
+            if signum == SIGKILL || signum == SIGSTOP {
+                return Err(SysError::EINVAL);
+            }
+            Some(unsafe { *self.vm().check_read_ptr(act)? })
+        };
+        if !oldact.is_null() {
+            let oldact = unsafe { self.vm().check_write_ptr(oldact)? };
+            *oldact = self.process().signal_actions[signum];
+        }
+        if let Some(action) = new_action {
+            info!("rt_sigaction: set signal {} to {:x?}", signum, action);
+            self.process().signal_actions[signum] = action;
+        }
+        Ok(0)
+    }
+
+    /// Examine and change the blocked signals of current thread
+    pub fn sys_rt_sigprocmask(
+        &mut self,
+        how: usize,
+        set: *const Sigset,
+        oldset: *mut Sigset,
+        sigsetsize: usize,
+    ) -> SysResult {
+        info!(
+            "rt_sigprocmask: how: {}, set: {:?}, oldset: {:?}",
+            how, set, oldset
+        );
+        if sigsetsize != SIGSET_SIZE {
+            return Err(SysError::EINVAL);
+        }
+        let new_set = if set.is_null() {
+            None
+        } else {
+            Some(unsafe { *self.vm().check_read_ptr(set)? })
+        };
+        if !oldset.is_null() {
+            let oldset = unsafe { self.vm().check_write_ptr(oldset)? };
+            *oldset = self.thread.sig_mask;
+        }
+        if let Some(set) = new_set {
+            let mask = match how {
+                SIG_BLOCK => self.thread.sig_mask | set,
+                SIG_UNBLOCK => self.thread.sig_mask & !set,
+                SIG_SETMASK => set,
+                _ => return Err(SysError::EINVAL),
+            };
+            self.thread.sig_mask = mask & !unblockable();
+        }
+        Ok(0)
+    }
+
+    /// Set and/or get the alternate signal stack of current thread
+    pub fn sys_sigaltstack(
+        &mut self,
+        ss: *const SignalStack,
+        old_ss: *mut SignalStack,
+    ) -> SysResult {
+        info!("sigaltstack: ss: {:?}, old_ss: {:?}", ss, old_ss);
+        let on_stack = self.thread.sig_alt_stack.contains(self.tf.get_sp());
+        let new_stack = if ss.is_null() {
+            None
+        } else {
+            Some(unsafe { *self.vm().check_read_ptr(ss)? })
+        };
+        if !old_ss.is_null() {
+            let old_ss = unsafe { self.vm().check_write_ptr(old_ss)? };
+            *old_ss = self.thread.sig_alt_stack;
+            if on_stack {
+                old_ss.flags = SignalStackFlags::ONSTACK.bits();
+            }
+        }
+        if let Some(stack) = new_stack {
+            if on_stack {
+                return Err(SysError::EPERM);
+            }
+            let flags = SignalStackFlags::from_bits(stack.flags).ok_or(SysError::EINVAL)?;
+            if flags.contains(SignalStackFlags::DISABLE) {
+                self.thread.sig_alt_stack = SignalStack::disabled();
+            } else {
+                if stack.size < MINSIGSTKSZ {
+                    return Err(SysError::ENOMEM);
+                }
+                self.thread.sig_alt_stack = SignalStack { flags: 0, ..stack };
+            }
+        }
+        Ok(0)
+    }
+
+    /// Return from a signal handler,
+    /// restoring the context and mask saved in the frame at sp
+    pub fn sys_rt_sigreturn(&mut self) -> SysResult {
+        let frame_addr = self.tf.get_sp();
+        info!("rt_sigreturn: frame: {:#x}", frame_addr);
+        let frame = unsafe { self.vm().check_read_ptr(frame_addr as *const SignalFrame) };
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(_) => {
+                // the context is lost, force the process to die
+                self.thread.sig_mask &= !sigmask(SIGSEGV);
+                self.process().force_signal(SIGSEGV);
+                return Err(SysError::EFAULT);
+            }
+        };
+        let context = frame.ucontext.context.clone();
+        let mask = frame.ucontext.mask;
+        self.tf.restore_signal_context(&context);
+        self.thread.sig_mask = mask & !unblockable();
+        // the return value register is also restored
+        Ok(self.tf.get_ret())
+    }
+}
+
+const SIG_BLOCK: usize = 0;
+const SIG_UNBLOCK: usize = 1;
+const SIG_SETMASK: usize = 2;
@@ -2,14 +2,18 @@
 
 use super::*;
 use crate::consts::USEC_PER_TICK;
+use crate::fs::{FileHandle, FileLike, OpenOptions, TimerFd, O_CLOEXEC, O_NONBLOCK};
+use crate::trap::uptime_usec;
 use core::time::Duration;
 use lazy_static::lazy_static;
 
 impl Syscall<'_> {
-    pub fn sys_gettimeofday(&mut self, tv: *mut TimeVal, tz: *const u8) -> SysResult {
+    /// Get the wall-clock time.
+    /// The obsolete timezone `tz` is ignored.
+    pub fn sys_gettimeofday(&mut self, tv: *mut TimeVal, tz: *mut u8) -> SysResult {
         info!("gettimeofday: tv: {:?}, tz: {:?}", tv, tz);
-        if tz as usize != 0 {
-            return Err(SysError::EINVAL);
+        if tv.is_null() {
+            return Ok(0);
         }
 
         let tv = unsafe { self.vm().check_write_ptr(tv)? };
@@ -22,13 +26,193 @@ impl Syscall<'_> {
     pub fn sys_clock_gettime(&mut self, clock: usize, ts: *mut TimeSpec) -> SysResult {
         info!("clock_gettime: clock: {:?}, ts: {:?}", clock, ts);
 
+        let usec = match clock {
+            CLOCK_REALTIME | CLOCK_REALTIME_COARSE => get_epoch_usec(),
+            CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {
+                get_boot_usec()
+            }
+            _ => return Err(SysError::EINVAL),
+        };
+
         let ts = unsafe { self.vm().check_write_ptr(ts)? };
+        *ts = TimeSpec::from_usec(usec);
+        Ok(0)
+    }
+
+    /// Sleep for `req`, or until interrupted by a signal,
+    /// in which case the remaining time is written to `rem`
+    pub fn sys_nanosleep(&mut self, req: *const TimeSpec, rem: *mut TimeSpec) -> SysResult {
+        let time = unsafe { *self.vm().check_read_ptr(req)? };
+        info!("nanosleep: time: {:#?}", time);
+        if !time.is_valid() {
+            return Err(SysError::EINVAL);
+        }
+        let deadline = get_epoch_usec() + time.to_usec_ceil();
+        loop {
+            let now = get_epoch_usec();
+            if now >= deadline {
+                return Ok(0);
+            }
+            thread::sleep(Duration::from_micros(deadline - now));
+            if self.has_signal_to_do() {
+                if !rem.is_null() {
+                    let rem = unsafe { self.vm().check_write_ptr(rem)? };
+                    *rem = TimeSpec::from_usec(deadline.saturating_sub(get_epoch_usec()));
+                }
+                return Err(SysError::EINTR);
+            }
+            // otherwise it is a spurious wakeup, sleep again
+        }
+    }
+
+    /// Create a timerfd on `clock`, which is disarmed
+    pub fn sys_timerfd_create(&mut self, clock: usize, flags: usize) -> SysResult {
+        info!("timerfd_create: clock: {}, flags: {:#x}", clock, flags);
+        match clock {
+            CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME => {}
+            _ => return Err(SysError::EINVAL),
+        }
+        if flags & !(O_CLOEXEC | O_NONBLOCK) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let file = FileHandle::new(
+            Arc::new(TimerFd::new(clock)),
+            OpenOptions {
+                read: true,
+                write: false,
+                append: false,
+                nonblock: flags & O_NONBLOCK != 0,
+                path: false,
+            },
+            String::from("anon_inode:[timerfd]"),
+            flags & O_CLOEXEC != 0,
+        );
+        let fd = self.process().add_file(FileLike::File(file))?;
+        Ok(fd)
+    }
+
+    /// Arm or disarm the timerfd `fd`, and write its previous setting to `old`
+    pub fn sys_timerfd_settime(
+        &mut self,
+        fd: usize,
+        flags: usize,
+        new: *const ITimerSpec,
+        old: *mut ITimerSpec,
+    ) -> SysResult {
+        info!(
+            "timerfd_settime: fd: {}, flags: {:#x}, new: {:?}, old: {:?}",
+            fd, flags, new, old
+        );
+        if flags & !TFD_TIMER_ABSTIME != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let new = unsafe { *self.vm().check_read_ptr(new)? };
+        if !new.interval.is_valid() || !new.value.is_valid() {
+            return Err(SysError::EINVAL);
+        }
+        // the timer must not be changed if the previous setting can not be written
+        let old = match old.is_null() {
+            true => None,
+            false => Some(unsafe { self.vm().check_write_ptr(old)? }),
+        };
+        let timer = self.get_timerfd(fd)?;
+        let timer = timer.as_any_ref().downcast_ref::<TimerFd>().unwrap();
+        let value = new.value.to_usec_ceil();
+        // a zero value disarms the timer
+        let deadline = match value {
+            0 => None,
+            _ if flags & TFD_TIMER_ABSTIME != 0 => {
+                let now = match timer.clock {
+                    CLOCK_REALTIME => get_epoch_usec(),
+                    _ => get_boot_usec(),
+                };
+                Some(uptime_usec() + value.saturating_sub(now))
+            }
+            _ => Some(uptime_usec() + value),
+        };
+        let (remaining, interval) = timer.set(deadline, new.interval.to_usec_ceil());
+        if let Some(old) = old {
+            *old = ITimerSpec::from_usec(remaining, interval);
+        }
+        Ok(0)
+    }
+
+    /// Get the time until the next expiration of the timerfd `fd`, and its interval
+    pub fn sys_timerfd_gettime(&mut self, fd: usize, curr: *mut ITimerSpec) -> SysResult {
+        info!("timerfd_gettime: fd: {}, curr: {:?}", fd, curr);
+        let timer = self.get_timerfd(fd)?;
+        let timer = timer.as_any_ref().downcast_ref::<TimerFd>().unwrap();
+        let (remaining, interval) = timer.get();
+        let curr = unsafe { self.vm().check_write_ptr(curr)? };
+        *curr = ITimerSpec::from_usec(remaining, interval);
+        Ok(0)
+    }
+
+    /// Get the inode of the timerfd `fd`
+    fn get_timerfd(&mut self, fd: usize) -> Result<Arc<dyn INode>, SysError> {
+        let inode = self.process().get_file(fd)?.inode();
+        if !inode.as_any_ref().is::<TimerFd>() {
+            return Err(SysError::EINVAL);
+        }
+        Ok(inode)
+    }
+
+    /// Get the interval timer `which` of the process
+    pub fn sys_getitimer(&mut self, which: usize, curr: *mut ITimerVal) -> SysResult {
+        info!("getitimer: which: {}, curr: {:?}", which, curr);
+        // only the real timer is supported
+        if which != ITIMER_REAL {
+            return Err(SysError::EINVAL);
+        }
+        let curr = unsafe { self.vm().check_write_ptr(curr)? };
+        let (value, interval) = get_real_timer(self.process().pid.get());
+        *curr = ITimerVal::from_usec(value, interval);
+        Ok(0)
+    }
 
-        let timespec = TimeSpec::get_epoch();
-        *ts = timespec;
+    /// Arm or disarm the interval timer `which` of the process,
+    /// and write its previous setting to `old`.
+    /// The real timer sends SIGALRM when it expires.
+    pub fn sys_setitimer(
+        &mut self,
+        which: usize,
+        new: *const ITimerVal,
+        old: *mut ITimerVal,
+    ) -> SysResult {
+        info!(
+            "setitimer: which: {}, new: {:?}, old: {:?}",
+            which, new, old
+        );
+        if which != ITIMER_REAL {
+            return Err(SysError::EINVAL);
+        }
+        let new = unsafe { *self.vm().check_read_ptr(new)? };
+        if !new.interval.is_valid() || !new.value.is_valid() {
+            return Err(SysError::EINVAL);
+        }
+        let pid = self.process().pid.get();
+        let (value, interval) = set_real_timer(pid, new.value.to_usec(), new.interval.to_usec());
+        if !old.is_null() {
+            let old = unsafe { self.vm().check_write_ptr(old)? };
+            *old = ITimerVal::from_usec(value, interval);
+        }
         Ok(0)
     }
 
+    /// Send SIGALRM after `seconds` through the real timer, or cancel it if `seconds` is 0.
+    /// Return the seconds left of the previous alarm.
+    pub fn sys_alarm(&mut self, seconds: usize) -> SysResult {
+        info!("alarm: seconds: {}", seconds);
+        let pid = self.process().pid.get();
+        let (value, _) = set_real_timer(pid, seconds as u64 * USEC_PER_SEC, 0);
+        // rounded to the nearest second, but a pending alarm never has 0 seconds left
+        let (sec, usec) = (value / USEC_PER_SEC, value % USEC_PER_SEC);
+        if sec == 0 && usec > 0 || usec >= USEC_PER_SEC / 2 {
+            return Ok(sec as usize + 1);
+        }
+        Ok(sec as usize)
+    }
+
     pub fn sys_time(&mut self, time: *mut u64) -> SysResult {
         let sec = get_epoch_usec() / USEC_PER_SEC;
         if time as usize != 0 {
@@ -40,23 +224,10 @@ impl Syscall<'_> {
 
     pub fn sys_getrusage(&mut self, who: usize, rusage: *mut RUsage) -> SysResult {
         info!("getrusage: who: {}, rusage: {:?}", who, rusage);
+        let usage = get_rusage(self.thread, who)?;
         let rusage = unsafe { self.vm().check_write_ptr(rusage)? };
 
-        let tick_base = *TICK_BASE;
-        let tick = unsafe { crate::trap::TICK as u64 };
-
-        let usec = (tick - tick_base) * USEC_PER_TICK as u64;
-        let new_rusage = RUsage {
-            utime: TimeVal {
-                sec: (usec / USEC_PER_SEC) as usize,
-                usec: (usec % USEC_PER_SEC) as usize,
-            },
-            stime: TimeVal {
-                sec: (usec / USEC_PER_SEC) as usize,
-                usec: (usec % USEC_PER_SEC) as usize,
-            },
-        };
-        *rusage = new_rusage;
+        *rusage = usage;
         Ok(0)
     }
 
@@ -64,14 +235,14 @@ impl Syscall<'_> {
         info!("times: buf: {:?}", buf);
         let buf = unsafe { self.vm().check_write_ptr(buf)? };
 
-        let tick_base = *TICK_BASE;
         let tick = unsafe { crate::trap::TICK as u64 };
 
+        let proc = self.process();
         let new_buf = Tms {
-            tms_utime: 0,
-            tms_stime: 0,
-            tms_cutime: 0,
-            tms_cstime: 0,
+            tms_utime: proc.utime as u64,
+            tms_stime: proc.stime as u64,
+            tms_cutime: proc.cutime as u64,
+            tms_cstime: proc.cstime as u64,
         };
 
         *buf = new_buf;
@@ -94,18 +265,41 @@ const MSEC_PER_SEC: u64 = 1_000;
 const USEC_PER_MSEC: u64 = 1_000;
 const NSEC_PER_USEC: u64 = 1_000;
 const NSEC_PER_MSEC: u64 = 1_000_000;
+const NSEC_PER_SEC: u64 = 1_000_000_000;
 
-/// Get time since epoch in usec
-fn get_epoch_usec() -> u64 {
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_REALTIME_COARSE: usize = 5;
+const CLOCK_MONOTONIC_COARSE: usize = 6;
+const CLOCK_BOOTTIME: usize = 7;
+
+// interval timers
+const ITIMER_REAL: usize = 0;
+
+// flags of timerfd_settime
+const TFD_TIMER_ABSTIME: usize = 1;
+
+/// Get time since boot in usec
+fn get_boot_usec() -> u64 {
     let tick_base = *TICK_BASE;
-    let epoch_base = *EPOCH_BASE;
     let tick = unsafe { crate::trap::TICK as u64 };
 
-    (tick - tick_base) * USEC_PER_TICK as u64 + epoch_base * USEC_PER_SEC
+    (tick - tick_base) * USEC_PER_TICK as u64
+}
+
+/// Get time since epoch in usec
+fn get_epoch_usec() -> u64 {
+    get_boot_usec() + *EPOCH_BASE * USEC_PER_SEC
+}
+
+/// Get seconds since epoch, for timestamps of IPC objects
+pub fn epoch_sec() -> usize {
+    (get_epoch_usec() / USEC_PER_SEC) as usize
 }
 
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub struct TimeVal {
     sec: usize,
     usec: usize,
@@ -116,13 +310,25 @@ impl TimeVal {
         (self.sec as u64) * MSEC_PER_SEC + (self.usec as u64) / USEC_PER_MSEC
     }
 
-    pub fn get_epoch() -> Self {
-        let usec = get_epoch_usec();
+    pub fn to_usec(&self) -> u64 {
+        (self.sec as u64) * USEC_PER_SEC + self.usec as u64
+    }
+
+    /// Whether it is a valid time, with usec in 0..1_000_000
+    pub fn is_valid(&self) -> bool {
+        (self.sec as isize) >= 0 && (self.usec as u64) < USEC_PER_SEC
+    }
+
+    pub fn from_usec(usec: u64) -> Self {
         TimeVal {
             sec: (usec / USEC_PER_SEC) as usize,
             usec: (usec % USEC_PER_SEC) as usize,
         }
     }
+
+    pub fn get_epoch() -> Self {
+        TimeVal::from_usec(get_epoch_usec())
+    }
 }
 
 #[repr(C)]
@@ -141,20 +347,110 @@ impl TimeSpec {
         Duration::new(self.sec as u64, self.nsec as u32)
     }
 
-    pub fn get_epoch() -> Self {
-        let usec = get_epoch_usec();
+    /// Round up to usec
+    pub fn to_usec_ceil(&self) -> u64 {
+        (self.sec as u64) * USEC_PER_SEC + (self.nsec as u64 + NSEC_PER_USEC - 1) / NSEC_PER_USEC
+    }
+
+    pub fn from_usec(usec: u64) -> Self {
         TimeSpec {
             sec: (usec / USEC_PER_SEC) as usize,
             nsec: (usec % USEC_PER_SEC * NSEC_PER_USEC) as usize,
         }
     }
+
+    /// Whether it is a valid time, with nsec in 0..1_000_000_000
+    pub fn is_valid(&self) -> bool {
+        (self.sec as isize) >= 0 && (self.nsec as u64) < NSEC_PER_SEC
+    }
+
+    pub fn get_epoch() -> Self {
+        TimeSpec::from_usec(get_epoch_usec())
+    }
+
+    /// Resolve a timestamp given to utimensat,
+    /// returning None for UTIME_OMIT and `now` for UTIME_NOW
+    pub fn to_utime(&self, now: &TimeSpec) -> Result<Option<TimeSpec>, SysError> {
+        match self.nsec {
+            UTIME_OMIT => Ok(None),
+            UTIME_NOW => Ok(Some(*now)),
+            _ if self.is_valid() => Ok(Some(*self)),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+}
+
+impl From<TimeSpec> for rcore_fs::vfs::Timespec {
+    fn from(time: TimeSpec) -> Self {
+        rcore_fs::vfs::Timespec {
+            sec: time.sec as i64,
+            nsec: time.nsec as i32,
+        }
+    }
+}
+
+/// Set the timestamp to the current time in utimensat
+pub const UTIME_NOW: usize = (1 << 30) - 1;
+/// Leave the timestamp unchanged in utimensat
+pub const UTIME_OMIT: usize = (1 << 30) - 2;
+
+/// Usage of `who`: `thread`, its process, or the children of the process
+fn get_rusage(thread: &Thread, who: usize) -> Result<RUsage, SysError> {
+    let (utime, stime, max_rss) = match who {
+        RUSAGE_SELF => {
+            let mut proc = thread.proc.lock();
+            proc.update_max_rss();
+            (proc.utime, proc.stime, proc.max_rss)
+        }
+        RUSAGE_CHILDREN => {
+            let proc = thread.proc.lock();
+            (proc.cutime, proc.cstime, proc.children_max_rss)
+        }
+        RUSAGE_THREAD => {
+            let max_rss = thread.proc.lock().max_rss;
+            (thread.utime, thread.stime, max_rss)
+        }
+        _ => return Err(SysError::EINVAL),
+    };
+    Ok(RUsage::new(utime, stime, max_rss))
 }
 
-// ignore other fields for now
+const RUSAGE_SELF: usize = 0;
+const RUSAGE_CHILDREN: usize = -1isize as usize;
+const RUSAGE_THREAD: usize = 1;
+
+// only times and max_rss are filled for now
 #[repr(C)]
+#[derive(Debug, Default)]
 pub struct RUsage {
     utime: TimeVal,
     stime: TimeVal,
+    max_rss: usize, // in KB
+    ix_rss: usize,
+    id_rss: usize,
+    is_rss: usize,
+    min_flt: usize,
+    maj_flt: usize,
+    nswap: usize,
+    in_block: usize,
+    ou_block: usize,
+    msg_snd: usize,
+    msg_rcv: usize,
+    nsignals: usize,
+    nvcsw: usize,
+    nivcsw: usize,
+}
+
+impl RUsage {
+    /// Usage of `utime` and `stime` in ticks, and `max_rss` in bytes
+    pub fn new(utime: usize, stime: usize, max_rss: usize) -> Self {
+        RUsage {
+            utime: TimeVal::from_usec((utime * USEC_PER_TICK) as u64),
+            stime: TimeVal::from_usec((stime * USEC_PER_TICK) as u64),
+            max_rss: max_rss / 1024,
+            ..RUsage::default()
+        }
+    }
 }
 
 #[repr(C)]
@@ -165,3 +461,83 @@ pub struct Tms {
     tms_cutime: u64, /* user time of children */
     tms_cstime: u64, /* system time of children */
 }
+
+/// Setting of a timer, in the layout of `struct itimerspec`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ITimerSpec {
+    interval: TimeSpec,
+    value: TimeSpec,
+}
+
+impl ITimerSpec {
+    fn from_usec(value: u64, interval: u64) -> Self {
+        ITimerSpec {
+            interval: TimeSpec::from_usec(interval),
+            value: TimeSpec::from_usec(value),
+        }
+    }
+}
+
+/// Setting of an interval timer, in the layout of `struct itimerval`
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ITimerVal {
+    interval: TimeVal,
+    value: TimeVal,
+}
+
+impl ITimerVal {
+    fn from_usec(value: u64, interval: u64) -> Self {
+        ITimerVal {
+            interval: TimeVal::from_usec(interval),
+            value: TimeVal::from_usec(value),
+        }
+    }
+}
+
+pub mod test {
+    //! Resource usage of a process
+
+    use super::*;
+    use crate::memory::{Delay, GlobalFrameAlloc, MemoryAttr};
+    use rcore_memory::PAGE_SIZE;
+
+    extern "C" fn never_run(_arg: usize) -> ! {
+        unreachable!()
+    }
+
+    /// The ticks and the peak of resident pages are reported
+    fn usage_after_work() {
+        let mut thread = Thread::new_kernel(never_run, 0);
+        let start = 0x1000_0000;
+        thread.vm.lock().push(
+            start,
+            start + PAGE_SIZE * 4,
+            MemoryAttr::default().user(),
+            Delay::new(GlobalFrameAlloc),
+            "work",
+        );
+        thread.vm.lock().prefault(start, start + PAGE_SIZE * 2);
+        for _ in 0..3 {
+            thread.tick(true);
+        }
+        thread.tick(false);
+        let usage = get_rusage(&thread, RUSAGE_SELF).unwrap();
+        assert!(usage.utime.sec > 0 || usage.utime.usec > 0);
+        assert!(usage.stime.sec > 0 || usage.stime.usec > 0);
+        assert!(usage.max_rss >= PAGE_SIZE * 2 / 1024);
+        // the peak stays when the pages are dropped
+        thread.vm.lock().discard(start, start + PAGE_SIZE * 4);
+        let max_rss = get_rusage(&thread, RUSAGE_SELF).unwrap().max_rss;
+        assert_eq!(max_rss, usage.max_rss);
+        let children = get_rusage(&thread, RUSAGE_CHILDREN).unwrap();
+        assert_eq!((children.utime.sec, children.utime.usec), (0, 0));
+        assert_eq!(get_rusage(&thread, 2).err(), Some(SysError::EINVAL));
+    }
+
+    pub fn test_all() {
+        usage_after_work();
+        println!("rusage test end");
+    }
+}
@@ -2,41 +2,80 @@ use crate::arch::cpu;
 use crate::arch::interrupt::TrapFrame;
 use crate::consts::INFORM_PER_MSEC;
 use crate::process::*;
-use crate::sync::Condvar;
+use crate::signal::{
+    is_ignored, sigmask, SignalAction, SEGV_ACCERR, SEGV_MAPERR, SIGSEGV, SIG_DFL,
+};
+use crate::sync::WaitQueue;
 use log::*;
 
 pub static mut TICK: usize = 0;
 
 lazy_static! {
-    pub static ref TICK_ACTIVITY: Condvar = Condvar::new();
+    pub static ref TICK_ACTIVITY: WaitQueue = WaitQueue::new();
 }
 
 pub fn uptime_msec() -> usize {
     unsafe { crate::trap::TICK * crate::consts::USEC_PER_TICK / 1000 }
 }
 
-pub fn timer() {
+pub fn uptime_usec() -> u64 {
+    unsafe { crate::trap::TICK as u64 * crate::consts::USEC_PER_TICK as u64 }
+}
+
+pub fn timer(tf: &TrapFrame) {
     if cpu::id() == 0 {
         unsafe {
             TICK += 1;
+            crate::fs::timerfd_tick();
+            crate::fs::STDIN.send_interrupt();
+            real_timer_tick();
             if uptime_msec() % INFORM_PER_MSEC == 0 {
-                TICK_ACTIVITY.notify_all();
+                TICK_ACTIVITY.wake_all();
             }
         }
     }
+    // ticks in the scheduling loop belong to no thread
+    if tf.is_user() || is_thread_running() {
+        unsafe { current_thread().tick(tf.is_user()) };
+    }
     processor().tick();
 }
 
 pub fn error(tf: &TrapFrame) -> ! {
     error!("{:#x?}", tf);
     unsafe {
-        let mut proc = current_thread().proc.lock();
-        proc.exit(0x100);
+        Process::exit(&current_thread().proc, 1);
     }
     processor().yield_now();
     unreachable!();
 }
 
+/// Handle a page fault at `addr` which is not resolved by the memory set.
+/// The faulting thread receives SIGSEGV if the fault is from user mode,
+/// which it can not ignore or block.
+pub fn segfault(tf: &TrapFrame, addr: usize) {
+    if !tf.is_user() {
+        error(tf);
+    }
+    let thread = unsafe { current_thread() };
+    let mapped = thread.vm.lock().iter().any(|area| area.contains(addr));
+    let code = if mapped { SEGV_ACCERR } else { SEGV_MAPERR };
+    let mut proc = thread.proc.lock();
+    let action = &mut proc.signal_actions[SIGSEGV];
+    if is_ignored(SIGSEGV, action) || thread.sig_mask & sigmask(SIGSEGV) != 0 {
+        *action = SignalAction::default();
+        thread.sig_mask &= !sigmask(SIGSEGV);
+    }
+    if proc.pid.is_init() && proc.signal_actions[SIGSEGV].handler == SIG_DFL {
+        // init would fault again
+        drop(proc);
+        error(tf);
+    }
+    // the info is taken by the faulting thread when it enters the handler
+    thread.segv_info = Some((addr, code));
+    thread.pending_signals |= sigmask(SIGSEGV);
+}
+
 pub fn serial(c: char) {
     if c == '\r' {
         // in linux, we use '\n' instead
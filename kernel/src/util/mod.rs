@@ -1,5 +1,7 @@
 use core::ptr::{read_volatile, write_volatile};
 
+pub mod user_ptr;
+
 /// Convert C string to Rust string
 pub unsafe fn from_cstr(s: *const u8) -> &'static str {
     use core::{slice, str};
@@ -0,0 +1,129 @@
+//! Pointers to user memory
+//!
+//! A user pointer can only be turned into a reference after checking
+//! that it is not null, is aligned, lies below `USER_END`,
+//! and is covered by the areas of the process memory set.
+
+use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE};
+use crate::memory::MemorySet;
+use crate::syscall::SysError;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+
+/// User memory lies below the top of user stack
+pub const USER_END: usize = USER_STACK_OFFSET + USER_STACK_SIZE;
+
+pub trait Policy {}
+pub trait Read: Policy {}
+pub trait Write: Policy {}
+
+pub enum In {}
+pub enum Out {}
+pub enum InOut {}
+
+impl Policy for In {}
+impl Policy for Out {}
+impl Policy for InOut {}
+impl Read for In {}
+impl Write for Out {}
+impl Read for InOut {}
+impl Write for InOut {}
+
+pub type UserInPtr<T> = UserPtr<T, In>;
+pub type UserOutPtr<T> = UserPtr<T, Out>;
+pub type UserInOutPtr<T> = UserPtr<T, InOut>;
+
+/// A pointer from user, which is read and/or written as `P` allows
+pub struct UserPtr<T, P: Policy> {
+    ptr: *mut T,
+    mark: PhantomData<P>,
+}
+
+impl<T, P: Policy> From<usize> for UserPtr<T, P> {
+    fn from(addr: usize) -> Self {
+        UserPtr {
+            ptr: addr as *mut T,
+            mark: PhantomData,
+        }
+    }
+}
+
+impl<T, P: Policy> Clone for UserPtr<T, P> {
+    fn clone(&self) -> Self {
+        UserPtr {
+            ptr: self.ptr,
+            mark: PhantomData,
+        }
+    }
+}
+
+impl<T, P: Policy> Copy for UserPtr<T, P> {}
+
+impl<T, P: Policy> fmt::Debug for UserPtr<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.ptr)
+    }
+}
+
+impl<T, P: Policy> UserPtr<T, P> {
+    pub fn is_null(&self) -> bool {
+        self.ptr.is_null()
+    }
+
+    pub fn as_addr(&self) -> usize {
+        self.ptr as usize
+    }
+
+    /// Check that `len` elements from the pointer are inside user space.
+    /// Fail with `EFAULT` if the pointer is null or misaligned,
+    /// or the range reaches the kernel.
+    pub fn check(&self, len: usize) -> Result<(), SysError> {
+        let addr = self.ptr as usize;
+        if self.ptr.is_null() || addr % align_of::<T>() != 0 {
+            return Err(SysError::EFAULT);
+        }
+        let end = size_of::<T>()
+            .checked_mul(len)
+            .and_then(|size| addr.checked_add(size))
+            .ok_or(SysError::EFAULT)?;
+        if end > USER_END {
+            return Err(SysError::EFAULT);
+        }
+        Ok(())
+    }
+}
+
+impl<T, P: Read> UserPtr<T, P> {
+    /// Get `len` elements which are readable in `vm`
+    pub fn as_slice(&self, vm: &MemorySet, len: usize) -> Result<&'static [T], SysError> {
+        if len == 0 {
+            return Ok(&[]);
+        }
+        self.check(len)?;
+        unsafe { vm.check_read_array(self.ptr, len) }.map_err(|_| SysError::EFAULT)
+    }
+
+    pub fn read(&self, vm: &MemorySet) -> Result<T, SysError>
+    where
+        T: Copy,
+    {
+        Ok(self.as_slice(vm, 1)?[0])
+    }
+}
+
+impl<T, P: Write> UserPtr<T, P> {
+    /// Get `len` elements which are writable in `vm`
+    pub fn as_mut_slice(&self, vm: &MemorySet, len: usize) -> Result<&'static mut [T], SysError> {
+        if len == 0 {
+            return Ok(&mut []);
+        }
+        self.check(len)?;
+        unsafe { vm.check_write_array(self.ptr, len) }.map_err(|_| SysError::EFAULT)
+    }
+
+    pub fn write(&self, vm: &MemorySet, value: T) -> Result<(), SysError> {
+        self.as_mut_slice(vm, 1)?[0] = value;
+        Ok(())
+    }
+}